@@ -0,0 +1,718 @@
+//! ISO 9660 (.iso) images, which can only be unpacked and listed, read in place.
+//!
+//! The names of the files come from the Rock Ridge extensions when the image has them, which also
+//! give their permissions, then from the Joliet ones, then from the plain ISO 9660 names, without
+//! their ";1" version suffixes.
+//!
+//! Limitations:
+//! - Images with only a UDF file system, like most Blu-ray discs, can't be read, the ones with both
+//!   UDF and ISO 9660, like most DVDs, are read through ISO 9660.
+//! - The symlinks and the other special files of Rock Ridge are skipped.
+
+use std::{
+    collections::HashSet,
+    io::{self, BufWriter, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use filetime::FileTime;
+use fs_err as fs;
+use time::{Date, Month, PrimitiveDateTime, Time, UtcOffset};
+
+use crate::{
+    archive::{self, CaseConflicts, UnpackOptions},
+    error::{Error, FinalError},
+    info,
+    list::FileInArchive,
+    utils::{self, Bytes, EscapedPathDisplay, ProgressEvent, RateLimitedReader, RetryingWriter},
+    BUFFER_CAPACITY,
+};
+
+const SECTOR_SIZE: u64 = 2048;
+
+/// The volume descriptors come after the system area, which is the first 16 sectors
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+
+/// Most volume descriptors read, images end their list long before
+const MAX_DESCRIPTORS: u64 = 64;
+
+/// Length of a directory record without its name and system use area
+const RECORD_HEADER_LEN: usize = 33;
+
+/// Flags of the directory records
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Type of file in the Unix mode of Rock Ridge
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// A file or directory of the image
+#[derive(Debug)]
+struct IsoEntry {
+    /// Path in the image, from its root
+    path: PathBuf,
+    is_dir: bool,
+    /// First sector and length in bytes of each part of the contents, files of 4 GiB and more are
+    /// split in several
+    extents: Vec<(u32, u32)>,
+    /// Modification time, in seconds since the Unix epoch
+    modified: Option<i64>,
+    /// Unix mode stored by Rock Ridge
+    mode: Option<u32>,
+}
+
+impl IsoEntry {
+    fn size(&self) -> u64 {
+        self.extents.iter().map(|&(_, length)| u64::from(length)).sum()
+    }
+}
+
+/// The directory tree the entries are read from
+struct Volume {
+    root_sector: u32,
+    root_length: u32,
+    /// The names are UCS-2, from the Joliet tree
+    joliet: bool,
+    /// Bytes skipped at the start of the system use areas, set if the tree has Rock Ridge
+    rock_ridge_skip: Option<usize>,
+}
+
+/// A directory record, as stored in the image
+struct Record {
+    name: Result<String, Vec<u8>>,
+    is_dir: bool,
+    multi_extent: bool,
+    sector: u32,
+    length: u32,
+    modified: Option<i64>,
+    mode: Option<u32>,
+    /// Rock Ridge: the sector of the directory moved away from here, to keep the tree shallow
+    child_link: Option<u32>,
+    /// Rock Ridge: the directory was moved here, it is read where it was moved from
+    relocated: bool,
+}
+
+/// Unpacks the image read from `reader` into `output_folder`, returning the count of unpacked entries.
+pub fn unpack_archive<R: Read + Seek>(
+    mut reader: R,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    quiet: bool,
+) -> crate::Result<usize> {
+    let entries = read_entries(&mut reader)?;
+    let mut case_conflicts = CaseConflicts::for_folder(output_folder, unpack_options.case_conflicts)?;
+    let retries = unpack_options.retries;
+    let mut count = 0;
+    // Set once all the files are in, read-only directories, which are common in images, couldn't
+    // get them otherwise
+    #[cfg(unix)]
+    let mut directory_modes = vec![];
+
+    for entry in entries {
+        if unpack_options.skips(&entry.path) {
+            continue;
+        }
+        let path = match case_conflicts.as_mut() {
+            Some(case_conflicts) if !entry.is_dir => match case_conflicts.resolve(&entry.path)? {
+                Some(path) => path,
+                None => continue,
+            },
+            _ => entry.path.clone(),
+        };
+        count += 1;
+        unpack_options.check_entry_count(count)?;
+
+        let dest = output_folder.join(&path);
+        utils::emit_progress(ProgressEvent::Entry {
+            path: &path,
+            bytes: entry.size(),
+        });
+
+        if entry.is_dir {
+            utils::retry_transient(retries, &dest, || fs::create_dir_all(&dest))?;
+        } else {
+            if let Some(parent) = dest.parent() {
+                utils::retry_transient(retries, parent, || fs::create_dir_all(parent))?;
+            }
+            // This is printed for every file in the image and has little importance for most users,
+            // but would generate lots of spoken text for users of screen readers
+            if !quiet {
+                info!(
+                    inaccessible,
+                    "{:?} extracted. ({})",
+                    dest.display(),
+                    Bytes::new(entry.size())
+                );
+            }
+            unpack_file(&mut reader, &entry, &dest, unpack_options)?;
+        }
+
+        archive::set_owner(&dest, unpack_options)?;
+        #[cfg(unix)]
+        if let Some(mode) = entry.mode {
+            if entry.is_dir {
+                directory_modes.push((dest, mode));
+            } else {
+                archive::set_unix_permissions(&dest, mode, unpack_options)?;
+            }
+        }
+    }
+
+    // Children first, the parents may be read-only too
+    #[cfg(unix)]
+    for (dir, mode) in directory_modes.into_iter().rev() {
+        archive::set_unix_permissions(&dir, mode, unpack_options)?;
+    }
+
+    Ok(count)
+}
+
+/// Lists the files of the image read from `reader`
+pub fn list_archive<R: Read + Seek>(mut reader: R) -> crate::Result<Vec<FileInArchive>> {
+    let files = read_entries(&mut reader)?
+        .into_iter()
+        .map(|entry| FileInArchive {
+            size: entry.size(),
+            path: entry.path,
+            is_dir: entry.is_dir,
+            modified: entry.modified,
+            is_symlink: entry.mode.is_some_and(archive::is_symlink_mode),
+            compressed_size: None,
+            mode: entry.mode,
+        })
+        .collect();
+    Ok(files)
+}
+
+/// Writes the contents of `entry`, read from `reader`, to the file `dest`
+fn unpack_file<R: Read + Seek>(
+    reader: &mut R,
+    entry: &IsoEntry,
+    dest: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<()> {
+    let retries = unpack_options.retries;
+    let file = utils::retry_transient(retries, dest, || fs::File::create(dest))?;
+    let mut writer = BufWriter::with_capacity(BUFFER_CAPACITY, RetryingWriter::new(file, retries, dest));
+
+    // Bytes left to unpack with `--head`
+    let mut remaining = unpack_options.head.unwrap_or(u64::MAX);
+    if remaining < entry.size() {
+        unpack_options.report_cut(&entry.path);
+    }
+    for &(sector, length) in &entry.extents {
+        let length = u64::from(length).min(remaining);
+        remaining -= length;
+        reader.seek(SeekFrom::Start(u64::from(sector) * SECTOR_SIZE))?;
+        let contents = reader.by_ref().take(length);
+        let mut contents = RateLimitedReader::new(contents, unpack_options.rate_limit.clone());
+        if io::copy(&mut contents, &mut writer)? < length {
+            return Err(invalid_image(format!(
+                "The contents of '{}' go past its end",
+                EscapedPathDisplay::new(&entry.path)
+            )));
+        }
+    }
+
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .into_inner();
+    // Like with the other formats, failing to set the time doesn't fail the unpacking
+    if let Some(modified) = entry.modified {
+        let _ = filetime::set_file_handle_times(file.file(), None, Some(FileTime::from_unix_time(modified, 0)));
+    }
+    Ok(())
+}
+
+/// Reads the entries of the image, each directory before its contents
+fn read_entries<R: Read + Seek>(reader: &mut R) -> crate::Result<Vec<IsoEntry>> {
+    let volume = read_volume(reader)?;
+
+    let mut entries = vec![];
+    // Directories already read, so loops in broken or malicious images end
+    let mut visited = HashSet::new();
+    let mut dirs = vec![(PathBuf::new(), volume.root_sector, volume.root_length)];
+
+    while let Some((dir_path, sector, length)) = dirs.pop() {
+        if !visited.insert(sector) {
+            continue;
+        }
+
+        let mut last_multi_extent = false;
+        for record in read_directory(reader, sector, length, &volume)? {
+            // The other parts of a file split in several extents follow its first record
+            if last_multi_extent {
+                // Safe unwrap, the record before was pushed
+                let entry: &mut IsoEntry = entries.last_mut().unwrap();
+                entry.extents.push((record.sector, record.length));
+                last_multi_extent = record.multi_extent;
+                continue;
+            }
+            if record.relocated {
+                continue;
+            }
+
+            let name = match record.name {
+                Ok(name) if is_valid_name(&name) => name,
+                Ok(name) => return Err(invalid_name(&dir_path, name.as_bytes())),
+                Err(name) => return Err(invalid_name(&dir_path, &name)),
+            };
+            let path = dir_path.join(name);
+
+            let (is_dir, sector, length) = match record.child_link {
+                Some(sector) => (true, sector, directory_length(reader, sector)?),
+                None => (record.is_dir, record.sector, record.length),
+            };
+            let is_special = record
+                .mode
+                .is_some_and(|mode| !matches!(mode & S_IFMT, S_IFDIR | S_IFREG));
+            if is_special {
+                utils::warn_or_fail(format!(
+                    "Skipping '{}', only the files and directories of images are unpacked.",
+                    EscapedPathDisplay::new(&path)
+                ))?;
+                continue;
+            }
+
+            if is_dir {
+                dirs.push((path.clone(), sector, length));
+            }
+            last_multi_extent = record.multi_extent && !is_dir;
+            entries.push(IsoEntry {
+                path,
+                is_dir,
+                extents: if is_dir { vec![] } else { vec![(sector, length)] },
+                modified: record.modified,
+                mode: record.mode,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Reads the volume descriptors, picking the directory tree with the best names
+fn read_volume<R: Read + Seek>(reader: &mut R) -> crate::Result<Volume> {
+    let mut primary_root = None;
+    let mut joliet_root = None;
+    let mut has_udf = false;
+
+    for sector in FIRST_DESCRIPTOR_SECTOR..FIRST_DESCRIPTOR_SECTOR + MAX_DESCRIPTORS {
+        let Some(descriptor) = read_sector(reader, sector)? else {
+            break;
+        };
+        match &descriptor[1..6] {
+            b"CD001" => match descriptor[0] {
+                1 => primary_root = primary_root.or(Some(root_record(&descriptor))),
+                // Supplementary descriptors are Joliet when their escape sequence is one of UCS-2
+                2 if matches!(&descriptor[88..91], b"%/@" | b"%/C" | b"%/E") => {
+                    joliet_root = joliet_root.or(Some(root_record(&descriptor)));
+                }
+                _ => {}
+            },
+            // The volume recognition sequence of UDF follows the descriptors of ISO 9660, if any
+            b"NSR02" | b"NSR03" => has_udf = true,
+            b"BEA01" | b"TEA01" | b"BOOT2" | b"CDW02" => {}
+            _ => break,
+        }
+    }
+
+    let Some((root_sector, root_length)) = primary_root else {
+        let error = if has_udf {
+            FinalError::with_title("Cannot read UDF images")
+                .detail("The image only has a UDF file system, ouch can only read ISO 9660 ones")
+                .hint("Mount the image, or unpack it with a tool that reads UDF, like 7-Zip")
+        } else {
+            FinalError::with_title("Invalid ISO 9660 image").detail("It has no primary volume descriptor")
+        };
+        return Err(Error::Custom { reason: error });
+    };
+
+    if let Some(skip) = rock_ridge_skip(reader, root_sector)? {
+        return Ok(Volume {
+            root_sector,
+            root_length,
+            joliet: false,
+            rock_ridge_skip: Some(skip),
+        });
+    }
+    Ok(match joliet_root {
+        Some((root_sector, root_length)) => Volume {
+            root_sector,
+            root_length,
+            joliet: true,
+            rock_ridge_skip: None,
+        },
+        None => Volume {
+            root_sector,
+            root_length,
+            joliet: false,
+            rock_ridge_skip: None,
+        },
+    })
+}
+
+/// The sector and length of the root directory of the volume `descriptor`
+fn root_record(descriptor: &[u8]) -> (u32, u32) {
+    let record = &descriptor[156..190];
+    (read_u32(&record[2..]), read_u32(&record[10..]))
+}
+
+/// Checks for the "SP" entry of the system use area of the first record of the root directory,
+/// which tells the image has Rock Ridge, returning how many bytes to skip in the system use areas
+fn rock_ridge_skip<R: Read + Seek>(reader: &mut R, root_sector: u32) -> crate::Result<Option<usize>> {
+    let Some(sector) = read_sector(reader, u64::from(root_sector))? else {
+        return Err(invalid_image("The root directory is past the end of the image".into()));
+    };
+    let Some(record) = sector.get(..usize::from(sector[0])) else {
+        return Ok(None);
+    };
+    let system_use = system_use_area(record, 0);
+    let skip = system_use_entries(system_use).find_map(|(signature, data)| {
+        (signature == *b"SP" && data.get(..2) == Some(&[0xBE, 0xEF])).then(|| data.get(2).copied().unwrap_or(0))
+    });
+    Ok(skip.map(usize::from))
+}
+
+/// The length of the directory at `sector`, given by its first record
+fn directory_length<R: Read + Seek>(reader: &mut R, sector: u32) -> crate::Result<u32> {
+    match read_sector(reader, u64::from(sector))? {
+        Some(sector) if usize::from(sector[0]) >= RECORD_HEADER_LEN => Ok(read_u32(&sector[10..])),
+        _ => Err(invalid_image("A relocated directory has no records".into())),
+    }
+}
+
+/// Reads the records of the directory at `sector`, `length` bytes long, without "." and ".."
+fn read_directory<R: Read + Seek>(
+    reader: &mut R,
+    sector: u32,
+    length: u32,
+    volume: &Volume,
+) -> crate::Result<Vec<Record>> {
+    let mut data = vec![];
+    reader.seek(SeekFrom::Start(u64::from(sector) * SECTOR_SIZE))?;
+    reader.by_ref().take(u64::from(length)).read_to_end(&mut data)?;
+
+    let mut records = vec![];
+    let mut position = 0;
+    while position < data.len() {
+        let record_length = usize::from(data[position]);
+        // Records don't cross sectors, the rest of a sector is filled with zeros
+        if record_length == 0 {
+            position = (position / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            continue;
+        }
+        let Some(record) = data.get(position..position + record_length) else {
+            return Err(invalid_image(
+                "A directory record goes past the end of its directory".into(),
+            ));
+        };
+        position += record_length;
+
+        let name_length = usize::from(record[32]);
+        let Some(raw_name) = record.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + name_length) else {
+            return Err(invalid_image("A directory record is shorter than its name".into()));
+        };
+        if matches!(raw_name, [0] | [1]) {
+            continue;
+        }
+
+        let flags = record[25];
+        let mut record = Record {
+            name: Err(raw_name.to_vec()),
+            is_dir: flags & FLAG_DIRECTORY != 0,
+            multi_extent: flags & FLAG_MULTI_EXTENT != 0,
+            sector: read_u32(&record[2..]),
+            length: read_u32(&record[10..]),
+            modified: recording_time(&record[18..25]),
+            mode: None,
+            child_link: None,
+            relocated: false,
+        };
+        record.name = if volume.joliet {
+            joliet_name(raw_name)
+        } else {
+            plain_name(raw_name)
+        };
+
+        if let Some(skip) = volume.rock_ridge_skip {
+            let system_use = system_use_area(&data[position - record_length..position], skip);
+            read_rock_ridge(reader, system_use, &mut record)?;
+        }
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Reads the name, mode and relocation of `record` from the Rock Ridge entries of its `system_use`
+/// area, following its continuation areas
+fn read_rock_ridge<R: Read + Seek>(reader: &mut R, system_use: &[u8], record: &mut Record) -> crate::Result<()> {
+    let mut name: Option<Vec<u8>> = None;
+    let mut areas = vec![system_use.to_vec()];
+    // Continuation areas could point to each other forever
+    let mut continuations = 0;
+
+    while let Some(area) = areas.pop() {
+        for (signature, data) in system_use_entries(&area) {
+            match &signature {
+                // Alternate name, split in several entries when the first one has the "continue" flag
+                b"NM" => {
+                    let Some((&flags, part)) = data.split_first() else {
+                        continue;
+                    };
+                    // Names of "." and ".."
+                    if flags & 0b110 != 0 {
+                        continue;
+                    }
+                    name.get_or_insert_with(Vec::new).extend_from_slice(part);
+                }
+                b"PX" if data.len() >= 4 => record.mode = Some(read_u32(data)),
+                b"CL" if data.len() >= 4 => record.child_link = Some(read_u32(data)),
+                b"RE" => record.relocated = true,
+                // Continuation area: sector, offset and length
+                b"CE" if data.len() >= 20 && continuations < 16 => {
+                    continuations += 1;
+                    let (sector, offset, length) = (read_u32(data), read_u32(&data[8..]), read_u32(&data[16..]));
+                    let mut continuation = vec![];
+                    reader.seek(SeekFrom::Start(u64::from(sector) * SECTOR_SIZE + u64::from(offset)))?;
+                    reader
+                        .by_ref()
+                        .take(u64::from(length.min(SECTOR_SIZE as u32)))
+                        .read_to_end(&mut continuation)?;
+                    areas.push(continuation);
+                }
+                // Stops reading the system use entries
+                b"ST" => break,
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(name) = name {
+        record.name = String::from_utf8(name).map_err(|err| err.into_bytes());
+    }
+    Ok(())
+}
+
+/// The system use area of `record`, after its name and the padding byte that keeps it at an even
+/// offset, without its first `skip` bytes
+fn system_use_area(record: &[u8], skip: usize) -> &[u8] {
+    let name_length = usize::from(record.get(32).copied().unwrap_or(0));
+    let start = RECORD_HEADER_LEN + name_length + (1 - name_length % 2) + skip;
+    record.get(start..).unwrap_or_default()
+}
+
+/// The signatures and data of the entries in a system use area
+fn system_use_entries(mut area: &[u8]) -> impl Iterator<Item = ([u8; 2], &[u8])> {
+    std::iter::from_fn(move || {
+        // Signature, length and version, the rest are the data
+        if area.len() < 4 {
+            return None;
+        }
+        let length = usize::from(area[2]);
+        let entry = area.get(..length).filter(|_| length >= 4)?;
+        area = &area[length..];
+        Some(([entry[0], entry[1]], &entry[4..]))
+    })
+}
+
+/// Decodes a plain ISO 9660 name, like "README.TXT;1", dropping its version and the trailing dot of
+/// the names without extension, like "MAKEFILE.;1"
+fn plain_name(raw_name: &[u8]) -> Result<String, Vec<u8>> {
+    let name = String::from_utf8(raw_name.to_vec()).map_err(|err| err.into_bytes())?;
+    let name = name.split_once(';').map_or(&*name, |(name, _version)| name);
+    Ok(name.strip_suffix('.').unwrap_or(name).to_owned())
+}
+
+/// Decodes a Joliet name, which is UCS-2 big-endian, dropping its version
+fn joliet_name(raw_name: &[u8]) -> Result<String, Vec<u8>> {
+    let units: Vec<u16> = raw_name
+        .chunks_exact(2)
+        .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+        .collect();
+    let name = String::from_utf16(&units).map_err(|_| raw_name.to_vec())?;
+    Ok(name.split_once(';').map_or(&*name, |(name, _version)| name).to_owned())
+}
+
+/// Checks that `name` is a single component that stays inside of the output directory
+fn is_valid_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..") && !name.contains(['/', '\\', '\0'])
+}
+
+/// The recording time of a record, from the years since 1900 to the seconds, and the offset from
+/// GMT in 15 minute intervals
+fn recording_time(date: &[u8]) -> Option<i64> {
+    let date_time = PrimitiveDateTime::new(
+        Date::from_calendar_date(1900 + i32::from(date[0]), Month::try_from(date[1]).ok()?, date[2]).ok()?,
+        Time::from_hms(date[3], date[4], date[5]).ok()?,
+    );
+    let offset = UtcOffset::from_whole_seconds(i32::from(date[6] as i8) * 15 * 60).ok()?;
+    Some(date_time.assume_offset(offset).unix_timestamp())
+}
+
+/// Reads the sector at `index`, `None` if it is past the end of the image
+fn read_sector<R: Read + Seek>(reader: &mut R, index: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut sector = vec![0; SECTOR_SIZE as usize];
+    reader.seek(SeekFrom::Start(index * SECTOR_SIZE))?;
+    match reader.read_exact(&mut sector) {
+        Ok(()) => Ok(Some(sector)),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads the little-endian half of a both-endian number
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid_image(detail: String) -> crate::Error {
+    let error = FinalError::with_title("Invalid ISO 9660 image").detail(detail);
+    Error::Custom { reason: error }
+}
+
+fn invalid_name(dir_path: &Path, name: &[u8]) -> crate::Error {
+    invalid_image(format!(
+        "'{}' has an entry named '{}', which can't be unpacked safely",
+        EscapedPathDisplay::new(dir_path),
+        String::from_utf8_lossy(name).escape_debug()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A directory record named `name`, followed by the Rock Ridge entries of `system_use`
+    fn record(name: &[u8], sector: u32, length: u32, flags: u8, system_use: &[u8]) -> Vec<u8> {
+        let padding = 1 - name.len() % 2;
+        let mut record = vec![0; RECORD_HEADER_LEN];
+        record[0] = (RECORD_HEADER_LEN + name.len() + padding + system_use.len()) as u8;
+        record[2..6].copy_from_slice(&sector.to_le_bytes());
+        record[6..10].copy_from_slice(&sector.to_be_bytes());
+        record[10..14].copy_from_slice(&length.to_le_bytes());
+        record[14..18].copy_from_slice(&length.to_be_bytes());
+        // 2024-01-02 03:04:05 UTC
+        record[18..25].copy_from_slice(&[124, 1, 2, 3, 4, 5, 0]);
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        record.resize(record.len() + padding, 0);
+        record.extend_from_slice(system_use);
+        record
+    }
+
+    fn rock_ridge_entry(signature: &[u8; 2], data: &[u8]) -> Vec<u8> {
+        [signature, &[data.len() as u8 + 4, 1][..], data].concat()
+    }
+
+    fn mode_entry(mode: u32) -> Vec<u8> {
+        let mut data = [mode.to_le_bytes(), mode.to_be_bytes()].concat();
+        data.resize(32, 0);
+        rock_ridge_entry(b"PX", &data)
+    }
+
+    /// An image with the directory "docs" and the file "hello.txt", named by Rock Ridge
+    fn image() -> Vec<u8> {
+        let mut image = vec![0; 21 * SECTOR_SIZE as usize];
+        let sector = |index: usize| index * SECTOR_SIZE as usize;
+
+        image[sector(16)] = 1;
+        image[sector(16) + 1..sector(16) + 6].copy_from_slice(b"CD001");
+        let root = record(&[0], 18, 2048, FLAG_DIRECTORY, &[]);
+        image[sector(16) + 156..sector(16) + 190].copy_from_slice(&root);
+        image[sector(17)] = 255;
+        image[sector(17) + 1..sector(17) + 6].copy_from_slice(b"CD001");
+
+        let root_directory = [
+            record(
+                &[0],
+                18,
+                2048,
+                FLAG_DIRECTORY,
+                &rock_ridge_entry(b"SP", &[0xBE, 0xEF, 0]),
+            ),
+            record(&[1], 18, 2048, FLAG_DIRECTORY, &[]),
+            record(
+                b"DOCS",
+                19,
+                2048,
+                FLAG_DIRECTORY,
+                &[rock_ridge_entry(b"NM", b"\0docs"), mode_entry(0o40755)].concat(),
+            ),
+            record(
+                b"HELLO.TXT;1",
+                20,
+                5,
+                0,
+                &[rock_ridge_entry(b"NM", b"\0hello.txt"), mode_entry(0o100644)].concat(),
+            ),
+        ]
+        .concat();
+        image[sector(18)..sector(18) + root_directory.len()].copy_from_slice(&root_directory);
+
+        let docs_directory = [
+            record(&[0], 19, 2048, FLAG_DIRECTORY, &[]),
+            record(&[1], 18, 2048, FLAG_DIRECTORY, &[]),
+        ]
+        .concat();
+        image[sector(19)..sector(19) + docs_directory.len()].copy_from_slice(&docs_directory);
+        image[sector(20)..sector(20) + 5].copy_from_slice(b"hello");
+
+        image
+    }
+
+    #[test]
+    fn test_list_archive() {
+        let files = list_archive(Cursor::new(image())).unwrap();
+        let files: Vec<_> = files
+            .iter()
+            .map(|file| (file.path.to_str().unwrap(), file.is_dir, file.size, file.modified))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("docs", true, 0, Some(1704164645)),
+                ("hello.txt", false, 5, Some(1704164645))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unpack_archive() {
+        let output = tempfile::tempdir().unwrap();
+        let count = unpack_archive(Cursor::new(image()), output.path(), &UnpackOptions::default(), true).unwrap();
+
+        assert_eq!(count, 2);
+        assert!(output.path().join("docs").is_dir());
+        assert_eq!(std::fs::read(output.path().join("hello.txt")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_udf_only_image() {
+        let mut image = vec![0; 20 * SECTOR_SIZE as usize];
+        for (index, identifier) in [b"BEA01", b"NSR02", b"TEA01"].into_iter().enumerate() {
+            let start = (16 + index) * SECTOR_SIZE as usize;
+            image[start + 1..start + 6].copy_from_slice(identifier);
+        }
+
+        let err = list_archive(Cursor::new(image)).unwrap_err();
+        assert!(err.to_string().contains("Cannot read UDF images"));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(plain_name(b"README.TXT;1").unwrap(), "README.TXT");
+        assert_eq!(plain_name(b"MAKEFILE.;1").unwrap(), "MAKEFILE");
+        assert_eq!(joliet_name(b"\0a\0 \0b\0;\x001").unwrap(), "a b");
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("a/b"));
+        assert!(is_valid_name("..a"));
+    }
+}
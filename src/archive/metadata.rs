@@ -0,0 +1,280 @@
+//! Provenance metadata embedded in the compressed output with `--metadata`, and printed back with
+//! `--show-metadata`.
+//!
+//! It is stored in the comment of zip archives, in a PAX global header of tar archives, and in a
+//! `.meta.json` file next to the output with `--sidecar`. 7z archives have no place for it.
+
+use std::{
+    fmt::{self, Write as _},
+    io::Read,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+};
+
+use fs_err as fs;
+use time::OffsetDateTime;
+
+use crate::utils::format_time;
+
+/// First line of the zip comments holding the metadata, other comments are left alone
+const ZIP_COMMENT_HEADER: &str = "ouch metadata";
+
+/// Prefix of the keys of the PAX records holding the metadata, like "OUCH.host"
+const PAX_KEY_PREFIX: &str = "OUCH.";
+
+/// Path of the PAX global header, the one GNU tar gives them
+pub const PAX_GLOBAL_HEADER_PATH: &str = "pax_global_header";
+
+/// Where and how the output was made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Fields, in the order they are written, like ("tool", "ouch 0.5.1")
+    fields: Vec<(String, String)>,
+}
+
+impl Provenance {
+    /// The metadata of an output made now, on this host
+    pub fn current() -> Self {
+        // Safe unwrap, the format is valid
+        let created = format_time(OffsetDateTime::now_utc(), "%Y-%m-%dT%H:%M:%SZ").unwrap();
+
+        Self {
+            fields: vec![
+                ("tool".into(), format!("ouch {}", env!("CARGO_PKG_VERSION"))),
+                ("host".into(), host_name().unwrap_or_else(|| "unknown".into())),
+                ("created".into(), created),
+            ],
+        }
+    }
+
+    /// The comment of zip archives, "ouch metadata" followed by a "key: value" line per field
+    pub fn to_zip_comment(&self) -> String {
+        let mut comment = String::from(ZIP_COMMENT_HEADER);
+        for (key, value) in &self.fields {
+            // Writing to a String can't fail
+            let _ = write!(comment, "\n{key}: {value}");
+        }
+        comment
+    }
+
+    /// Reads the metadata back from the comment of a zip archive, if it has some
+    pub fn from_zip_comment(comment: &[u8]) -> Option<Self> {
+        let comment = std::str::from_utf8(comment).ok()?;
+        let mut lines = comment.lines();
+        if lines.next()? != ZIP_COMMENT_HEADER {
+            return None;
+        }
+
+        let fields = lines
+            .filter_map(|line| line.split_once(": "))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        Some(Self { fields })
+    }
+
+    /// The keys and values of the PAX records of tar archives, like ("OUCH.host", "name")
+    pub fn pax_records(&self) -> impl Iterator<Item = (String, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (format!("{PAX_KEY_PREFIX}{key}"), value.as_str()))
+    }
+
+    /// Reads the metadata back from the first entry of a tar archive, if it is a PAX global
+    /// header with some
+    pub fn from_tar(mut archive: tar::Archive<impl Read>) -> crate::Result<Option<Self>> {
+        let Some(entry) = archive.entries()?.next() else {
+            return Ok(None);
+        };
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_pax_global_extensions() {
+            return Ok(None);
+        }
+        let Some(extensions) = entry.pax_extensions()? else {
+            return Ok(None);
+        };
+
+        let mut fields = vec![];
+        for extension in extensions {
+            let extension = extension?;
+            if let (Some(key), Ok(value)) = (
+                extension.key().ok().and_then(|key| key.strip_prefix(PAX_KEY_PREFIX)),
+                extension.value(),
+            ) {
+                fields.push((key.to_owned(), value.to_owned()));
+            }
+        }
+        Ok((!fields.is_empty()).then_some(Self { fields }))
+    }
+
+    /// Writes the metadata of `output_path` to the sidecar file next to it, as a JSON object
+    pub fn write_sidecar(&self, output_path: &Path) -> crate::Result<PathBuf> {
+        let mut json = String::from("{");
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{}:{}", json_string(key), json_string(value));
+        }
+        json.push_str("}\n");
+
+        let sidecar_path = sidecar_path(output_path);
+        fs::write(&sidecar_path, json)?;
+        Ok(sidecar_path)
+    }
+
+    /// Reads the metadata back from the sidecar file next to `path`, if there is one
+    pub fn from_sidecar(path: &Path) -> crate::Result<Option<Self>> {
+        let sidecar_path = sidecar_path(path);
+        if !sidecar_path.exists() {
+            return Ok(None);
+        }
+
+        Ok(parse_json_object(&fs::read_to_string(&sidecar_path)?).map(|fields| Self { fields }))
+    }
+}
+
+impl fmt::Display for Provenance {
+    /// One "key: value" line per field, indented
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  {key}: {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The sidecar file of `path`, "file.gz" -> "file.gz.meta.json"
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar_path = path.as_os_str().to_owned();
+    sidecar_path.push(".meta.json");
+    sidecar_path.into()
+}
+
+#[cfg(unix)]
+fn host_name() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    // Safety: `buffer` is writable for its whole length
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return None;
+    }
+    let length = buffer.iter().position(|&byte| byte == 0)?;
+    Some(String::from_utf8_lossy(&buffer[..length]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn host_name() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Quotes `text` as a JSON string
+fn json_string(text: &str) -> String {
+    let mut string = String::from('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            ch if ch.is_control() => {
+                let _ = write!(string, "\\u{:04x}", ch as u32);
+            }
+            ch => string.push(ch),
+        }
+    }
+    string.push('"');
+    string
+}
+
+/// Parses a JSON object whose values are all strings, like the sidecar files
+fn parse_json_object(json: &str) -> Option<Vec<(String, String)>> {
+    let mut chars = json.trim().strip_prefix('{')?.strip_suffix('}')?.chars().peekable();
+    let mut fields = vec![];
+
+    loop {
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(fields);
+        }
+
+        let key = parse_json_string(&mut chars)?;
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+        if chars.next()? != ':' {
+            return None;
+        }
+        let value = parse_json_string(&mut chars)?;
+        fields.push((key, value));
+
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some(',') => {}
+            None => return Some(fields),
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Parses the JSON string at the start of `chars`, after any whitespace
+fn parse_json_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => match chars.next()? {
+                'n' => string.push('\n'),
+                't' => string.push('\t'),
+                'r' => string.push('\r'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    string.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                ch => string.push(ch),
+            },
+            ch => string.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let provenance = Provenance {
+            fields: vec![
+                ("tool".into(), "ouch 1.0.0".into()),
+                ("host".into(), "a \"quoted\" host\\".into()),
+            ],
+        };
+
+        let comment = provenance.to_zip_comment();
+        assert_eq!(
+            Provenance::from_zip_comment(comment.as_bytes()),
+            Some(provenance.clone())
+        );
+        assert_eq!(Provenance::from_zip_comment(b"another comment"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("file.gz");
+        provenance.write_sidecar(&output_path).unwrap();
+        assert_eq!(Provenance::from_sidecar(&output_path).unwrap(), Some(provenance));
+    }
+
+    #[test]
+    fn test_parse_json_object() {
+        assert_eq!(
+            parse_json_object(r#"{ "a": "1", "b":"x\"A" }"#),
+            Some(vec![("a".into(), "1".into()), ("b".into(), "x\"A".into())])
+        );
+        assert_eq!(parse_json_object("{}"), Some(vec![]));
+        assert_eq!(parse_json_object(r#"{"a": 1}"#), None);
+        assert_eq!(parse_json_object("[]"), None);
+    }
+}
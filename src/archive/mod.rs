@@ -1,5 +1,652 @@
 //! Archive compression algorithms
 
+pub mod iso;
+pub mod metadata;
 pub mod sevenz;
 pub mod tar;
+pub mod wim;
 pub mod zip;
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    cli::{CaseConflictPolicy, ExtractionOrder, IndexRanges, MacosForks, OverwritePolicy, ParentDirPolicy},
+    error::FinalError,
+    info,
+    utils::{self, Bytes, DigestTable, EscapedPathDisplay, ExcludePatterns, Owner, RateLimit},
+};
+
+/// Options controlling how archive entries are written to disk when unpacking
+#[derive(Debug, Clone, Default)]
+pub struct UnpackOptions {
+    /// Whether to restore the permissions stored in the archive as they are, with the sticky bit,
+    /// instead of applying the umask to them, set unless `--no-preserve-permissions`
+    pub preserve_permissions: bool,
+
+    /// Whether setuid and setgid bits may be restored, only used if `preserve_permissions` is set
+    pub preserve_setuid: bool,
+
+    /// Entries matching these patterns are not unpacked
+    pub exclude: ExcludePatterns,
+
+    /// Whether to read every zip entry to completion, even the skipped ones, checking its CRC-32
+    pub verify_crc: bool,
+
+    /// What is done with the existing destinations, resolved from `--overwrite-policy`, `--if-newer`,
+    /// `--remove-destination`, `--yes` and `--no`
+    pub overwrite_policy: OverwritePolicy,
+
+    /// Whether existing directories are merged with the unpacked ones, only replacing the files
+    /// that conflict, set by `--merge`
+    pub merge: bool,
+
+    /// Whether the existing symlinks to directories are kept when the archive has directories
+    /// there, unpacking into their targets, set by `--keep-directory-symlink`
+    pub keep_directory_symlink: bool,
+
+    /// Whether the symlinks leading outside of the archive are skipped, or copied from the files
+    /// they point to in it, set by `--safe-symlinks`
+    pub safe_symlinks: bool,
+
+    /// Whether to allow unpacking into `/` and other system directories, set by `--force`
+    pub force: bool,
+
+    /// Service of the OS keyring to get and store the passwords of encrypted archives, set by `--keyring`
+    pub keyring_service: Option<String>,
+
+    /// Limit to the decompressed bytes per second, set by `--limit-rate`
+    pub rate_limit: Option<Arc<RateLimit>>,
+
+    /// Only this many bytes at the start of every file are unpacked, set by `--head`
+    pub head: Option<u64>,
+
+    /// Whether to restore the file flags stored in tar archives, set by `--preserve-flags`
+    pub preserve_flags: bool,
+
+    /// Whether to restore the creation times stored in tar archives, set by `--preserve-btime`
+    pub preserve_btime: bool,
+
+    /// Whether to restore the access times stored in tar archives, set by `--atime-preserve`
+    pub atime_preserve: bool,
+
+    /// What to do with files whose paths only differ in case, set by `--case-conflicts`
+    pub case_conflicts: CaseConflictPolicy,
+
+    /// What to do with the tar entries whose paths have `..` components, set by `--parent-dirs`
+    pub parent_dirs: ParentDirPolicy,
+
+    /// Order in which the entries of zip archives are unpacked, set by `--order`
+    pub order: ExtractionOrder,
+
+    /// What to do with the AppleDouble files macOS puts in archives, set by `--macos-forks`
+    pub macos_forks: MacosForks,
+
+    /// Where archives are unpacked before being moved to the output directory, set by `--temp-dir`,
+    /// the output directory itself if unset
+    pub temp_dir: Option<PathBuf>,
+
+    /// Only the entries inside of this path of the archive are unpacked, with it removed from their
+    /// paths, set by `--subtree`, has no `.`, `..` or root components
+    pub subtree: Option<PathBuf>,
+
+    /// Owner given to every unpacked file, set by `--chown`
+    pub owner: Option<Owner>,
+
+    /// How many times the writes of an unpacked file are retried when they fail with transient
+    /// errors, set by `--retries`
+    pub retries: u32,
+
+    /// Whether the backslashes in the entry names of zip archives are directory separators, set by
+    /// `--normalize-separators`
+    pub normalize_separators: bool,
+
+    /// Most entries unpacked from an archive, more fail the unpacking, set by `--max-entries`,
+    /// 0 for no limit
+    pub max_entries: usize,
+
+    /// Where the entries at the root of the archive end up, see [`UnpackStrategy`]
+    pub strategy: UnpackStrategy,
+
+    /// Tar archives concatenated after the first one are unpacked too, with `--concat-tar`
+    pub concat_tar: bool,
+
+    /// Modification times of the unpacked files are rounded down to a multiple of these seconds,
+    /// set by `--time-rounding`
+    pub time_rounding: Option<u64>,
+
+    /// Modification times of the unpacked files are set to the time they were unpacked, set by
+    /// `--touch`
+    pub touch: bool,
+
+    /// Only the entries at these positions, counted like `ouch list --index` does, are unpacked,
+    /// set by `--index`
+    pub indexes: Option<IndexRanges>,
+
+    /// Only the entries at these paths, or inside of them, are unpacked, picked with
+    /// `--interactive`, the paths have no `.`, `..` or root components
+    pub selected: Option<Arc<HashSet<PathBuf>>>,
+
+    /// Where the checksums of the archives and of the unpacked files go, with `--checksum`
+    pub checksums: Option<Arc<DigestTable>>,
+}
+
+/// Most entries that can be at the root of an archive before asking if they should go in a new
+/// directory, as they do by default, or straight in the output directory
+pub const MAX_ROOT_ENTRIES: usize = 3;
+
+/// Layout of the unpacked files in the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnpackStrategy {
+    /// A single entry at the root of the archive goes in the output directory, several go in a new
+    /// directory named after the archive, the user is asked to confirm it when there are more
+    /// than [`MAX_ROOT_ENTRIES`]
+    #[default]
+    Smart,
+    /// Always in a new directory named after the archive, with `--no-smart-unpack`
+    AlwaysSubdir,
+    /// Every file goes directly in the output directory, without the directories it was in, with
+    /// `--flatten`
+    Flatten,
+    /// Always in the output directory, which was made for this archive alone, with
+    /// `--output-name-template`
+    CurrentDir,
+}
+
+impl UnpackStrategy {
+    /// Whether the `root_entries` entries at the root of an archive go in a new directory named after
+    /// it, `ask` confirms it when there are many of them with [`UnpackStrategy::Smart`]
+    pub fn uses_new_directory(
+        self,
+        root_entries: usize,
+        ask: impl FnOnce() -> crate::Result<bool>,
+    ) -> crate::Result<bool> {
+        match self {
+            Self::Smart if root_entries > MAX_ROOT_ENTRIES => ask(),
+            Self::Smart => Ok(root_entries != 1),
+            Self::AlwaysSubdir => Ok(true),
+            Self::Flatten | Self::CurrentDir => Ok(false),
+        }
+    }
+}
+
+impl UnpackOptions {
+    /// Filters the `mode` stored in the archive down to the bits that should be restored
+    #[cfg(unix)]
+    pub fn permission_bits(&self, mode: u32) -> u32 {
+        const SETUID_SETGID: u32 = 0o6000;
+        const STICKY: u32 = 0o1000;
+
+        let mask = match (self.preserve_permissions, self.preserve_setuid) {
+            (false, _) => 0o777 & !umask(),
+            (true, false) => 0o777 | STICKY,
+            (true, true) => 0o777 | STICKY | SETUID_SETGID,
+        };
+
+        mode & mask
+    }
+
+    /// Reader of the part of `reader` that is unpacked, the first `--head` bytes, the entry at
+    /// `path` is reported as cut if its `size` is larger
+    pub fn head<R: Read>(&self, reader: R, path: &Path, size: u64) -> io::Take<R> {
+        if self.head.is_some_and(|head| size > head) {
+            self.report_cut(path);
+        }
+        reader.take(self.head.unwrap_or(u64::MAX))
+    }
+
+    /// Reports that only the first `--head` bytes of the entry at `path` were unpacked
+    pub fn report_cut(&self, path: &Path) {
+        if let Some(head) = self.head {
+            info!(
+                accessible,
+                "Only the first {} of '{}' were unpacked, --head cut the rest.",
+                Bytes::new(head),
+                EscapedPathDisplay::new(path)
+            );
+        }
+    }
+
+    /// Fails once the `count` entries unpacked so far, including the next one, are more than
+    /// `max_entries`, which guards against archives made to use up all the inodes with tiny entries
+    pub fn check_entry_count(&self, count: usize) -> crate::Result<()> {
+        if self.max_entries == 0 || count <= self.max_entries {
+            return Ok(());
+        }
+
+        let error = FinalError::with_title(format!("The archive has more than {} entries", self.max_entries))
+            .detail("Archives with huge numbers of entries can use up all the inodes of the file system")
+            .detail(format!(
+                "Stopped after unpacking {} entries, which were removed",
+                self.max_entries
+            ))
+            .hint("If the archive is trusted, raise the limit with --max-entries, or remove it with --max-entries 0");
+        Err(crate::Error::Custom { reason: error })
+    }
+
+    /// Fails if the positions given to `--index` are past the `entry_count` entries of the archive
+    pub fn check_indexes(&self, entry_count: usize) -> crate::Result<()> {
+        match &self.indexes {
+            Some(indexes) if indexes.last() > entry_count => {
+                let error = FinalError::with_title(format!("Index {} is out of range", indexes.last()))
+                    .detail(format!("The archive has {entry_count} entries"))
+                    .hint("Use `ouch list --index` to see the index of every entry");
+                Err(crate::Error::InvalidUsage { reason: error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if the entry at `path` is skipped, because it is excluded, outside of `subtree`, not
+    /// selected with `--interactive`, or an AppleDouble file with `--macos-forks strip`
+    pub fn skips(&self, path: &Path) -> bool {
+        self.exclude.is_excluded(path)
+            || self.selected.as_ref().is_some_and(|selected| {
+                !utils::normal_path(path)
+                    .ancestors()
+                    .any(|ancestor| selected.contains(ancestor))
+            })
+            || self.path_in_subtree(path).is_none()
+            || (self.macos_forks == MacosForks::Strip && utils::is_apple_double(path))
+    }
+
+    /// Gives the path relative to `subtree` of the entry at `path`, `None` if it is outside of it
+    pub fn path_in_subtree(&self, path: &Path) -> Option<PathBuf> {
+        let path: PathBuf = path
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+
+        match &self.subtree {
+            Some(subtree) => path.strip_prefix(subtree).ok().map(Path::to_path_buf),
+            None => Some(path),
+        }
+    }
+}
+
+/// Flattens the paths stored when compressing with `--junk-paths`, keeping only the file names
+#[derive(Debug, Default)]
+pub struct JunkPaths {
+    /// Whether to add a numeric suffix to the names already used, instead of failing
+    rename_collisions: bool,
+    /// Names already used, with the path of the file stored under each one
+    used_names: HashMap<OsString, PathBuf>,
+}
+
+impl JunkPaths {
+    pub fn new(rename_collisions: bool) -> Self {
+        Self {
+            rename_collisions,
+            used_names: HashMap::new(),
+        }
+    }
+
+    /// Returns the name `path` is stored as, "dir/file.txt" becomes "file.txt", or "file-1.txt"
+    /// if that one was already used
+    pub fn stored_name(&mut self, path: &Path) -> crate::Result<PathBuf> {
+        let name = path.file_name().unwrap_or(path.as_os_str()).to_owned();
+
+        let Some(first_path) = self.used_names.get(&name) else {
+            self.used_names.insert(name.clone(), path.to_owned());
+            return Ok(name.into());
+        };
+
+        if !self.rename_collisions {
+            let error = FinalError::with_title(format!(
+                "Cannot store two files as '{}'",
+                EscapedPathDisplay::new(Path::new(&name))
+            ))
+            .detail(format!(
+                "Both '{}' and '{}' have this name",
+                EscapedPathDisplay::new(first_path),
+                EscapedPathDisplay::new(path)
+            ))
+            .hint("Use --rename-collisions to add a numeric suffix to the repeated names.");
+
+            return Err(error.into());
+        }
+
+        for candidate in numbered_names(Path::new(&name)) {
+            if !self.used_names.contains_key(&candidate) {
+                self.used_names.insert(candidate.clone(), path.to_owned());
+                return Ok(candidate.into());
+            }
+        }
+        unreachable!("there are always unused suffixes")
+    }
+}
+
+/// Names to use instead of `name` when it is taken, "file.txt" gives "file-1.txt", "file-2.txt"...
+fn numbered_names(name: &Path) -> impl Iterator<Item = OsString> + '_ {
+    let stem = name.file_stem().unwrap_or(name.as_os_str());
+    (1..).map(move |suffix| {
+        let mut candidate = stem.to_owned();
+        candidate.push(format!("-{suffix}"));
+        if let Some(extension) = name.extension() {
+            candidate.push(".");
+            candidate.push(extension);
+        }
+        candidate
+    })
+}
+
+/// Renames or skips the files whose paths only differ in case from one unpacked before, set by
+/// `--case-conflicts`
+///
+/// Only used on case-insensitive file systems, where they would overwrite each other.
+#[derive(Debug)]
+pub struct CaseConflicts {
+    policy: CaseConflictPolicy,
+    /// Lowercase paths of the unpacked files, with the paths they were unpacked as
+    unpacked: HashMap<String, PathBuf>,
+    /// Paths of the renamed files, with the paths they were unpacked as
+    renamed: HashMap<PathBuf, PathBuf>,
+}
+
+impl CaseConflicts {
+    pub fn new(policy: CaseConflictPolicy) -> Self {
+        Self {
+            policy,
+            unpacked: HashMap::new(),
+            renamed: HashMap::new(),
+        }
+    }
+
+    /// Returns the conflict tracker for unpacking into `output_folder`, if its file system is
+    /// case-insensitive and `policy` isn't to overwrite
+    pub fn for_folder(output_folder: &Path, policy: CaseConflictPolicy) -> io::Result<Option<Self>> {
+        if policy == CaseConflictPolicy::Overwrite || !utils::is_case_insensitive(output_folder)? {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(policy)))
+    }
+
+    /// Returns the path to unpack the file at `path` as, or `None` to skip it.
+    ///
+    /// The same path unpacked twice isn't a conflict, like the newer copies appended to tar archives,
+    /// it is unpacked as the first one was.
+    pub fn resolve(&mut self, path: &Path) -> crate::Result<Option<PathBuf>> {
+        if let Some(renamed) = self.renamed.get(path) {
+            return Ok(Some(renamed.clone()));
+        }
+
+        let key = path.to_string_lossy().to_lowercase();
+        let Some(first_path) = self.unpacked.get(&key) else {
+            self.unpacked.insert(key, path.to_owned());
+            return Ok(Some(path.to_owned()));
+        };
+        if first_path == path {
+            return Ok(Some(path.to_owned()));
+        }
+
+        if self.policy == CaseConflictPolicy::Skip {
+            utils::warn_or_fail(format!(
+                "Skipping '{}', it only differs in case from '{}'.",
+                EscapedPathDisplay::new(path),
+                EscapedPathDisplay::new(first_path)
+            ))?;
+            return Ok(None);
+        }
+
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        for candidate in numbered_names(Path::new(name)) {
+            let candidate = path.with_file_name(candidate);
+            let key = candidate.to_string_lossy().to_lowercase();
+            if self.unpacked.contains_key(&key) {
+                continue;
+            }
+
+            utils::warn_or_fail(format!(
+                "Unpacking '{}' as '{}', it only differs in case from '{}'.",
+                EscapedPathDisplay::new(path),
+                EscapedPathDisplay::new(&candidate),
+                EscapedPathDisplay::new(first_path)
+            ))?;
+            self.unpacked.insert(key, candidate.clone());
+            self.renamed.insert(path.to_owned(), candidate.clone());
+            return Ok(Some(candidate));
+        }
+        unreachable!("there are always unused suffixes")
+    }
+}
+
+/// Returns the name `path` is stored as in the archive, which is only changed with `--junk-paths`
+pub fn stored_path<'a>(junk_paths: &mut Option<JunkPaths>, path: &'a Path) -> crate::Result<Cow<'a, Path>> {
+    match junk_paths {
+        Some(junk_paths) => Ok(Cow::Owned(junk_paths.stored_name(path)?)),
+        None => Ok(Cow::Borrowed(path)),
+    }
+}
+
+/// Writes the image read from `reader` to a temporary file, for the .wim and .iso images, which
+/// can only be read from files, not from the other formats they are compressed with
+pub fn write_temp_image(reader: &mut dyn io::Read) -> io::Result<tempfile::NamedTempFile> {
+    let image = tempfile::NamedTempFile::new()?;
+    io::copy(reader, &mut image.as_file())?;
+    Ok(image)
+}
+
+/// Sets the file flags unpacked by [`tar::unpack_archive`] on the files, now inside of `root`.
+///
+/// Children come before their parents, an immutable directory can't get new flags in it.
+pub fn restore_file_flags(root: &Path, file_flags: Vec<(PathBuf, String)>) -> crate::Result<()> {
+    for (path, flags) in file_flags.into_iter().rev() {
+        let path = root.join(path);
+        match utils::set_file_flags(&path, &flags) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                utils::warn_or_fail(format!(
+                    "Not enough privileges to set the flags '{flags}' of '{}', skipping them.",
+                    EscapedPathDisplay::new(&path)
+                ))?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the owner of an unpacked file couldn't be changed, which is only warned about once
+static OWNER_DENIED: AtomicBool = AtomicBool::new(false);
+
+/// Gives `path` to the owner set by `--chown`, if any, done before setting its permissions, which
+/// changing the owner could clear the setuid and setgid bits of.
+///
+/// Without the privileges to do it, the user is warned, and the files keep their owner.
+pub fn set_owner(path: &Path, options: &UnpackOptions) -> crate::Result<()> {
+    let Some(owner) = options.owner else {
+        return Ok(());
+    };
+
+    match owner.apply(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            if OWNER_DENIED.swap(true, Ordering::Relaxed) {
+                return Ok(());
+            }
+            utils::warn_or_fail(
+                "Not enough privileges to give the unpacked files to the owner from --chown, they keep theirs.".into(),
+            )
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The umask of the process, applied to the permissions unpacked without `preserve_permissions`
+#[cfg(unix)]
+fn umask() -> u32 {
+    static UMASK: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+
+    // `mode_t` is smaller than `u32` on some platforms
+    #[allow(clippy::useless_conversion)]
+    *UMASK.get_or_init(|| {
+        // It can only be read by setting it, so it is put back right away
+        // Safety: `umask` can't fail
+        let umask = unsafe { libc::umask(0) };
+        unsafe { libc::umask(umask) };
+        u32::from(umask)
+    })
+}
+
+/// Checks if the Unix `mode` stored in an archive is the one of a symlink
+pub fn is_symlink_mode(mode: u32) -> bool {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+
+    mode & S_IFMT == S_IFLNK
+}
+
+/// Sets the permissions of `path` from the `mode` stored in the archive, as allowed by `options`.
+///
+/// Warns the user when a setuid or setgid bit is applied, the sticky bit is restored silently.
+#[cfg(unix)]
+pub fn set_unix_permissions(path: &Path, mode: u32, options: &UnpackOptions) -> crate::Result<()> {
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    use fs_err as fs;
+
+    let mode = options.permission_bits(mode);
+
+    if mode & 0o6000 != 0 {
+        utils::warn_or_fail(format!(
+            "Applying setuid or setgid bits ({:04o}) to '{}'.",
+            mode,
+            EscapedPathDisplay::new(path)
+        ))?;
+    }
+
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_permission_bits() {
+        let default = UnpackOptions::default();
+        let preserve = UnpackOptions {
+            preserve_permissions: true,
+            ..UnpackOptions::default()
+        };
+        let preserve_setuid = UnpackOptions {
+            preserve_permissions: true,
+            preserve_setuid: true,
+            ..UnpackOptions::default()
+        };
+
+        assert_eq!(default.permission_bits(0o100755), 0o755 & !umask());
+        assert_eq!(default.permission_bits(0o7755), 0o755 & !umask());
+        assert_eq!(preserve.permission_bits(0o7755), 0o1755);
+        assert_eq!(preserve_setuid.permission_bits(0o7755), 0o7755);
+        // setuid is never restored with --no-preserve-permissions
+        assert_eq!(
+            UnpackOptions {
+                preserve_setuid: true,
+                ..UnpackOptions::default()
+            }
+            .permission_bits(0o4755),
+            0o755 & !umask()
+        );
+    }
+    #[test]
+    fn test_unpack_strategy() {
+        let yes = || Ok(true);
+        let unreachable = || -> crate::Result<bool> { unreachable!("only asked with many entries") };
+
+        assert!(!UnpackStrategy::Smart.uses_new_directory(1, unreachable).unwrap());
+        assert!(UnpackStrategy::Smart.uses_new_directory(2, unreachable).unwrap());
+        assert!(UnpackStrategy::Smart
+            .uses_new_directory(MAX_ROOT_ENTRIES, unreachable)
+            .unwrap());
+        assert!(UnpackStrategy::Smart
+            .uses_new_directory(MAX_ROOT_ENTRIES + 1, yes)
+            .unwrap());
+        assert!(!UnpackStrategy::Smart
+            .uses_new_directory(MAX_ROOT_ENTRIES + 1, || Ok(false))
+            .unwrap());
+        assert!(UnpackStrategy::AlwaysSubdir.uses_new_directory(1, unreachable).unwrap());
+        assert!(!UnpackStrategy::Flatten.uses_new_directory(10, unreachable).unwrap());
+        assert!(!UnpackStrategy::CurrentDir.uses_new_directory(10, unreachable).unwrap());
+    }
+
+    #[test]
+    fn test_junk_paths() {
+        let mut junk_paths = JunkPaths::new(false);
+        assert_eq!(
+            junk_paths.stored_name(Path::new("a/b.txt")).unwrap(),
+            Path::new("b.txt")
+        );
+        assert_eq!(junk_paths.stored_name(Path::new("a/c")).unwrap(), Path::new("c"));
+        assert!(junk_paths.stored_name(Path::new("d/b.txt")).is_err());
+
+        let mut junk_paths = JunkPaths::new(true);
+        assert_eq!(
+            junk_paths.stored_name(Path::new("a/b.txt")).unwrap(),
+            Path::new("b.txt")
+        );
+        assert_eq!(
+            junk_paths.stored_name(Path::new("d/b.txt")).unwrap(),
+            Path::new("b-1.txt")
+        );
+        assert_eq!(
+            junk_paths.stored_name(Path::new("b-1.txt")).unwrap(),
+            Path::new("b-1-1.txt")
+        );
+        assert_eq!(
+            junk_paths.stored_name(Path::new("e/b.txt")).unwrap(),
+            Path::new("b-2.txt")
+        );
+        assert_eq!(junk_paths.stored_name(Path::new("a/c")).unwrap(), Path::new("c"));
+        assert_eq!(junk_paths.stored_name(Path::new("d/c")).unwrap(), Path::new("c-1"));
+    }
+
+    #[test]
+    fn test_case_conflicts() {
+        let resolve = |case_conflicts: &mut CaseConflicts, path| case_conflicts.resolve(Path::new(path)).unwrap();
+
+        let mut rename = CaseConflicts::new(CaseConflictPolicy::Rename);
+        assert_eq!(resolve(&mut rename, "dir/File.txt"), Some("dir/File.txt".into()));
+        assert_eq!(resolve(&mut rename, "dir/file.txt"), Some("dir/file-1.txt".into()));
+        assert_eq!(resolve(&mut rename, "DIR/FILE.txt"), Some("DIR/FILE-2.txt".into()));
+        // Unpacking the same path again overwrites the file, wherever it was unpacked
+        assert_eq!(resolve(&mut rename, "dir/File.txt"), Some("dir/File.txt".into()));
+        assert_eq!(resolve(&mut rename, "dir/file.txt"), Some("dir/file-1.txt".into()));
+
+        let mut skip = CaseConflicts::new(CaseConflictPolicy::Skip);
+        assert_eq!(resolve(&mut skip, "File.txt"), Some("File.txt".into()));
+        assert_eq!(resolve(&mut skip, "file.txt"), None);
+    }
+
+    #[test]
+    fn test_skips_unselected() {
+        let options = UnpackOptions {
+            selected: Some(Arc::new(HashSet::from([
+                PathBuf::from("dir/sub"),
+                PathBuf::from("file"),
+            ]))),
+            ..UnpackOptions::default()
+        };
+
+        assert!(!options.skips(Path::new("./file")));
+        assert!(!options.skips(Path::new("dir/sub/")));
+        assert!(!options.skips(Path::new("dir/sub/inner/file")));
+        assert!(options.skips(Path::new("dir")));
+        assert!(options.skips(Path::new("dir/other")));
+        assert!(options.skips(Path::new("file2")));
+        assert!(!UnpackOptions::default().skips(Path::new("file2")));
+    }
+}
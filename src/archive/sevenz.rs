@@ -1,37 +1,485 @@
 //! SevenZip archive format compress function
-use std::path::{Path, PathBuf};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    env,
+    io::{self, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
 
-use crate::utils::strip_cur_dir;
+use filetime::FileTime;
+use fs_err as fs;
+use same_file::Handle;
 
-pub fn compress_sevenz(files: Vec<PathBuf>, output_path: &Path) -> crate::Result<bool> {
-    let mut writer = sevenz_rust::SevenZWriter::create(output_path).map_err(crate::Error::SevenzipError)?;
+use crate::{
+    archive::{self, CaseConflicts, JunkPaths, UnpackOptions},
+    cli::SevenZipMethod,
+    error::{Error, FinalError},
+    extension::CompressionFormat,
+    info,
+    list::FileInArchive,
+    utils::{
+        self, cd_into_walk_root, ArchivePassword, FileVisibilityPolicy, ProgressEvent, RateLimitedReader,
+        RetryingWriter,
+    },
+    BUFFER_CAPACITY,
+};
 
-    for filep in files.iter() {
-        writer
-            .push_archive_entry::<std::fs::File>(
-                sevenz_rust::SevenZArchiveEntry::from_path(
-                    filep,
-                    strip_cur_dir(filep)
-                        .as_os_str()
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                ),
-                None,
-            )
-            .map_err(crate::Error::SevenzipError)?;
+/// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
+///
+/// The contents are compressed with `method`, at `level` if it has levels.
+/// With `time_rounding`, from `--time-rounding`, the modification times are rounded down to a
+/// multiple of it.
+#[allow(clippy::too_many_arguments)]
+pub fn compress_sevenz<W>(
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    writer: W,
+    file_visibility_policy: FileVisibilityPolicy,
+    quiet: bool,
+    mut junk_paths: Option<JunkPaths>,
+    no_dir_entries: bool,
+    atime_preserve: bool,
+    method: SevenZipMethod,
+    level: Option<i16>,
+    time_rounding: Option<u64>,
+) -> crate::Result<W>
+where
+    W: Write + Seek,
+{
+    let mut writer = sevenz_rust::SevenZWriter::new(writer).map_err(crate::Error::SevenzipError)?;
+    writer.set_content_methods(vec![content_method(method, level)]);
+    let output_handle = Handle::from_path(output_path);
+
+    let mut truncated_subtrees = 0;
+    let mut compression_report = utils::CompressionReport::new(quiet);
+
+    for filename in input_filenames {
+        let (previous_location, filename) = cd_into_walk_root(filename, &file_visibility_policy)?;
+
+        for entry in file_visibility_policy.build_walker(filename) {
+            let entry = entry?;
+            let path = entry.path();
+
+            if file_visibility_policy.is_truncated_by_max_depth(&entry) {
+                truncated_subtrees += 1;
+            }
+
+            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
+            if let Ok(ref handle) = output_handle {
+                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+                    utils::warn_or_fail(format!(
+                        "The output file and the input file are the same: `{}`, skipping...",
+                        output_path.display()
+                    ))?;
+                    continue;
+                }
+            }
+
+            let metadata = match path.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::NotFound && utils::is_symlink(path) {
+                        // This path is for a broken symlink
+                        // We just ignore it
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            };
+
+            // Only the files are stored with --junk-paths and --no-dir-entries
+            if metadata.is_dir() && (junk_paths.is_some() || no_dir_entries) {
+                continue;
+            }
+
+            compression_report.start(path);
+            let bytes = if metadata.is_dir() { 0 } else { metadata.len() };
+            utils::emit_progress(ProgressEvent::Entry { path, bytes });
+
+            let stored_path = archive::stored_path(&mut junk_paths, path)?;
+            let stored_name = utils::to_utf8_lossy(&stored_path)?.into_owned();
+            let mut entry = sevenz_rust::SevenZArchiveEntry::from_path(path, stored_name);
+            if let Some(granularity) = time_rounding {
+                let modified = utils::round_time(entry.last_modified_date.to_unix_time(), granularity);
+                if let Ok(modified) = sevenz_rust::nt_time::FileTime::from_unix_time(modified) {
+                    entry.last_modified_date = modified;
+                }
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+
+                entry.has_windows_attributes = true;
+                entry.windows_attributes = unix_attributes(metadata.permissions().mode(), metadata.is_dir());
+            }
+            let entry_data = if metadata.is_dir() {
+                None
+            } else {
+                Some(fs::File::open(path)?)
+            };
+            let accessed = (atime_preserve && entry_data.is_some()).then(|| utils::AccessTime::save(path, &metadata));
+
+            writer
+                .push_archive_entry::<fs::File>(entry, entry_data)
+                .map_err(|err| match err {
+                    // Methods are only known to be unsupported once the first file is written
+                    sevenz_rust::Error::UnsupportedCompressionMethod(_) => unsupported_method_error(method),
+                    err => crate::Error::SevenzipError(err),
+                })?;
+            if let Some(accessed) = accessed {
+                accessed.restore()?;
+            }
+        }
+
+        env::set_current_dir(previous_location)?;
+    }
+
+    if truncated_subtrees > 0 {
+        info!(
+            accessible,
+            "{} subtrees deeper than --max-depth were stored without their contents.", truncated_subtrees
+        );
     }
 
-    writer.finish()?;
-    Ok(true)
+    let bytes = writer.finish()?;
+    Ok(bytes)
 }
 
-pub fn decompress_sevenz(input_file_path: &Path, output_path: &Path) -> crate::Result<usize> {
+/// Configuration of the content `method`, with the LZMA2 preset taken from `level`
+fn content_method(method: SevenZipMethod, level: Option<i16>) -> sevenz_rust::SevenZMethodConfiguration {
+    match method {
+        SevenZipMethod::Lzma2 => {
+            let preset = level.map_or(6, |l| CompressionFormat::SevenZip.clamp_level(l) as u32);
+            sevenz_rust::lzma::LZMA2Options::with_preset(preset).into()
+        }
+        SevenZipMethod::Copy => sevenz_rust::SevenZMethod::COPY.into(),
+        SevenZipMethod::Bzip2 => sevenz_rust::SevenZMethod::BZIP2.into(),
+    }
+}
+
+fn unsupported_method_error(method: SevenZipMethod) -> Error {
+    let method = format!("{method:?}").to_lowercase();
+    let error = FinalError::with_title(format!("Cannot compress 7z archives with the method '{method}'"))
+        .detail("It is not supported by the 7z library ouch was built with")
+        .hint("Use '--7z-method lzma2' instead, its fastest level is '--fast'.");
+
+    Error::InvalidUsage { reason: error }
+}
+
+/// Unpacks the archive read from `reader` into the folder given by `output_path`.
+///
+/// `password` is only asked for if the archive turns out to be encrypted.
+pub fn decompress_sevenz<R>(
+    mut reader: R,
+    output_path: &Path,
+    unpack_options: &UnpackOptions,
+    password: &mut ArchivePassword,
+) -> crate::Result<usize>
+where
+    R: Read + Seek,
+{
     let mut count: usize = 0;
-    sevenz_rust::decompress_file_with_extract_fn(input_file_path, output_path, |entry, reader, dest| {
+    let mut case_conflicts = CaseConflicts::for_folder(output_path, unpack_options.case_conflicts)?;
+    // Errors of ouch can't go through `sevenz_rust`, so the unpacking is stopped and it is returned after
+    let mut case_conflict_error = None;
+    let mut metadata_error = None;
+    let mut entry_count_error = None;
+    // Position of the entry, counted like `ouch list --index` does, for `--index`
+    let position = Cell::new(0);
+    let mut extract_fn = |entry: &sevenz_rust::SevenZArchiveEntry, reader: &mut dyn Read, dest: &PathBuf| {
+        position.set(position.get() + 1);
+        let unselected = (unpack_options.indexes.as_ref()).is_some_and(|indexes| !indexes.contains(position.get()));
+        // Still consume the skipped entries, so the solid stream stays in sync for the next ones
+        if unselected || unpack_options.skips(Path::new(entry.name())) {
+            io::copy(reader, &mut io::sink()).map_err(sevenz_rust::Error::io)?;
+            return Ok(true);
+        }
+
+        let mut dest = Cow::Borrowed(dest);
+        if let (Some(case_conflicts), false) = (case_conflicts.as_mut(), entry.is_directory()) {
+            let path = dest.strip_prefix(output_path).unwrap_or(&dest);
+            match case_conflicts.resolve(path) {
+                Ok(Some(path)) => dest = Cow::Owned(output_path.join(path)),
+                Ok(None) => {
+                    io::copy(reader, &mut io::sink()).map_err(sevenz_rust::Error::io)?;
+                    return Ok(true);
+                }
+                Err(err) => {
+                    case_conflict_error = Some(err);
+                    return Ok(false);
+                }
+            }
+        }
+
         count += 1;
-        sevenz_rust::default_entry_extract_fn(entry, reader, dest)
-    })
-    .map_err(crate::Error::SevenzipError)?;
+        if let Err(err) = unpack_options.check_entry_count(count) {
+            entry_count_error = Some(err);
+            return Ok(false);
+        }
+        utils::emit_progress(ProgressEvent::Entry {
+            path: Path::new(entry.name()),
+            bytes: entry.size(),
+        });
+        let mut reader = RateLimitedReader::new(reader, unpack_options.rate_limit.clone());
+        unpack_entry(entry, &mut reader, &dest, unpack_options).map_err(sevenz_rust::Error::io)?;
+
+        // The owner is given first, changing it may clear the setuid and setgid bits
+        let metadata =
+            archive::set_owner(&dest, unpack_options).and_then(|()| set_permissions(entry, &dest, unpack_options));
+        if let Err(err) = metadata {
+            metadata_error = Some(err);
+            return Ok(false);
+        }
+        Ok(true)
+    };
+
+    match sevenz_rust::decompress_with_extract_fn(&mut reader, output_path, &mut extract_fn) {
+        Ok(()) => {}
+        Err(sevenz_rust::Error::PasswordRequired) => {
+            // Starts over, the entries unpacked before finding an encrypted one are overwritten
+            reader.rewind()?;
+            position.set(0);
+            let key = sevenz_rust::Password::from(password.get()?);
+            sevenz_rust::decompress_with_extract_fn_and_password(&mut reader, output_path, key, &mut extract_fn)
+                .map_err(|err| {
+                    if is_wrong_password(&err) {
+                        password.wrong_password_error()
+                    } else {
+                        unpack_error(err)
+                    }
+                })?;
+            password.remember()?;
+        }
+        Err(err) => return Err(unpack_error(err)),
+    }
+
+    if let Some(err) = case_conflict_error.or(metadata_error).or(entry_count_error) {
+        return Err(err);
+    }
+    unpack_options.check_indexes(position.get())?;
+
     Ok(count)
 }
+
+/// Lists the entries of the archive read from `reader`
+pub fn list_archive<R>(reader: R) -> crate::Result<Vec<FileInArchive>>
+where
+    R: Read + Seek,
+{
+    let mut files = vec![];
+    sevenz_rust::decompress_with_extract_fn(reader, ".", |entry, _, _| {
+        files.push(FileInArchive {
+            path: entry.name().into(),
+            is_dir: entry.is_directory(),
+            size: entry.size(),
+            modified: entry
+                .has_last_modified_date
+                .then(|| entry.last_modified_date().to_unix_time()),
+            is_symlink: unix_mode(entry).is_some_and(archive::is_symlink_mode),
+            compressed_size: None,
+            mode: unix_mode(entry),
+        });
+        Ok(true)
+    })
+    .map_err(Error::SevenzipError)?;
+    Ok(files)
+}
+
+/// Calls `read_file` with the path and the contents of every file of the archive read from
+/// `reader`, leaving out the directories, encrypted archives fail as if their password was wrong
+pub fn read_files<R>(
+    mut reader: R,
+    mut read_file: impl FnMut(PathBuf, &mut dyn Read) -> crate::Result<()>,
+) -> crate::Result<()>
+where
+    R: Read + Seek,
+{
+    let len = reader.seek(io::SeekFrom::End(0))?;
+    reader.rewind()?;
+    let mut archive =
+        sevenz_rust::SevenZReader::new(reader, len, sevenz_rust::Password::empty()).map_err(unpack_error)?;
+
+    // Errors of ouch can't go through `sevenz_rust`, so the reading is stopped and it is returned after
+    let mut read_error = None;
+    archive
+        .for_each_entries(|entry, reader| {
+            if entry.is_directory() {
+                return Ok(true);
+            }
+            match read_file(PathBuf::from(entry.name()), reader) {
+                Ok(()) => Ok(true),
+                Err(err) => {
+                    read_error = Some(err);
+                    Ok(false)
+                }
+            }
+        })
+        .map_err(unpack_error)?;
+
+    match read_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Windows attribute of the entries whose upper 16 bits hold their Unix mode, as written by p7zip
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+/// Windows attribute of the directories
+#[cfg(unix)]
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+/// The Windows attributes of an entry with the Unix `mode`, which 7-Zip and p7zip restore
+#[cfg(unix)]
+fn unix_attributes(mode: u32, is_dir: bool) -> u32 {
+    let directory = if is_dir { FILE_ATTRIBUTE_DIRECTORY } else { 0 };
+    (mode << 16) | FILE_ATTRIBUTE_UNIX_EXTENSION | directory
+}
+
+/// The Unix mode stored in the attributes of `entry` by p7zip and ouch, if any
+pub fn unix_mode(entry: &sevenz_rust::SevenZArchiveEntry) -> Option<u32> {
+    let attributes = entry.windows_attributes();
+    (entry.has_windows_attributes && attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0).then_some(attributes >> 16)
+}
+
+/// Sets the permissions of `dest` from the Unix mode stored in the attributes of `entry`, if any
+fn set_permissions(
+    entry: &sevenz_rust::SevenZArchiveEntry,
+    dest: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<()> {
+    let Some(mode) = unix_mode(entry) else {
+        return Ok(());
+    };
+
+    #[cfg(unix)]
+    archive::set_unix_permissions(dest, mode, unpack_options)?;
+    #[cfg(not(unix))]
+    let _ = (dest, unpack_options);
+    Ok(())
+}
+
+/// Unpacks `entry` to `dest` like [`sevenz_rust::default_entry_extract_fn`], retrying the writes
+/// that fail with transient errors, used by `--retries`, and only its first bytes with `--head`
+///
+/// The contents are copied through a buffer of [`BUFFER_CAPACITY`] bytes, however large the entry is.
+fn unpack_entry(
+    entry: &sevenz_rust::SevenZArchiveEntry,
+    reader: &mut dyn Read,
+    dest: &Path,
+    unpack_options: &UnpackOptions,
+) -> io::Result<()> {
+    let retries = unpack_options.retries;
+    if entry.is_directory() {
+        return utils::retry_transient(retries, dest, || fs::create_dir_all(dest));
+    }
+    if let Some(parent) = dest.parent() {
+        utils::retry_transient(retries, parent, || fs::create_dir_all(parent))?;
+    }
+
+    let file = utils::retry_transient(retries, dest, || fs::File::create(dest))?;
+    let mut writer = BufWriter::with_capacity(BUFFER_CAPACITY, RetryingWriter::new(file, retries, dest));
+    io::copy(
+        &mut unpack_options.head(&mut *reader, Path::new(entry.name()), entry.size()),
+        &mut writer,
+    )?;
+    // The rest is still read, the next entries of solid archives come after it
+    io::copy(reader, &mut io::sink())?;
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .into_inner();
+
+    // Like the default function, failing to set the times doesn't fail the unpacking
+    let accessed = FileTime::from_system_time(entry.access_date().into());
+    let modified = FileTime::from_system_time(entry.last_modified_date().into());
+    let _ = filetime::set_file_handle_times(file.file(), Some(accessed), Some(modified));
+    Ok(())
+}
+
+/// Checks if unpacking an encrypted archive failed because of a wrong password, which usually
+/// decrypts to garbage that fails to match its CRC or to decode
+///
+/// The decoders report garbage as invalid input, errors of the OS, like a full disk or a file that
+/// can't be read, aren't caused by the password.
+fn is_wrong_password(err: &sevenz_rust::Error) -> bool {
+    match err {
+        sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::ChecksumVerificationFailed => true,
+        sevenz_rust::Error::Io(err, _) => {
+            err.raw_os_error().is_none()
+                && matches!(err.kind(), io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData)
+        }
+        _ => false,
+    }
+}
+
+/// Converts an error of `sevenz_rust` when unpacking, a full disk isn't reported as a 7z error
+fn unpack_error(err: sevenz_rust::Error) -> crate::Error {
+    match err {
+        sevenz_rust::Error::Io(err, _) | sevenz_rust::Error::FileOpen(err, _)
+            if err.kind() == io::ErrorKind::StorageFull =>
+        {
+            err.into()
+        }
+        err => crate::Error::SevenzipError(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader of `size` bytes that records the largest read asked of it
+    struct LargeEntry {
+        left: usize,
+        largest_read: usize,
+    }
+
+    impl Read for LargeEntry {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.largest_read = self.largest_read.max(buf.len());
+            let read = buf.len().min(self.left);
+            buf[..read].fill(b'a');
+            self.left -= read;
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn test_is_wrong_password() {
+        // What the LZMA2 decoder gives for data decrypted with a wrong password
+        let garbage = io::Error::new(io::ErrorKind::InvalidInput, "Corrupted input data (LZMA2:0)");
+        assert!(is_wrong_password(&sevenz_rust::Error::io(garbage)));
+        assert!(is_wrong_password(&sevenz_rust::Error::ChecksumVerificationFailed));
+        for err in [
+            io::Error::from_raw_os_error(libc::EIO),
+            io::Error::from_raw_os_error(libc::EACCES),
+            io::Error::from(io::ErrorKind::StorageFull),
+        ] {
+            assert!(!is_wrong_password(&sevenz_rust::Error::io(err)));
+        }
+        assert!(!is_wrong_password(&sevenz_rust::Error::other("unknown")));
+    }
+
+    #[test]
+    fn test_unpack_large_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("dir/file");
+        let size = BUFFER_CAPACITY * 10 + 1;
+        let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+        entry.size = size as u64;
+        let mut reader = LargeEntry {
+            left: size,
+            largest_read: 0,
+        };
+
+        unpack_entry(&entry, &mut reader, &dest, &UnpackOptions::default()).unwrap();
+
+        let contents = fs::read(&dest).unwrap();
+        assert_eq!(contents.len(), size);
+        assert!(contents.iter().all(|&byte| byte == b'a'));
+        // Copied a buffer at a time, not read whole
+        assert!(reader.largest_read <= BUFFER_CAPACITY, "{}", reader.largest_read);
+    }
+}
@@ -1,55 +1,470 @@
 //! Contains Tar-specific building and unpacking functions
 
 use std::{
+    collections::HashMap,
     env,
-    io::prelude::*,
-    path::{Path, PathBuf},
+    io::{self, prelude::*},
+    path::{Component, Path, PathBuf},
     sync::mpsc::{self, Receiver},
     thread,
 };
 
+use filetime::FileTime;
 use fs_err as fs;
 use same_file::Handle;
 
 use crate::{
+    archive::{
+        self,
+        metadata::{Provenance, PAX_GLOBAL_HEADER_PATH},
+        CaseConflicts, JunkPaths, UnpackOptions,
+    },
+    cli::ParentDirPolicy,
     error::FinalError,
     info,
     list::FileInArchive,
-    utils::{self, Bytes, EscapedPathDisplay, FileVisibilityPolicy},
-    warning,
+    utils::{self, Bytes, EntrySizes, EscapedPathDisplay, FileVisibilityPolicy, ProgressEvent, RetryingWriter},
 };
 
 /// Unpacks the archive given by `archive` into the folder given by `into`.
 /// Assumes that output_folder is empty
-pub fn unpack_archive(reader: Box<dyn Read>, output_folder: &Path, quiet: bool) -> crate::Result<usize> {
+///
+/// With `--preserve-flags`, the file flags of the entries are pushed to `file_flags`, with the paths
+/// relative to `output_folder`, or to `--subtree` inside of it, to be restored once the files won't
+/// be moved anymore.
+pub fn unpack_archive(
+    mut reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    quiet: bool,
+    file_flags: &mut Vec<(PathBuf, String)>,
+) -> crate::Result<usize> {
     assert!(output_folder.read_dir().expect("dir exists").count() == 0);
-    let mut archive = tar::Archive::new(reader);
+    let mut case_conflicts = CaseConflicts::for_folder(output_folder, unpack_options.case_conflicts)?;
 
     let mut files_unpacked = 0;
-    for file in archive.entries()? {
-        let mut file = file?;
+    // Also counted with --quiet, unlike `files_unpacked`
+    let mut entries = 0;
+    // Tar archives found in the stream, more than one with `--concat-tar`
+    let mut archives = 0;
+    loop {
+        let mut archive = tar::Archive::new(reader);
+        for file in archive.entries()? {
+            let mut file = file?;
+
+            // Holds the `--metadata` of the archive, or other global PAX records, not a file
+            if file.header().entry_type().is_pax_global_extensions() || unpack_options.skips(&file.path()?) {
+                continue;
+            }
+            entries += 1;
+            unpack_options.check_entry_count(entries)?;
+
+            let entry_type = file.header().entry_type();
+            let is_special = entry_type.is_block_special() || entry_type.is_character_special() || entry_type.is_fifo();
+            let mut path = sanitized_path(&file.path()?);
+
+            // Left to `unpack_in` otherwise, which would skip them silently
+            let stripped = has_parent_dir(&file.path()?);
+            if stripped && !keeps_parent_dir_entry(&file, unpack_options.parent_dirs)? {
+                continue;
+            }
+
+            let renamed = match case_conflicts.as_mut() {
+                Some(case_conflicts) if !entry_type.is_dir() && !is_special => {
+                    let Some(new_path) = case_conflicts.resolve(&path)? else {
+                        continue;
+                    };
+                    let renamed = new_path != path;
+                    path = new_path;
+                    renamed
+                }
+                _ => false,
+            };
+
+            // The `tar` crate would unpack these as empty regular files
+            let unpacked = if is_special {
+                unpack_special_file(&file, output_folder, &path)?
+            } else if (unpack_options.retries > 0 || unpack_options.head.is_some()) && entry_type.is_file() {
+                unpack_regular_file(&mut file, output_folder, &path, unpack_options)?
+            } else if renamed {
+                // Next to the file it conflicts with, so the parent directory was already checked
+                file.unpack(output_folder.join(&path))?;
+                true
+            } else if stripped {
+                // Not its own path anymore, which `unpack_in` would unpack it to
+                if create_parent_inside(output_folder, &path, 0)? {
+                    file.unpack(output_folder.join(&path))?;
+                    true
+                } else {
+                    false
+                }
+            } else {
+                file.unpack_in(output_folder)?
+            };
+
+            if unpacked {
+                archive::set_owner(&output_folder.join(&path), unpack_options)?;
+            }
+
+            // The `tar` crate already restores the regular 0o777 permission bits, without applying
+            // the umask, and the special bits need to be filtered out first, so they are set again here
+            #[cfg(unix)]
+            if unpacked && !file.header().entry_type().is_symlink() {
+                archive::set_unix_permissions(&output_folder.join(&path), file.header().mode()?, unpack_options)?;
+            }
+
+            if unpacked && unpack_options.preserve_btime {
+                let created = stored_pax_record(&mut file, utils::PAX_CREATION_TIME)?;
+                if let Some(created) = created.as_deref().and_then(utils::parse_pax_time) {
+                    utils::set_birth_time(&output_folder.join(&path), created)?;
+                }
+            }
 
-        file.unpack_in(output_folder)?;
-
-        // This is printed for every file in the archive and has little
-        // importance for most users, but would generate lots of
-        // spoken text for users using screen readers, braille displays
-        // and so on
-        if !quiet {
-            info!(
-                inaccessible,
-                "{:?} extracted. ({})",
-                utils::strip_cur_dir(&output_folder.join(file.path()?)),
-                Bytes::new(file.size()),
-            );
-
-            files_unpacked += 1;
+            if unpacked && unpack_options.atime_preserve {
+                let accessed = stored_pax_record(&mut file, utils::PAX_ACCESS_TIME)?;
+                if let Some(accessed) = accessed.as_deref().and_then(utils::parse_pax_time) {
+                    utils::set_access_time(&output_folder.join(&path), FileTime::from_system_time(accessed))?;
+                }
+            }
+
+            if unpacked && unpack_options.preserve_flags {
+                // Relative to the root of the subtree, where the files end up, which is left out itself
+                // if it's a directory, and keeps its name if it's a file
+                let moved_path = match unpack_options.path_in_subtree(&path) {
+                    Some(moved_path) if moved_path.as_os_str().is_empty() => path
+                        .file_name()
+                        .filter(|_| !file.header().entry_type().is_dir())
+                        .map(PathBuf::from),
+                    moved_path => moved_path,
+                };
+                let flags = stored_pax_record(&mut file, utils::PAX_FILE_FLAGS)?;
+                if let (Some(flags), Some(moved_path)) = (flags, moved_path) {
+                    file_flags.push((moved_path, flags));
+                }
+            }
+
+            if unpacked {
+                utils::emit_progress(ProgressEvent::Entry {
+                    path: &path,
+                    bytes: file.size(),
+                });
+            }
+
+            // This is printed for every file in the archive and has little
+            // importance for most users, but would generate lots of
+            // spoken text for users using screen readers, braille displays
+            // and so on
+            if !quiet {
+                info!(
+                    inaccessible,
+                    "{:?} extracted. ({})",
+                    utils::strip_cur_dir(&output_folder.join(&path)),
+                    Bytes::new(file.size()),
+                );
+
+                files_unpacked += 1;
+            }
         }
+        archives += 1;
+
+        // The `tar` crate stops at the end of the first archive
+        reader = archive.into_inner();
+        if !unpack_options.concat_tar {
+            break;
+        }
+        match next_concatenated_archive(reader)? {
+            Some(next) => reader = next,
+            None => break,
+        }
+    }
+
+    if unpack_options.concat_tar {
+        info!(accessible, "Found {archives} concatenated tar archives.");
     }
 
     Ok(files_unpacked)
 }
 
+/// Skips the zero blocks ending a tar archive, returns the reader at the start of the archive
+/// concatenated after it, `None` at the end of the stream
+fn next_concatenated_archive(mut reader: Box<dyn Read>) -> io::Result<Option<Box<dyn Read>>> {
+    let mut block = [0; 512];
+    loop {
+        let mut filled = 0;
+        while filled < block.len() {
+            match reader.read(&mut block[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if filled == 0 {
+            return Ok(None);
+        }
+        if block[..filled].iter().any(|&byte| byte != 0) {
+            let block = io::Cursor::new(block[..filled].to_vec());
+            return Ok(Some(Box::new(block.chain(reader))));
+        }
+    }
+}
+
+/// Reads the record with `key` of the PAX extended header of `file`, like its file flags, if any
+fn stored_pax_record(file: &mut tar::Entry<impl Read>, key: &str) -> crate::Result<Option<String>> {
+    let Some(extensions) = file.pax_extensions()? else {
+        return Ok(None);
+    };
+
+    for extension in extensions {
+        let extension = extension?;
+        if extension.key() == Ok(key) {
+            return Ok(extension.value().ok().map(str::to_owned));
+        }
+    }
+    Ok(None)
+}
+
+/// Appends a PAX global header with the `--metadata` of the archive, which belongs to no entry
+fn append_metadata<W: Write>(builder: &mut tar::Builder<W>, metadata: &Provenance) -> crate::Result<()> {
+    let records: String = metadata
+        .pax_records()
+        .map(|(key, value)| pax_record(&key, value))
+        .collect();
+
+    let mut header = tar::Header::new_ustar();
+    header.set_path(PAX_GLOBAL_HEADER_PATH)?;
+    header.set_entry_type(tar::EntryType::XGlobalHeader);
+    header.set_mode(0o644);
+    header.set_size(records.len() as u64);
+    header.set_cksum();
+    builder.append(&header, records.as_bytes())?;
+
+    Ok(())
+}
+
+/// Appends a PAX extended header with the file flags of `path` if `preserve_flags`, its creation
+/// time if `preserve_btime` and its access time if `atime_preserve`, which applies to the entry
+/// appended after it, if there are any
+///
+/// Only the last extended header before an entry is read, so they all go in the same one.
+fn append_entry_records<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    preserve_flags: bool,
+    preserve_btime: bool,
+    atime_preserve: bool,
+) -> crate::Result<()> {
+    let mut records = String::new();
+    if preserve_flags {
+        let flags = utils::get_file_flags(path)?;
+        if !flags.is_empty() {
+            records.push_str(&pax_record(utils::PAX_FILE_FLAGS, &flags));
+        }
+    }
+    if preserve_btime {
+        if let Some(created) = utils::birth_time_record(&path.symlink_metadata()?) {
+            records.push_str(&pax_record(utils::PAX_CREATION_TIME, &created));
+        }
+    }
+    if atime_preserve {
+        let accessed = path.symlink_metadata()?.accessed()?;
+        records.push_str(&pax_record(utils::PAX_ACCESS_TIME, &utils::format_pax_time(accessed)));
+    }
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let mut header = tar::Header::new_ustar();
+    header.set_path("././@PaxHeader")?;
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(records.len() as u64);
+    header.set_cksum();
+    builder.append(&header, records.as_bytes())?;
+
+    Ok(())
+}
+
+/// Formats a PAX record, "<length> <key>=<value>\n", where the length includes its own digits
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = format!(" {key}={value}\n");
+    let mut length = rest.len();
+    while length != rest.len() + length.to_string().len() {
+        length = rest.len() + length.to_string().len();
+    }
+
+    format!("{length}{rest}")
+}
+
+/// Unpacks the regular file `file` to `path` inside of `output_folder` like
+/// [`tar::Entry::unpack_in`], but retrying the writes that fail with transient errors, used by
+/// `--retries`, and only its first bytes with `--head`
+fn unpack_regular_file(
+    file: &mut tar::Entry<impl Read>,
+    output_folder: &Path,
+    path: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<bool> {
+    let retries = unpack_options.retries;
+    if !create_parent_inside(output_folder, path, retries)? {
+        return Ok(false);
+    }
+
+    let file_path = output_folder.join(path);
+
+    // Replaced instead of written through, like `unpack_in` does
+    if file_path.symlink_metadata().is_ok() {
+        fs::remove_file(&file_path)?;
+    }
+    let output_file = utils::retry_transient(retries, &file_path, || fs::File::create(&file_path))?;
+    let mut writer = RetryingWriter::new(output_file, retries, &file_path);
+    let size = file.size();
+    io::copy(&mut unpack_options.head(&mut *file, path, size), &mut writer)?;
+    let output_file = writer.into_inner();
+
+    let modified = FileTime::from_unix_time(file.header().mtime()? as i64, 0);
+    filetime::set_file_handle_times(output_file.file(), None, Some(modified))?;
+    #[cfg(unix)]
+    {
+        use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+        output_file.set_permissions(Permissions::from_mode(file.header().mode()? & 0o777))?;
+    }
+
+    Ok(true)
+}
+
+/// Creates the parent directories of `path` inside of `output_folder`, like
+/// [`tar::Entry::unpack_in`] does, retrying the transient errors `retries` times.
+///
+/// Returns `Ok(false)` if `path` has no name, or if it is inside of a symlink unpacked before, so
+/// it would be written outside of `output_folder`, which is skipped with a warning.
+fn create_parent_inside(output_folder: &Path, path: &Path, retries: u32) -> crate::Result<bool> {
+    if path.as_os_str().is_empty() {
+        return Ok(false);
+    }
+
+    let file_path = output_folder.join(path);
+    if let Some(parent) = file_path.parent() {
+        // Checked before creating anything inside of it
+        let existing = parent.ancestors().find(|dir| dir.exists()).unwrap_or(output_folder);
+        if !existing.canonicalize()?.starts_with(output_folder.canonicalize()?) {
+            utils::warn_or_fail(format!(
+                "Skipping '{}', it is inside of a symlink from the same archive.",
+                EscapedPathDisplay::new(&file_path)
+            ))?;
+            return Ok(false);
+        }
+        utils::retry_transient(retries, parent, || fs::create_dir_all(parent))?;
+    }
+    Ok(true)
+}
+
+/// Checks if the entry `file`, whose path has `..` components, is unpacked with them stripped,
+/// following `policy`, warning about it otherwise.
+///
+/// Hard links are never unpacked then, their targets are only checked by `unpack_in`.
+fn keeps_parent_dir_entry(file: &tar::Entry<impl Read>, policy: ParentDirPolicy) -> crate::Result<bool> {
+    let reason = match policy {
+        ParentDirPolicy::Strip if !file.header().entry_type().is_hard_link() => return Ok(true),
+        ParentDirPolicy::Strip => "it is a hard link with '..' components in its path",
+        ParentDirPolicy::Reject => "its path has '..' components, use --parent-dirs strip to unpack it without them",
+    };
+    utils::warn_or_fail(format!(
+        "Skipping '{}', {reason}.",
+        EscapedPathDisplay::new(&file.path()?)
+    ))?;
+    Ok(false)
+}
+
+/// Recreates the block device, character device or FIFO described by `file` with `mknod`.
+///
+/// Creating device nodes requires privileges, so, when denied, the entry is skipped with a
+/// warning instead of failing the whole extraction.
+#[cfg(unix)]
+fn unpack_special_file(file: &tar::Entry<impl Read>, output_folder: &Path, path: &Path) -> crate::Result<bool> {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt};
+
+    if !create_parent_inside(output_folder, path, 0)? {
+        return Ok(false);
+    }
+    let path = output_folder.join(path);
+
+    let header = file.header();
+    let file_type = match header.entry_type() {
+        tar::EntryType::Block => libc::S_IFBLK,
+        tar::EntryType::Char => libc::S_IFCHR,
+        _ => libc::S_IFIFO,
+    };
+    let mode = file_type | (header.mode()? & 0o777) as libc::mode_t;
+    let device = libc::makedev(
+        header.device_major()?.unwrap_or(0) as _,
+        header.device_minor()?.unwrap_or(0) as _,
+    );
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).expect("tar paths contain no NUL bytes");
+    // Safety: `c_path` is a valid NUL-terminated string
+    if unsafe { libc::mknod(c_path.as_ptr(), mode, device) } == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        utils::warn_or_fail(format!(
+            "Not enough privileges to create the device file '{}', skipping it.",
+            EscapedPathDisplay::new(&path)
+        ))?;
+        Ok(false)
+    } else {
+        Err(FinalError::with_title(format!(
+            "Could not create the special file '{}'",
+            EscapedPathDisplay::new(&path)
+        ))
+        .detail(format!("Error: {err}."))
+        .into())
+    }
+}
+
+/// Device files and FIFOs can't be recreated on this platform, so they are always skipped
+#[cfg(not(unix))]
+fn unpack_special_file(file: &tar::Entry<impl Read>, _output_folder: &Path, _path: &Path) -> crate::Result<bool> {
+    utils::warn_or_fail(format!(
+        "Special files are not supported on this platform, skipping '{}'.",
+        EscapedPathDisplay::new(&file.path()?)
+    ))?;
+    Ok(false)
+}
+
+/// Computes the path an entry is unpacked to inside of the output folder, the same way
+/// [`tar::Entry::unpack_in`] does, by skipping over root and current directory components
+fn sanitized_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Checks if `path` has a `..` component, entries with one are only unpacked with them stripped
+fn has_parent_dir(path: &Path) -> bool {
+    path.components().any(|component| component == Component::ParentDir)
+}
+
+/// Calls `read_file` with the path and the contents of every regular file of `archive`, leaving
+/// out the directories, links and special files
+pub fn read_files(
+    mut archive: tar::Archive<impl Read>,
+    mut read_file: impl FnMut(PathBuf, &mut dyn Read) -> crate::Result<()>,
+) -> crate::Result<()> {
+    for file in archive.entries()? {
+        let mut file = file?;
+        if file.header().entry_type().is_file() {
+            let path = file.path()?.into_owned();
+            read_file(path, &mut file)?;
+        }
+    }
+    Ok(())
+}
+
 /// List contents of `archive`, returning a vector of archive entries
 pub fn list_archive(
     mut archive: tar::Archive<impl Read + Send + 'static>,
@@ -68,62 +483,154 @@ pub fn list_archive(
         for file in archive.entries().expect("entries is only used once") {
             let file_in_archive = (|| {
                 let file = file?;
+                if file.header().entry_type().is_pax_global_extensions() {
+                    return Ok(None);
+                }
                 let path = file.path()?.into_owned();
                 let is_dir = file.header().entry_type().is_dir();
-                Ok(FileInArchive { path, is_dir })
+                let size = file.size();
+                let modified = file.header().mtime().ok().map(|mtime| mtime as i64);
+                Ok(Some(FileInArchive {
+                    path,
+                    is_dir,
+                    size,
+                    modified,
+                    is_symlink: file.header().entry_type().is_symlink(),
+                    compressed_size: None,
+                    mode: file.header().mode().ok(),
+                }))
             })();
-            tx.send(file_in_archive).unwrap();
+            if let Some(file_in_archive) = file_in_archive.transpose() {
+                tx.send(file_in_archive).unwrap();
+            }
         }
     });
 
     Files(rx)
 }
 
+/// Paths the files with hard links were stored as, by their device and inode
+#[derive(Default)]
+struct HardLinks {
+    stored_paths: HashMap<(u64, u64), PathBuf>,
+}
+
+impl HardLinks {
+    /// Returns the path a file with the same device and inode as `metadata` was stored as, or
+    /// remembers that it is stored as `stored_path`.
+    ///
+    /// Only files with more than one link are remembered, hard links are only detected on Unix.
+    fn stored_target(&mut self, metadata: &std::fs::Metadata, stored_path: &Path) -> Option<PathBuf> {
+        #[cfg(unix)]
+        {
+            use std::{collections::hash_map::Entry, os::unix::fs::MetadataExt};
+
+            if metadata.nlink() > 1 {
+                match self.stored_paths.entry((metadata.dev(), metadata.ino())) {
+                    Entry::Occupied(entry) => return Some(entry.get().clone()),
+                    Entry::Vacant(entry) => {
+                        entry.insert(stored_path.to_owned());
+                    }
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (metadata, stored_path, &self.stored_paths);
+
+        None
+    }
+}
+
 /// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
+///
+/// With `entry_sizes`, used by `-vv`, the stored size of every file is reported.
+/// With `preserve_flags`, the file flags are stored in PAX extended headers, with `preserve_btime`
+/// the creation times, and with `atime_preserve` the access times, which are set back after reading
+/// the files.
+/// Files with hard links are stored once, the next paths are stored as links to the first one,
+/// unless `hard_dereference` is set.
+/// With `metadata`, from `--metadata`, a PAX global header holding it is stored first.
+/// With `time_rounding`, from `--time-rounding`, the modification times of files and directories
+/// are rounded down to a multiple of it.
+#[allow(clippy::too_many_arguments)]
 pub fn build_archive_from_paths<W>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     quiet: bool,
+    mut junk_paths: Option<JunkPaths>,
+    no_dir_entries: bool,
+    mut entry_sizes: Option<&mut EntrySizes>,
+    preserve_flags: bool,
+    preserve_btime: bool,
+    atime_preserve: bool,
+    hard_dereference: bool,
+    time_rounding: Option<u64>,
+    metadata: Option<&Provenance>,
 ) -> crate::Result<W>
 where
     W: Write,
 {
     let mut builder = tar::Builder::new(writer);
+    // Only affects the entries appended by path, regular files are appended from their contents
+    builder.follow_symlinks(false);
+    if let Some(metadata) = metadata {
+        append_metadata(&mut builder, metadata)?;
+    }
     let output_handle = Handle::from_path(output_path);
 
-    for filename in input_filenames {
-        let previous_location = utils::cd_into_same_dir_as(filename)?;
+    let mut truncated_subtrees = 0;
+    let mut compression_report = utils::CompressionReport::new(quiet);
+    let mut hard_links = HardLinks::default();
 
-        // Safe unwrap, input shall be treated before
-        let filename = filename.file_name().unwrap();
+    for filename in input_filenames {
+        let (previous_location, filename) = utils::cd_into_walk_root(filename, &file_visibility_policy)?;
 
         for entry in file_visibility_policy.build_walker(filename) {
             let entry = entry?;
             let path = entry.path();
 
+            if file_visibility_policy.is_truncated_by_max_depth(&entry) {
+                truncated_subtrees += 1;
+            }
+
             // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
             if let Ok(ref handle) = output_handle {
-                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning!(
+                // Opening a FIFO to compare it would block until something writes to it
+                if !is_special_file(path) && matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+                    utils::warn_or_fail(format!(
                         "The output file and the input file are the same: `{}`, skipping...",
                         output_path.display()
-                    );
+                    ))?;
                     continue;
                 }
             }
 
-            // This is printed for every file in `input_filenames` and has
-            // little importance for most users, but would generate lots of
-            // spoken text for users using screen readers, braille displays
-            // and so on
-            if !quiet {
-                info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
+            // Stored as a link, unless it is being followed with --dereference
+            let is_dir_symlink = path.is_dir() && utils::is_symlink(path) && !file_visibility_policy.follow_links;
+
+            // Only the files are stored with --junk-paths and --no-dir-entries
+            if path.is_dir() && !is_dir_symlink && (junk_paths.is_some() || no_dir_entries) {
+                continue;
             }
 
-            if path.is_dir() {
-                builder.append_dir(path, path)?;
+            compression_report.start(path);
+            let bytes = if path.is_file() { path.metadata()?.len() } else { 0 };
+            utils::emit_progress(ProgressEvent::Entry { path, bytes });
+
+            if (preserve_flags || preserve_btime || atime_preserve) && !is_dir_symlink {
+                append_entry_records(&mut builder, path, preserve_flags, preserve_btime, atime_preserve)?;
+            }
+
+            if is_dir_symlink {
+                builder.append_path_with_name(path, archive::stored_path(&mut junk_paths, path)?)?;
+            } else if path.is_dir() {
+                let mut header = entry_header(&path.metadata()?, time_rounding);
+                builder.append_data(&mut header, path, io::empty())?;
+            } else if is_special_file(path) {
+                // Stores the device numbers of block and character devices, without reading them
+                builder.append_path_with_name(path, archive::stored_path(&mut junk_paths, path)?)?;
             } else {
                 let mut file = match fs::File::open(path) {
                     Ok(f) => f,
@@ -136,15 +643,83 @@ where
                         return Err(e.into());
                     }
                 };
-                builder.append_file(path, file.file_mut()).map_err(|err| {
-                    FinalError::with_title("Could not create archive")
-                        .detail("Unexpected error while trying to read file")
-                        .detail(format!("Error: {err}."))
-                })?;
+                let stored_path = archive::stored_path(&mut junk_paths, path)?;
+                let metadata = file.metadata()?;
+
+                if let (false, Some(target)) = (hard_dereference, hard_links.stored_target(&metadata, &stored_path)) {
+                    let mut header = entry_header(&metadata, time_rounding);
+                    header.set_entry_type(tar::EntryType::Link);
+                    header.set_size(0);
+                    builder.append_link(&mut header, &stored_path, target)?;
+                    continue;
+                }
+
+                if let Some(entry_sizes) = entry_sizes.as_mut() {
+                    entry_sizes.start_entry(&stored_path, metadata.len());
+                }
+                let accessed = atime_preserve.then(|| utils::AccessTime::save(path, &metadata));
+                let mut header = entry_header(&metadata, time_rounding);
+                builder
+                    .append_data(&mut header, stored_path, file.file_mut())
+                    .map_err(|err| {
+                        FinalError::with_title("Could not create archive")
+                            .detail("Unexpected error while trying to read file")
+                            .detail(format!("Error: {err}."))
+                    })?;
+                if let Some(accessed) = accessed {
+                    accessed.restore()?;
+                }
             }
         }
         env::set_current_dir(previous_location)?;
     }
 
+    if truncated_subtrees > 0 {
+        info!(
+            accessible,
+            "{} subtrees deeper than --max-depth were stored without their contents.", truncated_subtrees
+        );
+    }
+
     Ok(builder.into_inner()?)
 }
+
+/// Header of an entry with the `metadata` of its file, with the modification time rounded down to
+/// a multiple of `time_rounding`
+fn entry_header(metadata: &std::fs::Metadata, time_rounding: Option<u64>) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(metadata);
+    if let (Some(granularity), Ok(mtime)) = (time_rounding, header.mtime()) {
+        header.set_mtime(utils::round_time(mtime as i64, granularity) as u64);
+    }
+    header
+}
+
+/// Checks if `path` is a block device, character device or FIFO
+#[cfg(unix)]
+fn is_special_file(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    path.metadata().is_ok_and(|metadata| {
+        let file_type = metadata.file_type();
+        file_type.is_block_device() || file_type.is_char_device() || file_type.is_fifo()
+    })
+}
+
+#[cfg(not(unix))]
+fn is_special_file(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!(pax_record("SCHILY.fflags", "nodump"), "24 SCHILY.fflags=nodump\n");
+        // The length gains a digit once the record reaches 10 bytes
+        assert_eq!(pax_record("a", "bcde"), "9 a=bcde\n");
+        assert_eq!(pax_record("a", "bcdef"), "11 a=bcdef\n");
+    }
+}
@@ -0,0 +1,191 @@
+//! Windows imaging (.wim) support, done by running `wimlib-imagex`, which must be allowed with
+//! `--use-command`.
+//!
+//! Limitations:
+//! - Only the first image of a .wim file is listed and unpacked, and a single image is written.
+//! - The options controlling which files are compressed or how they are unpacked, like
+//!   `--exclude`, `--junk-paths`, `--preserve-permissions` or `--chown`, don't apply to .wim images.
+//! - Listing doesn't give the sizes and modification times of the files.
+
+use std::{
+    ffi::OsStr,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use fs_err as fs;
+
+use crate::{
+    error::{Error, FinalError},
+    list::FileInArchive,
+    utils::{self, EscapedPathDisplay, FileVisibilityPolicy},
+};
+
+/// The program of wimlib that reads and writes .wim images
+const WIMLIB_COMMAND: &str = "wimlib-imagex";
+
+/// Unpacks the first image of the .wim file at `archive_path` into `output_folder`, returning the
+/// count of unpacked files.
+pub fn unpack_archive(archive_path: &Path, output_folder: &Path) -> crate::Result<usize> {
+    wimlib([
+        OsStr::new("apply"),
+        archive_path.as_os_str(),
+        OsStr::new("1"),
+        output_folder.as_os_str(),
+    ])?;
+
+    Ok(unpacked_files(output_folder)?.len())
+}
+
+/// Lists the files of the first image of the .wim file at `archive_path`.
+pub fn list_archive(archive_path: &Path) -> crate::Result<Vec<FileInArchive>> {
+    let stdout = wimlib([OsStr::new("dir"), archive_path.as_os_str(), OsStr::new("1")])?;
+    let stdout = String::from_utf8_lossy(&stdout);
+
+    // One absolute path per line, starting with the root of the image, "/"
+    let paths: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.trim_start_matches('/'))
+        .filter(|path| !path.is_empty())
+        .collect();
+
+    // Only the paths are given, so directories are the ones with other paths inside of them
+    let files = paths
+        .iter()
+        .map(|path| FileInArchive {
+            path: PathBuf::from(path),
+            is_dir: paths.iter().any(|other| {
+                other.len() > path.len() && other.starts_with(path) && other[path.len()..].starts_with('/')
+            }),
+            size: 0,
+            modified: None,
+            is_symlink: false,
+            compressed_size: None,
+            mode: None,
+        })
+        .collect();
+
+    Ok(files)
+}
+
+/// Writes the .wim file `output_path` with one image, named `image_name`, holding `input_paths`.
+///
+/// Only the `base_dirs` of `file_visibility_policy` apply, the inputs are walked by `wimlib-imagex`.
+pub fn build_archive_from_paths(
+    input_paths: &[PathBuf],
+    output_path: &Path,
+    image_name: &OsStr,
+    file_visibility_policy: &FileVisibilityPolicy,
+) -> crate::Result<()> {
+    // Puts each input at the root of the image, or at its path inside of `--base`, like with the
+    // other archive formats
+    let mut source_list = tempfile::NamedTempFile::new()?;
+    for path in input_paths {
+        let (_, stored_path) = file_visibility_policy
+            .split_walk_root(path)
+            .ok_or(Error::CompressingRootFolder)?;
+        writeln!(
+            source_list,
+            "{} {}",
+            quote_source(path.as_os_str())?,
+            quote_source(&Path::new("/").join(stored_path).into_os_string())?
+        )?;
+    }
+    source_list.flush()?;
+
+    wimlib([
+        OsStr::new("capture"),
+        source_list.path().as_os_str(),
+        output_path.as_os_str(),
+        image_name,
+        OsStr::new("--source-list"),
+    ])?;
+
+    Ok(())
+}
+
+/// Paths of the files inside of `dir`, relative to it, used to count and verify unpacked files
+pub fn unpacked_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(relative_dir) = dirs.pop() {
+        for entry in fs::read_dir(dir.join(&relative_dir))? {
+            let entry = entry?;
+            let path = relative_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Quotes `path` for the source list of `wimlib-imagex capture`, which splits the lines at spaces
+fn quote_source(path: &OsStr) -> crate::Result<String> {
+    let Some(path) = path.to_str() else {
+        let error = FinalError::with_title(format!(
+            "Cannot compress '{}' into a .wim image",
+            EscapedPathDisplay::new(Path::new(path))
+        ))
+        .detail("Its path isn't valid UTF-8");
+        return Err(Error::Custom { reason: error });
+    };
+
+    if !path.contains('"') {
+        Ok(format!("\"{path}\""))
+    } else if !path.contains('\'') {
+        Ok(format!("'{path}'"))
+    } else {
+        let error = FinalError::with_title(format!(
+            "Cannot compress '{}' into a .wim image",
+            EscapedPathDisplay::new(Path::new(path))
+        ))
+        .detail("Paths with both single and double quotes can't be given to wimlib-imagex");
+        Err(Error::Custom { reason: error })
+    }
+}
+
+/// Runs `wimlib-imagex` with `args`, returning what it printed to stdout.
+fn wimlib<I>(args: I) -> crate::Result<Vec<u8>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<OsStr>,
+{
+    if !utils::use_command_allowed() {
+        let error = FinalError::with_title("Cannot handle .wim images")
+            .detail(format!(
+                "Ouch handles .wim images by running {WIMLIB_COMMAND}, from wimlib"
+            ))
+            .hint("Allow running it with --use-command");
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    let output = match Command::new(WIMLIB_COMMAND).args(args).stdin(Stdio::null()).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let error = FinalError::with_title(format!("Cannot run {WIMLIB_COMMAND}"))
+                .detail("It was not found")
+                .hint("Install wimlib, from https://wimlib.net or the package manager of your system");
+            return Err(Error::Custom { reason: error });
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if !output.status.success() {
+        let mut error = FinalError::with_title(format!("{WIMLIB_COMMAND} failed"))
+            .detail(format!("It exited with {}", output.status));
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            if !line.trim().is_empty() {
+                error = error.detail(line.trim().to_owned());
+            }
+        }
+        return Err(Error::Custom { reason: error });
+    }
+
+    Ok(output.stdout)
+}
@@ -3,52 +3,104 @@
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
-    env,
+    cmp::Reverse,
+    collections::HashSet,
+    env, fmt,
     io::{self, prelude::*},
+    ops::Deref,
     path::{Path, PathBuf},
     sync::mpsc,
     thread,
 };
 
+use deflate64::Deflate64Decoder;
 use filetime::{set_file_mtime, FileTime};
 use fs_err as fs;
 use same_file::Handle;
 use time::OffsetDateTime;
-use zip::{self, read::ZipFile, DateTime, ZipArchive};
+use zip::{self, read::ZipFile, result::ZipError, CompressionMethod, DateTime, ZipArchive};
 
 use crate::{
+    archive::{self, metadata::Provenance, CaseConflicts, JunkPaths, UnpackOptions},
+    cli::ExtractionOrder,
     error::FinalError,
     info,
     list::FileInArchive,
     utils::{
-        self, cd_into_same_dir_as, get_invalid_utf8_paths, pretty_format_list_of_paths, strip_cur_dir, Bytes,
-        EscapedPathDisplay, FileVisibilityPolicy,
+        self, cd_into_walk_root, strip_cur_dir, ArchivePassword, Bytes, EscapedPathDisplay, ExtensionLevels,
+        FileVisibilityPolicy, ProgressEvent, RateLimit, RateLimitedReader, RetryingWriter, STORE_LEVEL,
     },
-    warning,
+    warning, BUFFER_CAPACITY,
 };
 
 /// Unpacks the archive given by `archive` into the folder given by `output_folder`.
 /// Assumes that output_folder is empty
-pub fn unpack_archive<R>(mut archive: ZipArchive<R>, output_folder: &Path, quiet: bool) -> crate::Result<usize>
+///
+/// `password` is only asked for once an encrypted entry is found.
+pub fn unpack_archive<R>(
+    mut archive: ZipArchive<R>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    password: &mut ArchivePassword,
+    quiet: bool,
+) -> crate::Result<usize>
 where
     R: Read + Seek,
 {
     assert!(output_folder.read_dir().expect("dir exists").count() == 0);
 
     let mut unpacked_files = 0;
-
-    for idx in 0..archive.len() {
-        let mut file = archive.by_index(idx)?;
-        let file_path = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
-            None => continue,
+    let mut crc_mismatches = vec![];
+    let mut case_conflicts = CaseConflicts::for_folder(output_folder, unpack_options.case_conflicts)?;
+    #[cfg(unix)]
+    let mut unpacked_symlinks: Vec<PathBuf> = vec![];
+
+    let selected = selected_entries(&mut archive, unpack_options)?;
+    for idx in unpack_order(&mut archive, unpack_options.order)? {
+        if selected.as_ref().is_some_and(|selected| !selected.contains(&idx)) {
+            continue;
+        }
+        let mut file = open_entry(&mut archive, idx, Some(password))?;
+
+        let normalize_separators = unpack_options.normalize_separators;
+        let stored_path = match entry_path(&file, normalize_separators) {
+            Some(path) if !unpack_options.skips(&path) => match case_conflicts.as_mut() {
+                Some(case_conflicts) if !is_dir(&file, normalize_separators) => case_conflicts.resolve(&path)?,
+                _ => Some(path),
+            },
+            _ => None,
+        };
+        let Some(stored_path) = stored_path else {
+            // Skipped entries are not written, but are still read when verifying
+            if unpack_options.verify_crc && !copy_checking_crc(&mut file, &mut io::sink(), None)? {
+                report_crc_mismatch(&file, &mut crc_mismatches);
+            }
+            continue;
         };
+        unpack_options.check_entry_count(unpacked_files + 1)?;
 
-        let file_path = output_folder.join(file_path);
+        let file_path = output_folder.join(&stored_path);
+
+        // Writing through a symlink unpacked before could reach outside of the output folder
+        #[cfg(unix)]
+        if unpacked_symlinks
+            .iter()
+            .any(|symlink| strip_cur_dir(&file_path).starts_with(symlink))
+        {
+            utils::warn_or_fail(format!(
+                "Skipping '{}', it is inside of a symlink from the same archive.",
+                EscapedPathDisplay::new(&file_path)
+            ))?;
+            continue;
+        }
 
         display_zip_comment_if_exists(&file);
+        utils::emit_progress(ProgressEvent::Entry {
+            path: &stored_path,
+            bytes: file.size(),
+        });
 
-        match file.name().ends_with('/') {
+        match is_dir(&file, normalize_separators) {
             _is_dir @ true => {
                 // This is printed for every file in the archive and has little
                 // importance for most users, but would generate lots of
@@ -58,6 +110,10 @@ where
                     info!(inaccessible, "File {} extracted to \"{}\"", idx, file_path.display());
                 }
                 fs::create_dir_all(&file_path)?;
+
+                if unpack_options.verify_crc && !copy_checking_crc(&mut file, &mut io::sink(), None)? {
+                    report_crc_mismatch(&file, &mut crc_mismatches);
+                }
             }
             _is_file @ false => {
                 if let Some(path) = file_path.parent() {
@@ -67,6 +123,20 @@ where
                 }
                 let file_path = strip_cur_dir(file_path.as_path());
 
+                #[cfg(unix)]
+                if is_symlink(&file) {
+                    let mut target = String::new();
+                    file.read_to_string(&mut target)?;
+                    std::os::unix::fs::symlink(target, file_path)?;
+
+                    archive::set_owner(file_path, unpack_options)?;
+
+                    // Setting the permissions and modification time would follow the symlink
+                    unpacked_symlinks.push(file_path.to_owned());
+                    unpacked_files += 1;
+                    continue;
+                }
+
                 // same reason is in _is_dir: long, often not needed text
                 if !quiet {
                     info!(
@@ -77,22 +147,335 @@ where
                     );
                 }
 
-                let mut output_file = fs::File::create(file_path)?;
-                io::copy(&mut file, &mut output_file)?;
+                let retries = unpack_options.retries;
+                let output_file = utils::retry_transient(retries, file_path, || fs::File::create(file_path))?;
+                let mut output_file = RetryingWriter::new(output_file, retries, file_path);
+                let rate_limit = unpack_options.rate_limit.as_deref();
+                if unpack_options.verify_crc {
+                    if !copy_checking_crc(&mut file, &mut output_file, rate_limit)? {
+                        report_crc_mismatch(&file, &mut crc_mismatches);
+                    }
+                } else {
+                    let size = file.size();
+                    let file = RateLimitedReader::new(&mut file, unpack_options.rate_limit.clone());
+                    io::copy(&mut unpack_options.head(file, &stored_path, size), &mut output_file)?;
+                }
 
                 set_last_modified_time(&file, file_path)?;
             }
         }
 
+        archive::set_owner(&file_path, unpack_options)?;
+
         #[cfg(unix)]
-        unix_set_permissions(&file_path, &file)?;
+        if let Some(mode) = file.unix_mode() {
+            archive::set_unix_permissions(&file_path, mode, unpack_options)?;
+        }
 
         unpacked_files += 1;
     }
 
+    if !crc_mismatches.is_empty() {
+        let error = FinalError::with_title("Zip archive is corrupted")
+            .detail(format!(
+                "{} entries do not match their stored CRC-32: {}",
+                crc_mismatches.len(),
+                crc_mismatches.join(", ")
+            ))
+            .hint("The extracted contents of these entries are not what was archived.");
+
+        return Err(crate::Error::CorruptedArchive { reason: error });
+    }
+
+    password.remember()?;
+
     Ok(unpacked_files)
 }
 
+/// Entry of a zip archive being read, decompressed by the zip crate, or here for Deflate64 which
+/// it doesn't support
+enum ZipEntry<'a> {
+    Zip(ZipFile<'a>),
+    Deflate64 {
+        decoder: Deflate64Decoder<io::BufReader<ZipFile<'a>>>,
+        hasher: crc32fast::Hasher,
+    },
+}
+
+impl<'a> Deref for ZipEntry<'a> {
+    type Target = ZipFile<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Zip(file) => file,
+            Self::Deflate64 { decoder, .. } => decoder.get_ref().get_ref(),
+        }
+    }
+}
+
+impl Read for ZipEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (decoder, hasher) = match self {
+            Self::Zip(file) => return file.read(buf),
+            Self::Deflate64 { decoder, hasher } => (decoder, hasher),
+        };
+
+        let bytes_read = decoder.read(buf)?;
+        hasher.update(&buf[..bytes_read]);
+        // Checked like the zip crate does for the methods it decompresses
+        if bytes_read == 0 && !buf.is_empty() && hasher.clone().finalize() != decoder.get_ref().get_ref().crc32() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, CrcMismatch));
+        }
+        Ok(bytes_read)
+    }
+}
+
+/// Error of an entry whose contents don't match the CRC-32 stored for it
+#[derive(Debug)]
+struct CrcMismatch;
+
+impl fmt::Display for CrcMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Invalid checksum")
+    }
+}
+
+impl std::error::Error for CrcMismatch {}
+
+/// Opens the entry at `idx` of `archive`, `password` is only asked for if it is encrypted,
+/// without one encrypted entries can't be opened
+fn open_entry<'a, R>(
+    archive: &'a mut ZipArchive<R>,
+    idx: usize,
+    password: Option<&mut ArchivePassword>,
+) -> crate::Result<ZipEntry<'a>>
+where
+    R: Read + Seek,
+{
+    let is_encrypted = matches!(
+        archive.by_index(idx),
+        Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED))
+    );
+    let method = archive.by_index_raw(idx)?.compression();
+
+    #[allow(deprecated)]
+    if let CompressionMethod::Unsupported(id) = method {
+        if method == CompressionMethod::DEFLATE64 && !is_encrypted {
+            let decoder = Deflate64Decoder::new(archive.by_index_raw(idx)?);
+            let hasher = crc32fast::Hasher::new();
+            return Ok(ZipEntry::Deflate64 { decoder, hasher });
+        }
+
+        let name = archive.by_index_raw(idx)?.name().to_owned();
+        let encrypted = if is_encrypted { "encrypted " } else { "" };
+        let error = FinalError::with_title("Unsupported zip compression method")
+            .detail(format!("Entry '{name}' is {encrypted}compressed with method {id}"))
+            .detail("Supported methods are 0 (stored), 8 (deflate) and 9 (Deflate64, when not encrypted)")
+            .hint("Try extracting it with another tool, such as 7z");
+        return Err(error.into());
+    }
+
+    let file = match password {
+        Some(password) if is_encrypted => archive
+            .by_index_decrypt(idx, password.get()?.as_bytes())?
+            .map_err(|_| password.wrong_password_error())?,
+        _ => archive.by_index(idx)?,
+    };
+    Ok(ZipEntry::Zip(file))
+}
+
+/// Indexes of the entries of `archive` at the positions given to `--index`, which are counted
+/// like `ouch list --index` does, leaving out the entries whose paths would be outside of it
+fn selected_entries<R>(
+    archive: &mut ZipArchive<R>,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<Option<HashSet<usize>>>
+where
+    R: Read + Seek,
+{
+    let Some(indexes) = &unpack_options.indexes else {
+        return Ok(None);
+    };
+
+    let mut listed = vec![];
+    for idx in 0..archive.len() {
+        if entry_path(&archive.by_index_raw(idx)?, true).is_some() {
+            listed.push(idx);
+        }
+    }
+    unpack_options.check_indexes(listed.len())?;
+
+    let selected = listed
+        .into_iter()
+        .enumerate()
+        .filter(|&(position, _)| indexes.contains(position + 1))
+        .map(|(_, idx)| idx)
+        .collect();
+    Ok(Some(selected))
+}
+
+/// Indexes of the entries of `archive`, in the `order` they are unpacked in
+fn unpack_order<R>(archive: &mut ZipArchive<R>, order: ExtractionOrder) -> crate::Result<Vec<usize>>
+where
+    R: Read + Seek,
+{
+    let mut indexes: Vec<usize> = (0..archive.len()).collect();
+
+    match order {
+        ExtractionOrder::Archive => {}
+        ExtractionOrder::Name => {
+            // Raw, so encrypted entries don't need their password
+            let names = indexes
+                .iter()
+                .map(|&idx| Ok(archive.by_index_raw(idx)?.name_raw().to_vec()))
+                .collect::<crate::Result<Vec<_>>>()?;
+            indexes.sort_by(|&a, &b| names[a].cmp(&names[b]));
+        }
+        ExtractionOrder::SizeDesc => {
+            let sizes = indexes
+                .iter()
+                .map(|&idx| Ok(archive.by_index_raw(idx)?.size()))
+                .collect::<crate::Result<Vec<_>>>()?;
+            // Stable, so entries of the same size stay in archive order
+            indexes.sort_by_key(|&idx| Reverse(sizes[idx]));
+        }
+    }
+
+    Ok(indexes)
+}
+
+/// Copies `file` to `writer` until the end, computing the CRC-32 of its contents along the way.
+///
+/// Returns whether it matches the CRC-32 stored in the archive.
+fn copy_checking_crc(
+    file: &mut ZipEntry,
+    writer: &mut impl Write,
+    rate_limit: Option<&RateLimit>,
+) -> crate::Result<bool> {
+    let expected_crc = file.crc32();
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = [0; BUFFER_CAPACITY];
+    let mut total_read = 0;
+
+    loop {
+        let bytes_read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => bytes_read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // The CRC-32 is also checked when reaching the end of the entry, failing there after all
+            // of its contents were read, which the one computed here tells apart from other errors
+            Err(_) if total_read == file.size() && hasher.clone().finalize() != expected_crc => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        total_read += bytes_read as u64;
+        hasher.update(&buffer[..bytes_read]);
+        writer.write_all(&buffer[..bytes_read])?;
+        if let Some(rate_limit) = rate_limit {
+            rate_limit.consume(bytes_read);
+        }
+    }
+
+    Ok(hasher.finalize() == expected_crc)
+}
+
+fn report_crc_mismatch(file: &ZipFile, crc_mismatches: &mut Vec<String>) {
+    warning!(
+        "CRC-32 mismatch in entry '{}', its contents are corrupted.",
+        file.name()
+    );
+    crc_mismatches.push(file.name().to_owned());
+}
+
+/// Checks if the entry is a symlink, whose contents are the path it points to
+fn is_symlink(file: &ZipFile) -> bool {
+    file.unix_mode().is_some_and(archive::is_symlink_mode)
+}
+
+/// The path `file` is unpacked to, checked like [`ZipFile::enclosed_name`].
+///
+/// Names that aren't UTF-8 are decoded as CP437 by the zip crate, but on Unix they are most
+/// likely raw bytes from another Unix system, so they are used as they are.
+///
+/// With `normalize_separators`, the backslashes some Windows tools write instead of slashes are
+/// directory separators, instead of being part of the file names on Unix.
+fn entry_path(file: &ZipFile, normalize_separators: bool) -> Option<PathBuf> {
+    let normalize = normalize_separators && file.name_raw().contains(&b'\\');
+
+    #[cfg(unix)]
+    if std::str::from_utf8(file.name_raw()).is_err() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let name: Vec<u8> = file
+            .name_raw()
+            .iter()
+            .map(|&byte| if normalize && byte == b'\\' { b'/' } else { byte })
+            .collect();
+        return enclosed_path(Path::new(OsStr::from_bytes(&name)));
+    }
+
+    if normalize {
+        return enclosed_path(Path::new(&file.name().replace('\\', "/")));
+    }
+    file.enclosed_name().map(Path::to_owned)
+}
+
+/// `path` if it has no NUL bytes and stays inside of the folder it is unpacked in, like
+/// [`ZipFile::enclosed_name`] checks
+fn enclosed_path(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    if path.as_os_str().to_string_lossy().contains('\0') {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::ParentDir => depth = depth.checked_sub(1)?,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    Some(path.to_owned())
+}
+
+/// Checks if `file` is a directory, whose name ends with a separator, see [`entry_path`] for
+/// `normalize_separators`
+fn is_dir(file: &ZipFile, normalize_separators: bool) -> bool {
+    if normalize_separators {
+        // Also counts the names ending with a backslash
+        file.is_dir()
+    } else {
+        file.name().ends_with('/')
+    }
+}
+
+/// Calls `read_file` with the path and the contents of every file of `archive`, leaving out the
+/// directories, the symlinks and the entries whose paths would be outside of it
+pub fn read_files<R>(
+    mut archive: ZipArchive<R>,
+    mut read_file: impl FnMut(PathBuf, &mut dyn Read) -> crate::Result<()>,
+) -> crate::Result<()>
+where
+    R: Read + Seek,
+{
+    for idx in 0..archive.len() {
+        let mut file = open_entry(&mut archive, idx, None)?;
+        #[cfg(unix)]
+        if is_symlink(&file) {
+            continue;
+        }
+        if is_dir(&file, false) {
+            continue;
+        }
+        if let Some(path) = entry_path(&file, false) {
+            read_file(path, &mut file)?;
+        }
+    }
+    Ok(())
+}
+
 /// List contents of `archive`, returning a vector of archive entries
 pub fn list_archive<R>(mut archive: ZipArchive<R>) -> impl Iterator<Item = crate::Result<FileInArchive>>
 where
@@ -111,15 +494,28 @@ where
     thread::spawn(move || {
         for idx in 0..archive.len() {
             let maybe_file_in_archive = (|| {
-                let file = match archive.by_index(idx) {
+                // Raw, since listing doesn't need to decompress, whatever the method is
+                let file = match archive.by_index_raw(idx) {
                     Ok(f) => f,
                     Err(e) => return Some(Err(e.into())),
                 };
 
-                let path = file.enclosed_name()?.to_owned();
-                let is_dir = file.is_dir();
-
-                Some(Ok(FileInArchive { path, is_dir }))
+                // Listed as they are unpacked by default, without the trailing slash of the
+                // directories, which is added back when they are printed
+                let path = entry_path(&file, true)?.components().collect();
+                let is_dir = is_dir(&file, true);
+                let size = file.size();
+                let modified = file.last_modified().to_time().ok().map(|time| time.unix_timestamp());
+
+                Some(Ok(FileInArchive {
+                    path,
+                    is_dir,
+                    size,
+                    modified,
+                    is_symlink: is_symlink(&file),
+                    compressed_size: Some(file.compressed_size()),
+                    mode: file.unix_mode(),
+                }))
             })();
             if let Some(file_in_archive) = maybe_file_in_archive {
                 tx.send(file_in_archive).unwrap();
@@ -131,15 +527,33 @@ where
 }
 
 /// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
-pub fn build_archive_from_paths<W>(
+///
+/// With `existing_archive`, used by `--update`, its entries are copied over as they are, unless
+/// the file on disk is newer.
+/// With `atime_preserve`, from `--atime-preserve`, the access times of the files are set back after
+/// reading them.
+/// With `extension_levels`, each file is compressed with the level of its extension, or stored.
+/// With `time_rounding`, from `--time-rounding`, the modification times of files are rounded down
+/// to a multiple of it.
+/// With `metadata`, from `--metadata`, it is stored as the comment of the archive.
+#[allow(clippy::too_many_arguments)]
+pub fn build_archive_from_paths<W, R>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     quiet: bool,
+    mut existing_archive: Option<ZipArchive<R>>,
+    mut junk_paths: Option<JunkPaths>,
+    no_dir_entries: bool,
+    atime_preserve: bool,
+    extension_levels: Option<&ExtensionLevels>,
+    time_rounding: Option<u64>,
+    metadata: Option<&Provenance>,
 ) -> crate::Result<W>
 where
     W: Write + Seek,
+    R: Read + Seek,
 {
     let mut writer = zip::ZipWriter::new(writer);
     // always use ZIP64 to allow compression of files larger than 4GB
@@ -150,49 +564,34 @@ where
     #[cfg(not(unix))]
     let executable = options.unix_permissions(0o755);
 
-    // Vec of any filename that failed the UTF-8 check
-    let invalid_unicode_filenames = get_invalid_utf8_paths(input_filenames);
-
-    if !invalid_unicode_filenames.is_empty() {
-        let error = FinalError::with_title("Cannot build zip archive")
-            .detail("Zip archives require files to have valid UTF-8 paths")
-            .detail(format!(
-                "Files with invalid paths: {}",
-                pretty_format_list_of_paths(&invalid_unicode_filenames)
-            ));
-
-        return Err(error.into());
-    }
+    let mut truncated_subtrees = 0;
+    let mut compression_report = utils::CompressionReport::new(quiet);
+    let mut update_counts = UpdateCounts::default();
+    // Names written while walking the inputs, the other entries of `existing_archive` are kept
+    let mut written_names = HashSet::new();
 
     for filename in input_filenames {
-        let previous_location = cd_into_same_dir_as(filename)?;
-
-        // Safe unwrap, input shall be treated before
-        let filename = filename.file_name().unwrap();
+        let (previous_location, filename) = cd_into_walk_root(filename, &file_visibility_policy)?;
 
         for entry in file_visibility_policy.build_walker(filename) {
             let entry = entry?;
             let path = entry.path();
 
+            if file_visibility_policy.is_truncated_by_max_depth(&entry) {
+                truncated_subtrees += 1;
+            }
+
             // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
             if let Ok(ref handle) = output_handle {
                 if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning!(
+                    utils::warn_or_fail(format!(
                         "The output file and the input file are the same: `{}`, skipping...",
                         output_path.display()
-                    );
+                    ))?;
                     continue;
                 }
             }
 
-            // This is printed for every file in `input_filenames` and has
-            // little importance for most users, but would generate lots of
-            // spoken text for users using screen readers, braille displays
-            // and so on
-            if !quiet {
-                info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
-            }
-
             let metadata = match path.metadata() {
                 Ok(metadata) => metadata,
                 Err(e) => {
@@ -205,11 +604,57 @@ where
                 }
             };
 
+            // Stored as a link, unless it is being followed with --dereference
+            let is_dir_symlink = metadata.is_dir() && utils::is_symlink(path) && !file_visibility_policy.follow_links;
+
+            // Only the files are stored with --junk-paths and --no-dir-entries
+            if metadata.is_dir() && !is_dir_symlink && (junk_paths.is_some() || no_dir_entries) {
+                continue;
+            }
+
+            let stored_path = archive::stored_path(&mut junk_paths, path)?;
+            let stored_name = &*utils::to_utf8_lossy(&stored_path)?;
+
+            if let Some(existing_archive) = existing_archive.as_mut() {
+                let mut name = stored_name.to_owned();
+                if metadata.is_dir() && !is_dir_symlink {
+                    name.push('/');
+                }
+
+                match existing_archive.by_name(&name) {
+                    // Symlinks always get rewritten, copying an entry as it is loses its file type
+                    Ok(old_entry) if !is_dir_symlink && !is_newer_than(&metadata, &old_entry, time_rounding) => {
+                        if !quiet {
+                            info!(
+                                inaccessible,
+                                "Keeping '{}', it is up to date.",
+                                EscapedPathDisplay::new(path)
+                            );
+                        }
+                        writer.raw_copy_file(old_entry)?;
+                        written_names.insert(name);
+                        update_counts.kept += 1;
+                        continue;
+                    }
+                    Ok(_) => update_counts.updated += 1,
+                    Err(ZipError::FileNotFound) => update_counts.added += 1,
+                    Err(err) => return Err(err.into()),
+                }
+                written_names.insert(name);
+            }
+
+            compression_report.start(path);
+            let bytes = if metadata.is_dir() { 0 } else { metadata.len() };
+            utils::emit_progress(ProgressEvent::Entry { path, bytes });
+
             #[cfg(unix)]
             let options = options.unix_permissions(metadata.permissions().mode());
 
-            if metadata.is_dir() {
-                writer.add_directory(path.to_str().unwrap().to_owned(), options)?;
+            if is_dir_symlink {
+                let target = fs::read_link(path)?;
+                writer.add_symlink(stored_name, target.to_string_lossy(), options)?;
+            } else if metadata.is_dir() {
+                writer.add_directory(stored_name, options)?;
             } else {
                 #[cfg(not(unix))]
                 let options = if is_executable::is_executable(path) {
@@ -218,22 +663,108 @@ where
                     options
                 };
 
+                let options = match extension_levels.and_then(|levels| levels.level_for(path)) {
+                    Some(STORE_LEVEL) => options.compression_method(zip::CompressionMethod::Stored),
+                    Some(level) => options.compression_level(Some(level.into())),
+                    None => options,
+                };
+
+                let accessed = atime_preserve.then(|| utils::AccessTime::save(path, &metadata));
                 let mut file = fs::File::open(path)?;
                 writer.start_file(
-                    path.to_str().unwrap(),
-                    options.last_modified_time(get_last_modified_time(&file)),
+                    stored_name,
+                    options.last_modified_time(get_last_modified_time(&metadata, time_rounding)),
                 )?;
                 io::copy(&mut file, &mut writer)?;
+                if let Some(accessed) = accessed {
+                    accessed.restore()?;
+                }
             }
         }
 
         env::set_current_dir(previous_location)?;
     }
 
+    if let Some(mut existing_archive) = existing_archive {
+        for idx in 0..existing_archive.len() {
+            let old_entry = existing_archive.by_index_raw(idx)?;
+            if !written_names.contains(old_entry.name()) {
+                writer.raw_copy_file(old_entry)?;
+                update_counts.kept += 1;
+            }
+        }
+
+        info!(
+            accessible,
+            "{} entries updated, {} added and {} kept unchanged.",
+            update_counts.updated,
+            update_counts.added,
+            update_counts.kept
+        );
+    }
+
+    if truncated_subtrees > 0 {
+        info!(
+            accessible,
+            "{} subtrees deeper than --max-depth were stored without their contents.", truncated_subtrees
+        );
+    }
+
+    if let Some(metadata) = metadata {
+        writer.set_comment(metadata.to_zip_comment());
+    }
+
     let bytes = writer.finish()?;
     Ok(bytes)
 }
 
+/// Prints the original and stored sizes of the files in `archive`, used by `-vv`
+pub fn print_entry_sizes<R>(mut archive: ZipArchive<R>) -> crate::Result<()>
+where
+    R: Read + Seek,
+{
+    for idx in 0..archive.len() {
+        // Raw, so encrypted entries don't need their password
+        let file = archive.by_index_raw(idx)?;
+        if !file.is_dir() {
+            utils::print_entry_size(Path::new(file.name()), file.size(), file.compressed_size());
+        }
+    }
+
+    Ok(())
+}
+
+/// What happened to the entries of the existing archive with `--update`
+#[derive(Default)]
+struct UpdateCounts {
+    updated: usize,
+    added: usize,
+    kept: usize,
+}
+
+/// Whether the file on disk was modified after the stored entry.
+///
+/// Directories are never considered newer, their entries store when the archive was created
+/// instead of their own modification time.
+fn is_newer_than(metadata: &std::fs::Metadata, entry: &ZipFile, time_rounding: Option<u64>) -> bool {
+    if metadata.is_dir() {
+        return false;
+    }
+
+    let as_tuple = |time: DateTime| {
+        (
+            time.year(),
+            time.month(),
+            time.day(),
+            time.hour(),
+            time.minute(),
+            time.second(),
+        )
+    };
+
+    as_tuple(get_last_modified_time(metadata, time_rounding)) > as_tuple(entry.last_modified())
+}
+
 fn display_zip_comment_if_exists(file: &ZipFile) {
     let comment = file.comment();
     if !comment.is_empty() {
@@ -251,11 +782,19 @@ fn display_zip_comment_if_exists(file: &ZipFile) {
     }
 }
 
-fn get_last_modified_time(file: &fs::File) -> DateTime {
-    file.metadata()
-        .and_then(|metadata| metadata.modified())
+/// The modification time of a file, rounded down to a multiple of `time_rounding` seconds
+fn get_last_modified_time(metadata: &std::fs::Metadata, time_rounding: Option<u64>) -> DateTime {
+    metadata
+        .modified()
         .ok()
-        .and_then(|time| DateTime::try_from(OffsetDateTime::from(time)).ok())
+        .map(OffsetDateTime::from)
+        .and_then(|time| match time_rounding {
+            Some(granularity) => {
+                OffsetDateTime::from_unix_timestamp(utils::round_time(time.unix_timestamp(), granularity)).ok()
+            }
+            None => Some(time),
+        })
+        .and_then(|time| DateTime::try_from(time).ok())
         .unwrap_or_default()
 }
 
@@ -273,14 +812,3 @@ fn set_last_modified_time(zip_file: &ZipFile, path: &Path) -> crate::Result<()>
 
     Ok(())
 }
-
-#[cfg(unix)]
-fn unix_set_permissions(file_path: &Path, file: &ZipFile) -> crate::Result<()> {
-    use std::fs::Permissions;
-
-    if let Some(mode) = file.unix_mode() {
-        fs::set_permissions(file_path, Permissions::from_mode(mode))?;
-    }
-
-    Ok(())
-}
@@ -0,0 +1,174 @@
+//! bzip3 (.bz3) support, done by running the `bzip3` program, which must be allowed with
+//! `--use-command`.
+//!
+//! The bzip3 crates only bind the C library, which needs libclang to build and is LGPL licensed,
+//! so the program is run instead, with the data piped through it.
+//!
+//! Limitations:
+//! - The compression level is ignored, bzip3 only has a block size.
+//! - The compressed data is written to a temporary file before being decompressed.
+
+use std::{
+    io::{self, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    error::{Error, FinalError},
+    utils, warning,
+};
+
+/// The program of bzip3 that compresses and decompresses
+const BZIP3_COMMAND: &str = "bzip3";
+
+/// Reads the decompressed contents of the .bz3 stream read from the inner reader
+pub struct Bzip3Decoder {
+    child: Child,
+    stdout: ChildStdout,
+    /// The compressed data, read by `bzip3`, removed once it's done
+    _input: tempfile::NamedTempFile,
+    finished: bool,
+}
+
+impl Bzip3Decoder {
+    /// Starts decompressing what `reader` gives, which is first written to a temporary file, that
+    /// `bzip3` reads by itself
+    pub fn new(mut reader: impl Read) -> crate::Result<Self> {
+        let input = tempfile::NamedTempFile::new()?;
+        io::copy(&mut reader, &mut input.as_file())?;
+
+        let mut child = spawn(["-d", "-c"], Stdio::from(input.reopen()?), Stdio::piped())?;
+        // Safe unwrap, stdout is piped
+        let stdout = child.stdout.take().unwrap();
+
+        Ok(Self {
+            child,
+            stdout,
+            _input: input,
+            finished: false,
+        })
+    }
+}
+
+impl Read for Bzip3Decoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.stdout.read(buf)?;
+        if bytes_read == 0 && !buf.is_empty() && !self.finished {
+            self.finished = true;
+            check_exit(&mut self.child)?;
+        }
+        Ok(bytes_read)
+    }
+}
+
+/// Compresses what is written to it, writing the .bz3 stream to the inner writer, finished when
+/// dropped
+pub struct Bzip3Encoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// Copies the output of `bzip3` to the inner writer
+    copier: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Bzip3Encoder {
+    pub fn new(mut writer: impl Write + Send + 'static) -> crate::Result<Self> {
+        let mut child = spawn(["-e", "-c"], Stdio::piped(), Stdio::piped())?;
+        // Safe unwraps, stdin and stdout are piped
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+
+        let copier = thread::spawn(move || {
+            io::copy(&mut stdout, &mut writer)?;
+            writer.flush()
+        });
+
+        Ok(Self {
+            child,
+            stdin: Some(stdin),
+            copier: Some(copier),
+        })
+    }
+
+    /// Closes the input of `bzip3`, waiting for it to write the rest of the output
+    fn finish(&mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        if let Some(copier) = self.copier.take() {
+            copier
+                .join()
+                .map_err(|_| io::Error::other("the output of bzip3 could not be copied"))??;
+        }
+        check_exit(&mut self.child)
+    }
+
+    fn stdin(&mut self) -> io::Result<&mut ChildStdin> {
+        self.stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::other("bzip3 was already finished"))
+    }
+}
+
+impl Write for Bzip3Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin()?.flush()
+    }
+}
+
+impl Drop for Bzip3Encoder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warning!("Could not finish compressing with {BZIP3_COMMAND}: {err}");
+        }
+    }
+}
+
+/// Starts `bzip3` with `args`, if allowed
+fn spawn<const N: usize>(args: [&str; N], stdin: Stdio, stdout: Stdio) -> crate::Result<Child> {
+    if !utils::use_command_allowed() {
+        let error = FinalError::with_title("Cannot handle .bz3 files")
+            .detail(format!("Ouch handles .bz3 files by running {BZIP3_COMMAND}"))
+            .hint("Allow running it with --use-command");
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    let child = Command::new(BZIP3_COMMAND)
+        .args(args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn();
+
+    match child {
+        Ok(child) => Ok(child),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let error = FinalError::with_title(format!("Cannot run {BZIP3_COMMAND}"))
+                .detail("It was not found")
+                .hint(
+                    "Install bzip3, from https://github.com/kspalaiologos/bzip3 or the package manager of your system",
+                );
+            Err(Error::Custom { reason: error })
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Waits for `bzip3` to exit, failing with what it printed if it didn't succeed
+fn check_exit(child: &mut Child) -> io::Result<()> {
+    let status = child.wait()?;
+    if status.success() {
+        return Ok(());
+    }
+
+    let mut stderr = String::new();
+    if let Some(mut child_stderr) = child.stderr.take() {
+        child_stderr.read_to_string(&mut stderr)?;
+    }
+    Err(io::Error::other(format!(
+        "{BZIP3_COMMAND} exited with {status}: {}",
+        stderr.trim()
+    )))
+}
@@ -8,11 +8,22 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use fs_err as fs;
+
+use clap::ValueEnum;
+
 use crate::{
-    error::FinalError,
-    extension::{build_archive_file_suggestion, Extension, PRETTY_SUPPORTED_ALIASES, PRETTY_SUPPORTED_EXTENSIONS},
+    cli::{ExtractionOrder, ForcedFormat},
+    error::{Error, FinalError},
+    extension::{
+        build_archive_file_suggestion, extensions_from_path, flatten_compression_formats, parse_format,
+        CompressionFormat, Extension, PRETTY_SUPPORTED_ALIASES, PRETTY_SUPPORTED_EXTENSIONS, SUPPORTED_EXTENSIONS,
+    },
     info,
-    utils::{pretty_format_list_of_paths, try_infer_extension, user_wants_to_continue, EscapedPathDisplay},
+    utils::{
+        self, can_ask_user, pretty_format_list_of_paths, try_infer_extension, user_chooses_one, user_wants_to_continue,
+        user_wants_to_overwrite_newer, EscapedPathDisplay, Volumes,
+    },
     warning, QuestionAction, QuestionPolicy, Result,
 };
 
@@ -20,6 +31,9 @@ use crate::{
 ///
 /// In case the file doesn't has any extensions, try to infer the format.
 ///
+/// When the format is ambiguous, the user is asked to choose it, if possible. With `--yes`, the
+/// format detected from the contents is preferred over the extension, `--no` aborts instead.
+///
 /// TODO: maybe the name of this should be "magic numbers" or "file signature",
 /// and not MIME.
 pub fn check_mime_type(
@@ -44,6 +58,16 @@ pub fn check_mime_type(
             } else {
                 return Ok(ControlFlow::Break(()));
             }
+        } else if can_ask_user(question_policy) {
+            // Otherwise an error about the missing format is given later
+            let options = all_formats();
+            let labels: Vec<_> = options.iter().map(ToString::to_string).collect();
+            let prompt = "Could not detect the format of 'FILE', which one is it?";
+
+            match user_chooses_one(path, prompt, &labels)? {
+                Some(chosen) => formats.push(options[chosen].clone()),
+                None => return Ok(ControlFlow::Break(())),
+            }
         }
     } else if let Some(detected_format) = try_infer_extension(path) {
         // File ending with extension
@@ -59,8 +83,27 @@ pub fn check_mime_type(
                 outer_ext,
                 detected_format
             );
-            if !user_wants_to_continue(path, question_policy, QuestionAction::Decompression)? {
+
+            let use_detected_format = if question_policy == QuestionPolicy::AlwaysYes {
+                // The contents are more trustworthy than the name
+                true
+            } else if can_ask_user(question_policy) {
+                let labels = [
+                    format!("{outer_ext} (from the file extension)"),
+                    format!("{detected_format} (detected from the contents)"),
+                ];
+                match user_chooses_one(path, "Which format is 'FILE'?", &labels)? {
+                    Some(chosen) => chosen == 1,
+                    None => return Ok(ControlFlow::Break(())),
+                }
+            } else if user_wants_to_continue(path, question_policy, QuestionAction::Decompression)? {
+                false
+            } else {
                 return Ok(ControlFlow::Break(()));
+            };
+
+            if use_detected_format {
+                replace_outer_format(formats, detected_format);
             }
         }
     } else {
@@ -75,6 +118,35 @@ pub fn check_mime_type(
     Ok(ControlFlow::Continue(()))
 }
 
+/// Every supported format, without aliases or repeated extensions for the same format
+fn all_formats() -> Vec<Extension> {
+    let mut formats: Vec<Extension> = vec![];
+
+    for extension in SUPPORTED_EXTENSIONS {
+        let extension = parse_format(extension.as_ref()).expect("supported extensions are valid formats");
+        if !formats.contains(&extension[0]) {
+            formats.extend(extension);
+        }
+    }
+
+    formats
+}
+
+/// Replaces the outermost format in `formats` with `detected_format`, keeping the formats inside
+/// of it, so `tgz` detected as `zst` becomes `tar` and `zst`
+pub fn replace_outer_format(formats: &mut Vec<Extension>, detected_format: Extension) {
+    let outer_ext = formats.pop().expect("formats aren't empty");
+
+    // Aliases like `tgz` are always a tar archive inside of another format
+    if let [inner_formats @ .., _] = outer_ext.compression_formats {
+        if !inner_formats.is_empty() {
+            formats.push(Extension::new(inner_formats, "tar"));
+        }
+    }
+
+    formats.push(detected_format);
+}
+
 /// In the context of listing archives, this function checks if `ouch` was told to list
 /// the contents of a compressed file that is not an archive
 pub fn check_for_non_archive_formats(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
@@ -94,7 +166,7 @@ pub fn check_for_non_archive_formats(files: &[PathBuf], formats: &[Vec<Extension
                 pretty_format_list_of_paths(&not_archives)
             ));
 
-        return Err(error.into());
+        return Err(Error::InvalidUsage { reason: error });
     }
 
     Ok(())
@@ -120,7 +192,84 @@ pub fn check_archive_formats_position(formats: &[Extension], output_path: &Path)
             EscapedPathDisplay::new(output_path)
         ));
 
-        return Err(error.into());
+        return Err(Error::InvalidUsage { reason: error });
+    }
+    Ok(())
+}
+
+/// Show error if one of the formats can only be decompressed.
+pub fn check_decompression_only_formats(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let decompression_only = formats.iter().find(|format| {
+        format
+            .compression_formats
+            .iter()
+            .any(|format| format.is_decompression_only())
+    });
+
+    if let Some(format) = decompression_only {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail(format!("Ouch can only decompress the format '{format}'."))
+        .hint("Use a different format, like '.gz' or '.zst'.");
+
+        return Err(Error::InvalidUsage { reason: error });
+    }
+    Ok(())
+}
+
+/// Check that `--update` is only used to create plain `.zip` archives
+pub fn check_update_format(formats: &[Extension], output_path: &Path) -> Result<()> {
+    if let [format] = formats {
+        if format.compression_formats == [CompressionFormat::Zip] {
+            return Ok(());
+        }
+    }
+
+    let error = FinalError::with_title(format!("Cannot update '{}'.", EscapedPathDisplay::new(output_path)))
+        .detail("--update only works with .zip archives")
+        .hint("Remove --update to overwrite the output file instead.");
+
+    Err(Error::InvalidUsage { reason: error })
+}
+
+/// Directories where unpacking files could break the system, besides the root directory.
+#[cfg(unix)]
+const SENSITIVE_DIRECTORIES: &[&str] = &[
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/proc",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/usr/bin",
+    "/usr/lib",
+    "/usr/sbin",
+];
+#[cfg(not(unix))]
+const SENSITIVE_DIRECTORIES: &[&str] = &[];
+
+/// Show error if `output_dir` is the root directory or a system directory, similar to
+/// `rm --preserve-root`, can be skipped with `--force`.
+pub fn check_output_dir_is_not_sensitive(output_dir: &Path) -> Result<()> {
+    let output_dir = fs::canonicalize(output_dir)?;
+
+    let is_root = output_dir.parent().is_none();
+    if is_root || SENSITIVE_DIRECTORIES.iter().any(|dir| output_dir == Path::new(dir)) {
+        let error = FinalError::with_title(format!(
+            "Refusing to decompress into '{}'.",
+            EscapedPathDisplay::new(&output_dir)
+        ))
+        .detail("It is a system directory, overwriting files in it could break the system.")
+        .hint("Use --force if you are sure you want to decompress there.");
+
+        return Err(Error::InvalidUsage { reason: error });
     }
     Ok(())
 }
@@ -175,22 +324,281 @@ pub fn check_missing_formats_when_decompressing(files: &[PathBuf], formats: &[Ve
             ));
     }
 
-    Err(error.into())
+    Err(Error::InvalidUsage { reason: error })
+}
+
+/// Check if the archives can be unpacked in the `order` given by `--order`, which only zip
+/// archives, read from their central directory, can be in something other than archive order.
+pub fn check_extraction_order(files: &[PathBuf], formats: &[Vec<Extension>], order: ExtractionOrder) -> Result<()> {
+    if order == ExtractionOrder::Archive {
+        return Ok(());
+    }
+
+    for (path, formats) in files.iter().zip(formats) {
+        let reason = match formats.first().map(|extension| extension.compression_formats[0]) {
+            Some(CompressionFormat::Tar) => "Tar archives are read as a stream, one entry after the other",
+            Some(CompressionFormat::SevenZip) => {
+                "The entries of 7z archives are compressed together, and can only be read one after the other"
+            }
+            _ => continue,
+        };
+
+        let order = order.to_possible_value().expect("no value is skipped");
+        let error = FinalError::with_title(format!(
+            "Cannot unpack '{}' in {} order",
+            EscapedPathDisplay::new(path),
+            order.get_name()
+        ))
+        .detail(reason)
+        .hint("Only zip archives can be unpacked in another order, use '--order archive' for the others");
+
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    Ok(())
+}
+
+/// Check if the files can be unpacked with `--subtree`, which only archives have.
+pub fn check_subtree(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
+    let mut not_archives = files
+        .iter()
+        .zip(formats)
+        .filter(|(_, formats)| !formats.first().map(Extension::is_archive).unwrap_or(false))
+        .map(|(path, _)| path)
+        .peekable();
+
+    if not_archives.peek().is_some() {
+        let not_archives: Vec<_> = not_archives.collect();
+        let error = FinalError::with_title("Cannot use --subtree")
+            .detail("Only archives have directories to unpack")
+            .detail(format!(
+                "Files are not archives: {}",
+                pretty_format_list_of_paths(&not_archives)
+            ));
+
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    Ok(())
+}
+
+/// Check if the entries of the files can be picked with `--interactive`, which needs a terminal
+/// and archives whose entries can be listed before unpacking them.
+pub fn check_interactive(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
+    if !atty::is(atty::Stream::Stdin) {
+        let error = FinalError::with_title("Cannot use --interactive")
+            .detail("The entries to unpack are asked in a terminal, but stdin is not one")
+            .hint("Use --index, --subtree or --exclude to choose the entries in scripts");
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    let mut unsupported = files
+        .iter()
+        .zip(formats)
+        .filter(|(path, formats)| {
+            let archive_format = formats
+                .first()
+                .filter(|extension| extension.is_archive())
+                .and_then(|extension| extension.compression_formats.first());
+            utils::is_url(path) || matches!(archive_format, None | Some(CompressionFormat::Wim))
+        })
+        .map(|(path, _)| path)
+        .peekable();
+
+    if unsupported.peek().is_some() {
+        let unsupported: Vec<_> = unsupported.collect();
+        let error = FinalError::with_title("Cannot use --interactive")
+            .detail("Only the entries of archive files can be picked, not of wim images or downloads")
+            .detail(format!(
+                "Files that cannot be picked from: {}",
+                pretty_format_list_of_paths(&unsupported)
+            ));
+
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    Ok(())
+}
+
+/// Check if the files can be unpacked with `--index`, which only numbers the entries of zip and 7z
+/// archives.
+pub fn check_index(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
+    let mut unsupported = files
+        .iter()
+        .zip(formats)
+        .filter(|(_, formats)| {
+            let archive_format = formats
+                .first()
+                .and_then(|extension| extension.compression_formats.first());
+            !matches!(
+                archive_format,
+                Some(CompressionFormat::Zip | CompressionFormat::SevenZip)
+            )
+        })
+        .map(|(path, _)| path)
+        .peekable();
+
+    if unsupported.peek().is_some() {
+        let unsupported: Vec<_> = unsupported.collect();
+        let error = FinalError::with_title("Cannot use --index")
+            .detail("Only the entries of zip and 7z archives can be unpacked by index")
+            .detail(format!(
+                "Files are not zip or 7z archives: {}",
+                pretty_format_list_of_paths(&unsupported)
+            ))
+            .hint("Use --subtree or --exclude to unpack some of the entries of other archives");
+
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    Ok(())
+}
+
+/// Check if the URLs among `files` can be decompressed while they are downloaded.
+///
+/// Plain zip archives are read from the end, which a download can't do.
+pub fn check_url_formats(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
+    let zip_url = files.iter().zip(formats).find(|(path, formats)| {
+        utils::is_url(path)
+            && matches!(
+                flatten_compression_formats(formats).as_slice(),
+                [CompressionFormat::Zip]
+            )
+    });
+
+    if let Some((url, _)) = zip_url {
+        let error = FinalError::with_title("Cannot decompress zip archives from URLs")
+            .detail(format!("'{}' is a zip archive", url.display()))
+            .detail("Zip archives are read from their end, which requires the whole file")
+            .hint("Download the archive first, then decompress it:")
+            .hint(format!("  curl -LO '{}'", url.display()));
+
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    Ok(())
+}
+
+/// Check that the split 7z archives among `files`, named like "archive.7z.001", are given by their
+/// first volume and that the volumes after it are all there, before anything is unpacked.
+pub fn check_split_volumes(files: &[PathBuf]) -> Result<()> {
+    for path in files
+        .iter()
+        .filter(|path| !utils::is_url(path) && Volumes::is_volume(path))
+    {
+        Volumes::open(path)?;
+    }
+
+    Ok(())
+}
+
+/// Check if the existing output of the compression is older than one of the inputs, with
+/// `--no-overwrite-newer`, so a re-run doesn't replace a good archive with a stale one.
+///
+/// The user is asked before overwriting a newer output when possible, otherwise it's refused.
+pub fn check_output_not_newer(
+    output_path: &Path,
+    files: &[PathBuf],
+    question_policy: QuestionPolicy,
+) -> Result<ControlFlow<()>> {
+    let output_time = fs::metadata(output_path)?.modified()?;
+    let mut newest_input = None;
+    for file in files {
+        newest_modification(file, &mut newest_input)?;
+    }
+
+    if newest_input.is_none_or(|newest_input| newest_input >= output_time) {
+        return Ok(ControlFlow::Continue(()));
+    }
+
+    if can_ask_user(question_policy) {
+        return Ok(if user_wants_to_overwrite_newer(output_path)? {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(())
+        });
+    }
+
+    let error = FinalError::with_title(format!(
+        "Refusing to overwrite '{}'",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail("It is newer than all of the files to compress, it may be a better archive than this one")
+    .hint("Use --force to overwrite it anyway");
+    Err(Error::Custom { reason: error })
+}
+
+/// Updates `newest` with the modification time of `path`, and of the files inside of it if it's a
+/// directory, without following symlinks
+fn newest_modification(path: &Path, newest: &mut Option<std::time::SystemTime>) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let modified = metadata.modified()?;
+    if newest.is_none_or(|newest| modified > newest) {
+        *newest = Some(modified);
+    }
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            newest_modification(&entry?.path(), newest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check if the formats given by `--format` agree with the extensions of the output, returning
+/// which of them should be written.
+///
+/// When they disagree, the one forced by `--force-format-on-output` is written, without it this
+/// fails, so "out.zip" isn't written as a gzip stream by mistake.
+pub fn check_format_matches_output(
+    flag_formats: &[Extension],
+    output_path: &Path,
+    forced: Option<ForcedFormat>,
+) -> Result<ForcedFormat> {
+    let output_formats = extensions_from_path(output_path);
+    if output_formats.is_empty()
+        || flatten_compression_formats(&output_formats) == flatten_compression_formats(flag_formats)
+    {
+        return Ok(ForcedFormat::Format);
+    }
+    if let Some(forced) = forced {
+        return Ok(forced);
+    }
+
+    let join = |formats: &[Extension]| formats.iter().map(ToString::to_string).collect::<Vec<_>>().join(".");
+    let (flag_formats, output_formats) = (join(flag_formats), join(&output_formats));
+    let error = FinalError::with_title(format!(
+        "Cannot compress to '{}' with --format {flag_formats}",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail(format!(
+        "Its extension is {output_formats}, which disagrees with --format {flag_formats}"
+    ))
+    .hint(format!(
+        "Use --force-format-on-output=format to write {flag_formats} anyway"
+    ))
+    .hint(format!(
+        "Use --force-format-on-output=extension to write {output_formats} instead"
+    ))
+    .hint("Or give the output the extension of the format");
+
+    Err(Error::InvalidUsage { reason: error })
 }
 
 /// Check if there is a first format when compressing, and returns it.
 pub fn check_first_format_when_compressing<'a>(formats: &'a [Extension], output_path: &Path) -> Result<&'a Extension> {
     formats.first().ok_or_else(|| {
         let output_path = EscapedPathDisplay::new(output_path);
-        FinalError::with_title(format!("Cannot compress to '{output_path}'."))
+        let error = FinalError::with_title(format!("Cannot compress to '{output_path}'."))
             .detail("You shall supply the compression format")
             .hint("Try adding supported extensions (see --help):")
             .hint(format!("  ouch compress <FILES>... {output_path}.tar.gz"))
             .hint(format!("  ouch compress <FILES>... {output_path}.zip"))
             .hint("")
             .hint("Alternatively, you can overwrite this option by using the '--format' flag:")
-            .hint(format!("  ouch compress <FILES>... {output_path} --format tar.gz"))
-            .into()
+            .hint(format!("  ouch compress <FILES>... {output_path} --format tar.gz"));
+
+        Error::InvalidUsage { reason: error }
     })
 }
 
@@ -251,5 +659,5 @@ pub fn check_invalid_compression_with_non_archive_format(
         .hint(from_hint)
         .hint(to_hint);
 
-    Err(error.into())
+    Err(Error::InvalidUsage { reason: error })
 }
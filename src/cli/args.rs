@@ -1,11 +1,11 @@
 use std::{ffi::OsString, path::PathBuf};
 
-use clap::{Parser, ValueHint};
+use clap::{ArgAction, ColorChoice, Parser, ValueHint};
 
 // Ouch command line options (docstrings below are part of --help)
 /// A command-line utility for easily compressing and decompressing files and directories.
 ///
-/// Supported formats: tar, zip, gz, xz/lzma, bz/bz2, lz4, sz, zst.
+/// Supported formats: tar, zip, 7z, gz, xz/lzma, bz/bz2/bz3, lz4, sz, zst, Z, wim, iso.
 ///
 /// Repository: https://github.com/ouch-org/ouch
 #[derive(Parser, Debug, PartialEq)]
@@ -33,10 +33,72 @@ pub struct CliArgs {
     #[arg(short = 'q', long, global = true)]
     pub quiet: bool,
 
+    /// Prints more output, -vv shows the original size, stored size and compression ratio of
+    /// every file when compressing
+    ///
+    /// The stored sizes of files in tar archives compressed further are approximate, encoders
+    /// hold on to their output before writing it.
+    #[arg(short = 'v', long, action = ArgAction::Count, conflicts_with = "quiet", global = true)]
+    pub verbose: u8,
+
+    /// Print nothing on success, and errors as tab-separated lines, for scripts
+    ///
+    /// Every error is printed as an "error<TAB>TITLE" line, followed by "detail<TAB>TEXT" and
+    /// "hint<TAB>TEXT" lines, without colors. Warnings are silenced like with --quiet-errors.
+    #[arg(long, conflicts_with = "verbose", global = true)]
+    pub porcelain: bool,
+
+    /// Silences warnings, errors are still printed
+    #[arg(long, conflicts_with = "strict", global = true)]
+    pub quiet_errors: bool,
+
+    /// Fail instead of warning about skipped files and risky changes, like unsafe permissions
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Write newline-delimited JSON progress events to the open file descriptor N, for frontends
+    ///
+    /// {"type":"entry","path":...,"bytes":...} is written for every file compressed or unpacked,
+    /// with its uncompressed size, and {"type":"done","path":...} for every finished archive.
+    /// Only supported on Unix.
+    #[arg(long, value_name = "N", global = true)]
+    pub progress_fd: Option<i32>,
+
+    /// Also write the info messages, warnings and errors to the end of FILE, with the time they
+    /// were printed at and without colors
+    #[arg(long, global = true, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub log_file: Option<PathBuf>,
+
+    /// Allow running external programs for the formats ouch can't handle itself, like
+    /// wimlib-imagex for .wim images and bzip3 for .bz3 files
+    #[arg(long, global = true)]
+    pub use_command: bool,
+
+    /// When to color the output, `auto` only does it when printing to a terminal
+    #[arg(long, global = true, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
     /// Ignores files matched by git's ignore files
     #[arg(short = 'g', long, global = true)]
     pub gitignore: bool,
 
+    /// Ignores files matching the glob pattern, can be used multiple times
+    #[arg(long, global = true, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Ignores files matching the glob patterns listed in FILE, can be used multiple times
+    ///
+    /// FILE has one pattern per line, blank lines and lines starting with '#' are skipped
+    #[arg(long, global = true, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// Round the modification times stored and restored down to a multiple of SECONDS
+    ///
+    /// Like 2 for FAT file systems, so archives round-trip the same way across file systems with
+    /// different time resolutions.
+    #[arg(long, global = true, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub time_rounding: Option<u64>,
+
     /// Specify the format of the archive
     #[arg(short, long, global = true)]
     pub format: Option<OsString>,
@@ -50,16 +112,43 @@ pub struct CliArgs {
 #[allow(rustdoc::bare_urls)]
 pub enum Subcommand {
     /// Compress one or more files into one output file
-    #[command(visible_alias = "c")]
+    // The files can be missing with --to-stdout, then the output is the only file
+    #[command(visible_alias = "c", allow_missing_positional = true)]
     Compress {
         /// Files to be compressed
-        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        #[arg(required_unless_present = "to_stdout", value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
-        /// The resulting file. Its extensions can be used to specify the compression formats
+        /// The resulting file. Its extensions can be used to specify the compression formats,
+        /// with --to-stdout it is one more file to compress
         #[arg(required = true, value_hint = ValueHint::FilePath)]
         output: PathBuf,
 
+        /// Write the compressed output to stdout instead of a file, its formats are given by --format
+        ///
+        /// Zip archives are built in a temporary file first, they can't be written without seeking.
+        #[arg(
+            long,
+            requires = "format",
+            conflicts_with_all = ["auto_name", "update", "dry_run", "verify", "sidecar", "no_overwrite_newer", "checksum_file"]
+        )]
+        to_stdout: bool,
+
+        /// Create the missing parent directories of the output without asking
+        #[arg(short = 'p', long)]
+        mkdir: bool,
+
+        /// Add the current time to the output file name, before its extensions, formatted with
+        /// FORMAT (like --auto-name='%Y-%m-%d'), %Y-%m-%d-%H%M%S by default
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "%Y-%m-%d-%H%M%S"
+        )]
+        auto_name: Option<String>,
+
         /// Compression level, applied to all formats
         #[arg(short, long, group = "compression-level")]
         level: Option<i16>,
@@ -73,17 +162,393 @@ pub enum Subcommand {
         /// conflicts with --level and --fast
         #[arg(long, group = "compression-level")]
         slow: bool,
+
+        /// Descend at most N directories deep into the inputs,
+        /// deeper directories are stored without their contents
+        #[arg(long, value_name = "N")]
+        max_depth: Option<usize>,
+
+        /// Make zstd output friendlier to rsync and other delta-based tools, at a slightly
+        /// smaller compression ratio
+        ///
+        /// Flush points are periodically inserted in the compressed stream, so a small change in
+        /// the input only changes a small part of the output, instead of everything that follows it.
+        /// Only zstd has this mode.
+        #[arg(long)]
+        rsyncable: bool,
+
+        /// Compress gzip and zstd in chunks cut where the content matches a pattern (FastCDC), so
+        /// storage that deduplicates finds the same chunks even after bytes were inserted before them
+        ///
+        /// Every chunk of about 64 KiB is compressed on its own, as a zstd frame or a gzip member,
+        /// without the repeats from the chunks before it, so the output is larger, by a few percent
+        /// for most inputs and more for highly repetitive ones.
+        #[arg(long, conflicts_with_all = ["rsyncable", "ldm", "threads"])]
+        cdc: bool,
+
+        /// Enable the long distance matching of zstd, which finds the repeats far apart in the
+        /// input, like copies of the same files in large archives
+        ///
+        /// The window of zstd grows to 128 MiB, so it takes more memory to compress, and to
+        /// decompress with other tools. Only zstd has this mode.
+        #[arg(long)]
+        ldm: bool,
+
+        /// Compress zstd with N worker threads, the input is split into jobs compressed in
+        /// parallel, which are only a few MB large at the lowest levels and larger at the highest
+        #[arg(long, value_name = "N")]
+        threads: Option<u32>,
+
+        /// Use at most N threads in total, lowering --threads if it asks for more, or using as many
+        /// as the CPUs available without --threads, without N the number of CPUs available
+        ///
+        /// Only one output is compressed at a time, so its codec gets the whole budget.
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, value_parser = clap::value_parser!(u32).range(1..))]
+        total_threads: Option<Option<u32>>,
+
+        /// Follow symlinks, storing the directories they point to instead of the links
+        #[arg(long)]
+        dereference: bool,
+
+        /// Don't descend into other file systems, like mount points, skipping them
+        ///
+        /// Only supported on Unix.
+        #[arg(long)]
+        one_file_system: bool,
+
+        /// Skip the directories and files of version control systems, like .git, .svn, .hg and
+        /// CVS, the same ones as `tar --exclude-vcs`
+        #[arg(long)]
+        exclude_vcs: bool,
+
+        /// Only add files newer than their entries in the existing output zip, or missing from it
+        #[arg(short, long)]
+        update: bool,
+
+        /// Don't write the output, only list the files that would be compressed
+        #[arg(long)]
+        dry_run: bool,
+
+        /// With --dry-run, list how the contents of the existing output archive would change
+        #[arg(long, requires = "dry_run")]
+        list: bool,
+
+        /// Store the files by their names only, without the directories they are in
+        #[arg(short, long)]
+        junk_paths: bool,
+
+        /// With --junk-paths, add a numeric suffix to repeated names instead of failing
+        #[arg(long, requires = "junk_paths")]
+        rename_collisions: bool,
+
+        /// Don't store entries for the directories, only for the files in them, like `zip -D`, for
+        /// the extractors that can't handle them
+        ///
+        /// The directories are still created from the paths of their files, but the empty ones are
+        /// lost. Only applies to tar, zip and 7z archives.
+        #[arg(long)]
+        no_dir_entries: bool,
+
+        /// Store the paths of the files inside of DIR relative to it, instead of from their own names,
+        /// can be given more than once, the deepest DIR holding a file is used
+        #[arg(long = "base", value_name = "DIR", conflicts_with = "junk_paths", value_hint = ValueHint::DirPath)]
+        base_dirs: Vec<PathBuf>,
+
+        /// Store the entries in the order they have in FILE, like the archive they were
+        /// decompressed from, instead of the order of the file system, so recompressing them
+        /// doesn't reorder them
+        ///
+        /// FILE is an archive, or a list of paths, one per line. The entries that aren't in it are
+        /// stored after the others.
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        preserve_order: Option<PathBuf>,
+
+        /// Compression method of 7z archives, lzma2 by default, its level is set by --level
+        #[arg(long = "7z-method", value_name = "METHOD", value_enum)]
+        sevenz_method: Option<SevenZipMethod>,
+
+        /// When --format and the extensions of the output disagree, write the format given by WHICH
+        /// instead of failing
+        #[arg(long, value_name = "WHICH", value_enum)]
+        force_format_on_output: Option<ForcedFormat>,
+
+        /// Store file flags, like immutable or append-only, in tar archives
+        #[arg(long)]
+        preserve_flags: bool,
+
+        /// Store the creation times of the files in tar archives, for the platforms and file
+        /// systems that keep them
+        #[arg(long)]
+        preserve_btime: bool,
+
+        /// Set the access times of the files back to what they were before reading them, and store
+        /// them in tar archives
+        ///
+        /// Needs the permission to change the metadata of the files, which usually means owning
+        /// them, and changes their status change times (ctime).
+        #[arg(long, visible_alias = "preserve-atime")]
+        atime_preserve: bool,
+
+        /// Store hard-linked files as full copies in tar archives, instead of as links to the first one
+        #[arg(long)]
+        hard_dereference: bool,
+
+        /// Pick the level of each file of zip archives by its extension, storing media and archives
+        /// as they are and compressing text the most, RULES like "txt:6,jpg:0" change the levels
+        #[arg(long, value_name = "RULES", num_args = 0..=1, require_equals = true)]
+        compress_level_by_extension: Option<Option<String>>,
+
+        /// Embed the ouch version, host name and creation time in the output, as the comment of zip
+        /// archives or in a PAX global header of tar archives
+        #[arg(long)]
+        metadata: bool,
+
+        /// With --metadata, also write it to a .meta.json file next to the output, which is the only
+        /// place for it with formats like gz or xz
+        #[arg(long, requires = "metadata")]
+        sidecar: bool,
+
+        /// Write the SHA-256 checksum of the output to FILE, in the format of sha256sum, like the
+        /// SHA256SUMS files of package mirrors, replacing the line of a previous output of the same name
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        checksum_file: Option<PathBuf>,
+
+        /// Read the archive back after compressing it, checking that it decodes and that its files match their sources
+        #[arg(long)]
+        verify: bool,
+
+        /// Refuse to overwrite an existing output that is newer than all of the inputs, asking first
+        /// when possible, so a re-run doesn't replace a good archive with a stale one
+        #[arg(long)]
+        no_overwrite_newer: bool,
+
+        /// Overwrite the output even if it is newer than all of the inputs, with --no-overwrite-newer
+        #[arg(long)]
+        force: bool,
     },
     /// Decompresses one or more files, optionally into another folder
     #[command(visible_alias = "d")]
     Decompress {
-        /// Files to be decompressed
+        /// Files to be decompressed, or HTTP(S) URLs to download them from
         #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
         /// Place results in a directory other than the current one
         #[arg(short = 'd', long = "dir", value_hint = ValueHint::FilePath)]
         output_dir: Option<PathBuf>,
+
+        /// Name the output of each file with TEMPLATE, archives are unpacked into a directory of that
+        /// name, placeholders are {stem}, {format} and {index}, like '{stem}_extracted'
+        #[arg(long, value_name = "TEMPLATE")]
+        output_name_template: Option<String>,
+
+        /// Always unpack archives into a new directory named after them, even with a single file at
+        /// their root
+        #[arg(long, conflicts_with = "flatten")]
+        no_smart_unpack: bool,
+
+        /// Unpack all the archives into the output directory, when decompressing more than one,
+        /// instead of each into a directory named after it, where they can't overwrite each other
+        #[arg(long)]
+        shared_dir: bool,
+
+        /// Unpack every file directly into the output directory, without the directories it was in
+        #[arg(long)]
+        flatten: bool,
+
+        /// Unpack archives in a temporary directory inside of DIR, then move the files to the output
+        /// directory, without DIR the system one is used, like $TMPDIR
+        #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true, value_hint = ValueHint::DirPath)]
+        temp_dir: Option<Option<PathBuf>>,
+
+        /// Only unpack the entries inside of PATH, a directory in the archive, putting them directly
+        /// in the output directory
+        #[arg(long, value_name = "PATH")]
+        subtree: Option<PathBuf>,
+
+        /// Only unpack the entries at these positions of `ouch list --index`, like 3,5,10-12, only
+        /// for zip and 7z archives
+        #[arg(long, value_name = "INDEXES", value_parser = parse_index_ranges)]
+        index: Option<IndexRanges>,
+
+        /// Pick the entries to unpack from a list of the entries of each archive, moving with the
+        /// arrow keys and selecting with space, or by their numbers in accessible mode
+        ///
+        /// The selected directories are unpacked with everything inside of them. Needs a terminal to
+        /// ask in, and archives read from files.
+        #[arg(long, conflicts_with = "index")]
+        interactive: bool,
+
+        /// Give the unpacked files to OWNER, as USER[:GROUP], by name or numeric id, which
+        /// needs the privileges to do it
+        #[arg(long, value_name = "OWNER")]
+        chown: Option<String>,
+
+        /// Print the metadata embedded with --metadata when the files were compressed
+        #[arg(long)]
+        show_metadata: bool,
+
+        /// Deprecated, the permissions stored in the archive are restored as they are by default
+        #[arg(long, hide = true)]
+        preserve_permissions: bool,
+
+        /// Don't restore the permissions stored in the archive as they are, the umask is applied
+        /// to them and the sticky bit is dropped
+        #[arg(long, conflicts_with = "preserve_permissions")]
+        no_preserve_permissions: bool,
+
+        /// Also restore setuid and setgid bits, use with care
+        #[arg(long, conflicts_with = "no_preserve_permissions")]
+        preserve_setuid: bool,
+
+        /// Set the modification times of the unpacked files to the current time, instead of the
+        /// ones stored in the archive, so build systems treat them as new
+        #[arg(long)]
+        touch: bool,
+
+        /// Check the CRC-32 of every zip entry, including the ones that aren't written,
+        /// reporting each corrupted entry
+        #[arg(long)]
+        verify_crc: bool,
+
+        /// Remove existing destinations before writing to them, whatever their type is
+        /// (file, symlink or directory), instead of asking to overwrite
+        #[arg(long)]
+        remove_destination: bool,
+
+        /// Only replace the existing files that are older than the ones in the archive, merging
+        /// directories, instead of asking to overwrite, like `unzip -u`
+        #[arg(long, conflicts_with = "remove_destination")]
+        if_newer: bool,
+
+        /// Unpack into the existing directories, merging them, only the files that conflict are
+        /// overwritten, asking first, and the others are left as they are
+        #[arg(long, conflicts_with = "if_newer")]
+        merge: bool,
+
+        /// Keep the existing symlinks to directories where the archive has directories, unpacking
+        /// into their targets, like tar's option, instead of replacing them
+        #[arg(long)]
+        keep_directory_symlink: bool,
+
+        /// Don't unpack symlinks whose targets are outside of the archive, following its other
+        /// symlinks, skipping them with a warning
+        ///
+        /// Absolute targets are looked up in the archive as if it was a file system, and the ones
+        /// that are files there are unpacked as copies of them instead.
+        #[arg(long)]
+        safe_symlinks: bool,
+
+        /// What to do with the existing files in the way of the unpacked ones, it comes before
+        /// --if-newer and --remove-destination, which come before --yes and --no
+        #[arg(long, value_name = "POLICY", value_enum)]
+        overwrite_policy: Option<OverwritePolicy>,
+
+        /// Allow decompressing into `/` and other system directories
+        #[arg(long)]
+        force: bool,
+
+        /// Get the passwords of encrypted archives from the OS keyring, under SERVICE,
+        /// the ones typed in are stored there once they work
+        #[arg(long, value_name = "SERVICE")]
+        keyring: Option<String>,
+
+        /// Remove the stored passwords of the archives from the keyring, asking for them again,
+        /// requires --keyring
+        #[arg(long, requires = "keyring")]
+        forget_password: bool,
+
+        /// Keep decompressing the other files when the password of an encrypted archive is wrong
+        /// or missing, reporting the skipped archives at the end
+        #[arg(long)]
+        continue_on_password_failure: bool,
+
+        /// Limit the decompressed bytes written per second, like 512K or 10M
+        #[arg(long, value_name = "RATE", value_parser = parse_size)]
+        limit_rate: Option<u64>,
+
+        /// Unpack only the first SIZE bytes of every file, like 64K, to peek at large files
+        ///
+        /// The files that were cut are reported. Wim images are always unpacked whole.
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "verify_crc")]
+        head: Option<u64>,
+
+        /// Retry the writes of the unpacked files up to N times when they fail with transient
+        /// errors, like the I/O errors and timeouts of network file systems
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retries: u32,
+
+        /// Treat the backslashes in the entry names of zip archives, written by some Windows tools,
+        /// as directory separators, on by default, =false keeps them in the file names
+        #[arg(
+            long,
+            value_name = "BOOL",
+            num_args = 0..=1,
+            require_equals = true,
+            default_value_t = true,
+            default_missing_value = "true",
+            action = ArgAction::Set
+        )]
+        normalize_separators: bool,
+
+        /// Stop unpacking archives with more than N entries, which could use up all the inodes,
+        /// 0 for no limit
+        #[arg(long, value_name = "N", default_value_t = 1_000_000)]
+        max_entries: usize,
+
+        /// Keep reading tar archives concatenated after the first one in the same file, like
+        /// `tar --ignore-zeros`, unpacking all of them
+        #[arg(long)]
+        concat_tar: bool,
+
+        /// Restore the file flags stored in tar archives, like immutable or append-only,
+        /// setting most of them requires root
+        #[arg(long)]
+        preserve_flags: bool,
+
+        /// Restore the creation times stored in tar archives, which can only be set on macOS and
+        /// Windows, ignored with a warning elsewhere
+        #[arg(long)]
+        preserve_btime: bool,
+
+        /// Restore the access times stored in tar archives, by `--atime-preserve` when compressing
+        #[arg(long, visible_alias = "preserve-atime")]
+        atime_preserve: bool,
+
+        /// What to do with files whose paths only differ in case from one unpacked before,
+        /// which would overwrite it on case-insensitive file systems, like the default ones of
+        /// macOS and Windows
+        #[arg(long, value_name = "POLICY", value_enum, default_value_t)]
+        case_conflicts: CaseConflictPolicy,
+
+        /// What to do with the entries of tar archives whose paths have '..' components, which
+        /// could write outside of the output directory
+        #[arg(long, value_name = "POLICY", value_enum, default_value_t)]
+        parent_dirs: ParentDirPolicy,
+
+        /// Order in which the entries of zip archives are unpacked, tar and 7z archives can only be
+        /// unpacked in archive order
+        #[arg(long, value_enum, default_value_t)]
+        order: ExtractionOrder,
+
+        /// What to do with the AppleDouble files macOS puts in archives, named "._" followed by
+        /// the name of the file whose resource fork and extended attributes they hold
+        #[arg(long, value_name = "POLICY", value_enum, default_value_t)]
+        macos_forks: MacosForks,
+
+        /// Print the SHA-256 of every archive and of every file unpacked from it once done, in the
+        /// format of sha256sum
+        ///
+        /// The archives are hashed on another thread as they are read, and the unpacked files in
+        /// parallel.
+        #[arg(long)]
+        checksum: bool,
+
+        /// Which extensions are removed from the names of single compressed files, not archives,
+        /// to name their outputs, ".out" is appended instead when the output would be the
+        /// compressed file itself
+        #[arg(long, value_name = "WHICH", value_enum, default_value_t)]
+        strip_extension: StripExtension,
     },
     /// List contents of an archive
     #[command(visible_aliases = ["l", "ls"])]
@@ -95,7 +560,217 @@ pub enum Subcommand {
         /// Show archive contents as a tree
         #[arg(short, long)]
         tree: bool,
+
+        /// Sort the entries instead of listing them in the order of the archive
+        #[arg(long, value_name = "ORDER")]
+        sort: Option<ListSort>,
+
+        /// List the entries in reverse order
+        #[arg(long)]
+        reverse: bool,
+
+        /// Number the entries in the order of the archive, to unpack some of them with
+        /// `decompress --index`
+        #[arg(long, conflicts_with = "tree")]
+        index: bool,
+
+        /// Print the entries of all the archives as one JSON array, for cataloging
+        ///
+        /// Every entry is an object with its archive, index, name, type (file, dir or symlink),
+        /// size, compressed_size, mtime and mode, null when the archive doesn't store it. Entries
+        /// are printed as they are read, unless sorted.
+        #[arg(long, conflicts_with = "tree")]
+        flat_json: bool,
+
+        /// Print only the names of the entries, each followed by a NUL character instead of a
+        /// newline, for `xargs -0`
+        #[arg(long, conflicts_with_all = ["tree", "index", "flat_json"])]
+        print0: bool,
     },
+    /// Compare the compression ratio and speed of each format on the given files
+    ///
+    /// The files are compressed to a temporary directory with every format, at its default level.
+    Bench {
+        /// Files to be compressed
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+
+        /// Only test these formats, separated by commas, like tar.gz,tar.zst,zip
+        #[arg(long, value_name = "FORMATS", value_delimiter = ',')]
+        formats: Vec<OsString>,
+    },
+    /// Shows the formats detected for files, and where they were detected from, without decompressing them
+    Probe {
+        /// Files whose formats should be detected
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+    },
+    /// Lists the supported formats, their extensions, and whether they can be compressed,
+    /// decompressed, encrypted and at which levels
+    Formats,
+}
+
+/// Compression methods of 7z archives, set by `--7z-method`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SevenZipMethod {
+    #[default]
+    Lzma2,
+    /// Store the files without compressing them, faster for incompressible data
+    Copy,
+    Bzip2,
+}
+
+/// Order of the entries listed, set by `--sort`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListSort {
+    /// By path, byte by byte
+    Name,
+    /// By path, with the numbers in it compared by value, so "file2" comes before "file10"
+    Natural,
+    /// By uncompressed size, smallest first
+    Size,
+    /// By modification time, oldest first
+    Mtime,
+}
+
+/// Format written when `--format` and the extensions of the output disagree, set by
+/// `--force-format-on-output`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForcedFormat {
+    /// The one given by --format
+    Format,
+    /// The one of the extensions of the output
+    Extension,
+}
+
+/// What to do with the entries of tar archives whose paths have `..` components, set by
+/// `--parent-dirs`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParentDirPolicy {
+    /// Don't unpack them, warning about each
+    #[default]
+    Reject,
+    /// Unpack them with the '..' components removed, so "a/../../etc/x" is unpacked to "a/etc/x"
+    Strip,
+}
+
+/// What to do on case-insensitive file systems with files whose paths only differ in case from
+/// one unpacked before, set by `--case-conflicts`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseConflictPolicy {
+    /// Unpack them with a numeric suffix, like "file-1.txt"
+    #[default]
+    Rename,
+    /// Don't unpack them
+    Skip,
+    /// Overwrite the file unpacked before
+    Overwrite,
+}
+
+/// Which extensions are removed from the names of single compressed files to name their outputs,
+/// set by `--strip-extension`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StripExtension {
+    /// The ones of the formats it is decompressed from, "report.txt.gz" is named "report.txt"
+    #[default]
+    Formats,
+    /// All of them, "report.txt.gz" is named "report"
+    All,
+    /// None of them, "report.txt.gz" keeps its name, which is meant for --dir
+    Keep,
+}
+
+/// What is done with the existing files where new ones are written, set by `--overwrite-policy`,
+/// or by the flags it comes before
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Ask before overwriting each of them
+    #[default]
+    Ask,
+    /// Overwrite them without asking, like --yes
+    Overwrite,
+    /// Keep them, like --no
+    Skip,
+    /// Only overwrite the ones older than the new files, like --if-newer
+    IfNewer,
+    /// Remove them without asking, whatever their type is, like --remove-destination
+    RemoveDestination,
+}
+
+/// What to do with the AppleDouble files of an archive, set by `--macos-forks`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MacosForks {
+    /// Unpack them as they are
+    #[default]
+    Keep,
+    /// Set the resource forks and extended attributes they hold on the files they belong to,
+    /// only on macOS
+    Merge,
+    /// Don't unpack them, nor the "__MACOSX" directory of zip archives
+    Strip,
+}
+
+/// Order in which the entries of an archive are unpacked, set by `--order`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExtractionOrder {
+    /// The order they are stored in
+    #[default]
+    Archive,
+    /// Sorted by their paths
+    Name,
+    /// The largest files first
+    SizeDesc,
+}
+
+/// Positions of archive entries, counted from 1, set by `--index`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexRanges(pub Vec<std::ops::RangeInclusive<usize>>);
+
+/// Parses positions and ranges of positions separated by commas, like "3,5,10-12"
+pub fn parse_index_ranges(ranges: &str) -> Result<IndexRanges, String> {
+    let parse = |index: &str| match index.trim().parse() {
+        Ok(0) => Err("indexes start at 1".to_owned()),
+        Ok(index) => Ok(index),
+        Err(_) => Err(format!("'{}' is not an index", index.trim())),
+    };
+
+    ranges
+        .split(',')
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Err(format!("the range '{}' ends before it starts", range.trim()));
+            }
+            Ok(start..=end)
+        })
+        .collect::<Result<_, _>>()
+        .map(IndexRanges)
+}
+
+/// Parses sizes like "512", "64K", "10M" or "1GiB", in multiples of 1024
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits_end = size.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(size.len());
+    let (number, suffix) = size.split_at(digits_end);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{size}' does not start with a number"))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size suffix '{suffix}', expected K, M, G or T")),
+    };
+
+    match number.checked_mul(multiplier) {
+        Some(0) => Err("the size must be greater than zero".to_owned()),
+        Some(size) => Ok(size),
+        None => Err(format!("'{size}' is too large")),
+    }
 }
 
 #[cfg(test)]
@@ -130,13 +805,64 @@ mod tests {
             accessible: false,
             hidden: false,
             quiet: false,
+            verbose: 0,
+            porcelain: false,
+            quiet_errors: false,
+            strict: false,
+            progress_fd: None,
+            log_file: None,
+            use_command: false,
+            color: ColorChoice::Auto,
             gitignore: false,
+            exclude: vec![],
+            exclude_from: vec![],
+            time_rounding: None,
             format: None,
             // This is usually replaced in assertion tests
             cmd: Subcommand::Decompress {
                 // Put a crazy value here so no test can assert it unintentionally
                 files: vec!["\x00\x11\x22".into()],
                 output_dir: None,
+                output_name_template: None,
+                no_smart_unpack: false,
+                shared_dir: false,
+                flatten: false,
+                temp_dir: None,
+                subtree: None,
+                index: None,
+                interactive: false,
+                chown: None,
+                show_metadata: false,
+                preserve_permissions: false,
+                no_preserve_permissions: false,
+                preserve_setuid: false,
+                touch: false,
+                verify_crc: false,
+                remove_destination: false,
+                if_newer: false,
+                merge: false,
+                keep_directory_symlink: false,
+                safe_symlinks: false,
+                overwrite_policy: None,
+                force: false,
+                keyring: None,
+                forget_password: false,
+                continue_on_password_failure: false,
+                limit_rate: None,
+                head: None,
+                retries: 0,
+                normalize_separators: true,
+                max_entries: 1_000_000,
+                concat_tar: false,
+                preserve_flags: false,
+                preserve_btime: false,
+                atime_preserve: false,
+                case_conflicts: CaseConflictPolicy::Rename,
+                parent_dirs: ParentDirPolicy::Reject,
+                order: ExtractionOrder::Archive,
+                macos_forks: MacosForks::Keep,
+                checksum: false,
+                strip_extension: StripExtension::Formats,
             },
         }
     }
@@ -149,6 +875,46 @@ mod tests {
                 cmd: Subcommand::Decompress {
                     files: to_paths(["file.tar.gz"]),
                     output_dir: None,
+                    output_name_template: None,
+                    no_smart_unpack: false,
+                    shared_dir: false,
+                    flatten: false,
+                    temp_dir: None,
+                    subtree: None,
+                    index: None,
+                    interactive: false,
+                    chown: None,
+                    show_metadata: false,
+                    preserve_permissions: false,
+                    no_preserve_permissions: false,
+                    preserve_setuid: false,
+                    touch: false,
+                    verify_crc: false,
+                    remove_destination: false,
+                    if_newer: false,
+                    merge: false,
+                    keep_directory_symlink: false,
+                    safe_symlinks: false,
+                    overwrite_policy: None,
+                    force: false,
+                    keyring: None,
+                    forget_password: false,
+                    continue_on_password_failure: false,
+                    limit_rate: None,
+                    head: None,
+                    retries: 0,
+                    normalize_separators: true,
+                    max_entries: 1_000_000,
+                    concat_tar: false,
+                    preserve_flags: false,
+                    preserve_btime: false,
+                    atime_preserve: false,
+                    case_conflicts: CaseConflictPolicy::Rename,
+                    parent_dirs: ParentDirPolicy::Reject,
+                    order: ExtractionOrder::Archive,
+                    macos_forks: MacosForks::Keep,
+                    checksum: false,
+                    strip_extension: StripExtension::Formats,
                 },
                 ..mock_cli_args()
             }
@@ -159,6 +925,46 @@ mod tests {
                 cmd: Subcommand::Decompress {
                     files: to_paths(["file.tar.gz"]),
                     output_dir: None,
+                    output_name_template: None,
+                    no_smart_unpack: false,
+                    shared_dir: false,
+                    flatten: false,
+                    temp_dir: None,
+                    subtree: None,
+                    index: None,
+                    interactive: false,
+                    chown: None,
+                    show_metadata: false,
+                    preserve_permissions: false,
+                    no_preserve_permissions: false,
+                    preserve_setuid: false,
+                    touch: false,
+                    verify_crc: false,
+                    remove_destination: false,
+                    if_newer: false,
+                    merge: false,
+                    keep_directory_symlink: false,
+                    safe_symlinks: false,
+                    overwrite_policy: None,
+                    force: false,
+                    keyring: None,
+                    forget_password: false,
+                    continue_on_password_failure: false,
+                    limit_rate: None,
+                    head: None,
+                    retries: 0,
+                    normalize_separators: true,
+                    max_entries: 1_000_000,
+                    concat_tar: false,
+                    preserve_flags: false,
+                    preserve_btime: false,
+                    atime_preserve: false,
+                    case_conflicts: CaseConflictPolicy::Rename,
+                    parent_dirs: ParentDirPolicy::Reject,
+                    order: ExtractionOrder::Archive,
+                    macos_forks: MacosForks::Keep,
+                    checksum: false,
+                    strip_extension: StripExtension::Formats,
                 },
                 ..mock_cli_args()
             }
@@ -169,6 +975,46 @@ mod tests {
                 cmd: Subcommand::Decompress {
                     files: to_paths(["a", "b", "c"]),
                     output_dir: None,
+                    output_name_template: None,
+                    no_smart_unpack: false,
+                    shared_dir: false,
+                    flatten: false,
+                    temp_dir: None,
+                    subtree: None,
+                    index: None,
+                    interactive: false,
+                    chown: None,
+                    show_metadata: false,
+                    preserve_permissions: false,
+                    no_preserve_permissions: false,
+                    preserve_setuid: false,
+                    touch: false,
+                    verify_crc: false,
+                    remove_destination: false,
+                    if_newer: false,
+                    merge: false,
+                    keep_directory_symlink: false,
+                    safe_symlinks: false,
+                    overwrite_policy: None,
+                    force: false,
+                    keyring: None,
+                    forget_password: false,
+                    continue_on_password_failure: false,
+                    limit_rate: None,
+                    head: None,
+                    retries: 0,
+                    normalize_separators: true,
+                    max_entries: 1_000_000,
+                    concat_tar: false,
+                    preserve_flags: false,
+                    preserve_btime: false,
+                    atime_preserve: false,
+                    case_conflicts: CaseConflictPolicy::Rename,
+                    parent_dirs: ParentDirPolicy::Reject,
+                    order: ExtractionOrder::Archive,
+                    macos_forks: MacosForks::Keep,
+                    checksum: false,
+                    strip_extension: StripExtension::Formats,
                 },
                 ..mock_cli_args()
             }
@@ -180,9 +1026,42 @@ mod tests {
                 cmd: Subcommand::Compress {
                     files: to_paths(["file"]),
                     output: PathBuf::from("file.tar.gz"),
+                    to_stdout: false,
+                    mkdir: false,
+                    auto_name: None,
                     level: None,
                     fast: false,
                     slow: false,
+                    max_depth: None,
+                    rsyncable: false,
+                    cdc: false,
+                    ldm: false,
+                    threads: None,
+                    total_threads: None,
+                    dereference: false,
+                    one_file_system: false,
+                    exclude_vcs: false,
+                    update: false,
+                    dry_run: false,
+                    list: false,
+                    junk_paths: false,
+                    rename_collisions: false,
+                    no_dir_entries: false,
+                    base_dirs: vec![],
+                    preserve_order: None,
+                    hard_dereference: false,
+                    compress_level_by_extension: None,
+                    metadata: false,
+                    sidecar: false,
+                    checksum_file: None,
+                    sevenz_method: None,
+                    force_format_on_output: None,
+                    preserve_flags: false,
+                    preserve_btime: false,
+                    atime_preserve: false,
+                    verify: false,
+                    no_overwrite_newer: false,
+                    force: false,
                 },
                 ..mock_cli_args()
             }
@@ -193,9 +1072,42 @@ mod tests {
                 cmd: Subcommand::Compress {
                     files: to_paths(["a", "b", "c"]),
                     output: PathBuf::from("archive.tar.gz"),
+                    to_stdout: false,
+                    mkdir: false,
+                    auto_name: None,
                     level: None,
                     fast: false,
                     slow: false,
+                    max_depth: None,
+                    rsyncable: false,
+                    cdc: false,
+                    ldm: false,
+                    threads: None,
+                    total_threads: None,
+                    dereference: false,
+                    one_file_system: false,
+                    exclude_vcs: false,
+                    update: false,
+                    dry_run: false,
+                    list: false,
+                    junk_paths: false,
+                    rename_collisions: false,
+                    no_dir_entries: false,
+                    base_dirs: vec![],
+                    preserve_order: None,
+                    hard_dereference: false,
+                    compress_level_by_extension: None,
+                    metadata: false,
+                    sidecar: false,
+                    checksum_file: None,
+                    sevenz_method: None,
+                    force_format_on_output: None,
+                    preserve_flags: false,
+                    preserve_btime: false,
+                    atime_preserve: false,
+                    verify: false,
+                    no_overwrite_newer: false,
+                    force: false,
                 },
                 ..mock_cli_args()
             }
@@ -206,9 +1118,42 @@ mod tests {
                 cmd: Subcommand::Compress {
                     files: to_paths(["a", "b", "c"]),
                     output: PathBuf::from("archive.tar.gz"),
+                    to_stdout: false,
+                    mkdir: false,
+                    auto_name: None,
                     level: None,
                     fast: false,
                     slow: false,
+                    max_depth: None,
+                    rsyncable: false,
+                    cdc: false,
+                    ldm: false,
+                    threads: None,
+                    total_threads: None,
+                    dereference: false,
+                    one_file_system: false,
+                    exclude_vcs: false,
+                    update: false,
+                    dry_run: false,
+                    list: false,
+                    junk_paths: false,
+                    rename_collisions: false,
+                    no_dir_entries: false,
+                    base_dirs: vec![],
+                    preserve_order: None,
+                    hard_dereference: false,
+                    compress_level_by_extension: None,
+                    metadata: false,
+                    sidecar: false,
+                    checksum_file: None,
+                    sevenz_method: None,
+                    force_format_on_output: None,
+                    preserve_flags: false,
+                    preserve_btime: false,
+                    atime_preserve: false,
+                    verify: false,
+                    no_overwrite_newer: false,
+                    force: false,
                 },
                 ..mock_cli_args()
             }
@@ -230,9 +1175,42 @@ mod tests {
                     cmd: Subcommand::Compress {
                         files: to_paths(["a", "b", "c"]),
                         output: PathBuf::from("output"),
+                        to_stdout: false,
+                        mkdir: false,
+                        auto_name: None,
                         level: None,
                         fast: false,
                         slow: false,
+                        max_depth: None,
+                        rsyncable: false,
+                        cdc: false,
+                        ldm: false,
+                        threads: None,
+                        total_threads: None,
+                        dereference: false,
+                        one_file_system: false,
+                        exclude_vcs: false,
+                        update: false,
+                        dry_run: false,
+                        list: false,
+                        junk_paths: false,
+                        rename_collisions: false,
+                        no_dir_entries: false,
+                        base_dirs: vec![],
+                        preserve_order: None,
+                        hard_dereference: false,
+                        compress_level_by_extension: None,
+                        metadata: false,
+                        sidecar: false,
+                        checksum_file: None,
+                        sevenz_method: None,
+                        force_format_on_output: None,
+                        preserve_flags: false,
+                        preserve_btime: false,
+                        atime_preserve: false,
+                        verify: false,
+                        no_overwrite_newer: false,
+                        force: false,
                     },
                     format: Some("tar.gz".into()),
                     ..mock_cli_args()
@@ -248,4 +1226,29 @@ mod tests {
         assert!(CliArgs::try_parse_from(args_splitter("ouch d")).is_err());
         assert!(CliArgs::try_parse_from(args_splitter("ouch l")).is_err());
     }
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1GiB"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_size("2 mb"), Ok(2 * 1024 * 1024));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_parse_index_ranges() {
+        assert_eq!(
+            parse_index_ranges("3,5,10-12"),
+            Ok(IndexRanges(vec![3..=3, 5..=5, 10..=12]))
+        );
+        assert_eq!(parse_index_ranges(" 1 - 2 "), Ok(IndexRanges(vec![1..=2])));
+        assert!(parse_index_ranges("0").is_err());
+        assert!(parse_index_ranges("5-3").is_err());
+        assert!(parse_index_ranges("3,").is_err());
+        assert!(parse_index_ranges("a-b").is_err());
+    }
 }
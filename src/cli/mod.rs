@@ -3,16 +3,38 @@
 mod args;
 
 use std::{
-    io,
+    io, mem,
     path::{Path, PathBuf},
     vec::Vec,
 };
 
-use clap::Parser;
+use clap::{ColorChoice, Parser};
 use fs_err as fs;
 
-pub use self::args::{CliArgs, Subcommand};
-use crate::{accessible::set_accessible, utils::FileVisibilityPolicy, QuestionPolicy};
+pub use self::args::{
+    parse_index_ranges, CaseConflictPolicy, CliArgs, ExtractionOrder, ForcedFormat, IndexRanges, ListSort, MacosForks,
+    OverwritePolicy, ParentDirPolicy, SevenZipMethod, StripExtension, Subcommand,
+};
+use crate::{
+    accessible::set_accessible,
+    utils::{
+        colors, is_url, set_log_file, set_porcelain, set_progress_fd, set_use_command, set_warning_policy,
+        ExcludePatterns, FileVisibilityPolicy, WarningPolicy,
+    },
+    QuestionPolicy,
+};
+
+impl IndexRanges {
+    /// Checks if `index` is one of the positions
+    pub fn contains(&self, index: usize) -> bool {
+        self.0.iter().any(|range| range.contains(&index))
+    }
+
+    /// Largest of the positions
+    pub fn last(&self) -> usize {
+        self.0.iter().map(|range| *range.end()).max().unwrap_or(0)
+    }
+}
 
 impl CliArgs {
     /// A helper method that calls `clap::Parser::parse`.
@@ -24,11 +46,60 @@ impl CliArgs {
         let mut args = Self::parse();
 
         set_accessible(args.accessible);
+        set_porcelain(args.porcelain);
+        colors::set_color_choice(if args.porcelain { ColorChoice::Never } else { args.color });
+        set_warning_policy(match (args.quiet_errors || args.porcelain, args.strict) {
+            (false, false) => WarningPolicy::Print,
+            (true, false) => WarningPolicy::Silence,
+            (_, true) => WarningPolicy::Strict,
+        });
+
+        if let Some(path) = &args.log_file {
+            set_log_file(path)?;
+        }
+
+        if let Some(fd) = args.progress_fd {
+            set_progress_fd(fd)?;
+        }
+        set_use_command(args.use_command);
+
+        // With --to-stdout, there is no output file, the last path is one more input
+        if let Subcommand::Compress {
+            files,
+            output,
+            to_stdout: true,
+            ..
+        } = &mut args.cmd
+        {
+            files.push(mem::take(output));
+        }
 
-        let (Subcommand::Compress { files, .. }
+        let is_decompress = matches!(args.cmd, Subcommand::Decompress { .. });
+        if let Subcommand::Compress { files, .. }
         | Subcommand::Decompress { files, .. }
-        | Subcommand::List { archives: files, .. }) = &mut args.cmd;
-        *files = canonicalize_files(files)?;
+        | Subcommand::List { archives: files, .. }
+        | Subcommand::Bench { files, .. }
+        | Subcommand::Probe { files } = &mut args.cmd
+        {
+            if is_decompress {
+                // URLs are downloaded, they are no files to canonicalize
+                *files = files
+                    .iter()
+                    .map(|file| {
+                        if is_url(file) {
+                            Ok(file.clone())
+                        } else {
+                            fs::canonicalize(file)
+                        }
+                    })
+                    .collect::<io::Result<_>>()?;
+            } else {
+                *files = canonicalize_files(files)?;
+            }
+        }
+        if let Subcommand::Compress { base_dirs, .. } = &mut args.cmd {
+            *base_dirs = canonicalize_files(base_dirs)?;
+        }
 
         let skip_questions_positively = match (args.yes, args.no) {
             (false, false) => QuestionPolicy::Ask,
@@ -41,7 +112,8 @@ impl CliArgs {
             .read_git_exclude(args.gitignore)
             .read_ignore(args.gitignore)
             .read_git_ignore(args.gitignore)
-            .read_hidden(args.hidden);
+            .read_hidden(args.hidden)
+            .exclude(ExcludePatterns::new(&args.exclude, &args.exclude_from)?);
 
         Ok((args, skip_questions_positively, file_visibility_policy))
     }
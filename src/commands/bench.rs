@@ -0,0 +1,133 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io::{stdout, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use fs_err as fs;
+
+use crate::{
+    check,
+    commands::compress::{compress_files, files_to_compress, CompressOptions, CompressionOutput},
+    extension::parse_format,
+    info,
+    utils::{Bytes, FileVisibilityPolicy},
+    QuestionPolicy,
+};
+
+/// Formats compared by `ouch bench` when `--formats` isn't given
+const DEFAULT_FORMATS: &[&str] = &[
+    "tar", "tar.gz", "tar.bz2", "tar.xz", "tar.lz4", "tar.sz", "tar.zst", "zip", "7z",
+];
+
+/// The result of compressing the input with one format
+struct BenchResult {
+    format: String,
+    size: u64,
+    time: Duration,
+}
+
+/// Compress `files` with each of `formats`, or [`DEFAULT_FORMATS`] if it is empty, and print a
+/// table of the resulting sizes and the time each took, smallest first.
+///
+/// Every output is written to a temporary directory, which is deleted at the end.
+pub fn bench_formats(
+    files: Vec<PathBuf>,
+    formats: Vec<OsString>,
+    file_visibility_policy: FileVisibilityPolicy,
+) -> crate::Result<()> {
+    let formats = if formats.is_empty() {
+        DEFAULT_FORMATS.iter().map(OsString::from).collect()
+    } else {
+        formats
+    };
+
+    let temp_dir = tempfile::tempdir()?;
+
+    // Checked before compressing anything, so a typo doesn't fail after a long run
+    let mut parsed_formats = vec![];
+    for format in &formats {
+        let extensions = parse_format(format)?;
+        let file_name = PathBuf::from(bench_file_name(format));
+
+        check::check_invalid_compression_with_non_archive_format(&extensions, &file_name, &files, Some(format))?;
+        check::check_archive_formats_position(&extensions, &file_name)?;
+        check::check_decompression_only_formats(&extensions, &file_name)?;
+
+        let output_path = temp_dir.path().join(file_name);
+        parsed_formats.push((format.to_string_lossy().into_owned(), extensions, output_path));
+    }
+
+    let input_size: u64 = files_to_compress(&files, temp_dir.path(), &file_visibility_policy, None, false)?
+        .iter()
+        .map(|file| file.size)
+        .sum();
+
+    let mut results = vec![];
+    for (format, extensions, output_path) in parsed_formats {
+        info!(inaccessible, "Compressing with {format}.");
+
+        let start = Instant::now();
+        compress_files(
+            files.clone(),
+            extensions,
+            CompressionOutput::File(fs::File::create(&output_path)?),
+            &output_path,
+            true,
+            // Only asked before compressing zip or 7z archives in memory, which the user chose
+            QuestionPolicy::AlwaysYes,
+            CompressOptions {
+                file_visibility_policy: file_visibility_policy.clone(),
+                ..Default::default()
+            },
+        )?;
+        let time = start.elapsed();
+
+        let size = fs::metadata(&output_path)?.len();
+        fs::remove_file(&output_path)?;
+
+        results.push(BenchResult { format, size, time });
+    }
+
+    results.sort_by_key(|result| (result.size, result.time));
+    print_results(input_size, &results);
+
+    Ok(())
+}
+
+/// Name of the temporary output of `format`, also shown in the errors about invalid formats
+fn bench_file_name(format: &OsStr) -> OsString {
+    let mut name = OsString::from("bench.");
+    name.push(format);
+    name
+}
+
+fn print_results(input_size: u64, results: &[BenchResult]) {
+    let out = &mut stdout().lock();
+    let format_width = results
+        .iter()
+        .map(|result| result.format.len())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+
+    let _ = writeln!(out, "Input: {}", Bytes::new(input_size));
+    let _ = writeln!(
+        out,
+        "{:format_width$}  {:>11}  {:>7}  {:>8}",
+        "Format", "Size", "Ratio", "Time"
+    );
+
+    for BenchResult { format, size, time } in results {
+        let ratio = if input_size == 0 {
+            "-".to_string()
+        } else {
+            format!("{:.1}%", *size as f64 / input_size as f64 * 100.0)
+        };
+        let size = Bytes::new(*size).to_string();
+        let time = format!("{:.2}s", time.as_secs_f64());
+
+        let _ = writeln!(out, "{format:format_width$}  {size:>11}  {ratio:>7}  {time:>8}");
+    }
+}
@@ -1,113 +1,353 @@
 use std::{
+    collections::HashMap,
+    env,
     io::{self, BufWriter, Cursor, Seek, Write},
     path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 
 use fs_err as fs;
+use same_file::Handle;
+use zip::ZipArchive;
 
 use crate::{
-    archive,
-    commands::warn_user_about_loading_zip_in_memory,
-    extension::{split_first_compression_format, CompressionFormat::*, Extension},
-    utils::{user_wants_to_continue, FileVisibilityPolicy},
-    QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
+    archive::{self, metadata::Provenance, JunkPaths},
+    cli::SevenZipMethod,
+    commands::{warn_user_about_loading_sevenz_in_memory, warn_user_about_loading_zip_in_memory},
+    error::{Error, FinalError},
+    extension::{separate_known_extensions_from_name, split_first_compression_format, CompressionFormat::*, Extension},
+    list::FileInArchive,
+    utils::{
+        self, cd_into_walk_root, user_wants_to_continue, Bytes, Checksum, ChunkedEncoder, CountingWriter, EntrySizes,
+        EscapedPathDisplay, ExtensionLevels, FileVisibilityPolicy, HashingWriter, ProgressEvent,
+    },
+    warning, QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
-/// Compress files into `output_file`.
+/// Where [`compress_files`] writes the compressed output
+pub enum CompressionOutput {
+    File(fs::File),
+    /// Standard output, see `--to-stdout`
+    Stdout,
+}
+
+/// Options controlling how the inputs are stored and compressed, see [`compress_files`]
+#[derive(Default)]
+pub struct CompressOptions {
+    /// Which files are skipped when walking the inputs
+    pub file_visibility_policy: FileVisibilityPolicy,
+
+    /// Compression level given by the user, clamped to the ones of each format
+    pub level: Option<i16>,
+
+    /// Whether to use the rsyncable mode of zstd, set by `--rsyncable`
+    pub rsyncable: bool,
+
+    /// Whether to use the long distance matching of zstd, set by `--ldm`
+    pub ldm: bool,
+
+    /// Worker threads of zstd, set by `--threads`
+    pub threads: Option<u32>,
+
+    /// Whether gzip and zstd are compressed in content-defined chunks, set by `--cdc`
+    pub cdc: bool,
+
+    /// The zip archive being updated with `--update`, if any
+    pub existing_archive: Option<ZipArchive<fs::File>>,
+
+    /// Flattens the stored paths, set by `--junk-paths`
+    pub junk_paths: Option<JunkPaths>,
+
+    /// Whether only the files of tar, zip and 7z archives are stored, set by `--no-dir-entries`
+    pub no_dir_entries: bool,
+
+    /// Compression method of 7z archives, set by `--7z-method`
+    pub sevenz_method: SevenZipMethod,
+
+    /// Whether to store the file flags in tar archives, set by `--preserve-flags`
+    pub preserve_flags: bool,
+
+    /// Whether to store the creation times in tar archives, set by `--preserve-btime`
+    pub preserve_btime: bool,
+
+    /// Whether to set the access times of the files back after reading them, set by `--atime-preserve`
+    pub atime_preserve: bool,
+
+    /// Whether hard-linked files are stored as copies in tar archives, set by `--hard-dereference`
+    pub hard_dereference: bool,
+
+    /// Modification times are rounded down to a multiple of these seconds, set by `--time-rounding`
+    pub time_rounding: Option<u64>,
+
+    /// The level of each file of zip archives, set by `--compress-level-by-extension`
+    pub extension_levels: Option<ExtensionLevels>,
+
+    /// The provenance stored in zip and tar archives, set by `--metadata`
+    pub metadata: Option<Provenance>,
+
+    /// Whether to print the original and stored size of every file, set by `-vv`
+    pub print_entry_sizes: bool,
+
+    /// Hashes the output as it is written, set by `--checksum-file`
+    pub checksum: Option<Checksum>,
+}
+
+/// Compress files into `output`.
 ///
 /// # Arguments:
 /// - `files`: is the list of paths to be compressed: ["dir/file1.txt", "dir/file2.txt"]
 /// - `extensions`: is a list of compression formats for compressing, example: [Tar, Gz] (in compression order)
-/// - `output_file` is the resulting compressed file name, example: "archive.tar.gz"
+/// - `output`: is the file or the stream the output is written to
+/// - `output_path` is the resulting compressed file name, example: "archive.tar.gz"
 ///
 /// # Return value
 /// - Returns `Ok(true)` if compressed all files normally.
 /// - Returns `Ok(false)` if user opted to abort compression mid-way.
-#[allow(clippy::too_many_arguments)]
 pub fn compress_files(
     files: Vec<PathBuf>,
     extensions: Vec<Extension>,
-    output_file: fs::File,
+    output: CompressionOutput,
     output_path: &Path,
     quiet: bool,
     question_policy: QuestionPolicy,
-    file_visibility_policy: FileVisibilityPolicy,
-    level: Option<i16>,
+    options: CompressOptions,
 ) -> crate::Result<bool> {
-    // If the input files contain a directory, then the total size will be underestimated
-    let file_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output_file);
+    let CompressOptions {
+        file_visibility_policy,
+        level,
+        rsyncable,
+        ldm,
+        threads,
+        cdc,
+        existing_archive,
+        junk_paths,
+        no_dir_entries,
+        sevenz_method,
+        preserve_flags,
+        preserve_btime,
+        atime_preserve,
+        hard_dereference,
+        time_rounding,
+        extension_levels,
+        metadata,
+        print_entry_sizes,
+        checksum,
+    } = options;
+    let (first_format, formats) = split_first_compression_format(&extensions);
+
+    // A plain zip or 7z archive is built straight into the output file, instead of in memory like
+    // when it is compressed further, so entries larger than the available RAM can be stored
+    if matches!(first_format, Zip | SevenZip) && formats.is_empty() {
+        // Its headers are written by seeking back to them, which stdout can't do, so it is built
+        // in a temporary file first
+        let (output_file, temp_path) = match output {
+            CompressionOutput::File(file) => (file, None),
+            CompressionOutput::Stdout => {
+                let (file, temp_path) = tempfile::NamedTempFile::new()?.into_parts();
+                (fs::File::from_parts(file, &*temp_path), Some(temp_path))
+            }
+        };
+        let file_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output_file);
+        let mut file_writer = if first_format == Zip {
+            archive::zip::build_archive_from_paths(
+                &files,
+                output_path,
+                file_writer,
+                file_visibility_policy,
+                quiet,
+                existing_archive,
+                junk_paths,
+                no_dir_entries,
+                atime_preserve,
+                extension_levels.as_ref(),
+                time_rounding,
+                metadata.as_ref(),
+            )?
+        } else {
+            archive::sevenz::compress_sevenz(
+                &files,
+                output_path,
+                file_writer,
+                file_visibility_policy,
+                quiet,
+                junk_paths,
+                no_dir_entries,
+                atime_preserve,
+                sevenz_method,
+                level,
+                time_rounding,
+            )?
+        };
+        file_writer.flush()?;
+        let mut output_file = file_writer.into_inner().map_err(|err| err.into_error())?;
 
+        if print_entry_sizes && first_format == Zip {
+            archive::zip::print_entry_sizes(ZipArchive::new(fs::File::open(output_file.path())?)?)?;
+        }
+        // Its headers were written by seeking back, so it is read again to be hashed
+        if let Some(checksum) = &checksum {
+            checksum.update_from(&mut fs::File::open(output_file.path())?)?;
+        }
+        if temp_path.is_some() {
+            output_file.rewind()?;
+            io::copy(&mut output_file, &mut io::stdout().lock())?;
+        }
+        return Ok(true);
+    }
+
+    let output: Box<dyn Send + Write> = match output {
+        CompressionOutput::File(file) => Box::new(file),
+        CompressionOutput::Stdout => Box::new(io::stdout()),
+    };
+    let output: Box<dyn Send + Write> = match &checksum {
+        Some(checksum) => Box::new(HashingWriter::new(output, checksum.clone())),
+        None => output,
+    };
+    // If the input files contain a directory, then the total size will be underestimated
+    let file_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output);
+    let (file_writer, bytes_written) = CountingWriter::new(file_writer);
+    let mut entry_sizes = print_entry_sizes.then(|| EntrySizes::new(bytes_written.clone()));
     let mut writer: Box<dyn Send + Write> = Box::new(file_writer);
 
     // Grab previous encoder and wrap it inside of a new one
     let chain_writer_encoder = |format: &_, encoder| -> crate::Result<_> {
         let encoder: Box<dyn Send + Write> = match format {
+            Gzip if cdc => {
+                let level = level.map_or_else(flate2::Compression::default, |l| {
+                    flate2::Compression::new(Gzip.clamp_level(l) as u32)
+                });
+                Box::new(ChunkedEncoder::new(
+                    encoder,
+                    Box::new(move |chunk| {
+                        let mut member = flate2::write::GzEncoder::new(vec![], level);
+                        member.write_all(chunk)?;
+                        member.finish()
+                    }),
+                ))
+            }
+            Zstd if cdc => {
+                let level = zstd_level(level);
+                Box::new(ChunkedEncoder::new(
+                    encoder,
+                    Box::new(move |chunk| zstd::bulk::compress(chunk, level)),
+                ))
+            }
             Gzip => Box::new(
                 // by default, ParCompress uses a default compression level of 3
                 // instead of the regular default that flate2 uses
                 gzp::par::compress::ParCompress::<gzp::deflate::Gzip>::builder()
                     .compression_level(
-                        level.map_or_else(Default::default, |l| gzp::Compression::new((l as u32).clamp(0, 9))),
+                        level.map_or_else(Default::default, |l| gzp::Compression::new(Gzip.clamp_level(l) as u32)),
                     )
                     .from_writer(encoder),
             ),
             Bzip => Box::new(bzip2::write::BzEncoder::new(
                 encoder,
-                level.map_or_else(Default::default, |l| bzip2::Compression::new((l as u32).clamp(1, 9))),
+                level.map_or_else(
+                    Default::default,
+                    |l| bzip2::Compression::new(Bzip.clamp_level(l) as u32),
+                ),
             )),
+            Bzip3 => Box::new(crate::bzip3::Bzip3Encoder::new(encoder)?),
             Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(encoder).auto_finish()),
             Lzma => Box::new(xz2::write::XzEncoder::new(
                 encoder,
-                level.map_or(6, |l| (l as u32).clamp(0, 9)),
+                level.map_or(6, |l| Lzma.clamp_level(l) as u32),
             )),
             Snappy => Box::new(
                 gzp::par::compress::ParCompress::<gzp::snap::Snap>::builder()
                     .compression_level(gzp::par::compress::Compression::new(
-                        level.map_or_else(Default::default, |l| (l as u32).clamp(0, 9)),
+                        level.map_or_else(Default::default, |l| Snappy.clamp_level(l) as u32),
                     ))
                     .from_writer(encoder),
             ),
             Zstd => {
-                let zstd_encoder = zstd::stream::write::Encoder::new(
-                    encoder,
-                    level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
-                        (l as i32).clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
-                    }),
-                );
+                let zstd_encoder = zstd::stream::write::Encoder::new(encoder, zstd_level(level));
                 // Safety:
                 //     Encoder::new() can only fail if `level` is invalid, but the level
                 //     is `clamp`ed and therefore guaranteed to be valid
-                Box::new(zstd_encoder.unwrap().auto_finish())
+                let mut zstd_encoder = zstd_encoder.unwrap();
+                // The workers are set after the level, so the jobs are sized for it, and every
+                // job is compressed at it
+                let workers = threads.unwrap_or(0);
+                // zstd ignores the rsyncable mode when compressing without worker threads
+                let workers = if rsyncable { workers.max(1) } else { workers };
+                if workers > 0 {
+                    zstd_encoder.multithread(workers)?;
+                }
+                if rsyncable {
+                    zstd_encoder.set_parameter(zstd::zstd_safe::CParameter::RSyncable(true))?;
+                }
+                if ldm {
+                    zstd_encoder.long_distance_matching(true)?;
+                }
+                Box::new(zstd_encoder.auto_finish())
             }
-            Tar | Zip | SevenZip => unreachable!(),
+            Tar | Zip | SevenZip | Compress | Wim | Iso => unreachable!(),
         };
         Ok(encoder)
     };
 
-    let (first_format, formats) = split_first_compression_format(&extensions);
-
     for format in formats.iter().rev() {
         writer = chain_writer_encoder(format, writer)?;
     }
 
     match first_format {
-        Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd => {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress => {
             writer = chain_writer_encoder(&first_format, writer)?;
+            let accessed = if atime_preserve {
+                Some(utils::AccessTime::save(&files[0], &fs::metadata(&files[0])?))
+            } else {
+                None
+            };
             let mut reader = fs::File::open(&files[0]).unwrap();
 
-            io::copy(&mut reader, &mut writer)?;
+            let size = io::copy(&mut reader, &mut writer)?;
+            if let Some(accessed) = accessed {
+                accessed.restore()?;
+            }
+            utils::emit_progress(ProgressEvent::Entry {
+                path: &files[0],
+                bytes: size,
+            });
+
+            if print_entry_sizes {
+                // Dropping finishes the encoders, writing everything they buffered
+                drop(writer);
+                // Safe unwrap, input shall be treated before
+                let file_name = files[0].file_name().unwrap();
+                utils::print_entry_size(Path::new(file_name), size, bytes_written.get());
+            }
         }
         Tar => {
-            archive::tar::build_archive_from_paths(&files, output_path, &mut writer, file_visibility_policy, quiet)?;
+            archive::tar::build_archive_from_paths(
+                &files,
+                output_path,
+                &mut writer,
+                file_visibility_policy,
+                quiet,
+                junk_paths,
+                no_dir_entries,
+                entry_sizes.as_mut(),
+                preserve_flags,
+                preserve_btime,
+                atime_preserve,
+                hard_dereference,
+                time_rounding,
+                metadata.as_ref(),
+            )?;
             writer.flush()?;
+
+            if let Some(mut entry_sizes) = entry_sizes {
+                drop(writer);
+                entry_sizes.finish_entry();
+            }
         }
         Zip => {
-            if !formats.is_empty() {
-                warn_user_about_loading_zip_in_memory();
+            warn_user_about_loading_zip_in_memory();
 
-                if !user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
-                    return Ok(false);
-                }
+            if !user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
+                return Ok(false);
             }
 
             let mut vec_buffer = Cursor::new(vec![]);
@@ -118,14 +358,273 @@ pub fn compress_files(
                 &mut vec_buffer,
                 file_visibility_policy,
                 quiet,
+                existing_archive,
+                junk_paths,
+                no_dir_entries,
+                atime_preserve,
+                extension_levels.as_ref(),
+                time_rounding,
+                metadata.as_ref(),
             )?;
+
+            if print_entry_sizes {
+                archive::zip::print_entry_sizes(ZipArchive::new(&mut vec_buffer)?)?;
+            }
             vec_buffer.rewind()?;
             io::copy(&mut vec_buffer, &mut writer)?;
         }
         SevenZip => {
-            archive::sevenz::compress_sevenz(files, output_path)?;
+            warn_user_about_loading_sevenz_in_memory();
+
+            if !user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
+                return Ok(false);
+            }
+
+            let mut vec_buffer = Cursor::new(vec![]);
+
+            archive::sevenz::compress_sevenz(
+                &files,
+                output_path,
+                &mut vec_buffer,
+                file_visibility_policy,
+                quiet,
+                junk_paths,
+                no_dir_entries,
+                atime_preserve,
+                sevenz_method,
+                level,
+                time_rounding,
+            )?;
+            vec_buffer.rewind()?;
+            io::copy(&mut vec_buffer, &mut writer)?;
+        }
+        Wim => {
+            // wimlib-imagex writes the image to a file of its own, which then goes through the other formats
+            let temp_dir = tempfile::tempdir()?;
+            let image_path = temp_dir.path().join("image.wim");
+            let (image_name, _) = separate_known_extensions_from_name(output_path);
+            archive::wim::build_archive_from_paths(
+                &files,
+                &image_path,
+                image_name.as_os_str(),
+                &file_visibility_policy,
+            )?;
+            io::copy(&mut fs::File::open(&image_path)?, &mut writer)?;
         }
+        // Checked before, images can't be built
+        Iso => unreachable!(),
     }
 
     Ok(true)
 }
+
+/// The zstd compression level of the `level` given by the user, clamped to the ones zstd has
+fn zstd_level(level: Option<i16>) -> i32 {
+    level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| Zstd.clamp_level(l))
+}
+
+/// Free space for the output written to `output_path`, where an existing output of
+/// `replaced_size` bytes frees its space once it is overwritten, `None` if it can't be known.
+pub fn available_space_for(output_path: &Path, replaced_size: u64) -> Option<u64> {
+    let output_dir = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    utils::available_space(output_dir).map(|available| available.saturating_add(replaced_size))
+}
+
+/// An upper bound of the size of the inputs found without walking them, the size of the files
+/// given, and the used space of the file systems of the directories given.
+///
+/// It spares walking the inputs to check the free space in the common case of a disk that isn't
+/// mostly full, `None` if the used space can't be known.
+pub fn inputs_size_upper_bound(files: &[PathBuf]) -> Option<u64> {
+    let mut file_systems = vec![];
+    let mut size = 0u64;
+
+    for file in files {
+        let metadata = fs::metadata(file).ok()?;
+        if !metadata.is_dir() {
+            size = size.saturating_add(metadata.len());
+            continue;
+        }
+
+        // Each file system is counted once, whatever number of directories it holds
+        #[cfg(unix)]
+        let file_system = std::os::unix::fs::MetadataExt::dev(&metadata);
+        #[cfg(not(unix))]
+        let file_system = ();
+        if !file_systems.contains(&file_system) {
+            file_systems.push(file_system);
+            size = size.saturating_add(utils::used_space(file)?);
+        }
+    }
+
+    Some(size)
+}
+
+/// Warns if the inputs, `input_size` bytes large, may not fit in the `available` space for
+/// `output_path`, asking if they should be compressed anyway, failing if they shouldn't.
+///
+/// The output is usually smaller than the inputs, but not when they are stored or don't compress,
+/// so their size is the upper bound checked.
+pub fn check_free_space(
+    output_path: &Path,
+    input_size: u64,
+    available: u64,
+    question_policy: QuestionPolicy,
+) -> crate::Result<()> {
+    if input_size <= available {
+        return Ok(());
+    }
+
+    warning!(
+        "The inputs take {}, but only {} are free for '{}', which may not fit if they don't compress well.",
+        Bytes::new(input_size),
+        Bytes::new(available),
+        EscapedPathDisplay::new(output_path)
+    );
+    if user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title(format!(
+        "Not enough free space to compress '{}'",
+        EscapedPathDisplay::new(output_path)
+    ))
+    .detail(format!(
+        "The inputs take {}, but only {} are free",
+        Bytes::new(input_size),
+        Bytes::new(available)
+    ))
+    .hint("Free up some space, or compress to another disk")
+    .hint("Answer yes to compress anyway, if the inputs compress well");
+    Err(Error::StorageFull { reason: error })
+}
+
+/// Number of threads that can run in parallel, the budget of `--total-threads` without a number
+pub fn available_threads() -> u32 {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get() as u32)
+}
+
+/// Size of the jobs that zstd splits its input into with worker threads at `level`, computed
+/// like `ZSTDMT_computeTargetJobLog` does, a job is only ever compressed by one thread
+fn zstd_job_size(level: Option<i16>) -> u64 {
+    // Safety: `ZSTD_getCParams` only looks up the parameters of the level
+    let params = unsafe { zstd::zstd_safe::zstd_sys::ZSTD_getCParams(zstd_level(level), 0, 0) };
+    1 << (params.windowLog + 2).clamp(20, 30)
+}
+
+/// Warns the user when compressing `input_size` bytes with zstd at `level` uses a single one of
+/// the `threads` set by `option`, `--threads` or `--total-threads`, as the input isn't larger than
+/// a job.
+pub fn warn_if_threads_unused(input_size: u64, level: Option<i16>, threads: u32, option: &str) {
+    let job_size = zstd_job_size(level);
+    if threads > 1 && input_size <= job_size {
+        warning!(
+            "{option} has no effect on inputs of {} or less at this level, they are compressed by a single thread.",
+            Bytes::new(job_size)
+        );
+    }
+}
+
+/// Lists the entries that compressing `files` would create, without reading their contents.
+///
+/// Used by `--dry-run`, it walks the inputs with the same rules as the archive builders.
+pub fn files_to_compress(
+    files: &[PathBuf],
+    output_path: &Path,
+    file_visibility_policy: &FileVisibilityPolicy,
+    junk_paths: Option<JunkPaths>,
+    no_dir_entries: bool,
+) -> crate::Result<Vec<FileInArchive>> {
+    let entries = walk_files_to_compress(files, output_path, file_visibility_policy, junk_paths, no_dir_entries)?;
+
+    Ok(entries.into_iter().map(|(entry, _)| entry).collect())
+}
+
+/// Maps the paths of the regular files stored when compressing `files` to the files they are
+/// read from, used by `--verify` to compare them.
+pub fn source_files(
+    files: &[PathBuf],
+    output_path: &Path,
+    file_visibility_policy: &FileVisibilityPolicy,
+    junk_paths: Option<JunkPaths>,
+    no_dir_entries: bool,
+) -> crate::Result<HashMap<PathBuf, PathBuf>> {
+    let entries = walk_files_to_compress(files, output_path, file_visibility_policy, junk_paths, no_dir_entries)?;
+
+    // Symlinks are stored as links, unless they are followed
+    let is_stored_as_file =
+        |source: &Path| source.is_file() && (file_visibility_policy.follow_links || !utils::is_symlink(source));
+
+    Ok(entries
+        .into_iter()
+        .filter(|(_, source)| is_stored_as_file(source))
+        .map(|(entry, source)| (entry.path, source))
+        .collect())
+}
+
+/// Walks the inputs like [`files_to_compress`], returning the entries with their source paths.
+fn walk_files_to_compress(
+    files: &[PathBuf],
+    output_path: &Path,
+    file_visibility_policy: &FileVisibilityPolicy,
+    mut junk_paths: Option<JunkPaths>,
+    no_dir_entries: bool,
+) -> crate::Result<Vec<(FileInArchive, PathBuf)>> {
+    let output_handle = Handle::from_path(output_path);
+    let mut entries = vec![];
+
+    for filename in files {
+        let (previous_location, filename) = cd_into_walk_root(filename, file_visibility_policy)?;
+        let walk_dir = env::current_dir()?;
+
+        for entry in file_visibility_policy.build_walker(filename) {
+            let entry = entry?;
+            let path = entry.path();
+
+            if let Ok(ref handle) = output_handle {
+                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+                    continue;
+                }
+            }
+
+            // Broken symlinks are skipped when compressing too
+            let Ok(metadata) = path.metadata() else {
+                continue;
+            };
+
+            let is_dir_symlink = metadata.is_dir() && utils::is_symlink(path) && !file_visibility_policy.follow_links;
+            // Only the files are stored with --junk-paths and --no-dir-entries
+            if metadata.is_dir() && !is_dir_symlink && (junk_paths.is_some() || no_dir_entries) {
+                continue;
+            }
+
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs() as i64);
+            #[cfg(unix)]
+            let mode = Some(std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()));
+            #[cfg(not(unix))]
+            let mode = None;
+
+            let entry = FileInArchive {
+                path: archive::stored_path(&mut junk_paths, path)?.into_owned(),
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified,
+                is_symlink: utils::is_symlink(path) && !file_visibility_policy.follow_links,
+                compressed_size: None,
+                mode,
+            };
+            entries.push((entry, walk_dir.join(path)));
+        }
+
+        env::set_current_dir(previous_location)?;
+    }
+
+    Ok(entries)
+}
@@ -1,20 +1,29 @@
 use std::{
+    cell::Cell,
     io::{self, BufReader, Read},
     ops::ControlFlow,
     path::{Path, PathBuf},
 };
 
+use filetime::FileTime;
 use fs_err as fs;
 
 use crate::{
-    commands::warn_user_about_loading_zip_in_memory,
+    archive::{UnpackOptions, UnpackStrategy},
+    check,
+    cli::{MacosForks, OverwritePolicy},
+    commands::{warn_user_about_loading_sevenz_in_memory, warn_user_about_loading_zip_in_memory},
+    error::{Error, FinalError},
     extension::{
-        split_first_compression_format,
+        self, split_first_compression_format,
         CompressionFormat::{self, *},
         Extension,
     },
     info,
-    utils::{self, nice_directory_display, user_wants_to_continue},
+    utils::{
+        self, nice_directory_display, user_wants_to_continue, ArchivePassword, BackgroundChecksum, Bytes, Checksum,
+        EscapedPathDisplay, ProgressEvent, ProgressReport, RateLimitedReader, RetryingWriter, TruncationCheck, Volumes,
+    },
     QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
@@ -24,16 +33,24 @@ use crate::{
 // formats contains each format necessary for decompression, example: [Gz, Tar] (in decompression order)
 // output_dir it's where the file will be decompressed to, this function assumes that the directory exists
 // output_file_path is only used when extracting single file formats, not archive formats like .tar or .zip
+// unpack_options controls how archive entries are written, see `UnpackOptions`
 pub fn decompress_file(
     input_file_path: &Path,
     formats: Vec<Extension>,
     output_dir: &Path,
     output_file_path: PathBuf,
     question_policy: QuestionPolicy,
+    unpack_options: &UnpackOptions,
     quiet: bool,
 ) -> crate::Result<()> {
     assert!(output_dir.exists());
-    let reader = fs::File::open(input_file_path)?;
+    if !unpack_options.force {
+        check::check_output_dir_is_not_sensitive(output_dir)?;
+    }
+
+    let mut password = ArchivePassword::new(input_file_path, unpack_options.keyring_service.as_deref());
+    // Checksums of the unpacked files, with `--checksum`
+    let mut entry_digests = vec![];
 
     // Zip archives are special, because they require io::Seek, so it requires it's logic separated
     // from decoder chaining.
@@ -47,12 +64,23 @@ pub fn decompress_file(
         ..
     }] = formats.as_slice()
     {
-        let zip_archive = zip::ZipArchive::new(reader)?;
-        let files_unpacked = if let ControlFlow::Continue(files) = smart_unpack(
-            |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, quiet),
+        let file = fs::File::open(input_file_path)?;
+        let size = file.metadata()?.len();
+        let zip_archive = zip::ZipArchive::new(ProgressReport::new(file, size, quiet))?;
+        // The archive is read by seeking, so it is read again to be hashed
+        let archive_checksum = match unpack_options.checksums {
+            Some(_) => Some(BackgroundChecksum::new(fs::File::open(input_file_path)?).0),
+            None => None,
+        };
+        let files_unpacked = if let ControlFlow::Continue((files, _)) = smart_unpack(
+            |output_dir| {
+                crate::archive::zip::unpack_archive(zip_archive, output_dir, unpack_options, &mut password, quiet)
+            },
             output_dir,
             &output_file_path,
             question_policy,
+            unpack_options,
+            &mut entry_digests,
         )? {
             files
         } else {
@@ -69,27 +97,30 @@ pub fn decompress_file(
             nice_directory_display(output_dir),
             files_unpacked
         );
+        add_checksums(unpack_options, input_file_path, archive_checksum, entry_digests)?;
+        utils::emit_progress(ProgressEvent::Done { path: input_file_path });
 
         return Ok(());
     }
 
     // Will be used in decoder chaining
-    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
-    let mut reader: Box<dyn Read> = Box::new(reader);
-
-    // Grab previous decoder and wrap it inside of a new one
-    let chain_reader_decoder = |format: &CompressionFormat, decoder: Box<dyn Read>| -> crate::Result<Box<dyn Read>> {
-        let decoder: Box<dyn Read> = match format {
-            Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
-            Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
-            Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
-            Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
-            Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-            Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-            Tar | Zip | SevenZip => unreachable!(),
-        };
-        Ok(decoder)
+    let reader: Box<dyn Read + Send> = if utils::is_url(input_file_path) {
+        utils::open_url(input_file_path, quiet)?
+    } else {
+        let volumes = Volumes::open(input_file_path)?;
+        let size = volumes.total_size();
+        Box::new(ProgressReport::new(volumes, size, quiet))
+    };
+    // Hashed on another thread as it is read, with `--checksum`
+    let (archive_checksum, reader): (_, Box<dyn Read + Send>) = match unpack_options.checksums {
+        Some(_) => {
+            let (checksum, reader) = BackgroundChecksum::new(reader);
+            (Some(checksum), Box::new(reader))
+        }
+        None => (None, reader),
     };
+    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+    let mut reader: Box<dyn Read + Send> = Box::new(reader);
 
     let (first_extension, extensions) = split_first_compression_format(&formats);
 
@@ -98,25 +129,76 @@ pub fn decompress_file(
     }
 
     let files_unpacked = match first_extension {
-        Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd => {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress => {
             reader = chain_reader_decoder(&first_extension, reader)?;
 
-            let mut writer = match utils::ask_to_create_file(&output_file_path, question_policy)? {
+            // The only file has no time of its own, the one of the compressed file is used, downloads
+            // are always newer
+            let modified = if utils::is_url(input_file_path) {
+                None
+            } else {
+                Some(fs::metadata(input_file_path)?.modified()?)
+            };
+            let overwrite_policy = unpack_options.overwrite_policy;
+            let writer = match utils::ask_to_create_file(&output_file_path, modified, overwrite_policy)? {
                 Some(file) => file,
+                None if overwrite_policy == OverwritePolicy::IfNewer => {
+                    info!(
+                        accessible,
+                        "Kept '{}', it is as new as '{}', or newer.",
+                        EscapedPathDisplay::new(&output_file_path),
+                        EscapedPathDisplay::new(input_file_path)
+                    );
+                    return Ok(());
+                }
                 None => return Ok(()),
             };
 
-            io::copy(&mut reader, &mut writer)?;
+            let mut writer = RetryingWriter::new(writer, unpack_options.retries, &output_file_path);
+            let reader = RateLimitedReader::new(reader, unpack_options.rate_limit.clone());
+            // Its size is only known once decompressed, it was cut if anything is left
+            let mut reader = reader.take(unpack_options.head.unwrap_or(u64::MAX));
+            let bytes = match io::copy(&mut reader, &mut writer) {
+                Ok(bytes) => bytes,
+                // The partial file would only take the space needed by other files
+                Err(err) if err.kind() == io::ErrorKind::StorageFull => {
+                    drop(writer);
+                    let _ = fs::remove_file(&output_file_path);
+                    return Err(err.into());
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if unpack_options.head.is_some() && reader.into_inner().read(&mut [0])? > 0 {
+                unpack_options.report_cut(&output_file_path);
+            }
+            utils::emit_progress(ProgressEvent::Entry {
+                path: &output_file_path,
+                bytes,
+            });
+
+            if unpack_options.checksums.is_some() {
+                let checksum = Checksum::default();
+                checksum.update_from(&mut fs::File::open(&output_file_path)?)?;
+                entry_digests.push((output_file_path.clone(), checksum.to_hex()));
+            }
 
             1
         }
         Tar => {
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::tar::unpack_archive(reader, output_dir, quiet),
+            let reader = Box::new(RateLimitedReader::new(reader, unpack_options.rate_limit.clone()));
+            let mut file_flags = vec![];
+            if let ControlFlow::Continue((files, root)) = smart_unpack(
+                |output_dir| {
+                    crate::archive::tar::unpack_archive(reader, output_dir, unpack_options, quiet, &mut file_flags)
+                },
                 output_dir,
                 &output_file_path,
                 question_policy,
+                unpack_options,
+                &mut entry_digests,
             )? {
+                // Only set once the files are in place, immutable ones can't be moved
+                crate::archive::restore_file_flags(&root, file_flags)?;
                 files
             } else {
                 return Ok(());
@@ -135,11 +217,15 @@ pub fn decompress_file(
             io::copy(&mut reader, &mut vec)?;
             let zip_archive = zip::ZipArchive::new(io::Cursor::new(vec))?;
 
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, quiet),
+            if let ControlFlow::Continue((files, _)) = smart_unpack(
+                |output_dir| {
+                    crate::archive::zip::unpack_archive(zip_archive, output_dir, unpack_options, &mut password, quiet)
+                },
                 output_dir,
                 &output_file_path,
                 question_policy,
+                unpack_options,
+                &mut entry_digests,
             )? {
                 files
             } else {
@@ -147,11 +233,92 @@ pub fn decompress_file(
             }
         }
         SevenZip => {
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::sevenz::decompress_sevenz(input_file_path, output_dir),
+            // 7z archives are read by seeking, decoded and downloaded ones are loaded in memory first
+            let in_memory = formats.len() > 1 || utils::is_url(input_file_path);
+            if in_memory {
+                warn_user_about_loading_sevenz_in_memory();
+
+                if !user_wants_to_continue(input_file_path, question_policy, QuestionAction::Decompression)? {
+                    return Ok(());
+                }
+            }
+
+            if let ControlFlow::Continue((files, _)) = smart_unpack(
+                |output_dir| {
+                    if in_memory {
+                        let mut vec = vec![];
+                        io::copy(&mut reader, &mut vec)?;
+                        crate::archive::sevenz::decompress_sevenz(
+                            io::Cursor::new(vec),
+                            output_dir,
+                            unpack_options,
+                            &mut password,
+                        )
+                    } else {
+                        // Opened again to be read by seeking, `reader` is left unread, and hashed
+                        // whole once unpacked with `--checksum`
+                        let volumes = Volumes::open(input_file_path)?;
+                        let size = volumes.total_size();
+                        crate::archive::sevenz::decompress_sevenz(
+                            ProgressReport::new(volumes, size, quiet),
+                            output_dir,
+                            unpack_options,
+                            &mut password,
+                        )
+                    }
+                },
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                &mut entry_digests,
+            )? {
+                files
+            } else {
+                return Ok(());
+            }
+        }
+        Wim => {
+            // The image is read in place, unless it has to be decoded or downloaded first
+            let temp_image = (formats.len() > 1 || utils::is_url(input_file_path))
+                .then(|| crate::archive::write_temp_image(&mut reader))
+                .transpose()?;
+            let image_path = temp_image.as_ref().map_or(input_file_path, |image| image.path());
+
+            if let ControlFlow::Continue((files, _)) = smart_unpack(
+                |output_dir| crate::archive::wim::unpack_archive(image_path, output_dir),
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                &mut entry_digests,
+            )? {
+                files
+            } else {
+                return Ok(());
+            }
+        }
+        Iso => {
+            // The image is read in place, unless it has to be decoded or downloaded first, which
+            // reported the progress already
+            let temp_image = (formats.len() > 1 || utils::is_url(input_file_path))
+                .then(|| crate::archive::write_temp_image(&mut reader))
+                .transpose()?;
+            let image_path = temp_image.as_ref().map_or(input_file_path, |image| image.path());
+            let file = fs::File::open(image_path)?;
+            let size = file.metadata()?.len();
+            let image = BufReader::with_capacity(
+                BUFFER_CAPACITY,
+                ProgressReport::new(file, size, quiet || temp_image.is_some()),
+            );
+
+            if let ControlFlow::Continue((files, _)) = smart_unpack(
+                |output_dir| crate::archive::iso::unpack_archive(image, output_dir, unpack_options, quiet),
                 output_dir,
                 &output_file_path,
                 question_policy,
+                unpack_options,
+                &mut entry_digests,
             )? {
                 files
             } else {
@@ -170,23 +337,189 @@ pub fn decompress_file(
         nice_directory_display(output_dir)
     );
     info!(accessible, "Files unpacked: {}", files_unpacked);
+    add_checksums(unpack_options, input_file_path, archive_checksum, entry_digests)?;
+    utils::emit_progress(ProgressEvent::Done { path: input_file_path });
+
+    Ok(())
+}
 
+/// Adds the checksum of the archive at `input_file_path` and the ones of the files unpacked from
+/// it to the table printed with `--checksum`
+fn add_checksums(
+    unpack_options: &UnpackOptions,
+    input_file_path: &Path,
+    archive_checksum: Option<BackgroundChecksum<impl Read>>,
+    entry_digests: Vec<(PathBuf, String)>,
+) -> crate::Result<()> {
+    if let (Some(checksums), Some(archive_checksum)) = (&unpack_options.checksums, archive_checksum) {
+        checksums.add(input_file_path, archive_checksum.finish()?, entry_digests);
+    }
     Ok(())
 }
 
-/// Unpacks an archive with some heuristics
-/// - If the archive contains only one file, it will be extracted to the `output_dir`
-/// - If the archive contains multiple files, it will be extracted to a subdirectory of the
-///   output_dir named after the archive (given by `output_file_path`)
+/// Wraps `decoder`, the reader of data compressed with `format`, in the decoder of that format
+pub(super) fn chain_reader_decoder(
+    format: &CompressionFormat,
+    decoder: Box<dyn Read + Send>,
+) -> crate::Result<Box<dyn Read + Send>> {
+    let decoder: Box<dyn Read + Send> = match format {
+        Gzip => Box::new(flate2::read::MultiGzDecoder::new(decoder)),
+        Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
+        Bzip3 => Box::new(crate::bzip3::Bzip3Decoder::new(decoder)?),
+        Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
+        Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
+        Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
+        Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
+        Compress => Box::new(crate::lzw::LzwDecoder::new(decoder)),
+        Tar | Zip | SevenZip | Wim | Iso => unreachable!(),
+    };
+    // Counts the decompressed bytes, to report them if the data is truncated
+    Ok(Box::new(TruncationCheck::new(decoder)))
+}
+
+/// Decompresses the file at `input_file_path` into memory, without writing anything to disk, which
+/// is meant for small archives, like bundles of config files.
+///
+/// Its formats are given by its extensions. Archives give the path and contents of each of their
+/// files, leaving out directories, links and special files, single compressed files give their
+/// name without the extensions of the formats.
+///
+/// Fails once more than `size_limit` bytes would be held in memory, which counts the decompressed
+/// files, and the zip and 7z archives that have to be decoded before they can be read.
+/// Encrypted archives fail as if their password was wrong, it is never asked for.
+pub fn decompress_in_memory(input_file_path: &Path, size_limit: u64) -> crate::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let (output_path, formats) = extension::separate_known_extensions_from_name(input_file_path);
+    check::check_missing_formats_when_decompressing(&[input_file_path.to_owned()], std::slice::from_ref(&formats))?;
+    let name = output_path.file_name().map(PathBuf::from).unwrap_or_default();
+
+    let limit = MemoryLimit {
+        input_file_path,
+        size_limit,
+        remaining: Cell::new(size_limit),
+    };
+    let mut files = vec![];
+    let mut read_file = |path: PathBuf, reader: &mut dyn Read| -> crate::Result<()> {
+        files.push((path, limit.read(reader)?));
+        Ok(())
+    };
+
+    let file = Volumes::open(input_file_path)?;
+    let (first_extension, extensions) = split_first_compression_format(&formats);
+    // Plain zip and 7z archives are read in place, they need to seek
+    if extensions.is_empty() && matches!(first_extension, Zip | SevenZip) {
+        match first_extension {
+            Zip => crate::archive::zip::read_files(zip::ZipArchive::new(file)?, read_file)?,
+            _ => crate::archive::sevenz::read_files(file, read_file)?,
+        }
+        return Ok(files);
+    }
+
+    let mut reader: Box<dyn Read + Send> = Box::new(BufReader::with_capacity(BUFFER_CAPACITY, file));
+    for format in extensions.iter().rev() {
+        reader = chain_reader_decoder(format, reader)?;
+    }
+
+    match first_extension {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress => {
+            let mut reader = chain_reader_decoder(&first_extension, reader)?;
+            read_file(name, &mut reader)?;
+        }
+        Tar => crate::archive::tar::read_files(tar::Archive::new(reader), read_file)?,
+        Zip => {
+            let archive = io::Cursor::new(limit.read(&mut reader)?);
+            crate::archive::zip::read_files(zip::ZipArchive::new(archive)?, read_file)?;
+        }
+        SevenZip => {
+            let archive = io::Cursor::new(limit.read(&mut reader)?);
+            crate::archive::sevenz::read_files(archive, read_file)?;
+        }
+        Wim | Iso => {
+            let error = FinalError::with_title(format!(
+                "Cannot decompress '{}' into memory",
+                EscapedPathDisplay::new(input_file_path)
+            ))
+            .detail("WIM and ISO images can only be unpacked to disk");
+            return Err(Error::Custom { reason: error });
+        }
+    }
+
+    Ok(files)
+}
+
+/// How much more [`decompress_in_memory`] can hold in memory
+struct MemoryLimit<'a> {
+    input_file_path: &'a Path,
+    size_limit: u64,
+    remaining: Cell<u64>,
+}
+
+impl MemoryLimit<'_> {
+    /// Reads all of `reader` into memory, failing if it is more than what is left of the limit
+    fn read(&self, reader: &mut dyn Read) -> crate::Result<Vec<u8>> {
+        let remaining = self.remaining.get();
+        let mut data = vec![];
+        Read::take(reader, remaining.saturating_add(1)).read_to_end(&mut data)?;
+
+        if data.len() as u64 > remaining {
+            let error = FinalError::with_title(format!(
+                "Cannot decompress '{}' into memory",
+                EscapedPathDisplay::new(self.input_file_path)
+            ))
+            .detail(format!(
+                "It decompresses to more than the limit of {}",
+                Bytes::new(self.size_limit)
+            ));
+            return Err(Error::Custom { reason: error });
+        }
+        self.remaining.set(remaining - data.len() as u64);
+        Ok(data)
+    }
+}
+
+/// Unpacks an archive, laying out the files as set by `unpack_options.strategy`, by default with
+/// some heuristics
+/// - If the archive contains only one file at its root, it will be extracted to the `output_dir`
+/// - If the archive contains multiple files, they will be extracted to a subdirectory of the
+///   output_dir named after the archive (given by `output_file_path`), the user is asked to
+///   confirm it when there are more than [`MAX_ROOT_ENTRIES`](crate::archive::MAX_ROOT_ENTRIES)
+///
+/// The archive is unpacked in a temporary directory inside of `unpack_options.temp_dir`, or of
+/// `output_dir` by default, so that moving the files is a rename. When it is on another file system,
+/// the files are copied instead.
+///
+/// With `unpack_options.subtree`, only what is inside of that path of the archive is moved, as if
+/// it was the root of the archive.
+///
+/// Whatever is at the final destination is replaced as `unpack_options.overwrite_policy` says.
+/// With [`OverwritePolicy::IfNewer`], it is only replaced by newer files, and directories are
+/// merged. With `unpack_options.merge`, directories are merged too, and only the conflicting files
+/// are replaced. With `unpack_options.keep_directory_symlink`, existing symlinks to directories
+/// where there are unpacked directories are merged with instead of replaced.
+///
+/// With `unpack_options.safe_symlinks`, the symlinks leading outside of the archive are removed,
+/// or replaced by copies of their targets in it, before moving, the removed ones aren't counted.
+/// With `unpack_options.macos_forks` set to merge, the AppleDouble files are merged into the files
+/// they belong to before moving, and aren't counted. With `unpack_options.time_rounding`, the
+/// modification times are rounded before moving, with `unpack_options.touch` they are set to now. With `unpack_options.checksums`, the unpacked
+/// files are hashed before moving, and their checksums pushed to `entry_digests` by the paths they
+/// were moved to.
+///
+/// Returns the number of unpacked files, and the directory the root of the archive ended up in.
+///
 /// Note: This functions assumes that `output_dir` exists
 fn smart_unpack(
     unpack_fn: impl FnOnce(&Path) -> crate::Result<usize>,
     output_dir: &Path,
     output_file_path: &Path,
     question_policy: QuestionPolicy,
-) -> crate::Result<ControlFlow<(), usize>> {
+    unpack_options: &UnpackOptions,
+    entry_digests: &mut Vec<(PathBuf, String)>,
+) -> crate::Result<ControlFlow<(), (usize, PathBuf)>> {
     assert!(output_dir.exists());
-    let temp_dir = tempfile::tempdir_in(output_dir)?;
+    let overwrite_policy = unpack_options.overwrite_policy;
+    let merges = unpack_options.merge || overwrite_policy == OverwritePolicy::IfNewer;
+    let keep_directory_symlinks = unpack_options.keep_directory_symlink;
+    let temp_dir = tempfile::tempdir_in(unpack_options.temp_dir.as_deref().unwrap_or(output_dir))?;
     let temp_dir_path = temp_dir.path();
     info!(
         accessible,
@@ -194,43 +527,196 @@ fn smart_unpack(
         nice_directory_display(temp_dir_path)
     );
 
-    let files = unpack_fn(temp_dir_path)?;
-
-    let root_contains_only_one_element = fs::read_dir(temp_dir_path)?.count() == 1;
-    if root_contains_only_one_element {
-        // Only one file in the root directory, so we can just move it to the output directory
-        let file = fs::read_dir(temp_dir_path)?.next().expect("item exists")?;
-        let file_path = file.path();
-        let file_name = file_path
-            .file_name()
-            .expect("Should be safe because paths in archives should not end with '..'");
-        let correct_path = output_dir.join(file_name);
-        // Before moving, need to check if a file with the same name already exists
-        if !utils::clear_path(&correct_path, question_policy)? {
-            return Ok(ControlFlow::Break(()));
-        }
-        fs::rename(&file_path, &correct_path)?;
-        info!(
-            accessible,
-            "Successfully moved {} to {}.",
-            nice_directory_display(&file_path),
-            nice_directory_display(&correct_path)
-        );
+    let mut files = unpack_fn(temp_dir_path)?;
+    if unpack_options.safe_symlinks {
+        files = files.saturating_sub(utils::make_symlinks_safe(temp_dir_path)?);
+    }
+    if unpack_options.macos_forks == MacosForks::Merge {
+        let merged = utils::merge_apple_doubles(temp_dir_path)?;
+        if merged > 0 {
+            info!(
+                accessible,
+                "Merged {} AppleDouble files into the files they belong to.", merged
+            );
+        }
+        files = files.saturating_sub(merged);
+    }
+    if unpack_options.touch {
+        let now = FileTime::now();
+        let now = match unpack_options.time_rounding {
+            Some(granularity) => FileTime::from_unix_time(utils::round_time(now.unix_seconds(), granularity), 0),
+            None => now,
+        };
+        utils::touch_mtimes(temp_dir_path, now)?;
+    } else if let Some(granularity) = unpack_options.time_rounding {
+        utils::round_mtimes(temp_dir_path, granularity)?;
+    }
+
+    // With `--subtree`, the files are moved from that directory of the archive instead
+    let unpacked_dir = match &unpack_options.subtree {
+        Some(subtree) => temp_dir_path.join(subtree),
+        None => temp_dir_path.to_owned(),
+    };
+    let root_entries = match fs::symlink_metadata(&unpacked_dir) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(&unpacked_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?,
+        // The subtree is a single file
+        Ok(_) => vec![unpacked_dir.clone()],
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            // Safe unwrap, the temporary directory itself always exists
+            let subtree = unpack_options.subtree.as_deref().unwrap();
+            let error = FinalError::with_title(format!(
+                "No entries of the archive are inside of '{}'",
+                EscapedPathDisplay::new(subtree)
+            ))
+            .detail("--subtree must be a path in the archive")
+            .hint("Use `ouch list` to see the paths in the archive");
+            return Err(Error::InvalidUsage { reason: error });
+        }
+        Err(err) => return Err(err.into()),
+    };
+    // Hashed in parallel before being moved, with `--checksum`
+    let digests = match unpack_options.checksums {
+        Some(_) => utils::hash_files(&unpacked_dir)?,
+        None => vec![],
+    };
+    let into_new_directory = unpack_options.strategy.uses_new_directory(root_entries.len(), || {
+        utils::user_wants_new_directory(output_file_path, root_entries.len(), question_policy)
+    })?;
+    // Every file is moved on its own, out of the directories it was in
+    let root_entries = if unpack_options.strategy == UnpackStrategy::Flatten {
+        let mut files = vec![];
+        for entry in root_entries {
+            flattened_files(entry, &mut files)?;
+        }
+        files
+    } else {
+        root_entries
+    };
+
+    // Existing files that are newer than the unpacked ones, with `--if-newer`, or that the user
+    // didn't want overwritten, with `--merge`
+    let mut kept = 0;
+    let root = if !into_new_directory {
+        // A single file in the root directory, or the user wants them there, so we can just move
+        // them to the output directory
+        for file_path in root_entries {
+            let file_name = file_path
+                .file_name()
+                .expect("Should be safe because paths in archives should not end with '..'");
+            let correct_path = output_dir.join(file_name);
+            check_not_staging_dir(&correct_path, temp_dir_path)?;
+            // Before moving, need to check if a file with the same name already exists
+            // Kept symlinks to directories are merged with, the other files in them are kept
+            let into_directory_symlink =
+                keep_directory_symlinks && file_path.is_dir() && utils::is_directory_symlink(&correct_path);
+            if merges || into_directory_symlink {
+                kept += utils::move_merging(&file_path, &correct_path, overwrite_policy, keep_directory_symlinks)?;
+            } else {
+                let modified = fs::symlink_metadata(&file_path)?.modified()?;
+                if !utils::clear_path(&correct_path, Some(modified), overwrite_policy)? {
+                    return Ok(ControlFlow::Break(()));
+                }
+                utils::rename_or_copy(&file_path, &correct_path)?;
+            }
+            info!(
+                accessible,
+                "Successfully moved {} to {}.",
+                nice_directory_display(&file_path),
+                nice_directory_display(&correct_path)
+            );
+        }
+        output_dir
     } else {
-        // Multiple files in the root directory, so:
+        // Many files in the root directory, so:
         // Rename the temporary directory to the archive name, which is output_file_path
         // One case to handle tough is we need to check if a file with the same name already exists
-        if !utils::clear_path(output_file_path, question_policy)? {
-            return Ok(ControlFlow::Break(()));
+        check_not_staging_dir(output_file_path, temp_dir_path)?;
+        let into_directory_symlink = keep_directory_symlinks && utils::is_directory_symlink(output_file_path);
+        if merges || into_directory_symlink {
+            kept += utils::move_merging(
+                &unpacked_dir,
+                output_file_path,
+                overwrite_policy,
+                keep_directory_symlinks,
+            )?;
+        } else {
+            let modified = fs::symlink_metadata(&unpacked_dir)?.modified()?;
+            if !utils::clear_path(output_file_path, Some(modified), overwrite_policy)? {
+                return Ok(ControlFlow::Break(()));
+            }
+            utils::rename_or_copy(&unpacked_dir, output_file_path)?;
         }
-        fs::rename(temp_dir_path, output_file_path)?;
         info!(
             accessible,
             "Successfully moved {} to {}.",
-            nice_directory_display(temp_dir_path),
+            nice_directory_display(&unpacked_dir),
             nice_directory_display(output_file_path)
         );
+        output_file_path
+    };
+
+    if kept > 0 && overwrite_policy == OverwritePolicy::IfNewer {
+        info!(
+            accessible,
+            "Kept {} existing files that are as new as the ones in the archive, or newer.", kept
+        );
+    } else if kept > 0 {
+        info!(accessible, "Kept {} existing files that were not overwritten.", kept);
     }
 
-    Ok(ControlFlow::Continue(files))
+    let flattens = unpack_options.strategy == UnpackStrategy::Flatten;
+    entry_digests.extend(digests.into_iter().map(|(path, checksum)| match path.file_name() {
+        Some(file_name) if flattens => (root.join(file_name), checksum),
+        _ => (root.join(path), checksum),
+    }));
+
+    Ok(ControlFlow::Continue((files, root.to_owned())))
+}
+
+/// Fails if the unpacked files would be moved to `target` while it is, or is inside of, the
+/// temporary directory `staging_dir` they are in, overwriting it would lose them
+///
+/// Entries can have any name, so the random name of the temporary directory can be taken by one,
+/// and `--temp-dir` can be anywhere in the output directory.
+fn check_not_staging_dir(target: &Path, staging_dir: &Path) -> crate::Result<()> {
+    // A symlink at `target` would be replaced without touching what it points to
+    let resolved_target = match (target.parent(), target.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            fs::canonicalize(parent).map(|parent| parent.join(name))
+        }
+        (Some(_), Some(name)) => fs::canonicalize(".").map(|current_dir| current_dir.join(name)),
+        _ => fs::canonicalize(target),
+    };
+    let (Ok(resolved_target), Ok(resolved_staging_dir)) = (resolved_target, fs::canonicalize(staging_dir)) else {
+        return Ok(());
+    };
+    if !resolved_staging_dir.starts_with(&resolved_target) {
+        return Ok(());
+    }
+
+    let error = FinalError::with_title(format!(
+        "Cannot move the unpacked files to '{}'",
+        EscapedPathDisplay::new(target)
+    ))
+    .detail(format!(
+        "It holds the temporary directory '{}' they were unpacked to",
+        EscapedPathDisplay::new(staging_dir)
+    ))
+    .hint("Use --temp-dir to unpack somewhere else first, or -d to choose another output directory");
+    Err(Error::Custom { reason: error })
+}
+
+/// Pushes `path` to `files`, or the files inside of it if it's a directory, without following
+/// symlinks
+fn flattened_files(path: PathBuf, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !fs::symlink_metadata(&path)?.is_dir() {
+        files.push(path);
+        return Ok(());
+    }
+    for entry in fs::read_dir(&path)? {
+        flattened_files(entry?.path(), files)?;
+    }
+    Ok(())
 }
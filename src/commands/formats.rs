@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+use crate::extension::{self, ALL_FORMATS};
+
+const HEADER: [&str; 7] = [
+    "Format",
+    "Extensions",
+    "Compress",
+    "Decompress",
+    "Levels",
+    "Encrypted",
+    "Runs",
+];
+
+/// Prints a table of the formats ouch supports, with their extensions and what can be done with
+/// them, followed by the aliases of several formats.
+///
+/// Every format can be decompressed, the encrypted archives only with their passwords, and the
+/// formats handled by other programs only when `--use-command` allows running them.
+pub fn print_formats() -> crate::Result<()> {
+    let yes_no = |value: bool| if value { "yes" } else { "no" }.to_owned();
+    let rows: Vec<[String; 7]> = ALL_FORMATS
+        .iter()
+        .map(|format| {
+            let extensions = format.extensions();
+            [
+                extensions[0].to_owned(),
+                extensions.join(", "),
+                yes_no(!format.is_decompression_only()),
+                yes_no(true),
+                format.levels().map_or("-".to_owned(), |levels| {
+                    format!("{} to {}", levels.start(), levels.end())
+                }),
+                if format.can_decrypt() { "decompress" } else { "-" }.to_owned(),
+                format.external_command().unwrap_or("-").to_owned(),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADER.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut stdout = io::stdout().lock();
+    let header = HEADER.map(str::to_owned);
+    for row in [&header].into_iter().chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(stdout, "{}", line.trim_end())?;
+    }
+
+    let aliases = extension::aliases()
+        .into_iter()
+        .map(|(alias, formats)| format!("{alias} ({formats})"))
+        .collect::<Vec<_>>();
+    writeln!(stdout)?;
+    writeln!(stdout, "Aliases: {}", aliases.join(", "))?;
+
+    Ok(())
+}
@@ -6,10 +6,13 @@ use std::{
 use fs_err as fs;
 
 use crate::{
-    commands::warn_user_about_loading_zip_in_memory,
+    commands::{
+        decompress::chain_reader_decoder, warn_user_about_loading_sevenz_in_memory,
+        warn_user_about_loading_zip_in_memory,
+    },
     extension::CompressionFormat::{self, *},
-    list::{self, FileInArchive, ListOptions},
-    utils::user_wants_to_continue,
+    list::{self, FileInArchive, JsonManifest, ListOptions},
+    utils::{user_wants_to_continue, Volumes},
     QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
@@ -20,9 +23,22 @@ pub fn list_archive_contents(
     formats: Vec<CompressionFormat>,
     list_options: ListOptions,
     question_policy: QuestionPolicy,
+    manifest: Option<&mut JsonManifest>,
 ) -> crate::Result<()> {
-    let reader = fs::File::open(archive_path)?;
+    if let Some(files) = read_archive_entries(archive_path, formats, question_policy)? {
+        list::list_files(archive_path, files, list_options, manifest)?;
+    }
+    Ok(())
+}
 
+/// Reads the entries of the archive at `archive_path`, decoded with `formats` (in decompression order).
+///
+/// Returns `Ok(None)` if the user opted not to load the archive in memory.
+pub fn read_archive_entries(
+    archive_path: &Path,
+    formats: Vec<CompressionFormat>,
+    question_policy: QuestionPolicy,
+) -> crate::Result<Option<Box<dyn Iterator<Item = crate::Result<FileInArchive>>>>> {
     // Zip archives are special, because they require io::Seek, so it requires it's logic separated
     // from decoder chaining.
     //
@@ -31,32 +47,16 @@ pub fn list_archive_contents(
     //
     // Any other Zip decompression done can take up the whole RAM and freeze ouch.
     if let &[Zip] = formats.as_slice() {
-        let zip_archive = zip::ZipArchive::new(reader)?;
+        let zip_archive = zip::ZipArchive::new(fs::File::open(archive_path)?)?;
         let files = crate::archive::zip::list_archive(zip_archive);
-        list::list_files(archive_path, files, list_options)?;
 
-        return Ok(());
+        return Ok(Some(Box::new(files)));
     }
 
     // Will be used in decoder chaining
-    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+    let reader = BufReader::with_capacity(BUFFER_CAPACITY, Volumes::open(archive_path)?);
     let mut reader: Box<dyn Read + Send> = Box::new(reader);
 
-    // Grab previous decoder and wrap it inside of a new one
-    let chain_reader_decoder =
-        |format: &CompressionFormat, decoder: Box<dyn Read + Send>| -> crate::Result<Box<dyn Read + Send>> {
-            let decoder: Box<dyn Read + Send> = match format {
-                Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
-                Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
-                Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
-                Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
-                Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-                Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-                Tar | Zip | SevenZip => unreachable!(),
-            };
-            Ok(decoder)
-        };
-
     for format in formats.iter().skip(1).rev() {
         reader = chain_reader_decoder(format, reader)?;
     }
@@ -68,7 +68,7 @@ pub fn list_archive_contents(
                 warn_user_about_loading_zip_in_memory();
 
                 if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
+                    return Ok(None);
                 }
             }
 
@@ -79,29 +79,44 @@ pub fn list_archive_contents(
             Box::new(crate::archive::zip::list_archive(zip_archive))
         }
         SevenZip => {
-            if formats.len() > 1 {
-                warn_user_about_loading_zip_in_memory();
+            // Plain 7z archives are read by seeking, decoded ones are loaded in memory first
+            let files = if formats.len() > 1 {
+                warn_user_about_loading_sevenz_in_memory();
+
                 if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
+                    return Ok(None);
                 }
-            }
 
-            let mut files = Vec::new();
+                let mut vec = vec![];
+                io::copy(&mut reader, &mut vec)?;
+                crate::archive::sevenz::list_archive(io::Cursor::new(vec))?
+            } else {
+                crate::archive::sevenz::list_archive(Volumes::open(archive_path)?)?
+            };
+            Box::new(files.into_iter().map(Ok))
+        }
+        Wim => {
+            let temp_image = (formats.len() > 1)
+                .then(|| crate::archive::write_temp_image(&mut reader))
+                .transpose()?;
+            let image_path = temp_image.as_ref().map_or(archive_path, |image| image.path());
+
+            let files = crate::archive::wim::list_archive(image_path)?;
+            Box::new(files.into_iter().map(Ok))
+        }
+        Iso => {
+            let temp_image = (formats.len() > 1)
+                .then(|| crate::archive::write_temp_image(&mut reader))
+                .transpose()?;
+            let image_path = temp_image.as_ref().map_or(archive_path, |image| image.path());
 
-            sevenz_rust::decompress_file_with_extract_fn(archive_path, ".", |entry, _, _| {
-                files.push(Ok(FileInArchive {
-                    path: entry.name().into(),
-                    is_dir: entry.is_directory(),
-                }));
-                Ok(true)
-            })
-            .map_err(crate::Error::SevenzipError)?;
-            Box::new(files.into_iter())
+            let image = BufReader::with_capacity(BUFFER_CAPACITY, fs::File::open(image_path)?);
+            let files = crate::archive::iso::list_archive(image)?;
+            Box::new(files.into_iter().map(Ok))
         }
-        Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd => {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress => {
             panic!("Not an archive! This should never happen, if it does, something is wrong with `CompressionFormat::is_archive()`. Please report this error!");
         }
     };
-    list::list_files(archive_path, files, list_options)?;
-    Ok(())
+    Ok(Some(files))
 }
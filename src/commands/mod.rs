@@ -1,26 +1,57 @@
 //! Receive command from the cli and call the respective function for that command.
 
+mod bench;
 mod compress;
 mod decompress;
+mod formats;
 mod list;
+mod probe;
+mod show_metadata;
+mod verify;
 
-use std::{ops::ControlFlow, path::PathBuf};
+pub use decompress::decompress_in_memory;
 
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ops::ControlFlow,
+    path::{Component, Path, PathBuf},
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+
+use fs_err as fs;
 use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use utils::colors;
 
 use crate::{
+    archive::{metadata::Provenance, JunkPaths, UnpackOptions, UnpackStrategy},
     check,
-    cli::Subcommand,
-    commands::{compress::compress_files, decompress::decompress_file, list::list_archive_contents},
+    cli::{ForcedFormat, MacosForks, OverwritePolicy, StripExtension, Subcommand},
+    commands::{
+        bench::bench_formats,
+        compress::{compress_files, CompressOptions, CompressionOutput},
+        decompress::decompress_file,
+        formats::print_formats,
+        list::{list_archive_contents, read_archive_entries},
+        probe::probe_files,
+        show_metadata::show_metadata,
+        verify::verify_archive,
+    },
     error::{Error, FinalError},
-    extension::{self, parse_format},
+    extension::{self, parse_format, CompressionFormat, Extension},
     info,
-    list::ListOptions,
-    utils::{self, to_utf, EscapedPathDisplay, FileVisibilityPolicy},
+    list::{list_differences, list_files, JsonManifest, ListOptions},
+    utils::{
+        self, to_utf, EntryOrder, EscapedPathDisplay, ExtensionLevels, FileVisibilityPolicy, NameTemplate,
+        ProgressEvent, RateLimit,
+    },
     warning, CliArgs, QuestionPolicy,
 };
 
+/// Path of the output given to the checks and the archive builders with `--to-stdout`, which
+/// skip the input it resolves to, when stdout is redirected to one of them
+const STDOUT_PATH: &str = "/dev/stdout";
+
 /// Warn the user that (de)compressing this .zip archive might freeze their system.
 fn warn_user_about_loading_zip_in_memory() {
     const ZIP_IN_MEMORY_LIMITATION_WARNING: &str = "\n\
@@ -31,6 +62,70 @@ fn warn_user_about_loading_zip_in_memory() {
     warning!("{}", ZIP_IN_MEMORY_LIMITATION_WARNING);
 }
 
+/// Warn the user that (de)compressing this .7z archive might freeze their system.
+fn warn_user_about_loading_sevenz_in_memory() {
+    const SEVENZ_IN_MEMORY_LIMITATION_WARNING: &str = "\n\
+        \tThe format '.7z' is limited and cannot be (de)compressed using encoding streams.\n\
+        \tWhen using '.7z' with other formats, (de)compression must be done in-memory\n\
+        \tCareful, you might run out of RAM if the archive is too large!";
+
+    warning!("{}", SEVENZ_IN_MEMORY_LIMITATION_WARNING);
+}
+
+/// Reads the order of the entries to store from `path`, set by `--preserve-order`, which is an
+/// archive or a list of paths, one per line.
+///
+/// Returns `Ok(None)` if the user opted not to load the archive in memory.
+fn read_entry_order(path: &Path, question_policy: QuestionPolicy) -> crate::Result<Option<EntryOrder>> {
+    let formats = extension::extensions_from_path(path);
+    if formats.is_empty() {
+        let contents = fs::read_to_string(path)?;
+        let paths = contents.lines().filter(|line| !line.is_empty()).map(PathBuf::from);
+        return Ok(Some(EntryOrder::new(paths)));
+    }
+
+    let formats = extension::flatten_compression_formats(&formats);
+    let Some(files) = read_archive_entries(path, formats, question_policy)? else {
+        return Ok(None);
+    };
+    let paths = files
+        .map(|file| file.map(|file| file.path))
+        .collect::<crate::Result<Vec<_>>>()?;
+    Ok(Some(EntryOrder::new(paths)))
+}
+
+/// Where the single compressed file at `input` is decompressed to in `output_dir`, named without
+/// the extensions given by `strip`, `stem` is its name without the extensions of its formats.
+///
+/// ".out" is appended to the name when it would be `input` itself, which would be destroyed.
+fn single_file_output_path(
+    input: &Path,
+    stem: &Path,
+    strip: StripExtension,
+    output_dir: &Path,
+) -> crate::Result<PathBuf> {
+    if utils::is_url(input) {
+        let name = utils::url_file_name(input);
+        return Ok(output_dir.join(extension::single_file_output_name(&name, stem, strip)));
+    }
+
+    // Safe unwrap, the inputs are canonicalized
+    let name = extension::single_file_output_name(Path::new(input.file_name().unwrap()), stem, strip);
+    if fs::canonicalize(output_dir)?.join(&name) != input {
+        return Ok(output_dir.join(name));
+    }
+
+    let mut name = name.into_os_string();
+    name.push(".out");
+    info!(
+        accessible,
+        "Naming the output of '{}' '{}', so it doesn't overwrite the compressed file.",
+        EscapedPathDisplay::new(input),
+        EscapedPathDisplay::new(Path::new(&name))
+    );
+    Ok(output_dir.join(name))
+}
+
 /// This function checks what command needs to be run and performs A LOT of ahead-of-time checks
 /// to assume everything is OK.
 ///
@@ -42,23 +137,98 @@ pub fn run(
 ) -> crate::Result<()> {
     match args.cmd {
         Subcommand::Compress {
-            files,
+            mut files,
             output: output_path,
+            to_stdout,
+            mkdir,
+            auto_name,
             level,
             fast,
             slow,
+            max_depth,
+            rsyncable,
+            cdc,
+            ldm,
+            threads,
+            total_threads,
+            dereference,
+            one_file_system,
+            exclude_vcs,
+            update,
+            dry_run,
+            list,
+            junk_paths,
+            rename_collisions,
+            no_dir_entries,
+            base_dirs,
+            preserve_order,
+            sevenz_method,
+            force_format_on_output,
+            preserve_flags,
+            preserve_btime,
+            atime_preserve,
+            hard_dereference,
+            compress_level_by_extension,
+            metadata,
+            sidecar,
+            checksum_file,
+            verify,
+            no_overwrite_newer,
+            force,
         } => {
+            let output_path = if to_stdout {
+                PathBuf::from(STDOUT_PATH)
+            } else {
+                output_path
+            };
+
             // After cleaning, if there are no input files left, exit
             if files.is_empty() {
-                return Err(FinalError::with_title("No files to compress").into());
+                return Err(Error::InvalidUsage {
+                    reason: FinalError::with_title("No files to compress"),
+                });
             }
 
+            let output_path = match auto_name {
+                Some(format) => {
+                    let output_path = utils::timestamped_path(&output_path, &format)?;
+                    info!(
+                        accessible,
+                        "Named the output '{}'.",
+                        EscapedPathDisplay::new(&output_path)
+                    );
+                    output_path
+                }
+                None => output_path,
+            };
+
+            if to_stdout && atty::is(atty::Stream::Stdout) {
+                let error = FinalError::with_title("Refusing to write the compressed output to a terminal")
+                    .detail("--to-stdout writes binary data, which would garble the terminal")
+                    .hint("Redirect it to a file, like '> archive.tar.gz', or pipe it to another program");
+                return Err(Error::InvalidUsage { reason: error });
+            }
+            // The questions are printed to stdout, where the output goes
+            let question_policy = if to_stdout && question_policy == QuestionPolicy::Ask {
+                QuestionPolicy::AlwaysYes
+            } else {
+                question_policy
+            };
+
             // Formats from path extension, like "file.tar.gz.xz" -> vec![Tar, Gzip, Lzma]
             let (formats_from_flag, formats) = match args.format {
-                Some(formats) => {
+                // --to-stdout requires --format, there are no extensions to compare it with
+                Some(formats) if to_stdout => {
                     let parsed_formats = parse_format(&formats)?;
                     (Some(formats), parsed_formats)
                 }
+                Some(formats) => {
+                    let parsed_formats = parse_format(&formats)?;
+                    match check::check_format_matches_output(&parsed_formats, &output_path, force_format_on_output)? {
+                        ForcedFormat::Format => (Some(formats), parsed_formats),
+                        ForcedFormat::Extension => (None, extension::extensions_from_path(&output_path)),
+                    }
+                }
                 None => (None, extension::extensions_from_path(&output_path)),
             };
 
@@ -69,11 +239,179 @@ pub fn run(
                 formats_from_flag.as_ref(),
             )?;
             check::check_archive_formats_position(&formats, &output_path)?;
+            check::check_decompression_only_formats(&formats, &output_path)?;
 
-            let output_file = match utils::ask_to_create_file(&output_path, question_policy)? {
-                Some(writer) => writer,
-                None => return Ok(()),
+            let has_zstd = formats
+                .iter()
+                .any(|extension| extension.compression_formats.contains(&CompressionFormat::Zstd));
+            if rsyncable && !has_zstd {
+                warning!("--rsyncable only has an effect when compressing with zstd, ignoring it.");
+            }
+            if ldm && !has_zstd {
+                warning!("--ldm only has an effect when compressing with zstd, ignoring it.");
+            }
+            if threads.is_some() && !has_zstd {
+                warning!("--threads only has an effect when compressing with zstd, ignoring it.");
+            }
+            if total_threads.is_some() && !has_zstd {
+                warning!("--total-threads only has an effect when compressing with zstd, ignoring it.");
+            }
+            let has_gzip = formats
+                .iter()
+                .any(|extension| extension.compression_formats.contains(&CompressionFormat::Gzip));
+            if cdc && !has_zstd && !has_gzip {
+                warning!("--cdc only has an effect when compressing with gzip or zstd, ignoring it.");
+            }
+            // The option that set the zstd threads, to tell which one has no effect
+            let threads_option = if threads.is_some() {
+                "--threads"
+            } else {
+                "--total-threads"
             };
+            let threads = match total_threads {
+                Some(total_threads) if has_zstd => {
+                    let total_threads = total_threads.unwrap_or_else(compress::available_threads);
+                    // Without --threads, as many as can run in parallel
+                    let effective = threads.unwrap_or_else(compress::available_threads).min(total_threads);
+                    info!(
+                        accessible,
+                        "Compressing with {effective} zstd threads, of the {total_threads} allowed by --total-threads."
+                    );
+                    Some(effective)
+                }
+                _ => threads,
+            };
+
+            let has_sevenz = formats
+                .iter()
+                .any(|extension| extension.compression_formats.contains(&CompressionFormat::SevenZip));
+            if sevenz_method.is_some() && !has_sevenz {
+                warning!("--7z-method only has an effect when compressing to 7z, ignoring it.");
+            }
+
+            let has_tar = formats
+                .iter()
+                .any(|extension| extension.compression_formats.contains(&CompressionFormat::Tar));
+            if preserve_flags && !has_tar {
+                warning!("--preserve-flags only has an effect when compressing to tar, ignoring it.");
+            }
+            if preserve_btime && !has_tar {
+                warning!("--preserve-btime only has an effect when compressing to tar, ignoring it.");
+            }
+            if hard_dereference && !has_tar {
+                warning!("--hard-dereference only has an effect when compressing to tar, ignoring it.");
+            }
+
+            let extension_levels = match compress_level_by_extension {
+                Some(rules) => Some(ExtensionLevels::with_rules(rules.as_deref().unwrap_or_default())?),
+                None => None,
+            };
+            if let Some(extension_levels) = &extension_levels {
+                if formats[0].compression_formats[0] != CompressionFormat::Zip {
+                    warning!("--compress-level-by-extension only has an effect when compressing to zip, ignoring it.");
+                } else if args.verbose >= 1 {
+                    info!(accessible, "Compression levels by extension: {extension_levels}.");
+                }
+            }
+
+            let metadata = metadata.then(Provenance::current);
+            if metadata.is_some() && !sidecar {
+                match formats[0].compression_formats[0] {
+                    CompressionFormat::Tar | CompressionFormat::Zip => {}
+                    CompressionFormat::SevenZip | CompressionFormat::Wim => {
+                        warning!("This archive format has no place for --metadata, use --sidecar to keep it.");
+                    }
+                    _ => warning!("--metadata is only embedded in tar and zip archives, use --sidecar to keep it."),
+                }
+            }
+
+            #[cfg(not(unix))]
+            if one_file_system {
+                warning!("--one-file-system is only supported on Unix, ignoring it.");
+            }
+
+            for file in &files {
+                if !base_dirs.is_empty() && !base_dirs.iter().any(|dir| file != dir && file.starts_with(dir)) {
+                    warning!(
+                        "'{}' is not inside of the --base directories, it is stored by its name.",
+                        EscapedPathDisplay::new(file)
+                    );
+                }
+            }
+
+            let file_visibility_policy = file_visibility_policy
+                .max_depth(max_depth)
+                .follow_links(dereference)
+                .one_file_system(one_file_system)
+                .exclude_vcs(exclude_vcs)
+                .base_dirs(base_dirs);
+            let file_visibility_policy = match preserve_order {
+                Some(path) => match read_entry_order(&path, question_policy)? {
+                    Some(entry_order) => file_visibility_policy.entry_order(Some(Arc::new(entry_order))),
+                    None => return Ok(()),
+                },
+                None => file_visibility_policy,
+            };
+            file_visibility_policy.sort_inputs(&mut files);
+            let junk_paths = junk_paths.then(|| JunkPaths::new(rename_collisions));
+
+            if dry_run {
+                let new_files = compress::files_to_compress(
+                    &files,
+                    &output_path,
+                    &file_visibility_policy,
+                    junk_paths,
+                    no_dir_entries,
+                )?;
+
+                if !list {
+                    let new_files = new_files.into_iter().map(Ok);
+                    return list_files(&output_path, new_files, ListOptions::default(), None);
+                }
+
+                check::check_for_non_archive_formats(
+                    std::slice::from_ref(&output_path),
+                    std::slice::from_ref(&formats),
+                )?;
+
+                if !output_path.exists() {
+                    return list_differences(&output_path, [], new_files);
+                }
+
+                let formats = extension::flatten_compression_formats(&formats);
+                if let Some(old_files) = read_archive_entries(&output_path, formats, question_policy)? {
+                    list_differences(&output_path, old_files, new_files)?;
+                }
+                return Ok(());
+            }
+
+            if update {
+                check::check_update_format(&formats, &output_path)?;
+            }
+
+            // With --update, the existing archive isn't replaced, its entries are kept
+            if no_overwrite_newer && !force && !update && output_path.exists() {
+                if let ControlFlow::Break(_) = check::check_output_not_newer(&output_path, &files, question_policy)? {
+                    return Ok(());
+                }
+            }
+
+            // With --update, the new archive is written next to the existing one, which only gets
+            // replaced once it is complete
+            let existing_archive = if update && output_path.exists() {
+                Some(zip::ZipArchive::new(fs::File::open(&output_path)?)?)
+            } else {
+                None
+            };
+
+            if let Some(output_dir) = output_path.parent().filter(|_| !to_stdout) {
+                if !output_dir.as_os_str().is_empty() && !output_dir.exists() {
+                    if !mkdir && !utils::user_wants_to_create_dir(output_dir, question_policy)? {
+                        return Ok(());
+                    }
+                    utils::create_dir_if_non_existent(output_dir)?;
+                }
+            }
 
             let level = if fast {
                 Some(1) // Lowest level of compression
@@ -83,25 +421,141 @@ pub fn run(
                 level
             };
 
+            // The walks made before compressing skip the same entries, which are only reported once
+            let silent_policy = file_visibility_policy.clone().silent(true);
+
+            // With --update, the existing archive is only replaced once the new one is complete
+            let replaced_size = match fs::metadata(&output_path) {
+                Ok(metadata) if !update && metadata.is_file() => metadata.len(),
+                _ => 0,
+            };
+            let available = compress::available_space_for(&output_path, replaced_size).filter(|_| !to_stdout);
+            // The inputs are only walked to check the free space when it is lower than a quick upper
+            // bound of their size, and to warn about --threads
+            let check_space = available.is_some_and(|available| {
+                compress::inputs_size_upper_bound(&files).is_none_or(|bound| bound > available)
+            });
+            let warn_threads = threads.filter(|_| has_zstd);
+            if check_space || warn_threads.is_some() {
+                let input_size =
+                    compress::files_to_compress(&files, &output_path, &silent_policy, None, no_dir_entries)?
+                        .iter()
+                        .map(|file| file.size)
+                        .sum();
+                if let Some(available) = available.filter(|_| check_space) {
+                    compress::check_free_space(&output_path, input_size, available, question_policy)?;
+                }
+                if let Some(threads) = warn_threads {
+                    compress::warn_if_threads_unused(input_size, level, threads, threads_option);
+                }
+            }
+
+            let (output, temp_path) = if to_stdout {
+                (CompressionOutput::Stdout, None)
+            } else if existing_archive.is_some() {
+                let output_dir = match output_path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let (file, temp_path) = tempfile::NamedTempFile::new_in(output_dir)?.into_parts();
+                fs::set_permissions(&temp_path, fs::metadata(&output_path)?.permissions())?;
+                (
+                    CompressionOutput::File(fs::File::from_parts(file, &*temp_path)),
+                    Some(temp_path),
+                )
+            } else {
+                match utils::ask_to_create_file(&output_path, None, question_policy.into())? {
+                    Some(writer) => (CompressionOutput::File(writer), None),
+                    None => return Ok(()),
+                }
+            };
+
+            // The stored paths are computed before compressing, `compress_files` consumes the inputs
+            let sources = if verify {
+                let junk_paths = junk_paths.is_some().then(|| JunkPaths::new(rename_collisions));
+                Some(compress::source_files(
+                    &files,
+                    &output_path,
+                    &silent_policy,
+                    junk_paths,
+                    no_dir_entries,
+                )?)
+            } else {
+                None
+            };
+            let verify_formats = extension::flatten_compression_formats(&formats);
+
+            let vcs_skipped = Arc::clone(&file_visibility_policy.vcs_skipped);
+
+            let checksum = checksum_file.is_some().then(utils::Checksum::default);
             let compress_result = compress_files(
                 files,
                 formats,
-                output_file,
+                output,
                 &output_path,
                 args.quiet,
                 question_policy,
-                file_visibility_policy,
-                level,
+                CompressOptions {
+                    file_visibility_policy,
+                    level,
+                    rsyncable,
+                    ldm,
+                    threads,
+                    cdc,
+                    existing_archive,
+                    junk_paths,
+                    no_dir_entries,
+                    sevenz_method: sevenz_method.unwrap_or_default(),
+                    preserve_flags,
+                    preserve_btime,
+                    atime_preserve,
+                    hard_dereference,
+                    time_rounding: args.time_rounding,
+                    extension_levels,
+                    metadata: metadata.clone(),
+                    print_entry_sizes: args.verbose >= 2,
+                    checksum: checksum.clone(),
+                },
             );
 
             if let Ok(true) = compress_result {
+                if let Some(temp_path) = temp_path {
+                    temp_path.persist(&output_path).map_err(|err| err.error)?;
+                }
+
                 // this is only printed once, so it doesn't result in much text. On the other hand,
                 // having a final status message is important especially in an accessibility context
                 // as screen readers may not read a commands exit code, making it hard to reason
                 // about whether the command succeeded without such a message
-                info!(accessible, "Successfully compressed '{}'.", to_utf(&output_path));
-            } else {
-                // If Ok(false) or Err() occurred, delete incomplete file at `output_path`
+                if to_stdout {
+                    info!(accessible, "Successfully compressed to stdout.");
+                } else {
+                    info!(accessible, "Successfully compressed '{}'.", to_utf(&output_path));
+                }
+                utils::emit_progress(ProgressEvent::Done { path: &output_path });
+
+                let vcs_skipped = vcs_skipped.load(Ordering::Relaxed);
+                if vcs_skipped > 0 {
+                    info!(accessible, "Skipped {} version control paths.", vcs_skipped);
+                }
+
+                if let (Some(metadata), true) = (&metadata, sidecar) {
+                    let sidecar_path = metadata.write_sidecar(&output_path)?;
+                    info!(accessible, "Wrote the metadata to '{}'.", to_utf(&sidecar_path));
+                }
+
+                if let (Some(checksum_file), Some(checksum)) = (&checksum_file, &checksum) {
+                    utils::write_checksum_file(checksum_file, &output_path, checksum)?;
+                    info!(accessible, "Wrote the checksum to '{}'.", to_utf(checksum_file));
+                }
+
+                if let Some(sources) = &sources {
+                    verify_archive(&output_path, verify_formats, sources)?;
+                }
+            } else if temp_path.is_none() && !to_stdout {
+                // If Ok(false) or Err() occurred, delete incomplete file at `output_path`, unless
+                // it is the archive being updated, then the dropped `temp_path` is deleted instead,
+                // or it was written to stdout
                 //
                 // if deleting fails, print an extra alert message pointing
                 // out that we left a possibly CORRUPTED file at `output_path`
@@ -122,33 +576,151 @@ pub fn run(
 
             compress_result?;
         }
-        Subcommand::Decompress { files, output_dir } => {
+        Subcommand::Decompress {
+            files,
+            output_dir,
+            output_name_template,
+            no_smart_unpack,
+            shared_dir,
+            flatten,
+            temp_dir,
+            subtree,
+            index,
+            interactive,
+            chown,
+            show_metadata: print_metadata,
+            preserve_permissions,
+            no_preserve_permissions,
+            preserve_setuid,
+            touch,
+            verify_crc,
+            remove_destination,
+            if_newer,
+            merge,
+            keep_directory_symlink,
+            safe_symlinks,
+            overwrite_policy,
+            force,
+            keyring,
+            forget_password,
+            continue_on_password_failure,
+            limit_rate,
+            head,
+            retries,
+            normalize_separators,
+            max_entries,
+            concat_tar,
+            preserve_flags,
+            preserve_btime,
+            atime_preserve,
+            case_conflicts,
+            parent_dirs,
+            order,
+            macos_forks,
+            checksum,
+            strip_extension,
+        } => {
+            if preserve_permissions {
+                warning!(
+                    "--preserve-permissions is deprecated and has no effect, the permissions are restored by default."
+                );
+            }
+
+            let name_template = output_name_template.as_deref().map(NameTemplate::parse).transpose()?;
             let mut output_paths = vec![];
             let mut formats = vec![];
 
+            check::check_split_volumes(&files)?;
+
             if let Some(format) = args.format {
                 let format = parse_format(&format)?;
                 for path in files.iter() {
+                    let url_file_name = utils::is_url(path).then(|| utils::url_file_name(path));
+                    let path = url_file_name.as_deref().unwrap_or(path);
                     let file_name = path.file_name().ok_or_else(|| Error::NotFound {
                         error_title: format!("{} does not have a file name", EscapedPathDisplay::new(path)),
                     })?;
-                    output_paths.push(file_name.as_ref());
+                    output_paths.push(extension::name_without_format(Path::new(file_name), &format).to_owned());
                     formats.push(format.clone());
                 }
             } else {
                 for path in files.iter() {
-                    let (path, mut file_formats) = extension::separate_known_extensions_from_name(path);
+                    // Downloads can't be peeked at, only the extensions of their URLs tell the formats
+                    if utils::is_url(path) {
+                        let file_name = utils::url_file_name(path);
+                        let (output_path, file_formats) = extension::separate_known_extensions_from_name(&file_name);
+                        output_paths.push(output_path.to_owned());
+                        formats.push(file_formats);
+                        continue;
+                    }
+
+                    let (output_path, mut file_formats) = extension::separate_known_extensions_from_name(path);
 
                     if let ControlFlow::Break(_) = check::check_mime_type(path, &mut file_formats, question_policy)? {
                         return Ok(());
                     }
 
-                    output_paths.push(path);
+                    output_paths.push(output_path.to_owned());
                     formats.push(file_formats);
                 }
             }
 
             check::check_missing_formats_when_decompressing(&files, &formats)?;
+            check::check_extraction_order(&files, &formats, order)?;
+            check::check_url_formats(&files, &formats)?;
+
+            let has_tar = formats
+                .iter()
+                .flatten()
+                .any(|extension| extension.compression_formats.contains(&CompressionFormat::Tar));
+            if concat_tar && !has_tar {
+                warning!("--concat-tar only has an effect when decompressing tar archives, ignoring it.");
+            }
+            let has_wim = formats
+                .iter()
+                .flatten()
+                .any(|extension| extension.compression_formats.contains(&CompressionFormat::Wim));
+            let preserve_btime = if preserve_btime && !has_tar {
+                warning!("--preserve-btime only has an effect when decompressing tar archives, ignoring it.");
+                false
+            } else if preserve_btime && !utils::CAN_SET_BIRTH_TIME {
+                warning!("--preserve-btime can't set creation times on this platform, ignoring it.");
+                false
+            } else {
+                preserve_btime
+            };
+            if atime_preserve && !has_tar {
+                warning!("--atime-preserve only has an effect when decompressing tar archives, ignoring it.");
+            }
+            if head.is_some() && has_wim {
+                warning!("--head has no effect on wim images, they are unpacked whole.");
+            }
+
+            // Archive paths are relative, so "./dir/", "/dir" and "dir" are the same directory
+            let subtree = subtree
+                .map(|subtree| -> PathBuf {
+                    subtree
+                        .components()
+                        .filter(|component| matches!(component, Component::Normal(_)))
+                        .collect()
+                })
+                .filter(|subtree| !subtree.as_os_str().is_empty());
+            if subtree.is_some() {
+                check::check_subtree(&files, &formats)?;
+            }
+            if index.is_some() {
+                check::check_index(&files, &formats)?;
+            }
+            if interactive {
+                check::check_interactive(&files, &formats)?;
+            }
+            let macos_forks = if macos_forks == MacosForks::Merge && !cfg!(target_os = "macos") {
+                warning!("--macos-forks merge only works on macOS, keeping the AppleDouble files as they are.");
+                MacosForks::Keep
+            } else {
+                macos_forks
+            };
+            let owner = chown.as_deref().map(utils::Owner::parse).transpose()?;
 
             // The directory that will contain the output files
             // We default to the current directory if the user didn't specify an output directory with --dir
@@ -159,23 +731,232 @@ pub fn run(
                 PathBuf::from(".")
             };
 
+            // Without a directory, `--temp-dir` uses the system one, which is where $TMPDIR points to
+            let temp_dir = temp_dir.map(|dir| dir.unwrap_or_else(std::env::temp_dir));
+            if let Some(temp_dir) = &temp_dir {
+                utils::create_dir_if_non_existent(temp_dir)?;
+            }
+
+            // Each archive of a batch goes into a directory named after it, unless they always do
+            let archive_count = formats
+                .iter()
+                .filter(|formats| formats.first().is_some_and(Extension::is_archive))
+                .count();
+            let dir_per_archive = archive_count > 1 && !shared_dir && !no_smart_unpack && name_template.is_none();
+
+            // Archives named by the template get a directory of their own, their files go right in it
+            let strategy = if flatten {
+                UnpackStrategy::Flatten
+            } else if no_smart_unpack {
+                UnpackStrategy::AlwaysSubdir
+            } else if name_template.is_some() || dir_per_archive {
+                UnpackStrategy::CurrentDir
+            } else {
+                UnpackStrategy::Smart
+            };
+
+            let unpack_options = UnpackOptions {
+                preserve_permissions: !no_preserve_permissions,
+                preserve_setuid,
+                exclude: file_visibility_policy.exclude,
+                verify_crc,
+                overwrite_policy: OverwritePolicy::resolve(
+                    overwrite_policy,
+                    if_newer,
+                    remove_destination,
+                    question_policy,
+                ),
+                merge,
+                keep_directory_symlink,
+                safe_symlinks,
+                force,
+                keyring_service: keyring,
+                rate_limit: limit_rate.map(|bytes_per_second| Arc::new(RateLimit::new(bytes_per_second))),
+                head,
+                preserve_flags,
+                preserve_btime,
+                atime_preserve,
+                case_conflicts,
+                parent_dirs,
+                order,
+                macos_forks,
+                temp_dir,
+                subtree,
+                indexes: index,
+                selected: None,
+                owner,
+                retries,
+                normalize_separators,
+                max_entries,
+                strategy,
+                concat_tar,
+                time_rounding: args.time_rounding,
+                touch,
+                checksums: checksum.then(Default::default),
+            };
+
+            if let (true, Some(keyring_service)) = (forget_password, &unpack_options.keyring_service) {
+                for path in &files {
+                    utils::forget_password(keyring_service, path)?;
+                }
+            }
+
+            if print_metadata {
+                for (path, formats) in files.iter().zip(&formats) {
+                    if utils::is_url(path) {
+                        warning!(
+                            "Cannot show the metadata of '{}', it is only read from files.",
+                            EscapedPathDisplay::new(path)
+                        );
+                        continue;
+                    }
+                    show_metadata(path, &extension::flatten_compression_formats(formats))?;
+                }
+            }
+
+            // Where each file goes, archives are unpacked into the directory and single file formats
+            // are written to the path
+            let outputs = formats
+                .iter()
+                .zip(output_paths)
+                .enumerate()
+                .map(|(i, (formats, file_name))| -> crate::Result<(PathBuf, PathBuf)> {
+                    let Some(template) = &name_template else {
+                        if formats.first().is_some_and(Extension::is_archive) {
+                            let output_path = output_dir.join(file_name);
+                            if dir_per_archive {
+                                utils::create_dir_if_non_existent(&output_path)?;
+                                return Ok((output_path.clone(), output_path));
+                            }
+                            return Ok((output_dir.clone(), output_path));
+                        }
+                        return Ok((
+                            output_dir.clone(),
+                            single_file_output_path(&files[i], &file_name, strip_extension, &output_dir)?,
+                        ));
+                    };
+                    let stem = file_name.to_string_lossy();
+                    let format = formats.iter().map(ToString::to_string).collect::<Vec<_>>().join(".");
+                    let output_path = output_dir.join(template.render(&stem, &format, i + 1));
+
+                    if formats.first().is_some_and(Extension::is_archive) {
+                        utils::create_dir_if_non_existent(&output_path)?;
+                        Ok((output_path.clone(), output_path))
+                    } else {
+                        Ok((output_dir.clone(), output_path))
+                    }
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+
+            // The entries of every archive are picked before any of them is unpacked in parallel
+            let selections = files
+                .iter()
+                .zip(&formats)
+                .map(|(path, formats)| -> crate::Result<Option<Arc<HashSet<PathBuf>>>> {
+                    if !interactive {
+                        return Ok(None);
+                    }
+                    let formats = extension::flatten_compression_formats(formats);
+                    let entries = match read_archive_entries(path, formats, question_policy)? {
+                        Some(entries) => entries.collect::<crate::Result<Vec<_>>>()?,
+                        None => vec![],
+                    };
+                    Ok(Some(Arc::new(utils::select_entries(path, entries)?)))
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+
+            // Archives skipped by --continue-on-password-failure, reported once the others are done
+            let password_failures = Mutex::new(vec![]);
+
             files
                 .par_iter()
                 .zip(formats)
-                .zip(output_paths)
-                .try_for_each(|((input_path, formats), file_name)| {
-                    let output_file_path = output_dir.join(file_name); // Path used by single file format archives
-                    decompress_file(
+                .zip(outputs)
+                .zip(selections)
+                .try_for_each(|(((input_path, formats), (output_dir, output_file_path)), selected)| {
+                    let unpack_options = match selected {
+                        Some(selected) if selected.is_empty() => {
+                            info!(
+                                accessible,
+                                "Skipping '{}', none of its entries were selected.",
+                                EscapedPathDisplay::new(input_path)
+                            );
+                            return Ok(());
+                        }
+                        Some(selected) => Cow::Owned(UnpackOptions {
+                            selected: Some(selected),
+                            ..unpack_options.clone()
+                        }),
+                        None => Cow::Borrowed(&unpack_options),
+                    };
+                    let result = decompress_file(
                         input_path,
                         formats,
                         &output_dir,
                         output_file_path,
                         question_policy,
+                        &unpack_options,
                         args.quiet,
                     )
+                    .map_err(|err| match err {
+                        // The partial files were removed along with the temporary directory
+                        Error::StorageFull { reason } => Error::StorageFull {
+                            reason: reason
+                                .detail(format!(
+                                    "Cleaned up the partial extraction of '{}'",
+                                    EscapedPathDisplay::new(input_path)
+                                ))
+                                .hint("Free up some space, or decompress to another disk with --dir"),
+                        },
+                        err => err,
+                    });
+
+                    match result {
+                        Err(err) if continue_on_password_failure && err.is_password_error() => {
+                            utils::warn_or_fail(format!(
+                                "Skipping '{}', its password is wrong or missing",
+                                EscapedPathDisplay::new(input_path)
+                            ))?;
+                            password_failures.lock().unwrap().push(input_path.clone());
+                            Ok(())
+                        }
+                        result => result,
+                    }
                 })?;
+
+            if let Some(checksums) = &unpack_options.checksums {
+                checksums.print();
+            }
+
+            let password_failures = password_failures.into_inner().unwrap();
+            if !password_failures.is_empty() {
+                let error = FinalError::with_title(format!(
+                    "Could not decompress {} of {} files, their passwords are wrong or missing",
+                    password_failures.len(),
+                    files.len()
+                ))
+                .detail(format!(
+                    "Skipped: {}",
+                    utils::pretty_format_list_of_paths(&password_failures)
+                ))
+                .hint("Decompress them again with their passwords");
+
+                // Nothing was decompressed, it's no partial success
+                if password_failures.len() == files.len() {
+                    return Err(Error::PasswordError { reason: error });
+                }
+                return Err(Error::PartialSuccess { reason: error });
+            }
         }
-        Subcommand::List { archives: files, tree } => {
+        Subcommand::List {
+            archives: files,
+            tree,
+            sort,
+            reverse,
+            index,
+            flat_json,
+            print0,
+        } => {
             let mut formats = vec![];
 
             if let Some(format) = args.format {
@@ -198,16 +979,36 @@ pub fn run(
             // Ensure we were not told to list the content of a non-archive compressed file
             check::check_for_non_archive_formats(&files, &formats)?;
 
-            let list_options = ListOptions { tree };
+            let list_options = ListOptions {
+                tree,
+                sort,
+                reverse,
+                index,
+                print0,
+                exclude: Some(&file_visibility_policy.exclude),
+            };
 
+            let mut manifest = flat_json.then(JsonManifest::start);
             for (i, (archive_path, formats)) in files.iter().zip(formats).enumerate() {
-                if i > 0 {
+                if i > 0 && manifest.is_none() && !print0 {
                     println!();
                 }
                 let formats = extension::flatten_compression_formats(&formats);
-                list_archive_contents(archive_path, formats, list_options, question_policy)?;
+                list_archive_contents(archive_path, formats, list_options, question_policy, manifest.as_mut())?;
+            }
+            if let Some(manifest) = manifest {
+                manifest.finish();
             }
         }
+        Subcommand::Bench { files, formats } => {
+            bench_formats(files, formats, file_visibility_policy)?;
+        }
+        Subcommand::Probe { files } => {
+            probe_files(files, args.format)?;
+        }
+        Subcommand::Formats => {
+            print_formats()?;
+        }
     }
     Ok(())
 }
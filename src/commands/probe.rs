@@ -0,0 +1,170 @@
+use std::{
+    ffi::OsString,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use zip::result::ZipError;
+
+use crate::{
+    check,
+    extension::{self, parse_format, split_first_compression_format, CompressionFormat, Extension},
+    utils::{try_infer_extension, EscapedPathDisplay},
+};
+
+/// Where the formats reported by `ouch probe` were detected from
+enum Detection {
+    /// Given with `--format`
+    Flag,
+    /// The file extension, and the contents were recognized as the same format
+    ExtensionConfirmed,
+    /// The file extension, the contents weren't recognized
+    Extension,
+    /// The magic bytes of the contents, the file has no known extension
+    Contents,
+    /// The magic bytes of the contents, which differ from this file extension
+    ContentsOverExtension(Extension),
+    /// Nothing, the format is unknown
+    Unknown,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Detection::Flag => write!(f, "--format"),
+            Detection::ExtensionConfirmed => write!(f, "file extension, confirmed by the magic bytes"),
+            Detection::Extension => write!(f, "file extension, the magic bytes weren't recognized"),
+            Detection::Contents => write!(f, "magic bytes, the file has no known extension"),
+            Detection::ContentsOverExtension(extension) => {
+                write!(f, "magic bytes, which differ from the file extension `{extension}`")
+            }
+            Detection::Unknown => write!(
+                f,
+                "nothing, neither the file extension nor the magic bytes were recognized"
+            ),
+        }
+    }
+}
+
+/// Prints the formats detected for each of `files`, and where they were detected from, without
+/// decompressing them.
+///
+/// The detection is the one done when decompressing, except that nothing is asked, the magic bytes
+/// are preferred over a differing file extension.
+pub fn probe_files(files: Vec<PathBuf>, format: Option<OsString>) -> crate::Result<()> {
+    let format = format.map(|format| parse_format(&format)).transpose()?;
+    let mut stdout = io::stdout().lock();
+
+    for (i, path) in files.iter().enumerate() {
+        if i > 0 {
+            writeln!(stdout)?;
+        }
+
+        let (formats, detection) = match &format {
+            Some(format) => (format.clone(), Detection::Flag),
+            None => detect_formats(path),
+        };
+
+        writeln!(stdout, "{}", EscapedPathDisplay::new(path))?;
+
+        if formats.is_empty() {
+            writeln!(stdout, "  Formats: unknown")?;
+            writeln!(stdout, "  Detected from: {detection}")?;
+            continue;
+        }
+
+        let (first_format, other_formats) = split_first_compression_format(&formats);
+        let kind = if formats[0].is_archive() {
+            "archive"
+        } else {
+            "compressed file"
+        };
+
+        writeln!(
+            stdout,
+            "  Formats: {:?}",
+            extension::flatten_compression_formats(&formats)
+        )?;
+        writeln!(stdout, "  Type: {kind}")?;
+        writeln!(stdout, "  Detected from: {detection}")?;
+
+        // Inside of other formats, the archive would need to be decompressed first
+        let encrypted = match (first_format, other_formats.is_empty()) {
+            (CompressionFormat::Zip, true) => Some(is_zip_encrypted(path)?),
+            (CompressionFormat::SevenZip, true) => Some(is_sevenz_encrypted(path)?),
+            (CompressionFormat::Zip | CompressionFormat::SevenZip, false) => None,
+            _ => Some(false),
+        };
+        let encrypted = match encrypted {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown, the archive is compressed",
+        };
+        writeln!(stdout, "  Encrypted: {encrypted}")?;
+    }
+
+    Ok(())
+}
+
+/// Detects the formats of `path` from its extension and magic bytes, like when decompressing
+fn detect_formats(path: &Path) -> (Vec<Extension>, Detection) {
+    let mut formats = extension::extensions_from_path(path);
+    let detected_format = try_infer_extension(path);
+
+    let detection = match (formats.last(), detected_format) {
+        (None, None) => Detection::Unknown,
+        (None, Some(detected_format)) => {
+            formats.push(detected_format);
+            Detection::Contents
+        }
+        (Some(_), None) => Detection::Extension,
+        (Some(outer_ext), Some(detected_format)) => {
+            if outer_ext
+                .compression_formats
+                .ends_with(detected_format.compression_formats)
+            {
+                Detection::ExtensionConfirmed
+            } else {
+                let outer_ext = outer_ext.clone();
+                check::replace_outer_format(&mut formats, detected_format);
+                Detection::ContentsOverExtension(outer_ext)
+            }
+        }
+    };
+
+    (formats, detection)
+}
+
+/// Checks if any entry of the zip archive at `path` needs a password
+fn is_zip_encrypted(path: &Path) -> crate::Result<bool> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+
+    for idx in 0..archive.len() {
+        if let Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)) = archive.by_index(idx) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Checks if the headers or any of the contents of the 7z archive at `path` are encrypted
+fn is_sevenz_encrypted(path: &Path) -> crate::Result<bool> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let archive = match sevenz_rust::Archive::read(&mut file, len, &[]) {
+        Ok(archive) => archive,
+        // The headers are encrypted too
+        Err(sevenz_rust::Error::PasswordRequired) => return Ok(true),
+        Err(err) => return Err(crate::Error::SevenzipError(err)),
+    };
+
+    Ok(archive.folders.iter().any(|folder| {
+        folder
+            .coders
+            .iter()
+            .any(|coder| coder.decompression_method_id() == sevenz_rust::SevenZMethod::ID_AES256SHA256)
+    }))
+}
@@ -0,0 +1,60 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::metadata::Provenance,
+    commands::decompress::chain_reader_decoder,
+    extension::CompressionFormat::{self, *},
+    utils::EscapedPathDisplay,
+    BUFFER_CAPACITY,
+};
+
+/// Prints the metadata stored with `--metadata` in the file at `path`, decoded with `formats`
+/// (in decompression order), used by `--show-metadata`.
+///
+/// The sidecar file next to it is preferred, then the comment of zip archives and the PAX global
+/// header of tar archives are read, the other formats have no place for it.
+pub fn show_metadata(path: &Path, formats: &[CompressionFormat]) -> crate::Result<()> {
+    let metadata = match Provenance::from_sidecar(path)? {
+        Some(metadata) => Some(metadata),
+        None => embedded_metadata(path, formats)?,
+    };
+
+    let mut stdout = io::stdout().lock();
+    match metadata {
+        Some(metadata) => writeln!(stdout, "Metadata of '{}':\n{metadata}", EscapedPathDisplay::new(path))?,
+        None => writeln!(stdout, "'{}' has no metadata.", EscapedPathDisplay::new(path))?,
+    }
+    Ok(())
+}
+
+fn embedded_metadata(path: &Path, formats: &[CompressionFormat]) -> crate::Result<Option<Provenance>> {
+    let reader = fs::File::open(path)?;
+
+    // A plain zip archive can be read in place, without loading it in memory first
+    if let &[Zip] = formats {
+        return Ok(Provenance::from_zip_comment(zip::ZipArchive::new(reader)?.comment()));
+    }
+
+    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+    let mut reader: Box<dyn Read + Send> = Box::new(reader);
+    for format in formats.iter().skip(1).rev() {
+        reader = chain_reader_decoder(format, reader)?;
+    }
+
+    match formats[0] {
+        Tar => Provenance::from_tar(tar::Archive::new(reader)),
+        Zip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+            Ok(Provenance::from_zip_comment(
+                zip::ZipArchive::new(io::Cursor::new(vec))?.comment(),
+            ))
+        }
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress | SevenZip | Wim | Iso => Ok(None),
+    }
+}
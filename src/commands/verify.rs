@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    commands::decompress::chain_reader_decoder,
+    error::FinalError,
+    extension::CompressionFormat::{self, *},
+    info,
+    utils::EscapedPathDisplay,
+    BUFFER_CAPACITY,
+};
+
+/// Reads back the archive at `archive_path`, decoded with `formats` (in decompression order), used by `--verify`.
+///
+/// Every entry is decoded, so the checksums of the formats are checked, and the contents of the
+/// regular files are compared with their sources in `sources`, keyed by the paths they are stored as.
+pub fn verify_archive(
+    archive_path: &Path,
+    formats: Vec<CompressionFormat>,
+    sources: &HashMap<PathBuf, PathBuf>,
+) -> crate::Result<()> {
+    let reader = fs::File::open(archive_path)?;
+    let mut verification = Verification::new(sources);
+
+    // A plain zip archive can be read in place, without loading it in memory first
+    if let &[Zip] = formats.as_slice() {
+        verify_zip(zip::ZipArchive::new(reader)?, &mut verification)?;
+        return verification.finish(archive_path);
+    }
+
+    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+    let mut reader: Box<dyn Read + Send> = Box::new(reader);
+
+    for format in formats.iter().skip(1).rev() {
+        reader = chain_reader_decoder(format, reader)?;
+    }
+
+    match formats[0] {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress => {
+            let mut reader = chain_reader_decoder(&formats[0], reader)?;
+            // The only file is stored under its own name
+            match sources.keys().next() {
+                Some(path) => verification.check_file(path, &mut reader)?,
+                None => drop(io::copy(&mut reader, &mut io::sink())?),
+            }
+        }
+        Tar => {
+            let mut archive = tar::Archive::new(reader);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let path = entry.path()?.into_owned();
+                    verification.check_file(&path, &mut entry)?;
+                }
+            }
+            // The end of the tar archive can come before the end of the compressed stream, which
+            // holds the checksums of some formats
+            io::copy(&mut archive.into_inner(), &mut io::sink())?;
+        }
+        Zip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+            verify_zip(zip::ZipArchive::new(io::Cursor::new(vec))?, &mut verification)?;
+        }
+        SevenZip => {
+            let mut vec = vec![];
+            io::copy(&mut reader, &mut vec)?;
+
+            // Errors of ouch can't go through `sevenz_rust`, so the reading is stopped and it is returned after
+            let mut check_error = None;
+            sevenz_rust::decompress_with_extract_fn(io::Cursor::new(vec), ".", |entry, reader, _| {
+                if entry.is_directory() {
+                    return Ok(true);
+                }
+                match verification.check_file(Path::new(entry.name()), reader) {
+                    Ok(()) => Ok(true),
+                    Err(err) => {
+                        check_error = Some(err);
+                        Ok(false)
+                    }
+                }
+            })
+            .map_err(crate::Error::SevenzipError)?;
+
+            if let Some(err) = check_error {
+                return Err(err);
+            }
+        }
+        Wim => {
+            let temp_image = (formats.len() > 1)
+                .then(|| crate::archive::write_temp_image(&mut reader))
+                .transpose()?;
+            let image_path = temp_image.as_ref().map_or(archive_path, |image| image.path());
+
+            // wimlib-imagex can only unpack the files to compare them
+            let temp_dir = tempfile::tempdir()?;
+            crate::archive::wim::unpack_archive(image_path, temp_dir.path())?;
+            for path in crate::archive::wim::unpacked_files(temp_dir.path())? {
+                let unpacked_path = temp_dir.path().join(&path);
+                if fs::symlink_metadata(&unpacked_path)?.is_file() {
+                    verification.check_file(&path, &mut fs::File::open(unpacked_path)?)?;
+                }
+            }
+        }
+        // Only the archives just compressed are verified, images can't be
+        Iso => unreachable!(),
+    }
+
+    verification.finish(archive_path)
+}
+
+fn verify_zip<R: Read + io::Seek>(
+    mut archive: zip::ZipArchive<R>,
+    verification: &mut Verification,
+) -> crate::Result<()> {
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        if file.is_file() {
+            let path = PathBuf::from(file.name());
+            verification.check_file(&path, &mut file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compares the files read back from the archive with their sources
+struct Verification<'a> {
+    sources: &'a HashMap<PathBuf, PathBuf>,
+    /// Count of files whose contents matched their sources
+    matched: usize,
+    /// Paths of the files whose contents didn't match their sources
+    mismatched: Vec<PathBuf>,
+}
+
+impl<'a> Verification<'a> {
+    fn new(sources: &'a HashMap<PathBuf, PathBuf>) -> Self {
+        Self {
+            sources,
+            matched: 0,
+            mismatched: vec![],
+        }
+    }
+
+    /// Reads the file stored as `path` from `reader` to the end, comparing it with its source
+    fn check_file(&mut self, path: &Path, reader: &mut dyn Read) -> crate::Result<()> {
+        let stored_crc = crc32(reader)?;
+
+        let Some(source) = self.sources.get(path) else {
+            // Kept from the archive before `--update`, or not readable, so there is nothing to compare
+            return Ok(());
+        };
+        if crc32(&mut fs::File::open(source)?)? == stored_crc {
+            self.matched += 1;
+        } else {
+            self.mismatched.push(path.to_owned());
+        }
+        Ok(())
+    }
+
+    fn finish(self, archive_path: &Path) -> crate::Result<()> {
+        if !self.mismatched.is_empty() {
+            let paths: Vec<_> = self
+                .mismatched
+                .iter()
+                .map(|path| format!("'{}'", EscapedPathDisplay::new(path)))
+                .collect();
+            let error = FinalError::with_title(format!(
+                "Verification of '{}' failed",
+                EscapedPathDisplay::new(archive_path)
+            ))
+            .detail(format!(
+                "{} files do not match their sources: {}",
+                paths.len(),
+                paths.join(", ")
+            ))
+            .hint("The sources may have changed while compressing, otherwise don't rely on this archive.");
+
+            return Err(crate::Error::CorruptedArchive { reason: error });
+        }
+
+        info!(
+            accessible,
+            "Verified '{}', {} files match their sources.",
+            EscapedPathDisplay::new(archive_path),
+            self.matched
+        );
+        Ok(())
+    }
+}
+
+/// Reads `reader` to the end, returning the CRC-32 of its contents
+fn crc32(reader: &mut dyn Read) -> io::Result<u32> {
+    struct Crc32Writer(crc32fast::Hasher);
+
+    impl Write for Crc32Writer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.update(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut writer = Crc32Writer(crc32fast::Hasher::new());
+    io::copy(reader, &mut writer)?;
+    Ok(writer.0.finalize())
+}
@@ -7,7 +7,11 @@ use std::{
     fmt::{self, Display},
 };
 
-use crate::{accessible::is_running_in_accessible_mode, utils::colors::*};
+use crate::{
+    accessible::is_running_in_accessible_mode,
+    utils::{colors::*, find_truncated_error, is_porcelain, write_porcelain_line, Bytes},
+    EXIT_CORRUPTED_ARCHIVE, EXIT_FAILURE, EXIT_PARTIAL_SUCCESS, EXIT_PASSWORD, EXIT_STORAGE_FULL, EXIT_USAGE,
+};
 
 /// All errors that can be generated by `ouch`
 #[derive(Debug)]
@@ -36,6 +40,20 @@ pub enum Error {
     InvalidFormat { reason: String },
     /// From sevenz_rust::Error
     SevenzipError(sevenz_rust::Error),
+    /// Detected from io::Error if .kind() is io::ErrorKind::UnexpectedEof, the count of decompressed
+    /// bytes is known if the error passed through a `TruncationCheck`
+    TruncatedArchive { reason: String, bytes_read: Option<u64> },
+    /// Arguments that can't work, like formats that don't go together, reported before doing anything
+    InvalidUsage { reason: FinalError },
+    /// Damaged archive contents, like a checksum mismatch, also detected from io::Error if
+    /// .kind() is io::ErrorKind::InvalidData
+    CorruptedArchive { reason: FinalError },
+    /// The password of an encrypted archive is wrong, or couldn't be asked for
+    PasswordError { reason: FinalError },
+    /// Detected from io::Error if .kind() is io::ErrorKind::StorageFull
+    StorageFull { reason: FinalError },
+    /// Some of the files were decompressed, the others were skipped with `--continue-on-password-failure`
+    PartialSuccess { reason: FinalError },
 }
 
 /// Alias to std's Result with ouch's Error
@@ -57,6 +75,19 @@ pub struct FinalError {
 
 impl Display for FinalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if is_porcelain() {
+            write_porcelain_line(f, "error", &self.title)?;
+            for detail in &self.details {
+                writeln!(f)?;
+                write_porcelain_line(f, "detail", detail)?;
+            }
+            for hint in &self.hints {
+                writeln!(f)?;
+                write_porcelain_line(f, "hint", hint)?;
+            }
+            return Ok(());
+        }
+
         // Title
         //
         // When in ACCESSIBLE mode, the square brackets are suppressed
@@ -119,6 +150,33 @@ impl FinalError {
     }
 }
 
+impl Error {
+    /// The status code `ouch` exits with when failing with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidUsage { .. } | Error::InvalidFormat { .. } => EXIT_USAGE,
+            err if err.is_password_error() => EXIT_PASSWORD,
+            Error::CorruptedArchive { .. }
+            | Error::InvalidZipArchive(_)
+            | Error::TruncatedArchive { .. }
+            | Error::SevenzipError(_) => EXIT_CORRUPTED_ARCHIVE,
+            Error::StorageFull { .. } => EXIT_STORAGE_FULL,
+            Error::PartialSuccess { .. } => EXIT_PARTIAL_SUCCESS,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    /// Checks if the password of an encrypted archive is wrong or missing
+    pub fn is_password_error(&self) -> bool {
+        matches!(
+            self,
+            Error::PasswordError { .. }
+                | Error::UnsupportedZipArchive(zip::result::ZipError::PASSWORD_REQUIRED)
+                | Error::SevenzipError(sevenz_rust::Error::PasswordRequired)
+        )
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let err = match self {
@@ -140,8 +198,24 @@ impl fmt::Display for Error {
             }
             Error::UnsupportedZipArchive(reason) => FinalError::with_title("Unsupported zip archive").detail(*reason),
             Error::InvalidFormat { reason } => FinalError::with_title("Invalid archive format").detail(reason.clone()),
-            Error::Custom { reason } => reason.clone(),
+            Error::Custom { reason }
+            | Error::InvalidUsage { reason }
+            | Error::CorruptedArchive { reason }
+            | Error::PasswordError { reason }
+            | Error::StorageFull { reason }
+            | Error::PartialSuccess { reason } => reason.clone(),
             Error::SevenzipError(reason) => FinalError::with_title("7z error").detail(reason.to_string()),
+            Error::TruncatedArchive { reason, bytes_read } => {
+                let error = FinalError::with_title("The file appears to be truncated").detail(reason.to_string());
+                let error = match bytes_read {
+                    Some(bytes_read) => error.detail(format!(
+                        "The data ended unexpectedly after decompressing {}",
+                        Bytes::new(*bytes_read)
+                    )),
+                    None => error,
+                };
+                error.hint("The file may be incomplete, like after an interrupted download, try getting it again.")
+            }
         };
 
         write!(f, "{err}")
@@ -160,6 +234,22 @@ impl From<std::io::Error> for Error {
             std::io::ErrorKind::AlreadyExists => Self::AlreadyExists {
                 error_title: err.to_string(),
             },
+            std::io::ErrorKind::UnexpectedEof => match find_truncated_error(&err) {
+                Some(truncated) => Self::TruncatedArchive {
+                    reason: truncated.reason.clone(),
+                    bytes_read: Some(truncated.bytes_read),
+                },
+                None => Self::TruncatedArchive {
+                    reason: err.to_string(),
+                    bytes_read: None,
+                },
+            },
+            std::io::ErrorKind::InvalidData => Self::CorruptedArchive {
+                reason: FinalError::with_title(err.to_string()),
+            },
+            std::io::ErrorKind::StorageFull => Self::StorageFull {
+                reason: FinalError::with_title("Out of disk space").detail(err.to_string()),
+            },
             _other => Self::IoError {
                 reason: err.to_string(),
             },
@@ -194,3 +284,34 @@ impl From<FinalError> for Error {
         Self::Custom { reason: err }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_from_io_errors() {
+        let exit_code = |kind| Error::from(io::Error::from(kind)).exit_code();
+
+        assert_eq!(exit_code(io::ErrorKind::StorageFull), EXIT_STORAGE_FULL);
+        assert_eq!(exit_code(io::ErrorKind::InvalidData), EXIT_CORRUPTED_ARCHIVE);
+        assert_eq!(exit_code(io::ErrorKind::UnexpectedEof), EXIT_CORRUPTED_ARCHIVE);
+        assert_eq!(exit_code(io::ErrorKind::Other), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_password_errors() {
+        let password_required = Error::UnsupportedZipArchive(zip::result::ZipError::PASSWORD_REQUIRED);
+        assert!(password_required.is_password_error());
+        assert_eq!(password_required.exit_code(), EXIT_PASSWORD);
+        assert!(!Error::UnsupportedZipArchive("Compression method not supported").is_password_error());
+
+        let partial = Error::PartialSuccess {
+            reason: FinalError::with_title("Could not decompress 1 of 2 files"),
+        };
+        assert!(!partial.is_password_error());
+        assert_eq!(partial.exit_code(), EXIT_PARTIAL_SUCCESS);
+    }
+}
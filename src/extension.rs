@@ -1,16 +1,23 @@
 //! Our representation of all the supported compression formats.
 
-use std::{ffi::OsStr, fmt, path::Path};
+use std::{
+    ffi::OsStr,
+    fmt,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
 use bstr::ByteSlice;
 
 use self::CompressionFormat::*;
-use crate::{error::Error, warning};
+use crate::{cli::StripExtension, error::Error, warning};
 
-pub const SUPPORTED_EXTENSIONS: &[&str] = &["tar", "zip", "bz", "bz2", "gz", "lz4", "xz", "lzma", "sz", "zst", "7z"];
-pub const SUPPORTED_ALIASES: &[&str] = &["tgz", "tbz", "tlz4", "txz", "tzlma", "tsz", "tzst"];
-pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, gz, lz4, xz, lzma, sz, zst, 7z";
-pub const PRETTY_SUPPORTED_ALIASES: &str = "tgz, tbz, tlz4, txz, tzlma, tsz, tzst";
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "tar", "zip", "bz", "bz2", "bz3", "gz", "lz4", "xz", "lzma", "sz", "zst", "7z", "Z", "wim", "iso",
+];
+pub const SUPPORTED_ALIASES: &[&str] = &["tgz", "tbz", "tlz4", "txz", "tlzma", "tsz", "tzst", "taz"];
+pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, bz3, gz, lz4, xz, lzma, sz, zst, 7z, Z, wim, iso";
+pub const PRETTY_SUPPORTED_ALIASES: &str = "tgz, tbz, tlz4, txz, tlzma, tsz, tzst, taz";
 
 /// A wrapper around `CompressionFormat` that allows combinations like `tgz`
 #[derive(Debug, Clone, Eq)]
@@ -53,6 +60,11 @@ impl fmt::Display for Extension {
     }
 }
 
+/// Every format, in the order `ouch formats` lists them
+pub const ALL_FORMATS: &[CompressionFormat] = &[
+    Tar, Zip, SevenZip, Gzip, Bzip, Bzip3, Lz4, Lzma, Snappy, Zstd, Compress, Wim, Iso,
+];
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 /// Accepted extensions for input and output
 pub enum CompressionFormat {
@@ -60,6 +72,8 @@ pub enum CompressionFormat {
     Gzip,
     /// .bz .bz2
     Bzip,
+    /// .bz3, handled by running `bzip3` when allowed by `--use-command`
+    Bzip3,
     /// .lz4
     Lz4,
     /// .xz .lzma
@@ -74,6 +88,12 @@ pub enum CompressionFormat {
     Zip,
     /// .7z
     SevenZip,
+    /// .Z, from the Unix `compress` tool, can only be decompressed
+    Compress,
+    /// .wim, Windows imaging format, handled by running `wimlib-imagex` when allowed by `--use-command`
+    Wim,
+    /// .iso, ISO 9660 disc images, can only be unpacked and listed
+    Iso,
 }
 
 impl CompressionFormat {
@@ -81,15 +101,83 @@ impl CompressionFormat {
     fn is_archive_format(&self) -> bool {
         // Keep this match like that without a wildcard `_` so we don't forget to update it
         match self {
-            Tar | Zip | SevenZip => true,
+            Tar | Zip | SevenZip | Wim | Iso => true,
             Gzip => false,
             Bzip => false,
+            Bzip3 => false,
             Lz4 => false,
             Lzma => false,
             Snappy => false,
             Zstd => false,
+            Compress => false,
+        }
+    }
+
+    /// Checks if ouch can only decompress this format, but not compress to it
+    pub fn is_decompression_only(&self) -> bool {
+        matches!(self, Compress | Iso)
+    }
+
+    /// The levels of `--level` this format is compressed at, `None` if it has none
+    pub fn levels(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Gzip | Lzma | Snappy | SevenZip => Some(0..=9),
+            Bzip => Some(1..=9),
+            Zstd => Some(zstd::zstd_safe::min_c_level()..=zstd::zstd_safe::max_c_level()),
+            Tar | Zip | Bzip3 | Lz4 | Compress | Wim | Iso => None,
+        }
+    }
+
+    /// `level` clamped to the levels of this format
+    pub fn clamp_level(&self, level: i16) -> i32 {
+        let level = level as i32;
+        self.levels()
+            .map_or(level, |levels| level.clamp(*levels.start(), *levels.end()))
+    }
+
+    /// Checks if ouch can decompress the encrypted archives of this format, given their passwords
+    pub fn can_decrypt(&self) -> bool {
+        match self {
+            Zip | SevenZip => true,
+            Tar | Gzip | Bzip | Bzip3 | Lz4 | Lzma | Snappy | Zstd | Compress | Wim | Iso => false,
         }
     }
+
+    /// The program run to handle this format, when allowed by `--use-command`
+    pub fn external_command(&self) -> Option<&'static str> {
+        match self {
+            Bzip3 => Some("bzip3"),
+            Wim => Some("wimlib-imagex"),
+            Tar | Zip | SevenZip | Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd | Compress | Iso => None,
+        }
+    }
+
+    /// The extensions of this format alone, without the aliases
+    pub fn extensions(&self) -> Vec<&'static str> {
+        SUPPORTED_EXTENSIONS
+            .iter()
+            .copied()
+            .filter(|extension| {
+                to_extension(extension.as_bytes()).is_some_and(|extension| extension.compression_formats == [*self])
+            })
+            .collect()
+    }
+}
+
+/// The aliases of several formats, with their extensions, like ("tgz", "tar.gz")
+pub fn aliases() -> Vec<(&'static str, String)> {
+    SUPPORTED_ALIASES
+        .iter()
+        .filter_map(|alias| {
+            let extension = to_extension(alias.as_bytes())?;
+            let formats = extension
+                .compression_formats
+                .iter()
+                .map(|format| format.extensions()[0])
+                .collect::<Vec<_>>();
+            Some((*alias, formats.join(".")))
+        })
+        .collect()
 }
 
 fn to_extension(ext: &[u8]) -> Option<Extension> {
@@ -102,14 +190,19 @@ fn to_extension(ext: &[u8]) -> Option<Extension> {
             b"txz" | b"tlzma" => &[Tar, Lzma],
             b"tsz" => &[Tar, Snappy],
             b"tzst" => &[Tar, Zstd],
+            b"taz" => &[Tar, Compress],
             b"zip" => &[Zip],
             b"bz" | b"bz2" => &[Bzip],
+            b"bz3" => &[Bzip3],
             b"gz" => &[Gzip],
             b"lz4" => &[Lz4],
             b"xz" | b"lzma" => &[Lzma],
             b"sz" => &[Snappy],
             b"zst" => &[Zstd],
             b"7z" => &[SevenZip],
+            b"Z" => &[Compress],
+            b"wim" => &[Wim],
+            b"iso" => &[Iso],
             _ => return None,
         },
         ext.to_str_lossy(),
@@ -126,6 +219,16 @@ fn split_extension(name: &mut &[u8]) -> Option<Extension> {
     Some(ext)
 }
 
+/// Splits the name of a volume of a split 7z archive into the name of the archive and the number
+/// of the volume, like "archive.7z.001" into "archive.7z" and "001"
+pub fn split_volume_number(name: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (archive_name, number) = name.rsplit_once_str(b".")?;
+    if number.is_empty() || !number.iter().all(u8::is_ascii_digit) || !archive_name.ends_with(b".7z") {
+        return None;
+    }
+    Some((archive_name, number))
+}
+
 pub fn parse_format(fmt: &OsStr) -> crate::Result<Vec<Extension>> {
     let fmt = <[u8] as ByteSlice>::from_os_str(fmt).ok_or_else(|| Error::InvalidFormat {
         reason: "Invalid UTF-8".into(),
@@ -152,6 +255,11 @@ pub fn separate_known_extensions_from_name(path: &Path) -> (&Path, Vec<Extension
         return (path, extensions);
     };
 
+    // The volumes of a split 7z archive are read as the archive they are split from
+    if let Some((archive_name, _)) = split_volume_number(name) {
+        name = archive_name;
+    }
+
     // While there is known extensions at the tail, grab them
     while let Some(extension) = split_extension(&mut name) {
         extensions.insert(0, extension);
@@ -159,7 +267,7 @@ pub fn separate_known_extensions_from_name(path: &Path) -> (&Path, Vec<Extension
 
     if let Ok(name) = name.to_str() {
         let file_stem = name.trim_matches('.');
-        if _EXTENSIONS.contains(&file_stem) || SUPPORTED_ALIASES.contains(&file_stem) {
+        if SUPPORTED_EXTENSIONS.contains(&file_stem) || SUPPORTED_ALIASES.contains(&file_stem) {
             warning!("Received a file with name '{file_stem}', but {file_stem} was expected as the extension.");
         }
     }
@@ -167,6 +275,38 @@ pub fn separate_known_extensions_from_name(path: &Path) -> (&Path, Vec<Extension
     (name.to_path().unwrap(), extensions)
 }
 
+/// Removes the extensions of `format`, given by `--format`, from `name`, like "data.tar.gz" -> "data",
+/// or its last extension if it has others, like "data.dat" -> "data".
+pub fn name_without_format<'a>(name: &'a Path, format: &[Extension]) -> &'a Path {
+    let (stem, extensions) = separate_known_extensions_from_name(name);
+    if !extensions.is_empty() && flatten_compression_formats(&extensions) == flatten_compression_formats(format) {
+        return stem;
+    }
+
+    name.file_stem().map_or(name, Path::new)
+}
+
+/// The output name of the single compressed file `name`, which is `stem` without the extensions of
+/// its formats, with the extensions given by `strip` removed, see `--strip-extension`.
+///
+/// A leading dot isn't an extension, so ".bashrc.gz" is named ".bashrc" with [`StripExtension::All`].
+pub fn single_file_output_name(name: &Path, stem: &Path, strip: StripExtension) -> PathBuf {
+    match strip {
+        StripExtension::Formats => stem.to_owned(),
+        StripExtension::Keep => name.to_owned(),
+        StripExtension::All => {
+            let Some(stem) = stem.to_str() else {
+                return stem.to_owned();
+            };
+            let leading_dots = stem.len() - stem.trim_start_matches('.').len();
+            match stem[leading_dots..].find('.') {
+                Some(dot) => PathBuf::from(&stem[..leading_dots + dot]),
+                None => PathBuf::from(stem),
+            }
+        }
+    }
+}
+
 /// Extracts extensions from a path, return only the list of extension objects
 pub fn extensions_from_path(path: &Path) -> Vec<Extension> {
     let (_, extensions) = separate_known_extensions_from_name(path);
@@ -228,6 +368,25 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_all_formats() {
+        // Every extension ouch recognizes is one of them
+        for extension in SUPPORTED_EXTENSIONS.iter().chain(SUPPORTED_ALIASES) {
+            let extension = to_extension(extension.as_bytes()).unwrap();
+            for format in extension.compression_formats {
+                assert!(ALL_FORMATS.contains(format), "{format:?}");
+            }
+        }
+        for format in ALL_FORMATS {
+            assert!(!format.extensions().is_empty(), "{format:?}");
+        }
+
+        assert_eq!(Bzip.clamp_level(0), 1);
+        assert_eq!(Gzip.clamp_level(20), 9);
+        assert_eq!(Lz4.clamp_level(20), 20);
+        assert!(aliases().contains(&("tgz", "tar.gz".to_owned())));
+    }
+
     #[test]
     fn test_extensions_from_path() {
         let path = Path::new("bolovo.tar.gz");
@@ -238,6 +397,21 @@ mod tests {
         assert_eq!(formats, vec![Tar, Gzip]);
     }
 
+    #[test]
+    fn test_extensions_of_split_volumes() {
+        let (stem, extensions) = separate_known_extensions_from_name(Path::new("photos.7z.001"));
+        assert_eq!(stem, Path::new("photos"));
+        assert_eq!(flatten_compression_formats(&extensions), vec![SevenZip]);
+
+        assert_eq!(
+            split_volume_number(b"photos.7z.012"),
+            Some((&b"photos.7z"[..], &b"012"[..]))
+        );
+        assert_eq!(split_volume_number(b"photos.zip.001"), None);
+        assert_eq!(split_volume_number(b"photos.7z.1a"), None);
+        assert!(extensions_from_path(Path::new("notes.001")).is_empty());
+    }
+
     #[test]
     fn builds_suggestion_correctly() {
         assert_eq!(build_archive_file_suggestion(Path::new("linux.png"), ".tar"), None);
@@ -258,4 +432,40 @@ mod tests {
             "linux.pkg.info.tar.zst"
         );
     }
+
+    #[test]
+    fn test_name_without_format() {
+        let format = parse_format(OsStr::new("tar.gz")).unwrap();
+
+        assert_eq!(
+            name_without_format(Path::new("data.tar.gz"), &format),
+            Path::new("data")
+        );
+        assert_eq!(name_without_format(Path::new("data.tgz"), &format), Path::new("data"));
+        assert_eq!(name_without_format(Path::new("data.gz"), &format), Path::new("data"));
+        assert_eq!(name_without_format(Path::new("weird.dat"), &format), Path::new("weird"));
+        assert_eq!(name_without_format(Path::new("blob"), &format), Path::new("blob"));
+    }
+
+    #[test]
+    fn test_single_file_output_name() {
+        let output_name = |name, strip| {
+            let (stem, _) = separate_known_extensions_from_name(Path::new(name));
+            single_file_output_name(Path::new(name), stem, strip)
+        };
+
+        for (name, formats, all) in [
+            ("report.txt.gz", "report.txt", "report"),
+            ("my.report.v2.gz.xz", "my.report.v2", "my"),
+            ("data.gz", "data", "data"),
+            ("data.unknown", "data.unknown", "data"),
+            ("noextension", "noextension", "noextension"),
+            (".bashrc.gz", ".bashrc", ".bashrc"),
+            ("..notes.txt.zst", "..notes.txt", "..notes"),
+        ] {
+            assert_eq!(output_name(name, StripExtension::Formats), Path::new(formats), "{name}");
+            assert_eq!(output_name(name, StripExtension::All), Path::new(all), "{name}");
+            assert_eq!(output_name(name, StripExtension::Keep), Path::new(name), "{name}");
+        }
+    }
 }
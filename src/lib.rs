@@ -0,0 +1,53 @@
+//! Painless compression and decompression in the terminal, the library behind the `ouch` binary.
+//!
+//! Most of it is the command line interface, [`decompress_in_memory`] is meant to be used from
+//! other programs.
+
+// Macros should be declared first
+pub mod macros;
+
+pub mod accessible;
+pub mod archive;
+pub mod bzip3;
+pub mod check;
+pub mod cli;
+pub mod commands;
+pub mod error;
+pub mod extension;
+pub mod list;
+pub mod lzw;
+pub mod utils;
+
+use std::{env, path::PathBuf};
+
+use cli::CliArgs;
+pub use commands::decompress_in_memory;
+use error::{Error, Result};
+use once_cell::sync::Lazy;
+use utils::{QuestionAction, QuestionPolicy};
+
+// Used in BufReader and BufWriter to perform less syscalls
+const BUFFER_CAPACITY: usize = 1024 * 32;
+
+/// Current directory or empty directory
+static CURRENT_DIRECTORY: Lazy<PathBuf> = Lazy::new(|| env::current_dir().unwrap_or_default());
+
+/// The status code returned from `ouch` on error, when there isn't a more specific one
+pub const EXIT_FAILURE: i32 = libc::EXIT_FAILURE;
+
+/// The status code returned from `ouch` on invalid arguments, the same one clap uses
+pub const EXIT_USAGE: i32 = 2;
+
+/// The status code returned from `ouch` when an archive is corrupted or truncated
+pub const EXIT_CORRUPTED_ARCHIVE: i32 = 3;
+
+/// The status code returned from `ouch` when the password of an encrypted archive is wrong or missing
+pub const EXIT_PASSWORD: i32 = 4;
+
+/// The status code returned from `ouch` when the disk gets full while writing the output, or
+/// would, and the user chose not to compress anyway
+pub const EXIT_STORAGE_FULL: i32 = 5;
+
+/// The status code returned from `ouch` when some of the files were decompressed, but the others
+/// were skipped with `--continue-on-password-failure`
+pub const EXIT_PARTIAL_SUCCESS: i32 = 6;
@@ -1,18 +1,43 @@
 //! Some implementation helpers related to the 'list' command.
 
 use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
     io::{stdout, Write},
     path::{Path, PathBuf},
 };
 
 use self::tree::Tree;
-use crate::{accessible::is_running_in_accessible_mode, utils::EscapedPathDisplay};
+use crate::{
+    accessible::is_running_in_accessible_mode,
+    cli::ListSort,
+    info,
+    utils::{
+        colors::{ALL_RESET, GREEN, RED, YELLOW},
+        json_string, EscapedPathDisplay, ExcludePatterns,
+    },
+};
 
 /// Options controlling how archive contents should be listed
-#[derive(Debug, Clone, Copy)]
-pub struct ListOptions {
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListOptions<'a> {
     /// Whether to show a tree view
     pub tree: bool,
+
+    /// Order of the entries, the one of the archive if not set
+    pub sort: Option<ListSort>,
+
+    /// Whether to list the entries in reverse order
+    pub reverse: bool,
+
+    /// Whether to number the entries in the order of the archive, for `decompress --index`
+    pub index: bool,
+
+    /// Whether to print only the names of the entries, terminated by NUL characters
+    pub print0: bool,
+
+    /// Patterns of the entries left out of the listing
+    pub exclude: Option<&'a ExcludePatterns>,
 }
 
 /// Represents a single file in an archive, used in `list::list_files()`
@@ -23,30 +48,290 @@ pub struct FileInArchive {
 
     /// Whether this file is a directory
     pub is_dir: bool,
+
+    /// The uncompressed size of the file
+    pub size: u64,
+
+    /// The modification time stored in the archive, in seconds since the Unix epoch
+    pub modified: Option<i64>,
+
+    /// Whether this file is a symlink
+    pub is_symlink: bool,
+
+    /// The size of the file as stored in the archive, for the formats that store it per entry
+    pub compressed_size: Option<u64>,
+
+    /// The Unix mode stored in the archive, with the bits of the file type
+    pub mode: Option<u32>,
 }
 
-/// Actually print the files
+/// Actually print the files, or add them to `manifest` with `--flat-json`
 /// Returns an Error, if one of the files can't be read
 pub fn list_files(
     archive: &Path,
     files: impl IntoIterator<Item = crate::Result<FileInArchive>>,
     list_options: ListOptions,
+    mut manifest: Option<&mut JsonManifest>,
 ) -> crate::Result<()> {
     let out = &mut stdout().lock();
-    let _ = writeln!(out, "Archive: {}", EscapedPathDisplay::new(archive));
+    if manifest.is_none() && !list_options.print0 {
+        let _ = writeln!(out, "Archive: {}", EscapedPathDisplay::new(archive));
+    }
+
+    // Numbered in the order of the archive, before being sorted
+    let files = (files.into_iter().enumerate()).map(|(i, file)| file.map(|file| (i + 1, file)));
+    let files = files.filter(|file| match (file, list_options.exclude) {
+        (Ok((_, file)), Some(exclude)) => !exclude.is_excluded(&file.path),
+        _ => true,
+    });
+
+    // Sorting needs all the entries first, in the order of the archive they are printed as they are read
+    let files: Box<dyn Iterator<Item = crate::Result<(usize, FileInArchive)>>> =
+        if list_options.sort.is_some() || list_options.reverse {
+            let mut files = files.collect::<crate::Result<Vec<_>>>()?;
+            if let Some(sort) = list_options.sort {
+                sort_files(&mut files, sort);
+            }
+            if list_options.reverse {
+                files.reverse();
+            }
+            Box::new(files.into_iter().map(Ok))
+        } else {
+            Box::new(files)
+        };
 
     if list_options.tree {
-        let tree = files.into_iter().collect::<crate::Result<Tree>>()?;
+        let tree = files
+            .map(|file| file.map(|(_, file)| file))
+            .collect::<crate::Result<Tree>>()?;
         tree.print(out);
     } else {
         for file in files {
-            let FileInArchive { path, is_dir } = file?;
+            let (index, file) = file?;
+            if let Some(manifest) = manifest.as_deref_mut() {
+                manifest.write_entry(out, archive, index, &file);
+                continue;
+            }
+
+            let FileInArchive { path, is_dir, .. } = file;
+            if list_options.print0 {
+                let _ = out.write_all(path.as_os_str().as_encoded_bytes());
+                let _ = out.write_all(b"\0");
+                continue;
+            }
+            if list_options.index {
+                let _ = write!(out, "{index:>6}  ");
+            }
             print_entry(out, EscapedPathDisplay::new(&path), is_dir);
         }
     }
     Ok(())
 }
 
+/// JSON array of the entries of the listed archives, printed with `--flat-json`, each entry is
+/// printed as soon as it is listed so the archives don't have to be read whole first
+pub struct JsonManifest {
+    is_empty: bool,
+}
+
+impl JsonManifest {
+    /// Prints the start of the array
+    pub fn start() -> Self {
+        print!("[");
+        Self { is_empty: true }
+    }
+
+    /// Prints `file`, the entry at `index` in `archive`, as one object of the array
+    fn write_entry(&mut self, out: &mut impl Write, archive: &Path, index: usize, file: &FileInArchive) {
+        let separator = if self.is_empty { "" } else { "," };
+        self.is_empty = false;
+
+        let kind = match file {
+            FileInArchive { is_symlink: true, .. } => "symlink",
+            FileInArchive { is_dir: true, .. } => "dir",
+            _ => "file",
+        };
+        let or_null = |value: Option<String>| value.unwrap_or_else(|| "null".to_owned());
+        let _ = write!(
+            out,
+            r#"{separator}
+  {{"archive":{},"index":{index},"name":{},"type":"{kind}","size":{},"compressed_size":{},"mtime":{},"mode":{}}}"#,
+            json_string(archive),
+            json_string(&file.path),
+            file.size,
+            or_null(file.compressed_size.map(|size| size.to_string())),
+            or_null(file.modified.map(|mtime| mtime.to_string())),
+            or_null(file.mode.map(|mode| format!(r#""{:04o}""#, mode & 0o7777))),
+        );
+    }
+
+    /// Prints the end of the array
+    pub fn finish(self) {
+        println!("{}]", if self.is_empty { "" } else { "\n" });
+    }
+}
+
+/// Sorts `files` in the `sort` order, the ones that are equal in it are sorted by path
+fn sort_files<T: AsRef<FileInArchive>>(files: &mut [T], sort: ListSort) {
+    files.sort_by(|a, b| {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        match sort {
+            ListSort::Name => a.path.cmp(&b.path),
+            ListSort::Natural => natural_cmp(&a.path.to_string_lossy(), &b.path.to_string_lossy()),
+            ListSort::Size => a.size.cmp(&b.size).then_with(|| a.path.cmp(&b.path)),
+            ListSort::Mtime => a.modified.cmp(&b.modified).then_with(|| a.path.cmp(&b.path)),
+        }
+    });
+}
+
+impl AsRef<FileInArchive> for FileInArchive {
+    fn as_ref(&self) -> &FileInArchive {
+        self
+    }
+}
+
+/// Entries numbered by their position in the archive, with `--index`
+impl AsRef<FileInArchive> for (usize, FileInArchive) {
+    fn as_ref(&self) -> &FileInArchive {
+        &self.1
+    }
+}
+
+/// Compares `a` and `b` with the numbers in them compared by value, so "file2" comes before
+/// "file10", numbers with more leading zeros come first when they are equal, like "01" and "1"
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(a_char), Some(b_char)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+
+        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let a_end = a.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(b.len());
+            let (a_number, b_number) = (&a[..a_end], &b[..b_end]);
+            let (a_digits, b_digits) = (a_number.trim_start_matches('0'), b_number.trim_start_matches('0'));
+
+            // Without leading zeros, longer numbers are bigger, and same length ones compare like text
+            let ordering = a_digits
+                .len()
+                .cmp(&b_digits.len())
+                .then_with(|| a_digits.cmp(b_digits))
+                .then_with(|| b_number.len().cmp(&a_number.len()));
+            if ordering.is_ne() {
+                return ordering;
+            }
+            (a, b) = (&a[a_end..], &b[b_end..]);
+        } else {
+            let ordering = a_char.cmp(&b_char);
+            if ordering.is_ne() {
+                return ordering;
+            }
+            (a, b) = (&a[a_char.len_utf8()..], &b[b_char.len_utf8()..]);
+        }
+    }
+}
+
+/// Print how the entries of the existing `archive` would change if it was replaced by `new_files`
+///
+/// Files are considered modified when their size or modification time changed, the times are
+/// compared with a tolerance of 2 seconds, the resolution of the zip format.
+pub fn list_differences(
+    archive: &Path,
+    old_files: impl IntoIterator<Item = crate::Result<FileInArchive>>,
+    new_files: Vec<FileInArchive>,
+) -> crate::Result<()> {
+    // `PathBuf` compares by components, so "dir/" and "dir" are the same entry
+    let mut old_files = old_files
+        .into_iter()
+        .map(|file| file.map(|file| (file.path.clone(), file)))
+        .collect::<crate::Result<BTreeMap<_, _>>>()?;
+
+    let mut changes = BTreeMap::new();
+    for new_file in new_files {
+        let change = match old_files.remove(&new_file.path) {
+            None => Change::New,
+            Some(old_file) if is_modified(&old_file, &new_file) => Change::Modified,
+            Some(_) => continue,
+        };
+        changes.insert(new_file.path, (change, new_file.is_dir));
+    }
+    for (path, old_file) in old_files {
+        changes.insert(path, (Change::Removed, old_file.is_dir));
+    }
+
+    let out = &mut stdout().lock();
+    let _ = writeln!(out, "Archive: {}", EscapedPathDisplay::new(archive));
+
+    for (path, (change, is_dir)) in &changes {
+        let name = EscapedPathDisplay::new(path);
+        let slash = if *is_dir { "/" } else { "" };
+        if is_running_in_accessible_mode() {
+            let _ = writeln!(out, "{}: {name}{slash}", change.description());
+        } else {
+            let _ = writeln!(out, "{}{} {name}{slash}{}", change.color(), change.marker(), *ALL_RESET);
+        }
+    }
+
+    let count = |kind| changes.values().filter(|(change, _)| *change == kind).count();
+    info!(
+        accessible,
+        "{} new, {} removed and {} modified entries.",
+        count(Change::New),
+        count(Change::Removed),
+        count(Change::Modified)
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    New,
+    Removed,
+    Modified,
+}
+
+impl Change {
+    fn marker(self) -> char {
+        match self {
+            Change::New => '+',
+            Change::Removed => '-',
+            Change::Modified => '~',
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Change::New => "new",
+            Change::Removed => "removed",
+            Change::Modified => "modified",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Change::New => *GREEN,
+            Change::Removed => *RED,
+            Change::Modified => *YELLOW,
+        }
+    }
+}
+
+fn is_modified(old_file: &FileInArchive, new_file: &FileInArchive) -> bool {
+    if old_file.is_dir || new_file.is_dir {
+        // Directory times depend on the format, only a change of type is reported
+        return old_file.is_dir != new_file.is_dir;
+    }
+
+    let time_changed = match (old_file.modified, new_file.modified) {
+        (Some(old), Some(new)) => (old - new).abs() >= 2,
+        _ => false,
+    };
+
+    old_file.size != new_file.size || time_changed
+}
+
 /// Print an entry and highlight directories, either by coloring them
 /// if that's supported or by adding a trailing /
 fn print_entry(out: &mut impl Write, name: impl std::fmt::Display, is_dir: bool) {
@@ -197,3 +482,86 @@ mod tree {
         pub const FINAL_BRANCH: &str = "├── ";
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, modified: Option<i64>) -> FileInArchive {
+        FileInArchive {
+            path: "file".into(),
+            is_dir: false,
+            size,
+            modified,
+            is_symlink: false,
+            compressed_size: None,
+            mode: None,
+        }
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = [
+            "file10",
+            "file2",
+            "file1",
+            "file02",
+            "File3",
+            "file",
+            "dir/10",
+            "dir/9.txt",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "File3",
+                "dir/9.txt",
+                "dir/10",
+                "file",
+                "file1",
+                "file02",
+                "file2",
+                "file10"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_files() {
+        let named = |path: &str, size, modified| FileInArchive {
+            path: path.into(),
+            ..file(size, modified)
+        };
+        let mut files = vec![named("b", 1, Some(30)), named("a", 3, None), named("c", 1, Some(20))];
+        let paths = |files: &[FileInArchive]| files.iter().map(|file| file.path.clone()).collect::<Vec<_>>();
+
+        sort_files(&mut files, ListSort::Size);
+        assert_eq!(paths(&files), ["b", "c", "a"].map(PathBuf::from));
+        sort_files(&mut files, ListSort::Mtime);
+        assert_eq!(paths(&files), ["a", "c", "b"].map(PathBuf::from));
+        sort_files(&mut files, ListSort::Name);
+        assert_eq!(paths(&files), ["a", "b", "c"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_is_modified() {
+        assert!(!is_modified(&file(10, Some(100)), &file(10, Some(101))));
+        assert!(!is_modified(&file(10, None), &file(10, Some(100))));
+        assert!(is_modified(&file(10, Some(100)), &file(10, Some(102))));
+        assert!(is_modified(&file(10, Some(100)), &file(11, Some(100))));
+
+        let dir = FileInArchive {
+            is_dir: true,
+            ..file(0, Some(0))
+        };
+        assert!(!is_modified(
+            &dir,
+            &FileInArchive {
+                modified: Some(100),
+                ..dir.clone()
+            }
+        ));
+        assert!(is_modified(&dir, &file(0, Some(0))));
+    }
+}
@@ -0,0 +1,235 @@
+//! Decoder for the `.Z` format of the Unix `compress` tool.
+//!
+//! The format is LZW with variable width codes, from 9 bits up to the maximum stored in the
+//! header, packed least significant bit first. Codes are written in groups of 8, so, when the
+//! width changes or the table is cleared, the rest of the current group is padding.
+
+use std::io::{self, Read};
+
+const MAGIC: [u8; 2] = [0x1F, 0x9D];
+const BLOCK_MODE_FLAG: u8 = 0x80;
+const MAX_BITS_MASK: u8 = 0x1F;
+const INIT_BITS: u32 = 9;
+const MAX_BITS: u32 = 16;
+/// Only present in block mode, resets the table
+const CLEAR: u32 = 256;
+
+/// Reads the decompressed contents of a `.Z` stream read from `inner`.
+pub struct LzwDecoder<R> {
+    inner: R,
+    header_read: bool,
+    block_mode: bool,
+    max_bits: u32,
+
+    bit_buffer: u32,
+    bit_count: u32,
+    /// Codes read since the width last changed, to skip the padding at the end of a group
+    codes_in_group: u32,
+    n_bits: u32,
+    max_code: u32,
+    /// Next table entry to be defined
+    free_entry: u32,
+
+    prefixes: Vec<u16>,
+    suffixes: Vec<u8>,
+    old_code: Option<u32>,
+    first_char: u8,
+
+    /// Decoded bytes not read yet, in reverse order, because strings are decoded from their end
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> LzwDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            header_read: false,
+            block_mode: false,
+            max_bits: MAX_BITS,
+            bit_buffer: 0,
+            bit_count: 0,
+            codes_in_group: 0,
+            n_bits: INIT_BITS,
+            max_code: (1 << INIT_BITS) - 1,
+            free_entry: CLEAR,
+            prefixes: vec![0; 1 << MAX_BITS],
+            suffixes: (0..1 << MAX_BITS).map(|code| code as u8).collect(),
+            old_code: None,
+            first_char: 0,
+            pending: vec![],
+            finished: false,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 3];
+        self.inner.read_exact(&mut header)?;
+
+        if header[..2] != MAGIC {
+            return Err(invalid_data("not in the .Z format"));
+        }
+
+        self.block_mode = header[2] & BLOCK_MODE_FLAG != 0;
+        self.max_bits = u32::from(header[2] & MAX_BITS_MASK);
+        if !(INIT_BITS..=MAX_BITS).contains(&self.max_bits) {
+            return Err(invalid_data("unsupported maximum code width"));
+        }
+
+        self.free_entry = if self.block_mode { CLEAR + 1 } else { CLEAR };
+        self.header_read = true;
+        Ok(())
+    }
+
+    /// Reads `n_bits` bits, returns `None` at the end of the stream
+    fn read_bits(&mut self, n_bits: u32) -> io::Result<Option<u32>> {
+        while self.bit_count < n_bits {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            self.bit_buffer |= u32::from(byte[0]) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let bits = self.bit_buffer & ((1 << n_bits) - 1);
+        self.bit_buffer >>= n_bits;
+        self.bit_count -= n_bits;
+        Ok(Some(bits))
+    }
+
+    fn read_code(&mut self) -> io::Result<Option<u32>> {
+        self.codes_in_group = (self.codes_in_group + 1) % 8;
+        self.read_bits(self.n_bits)
+    }
+
+    /// Sets the code width, skipping the padding at the end of the current group of codes
+    fn set_code_width(&mut self, n_bits: u32) -> io::Result<()> {
+        if self.codes_in_group != 0 {
+            for _ in self.codes_in_group..8 {
+                self.read_bits(self.n_bits)?;
+            }
+            self.codes_in_group = 0;
+        }
+
+        self.n_bits = n_bits;
+        self.max_code = if n_bits == self.max_bits {
+            1 << n_bits
+        } else {
+            (1 << n_bits) - 1
+        };
+        Ok(())
+    }
+
+    /// Decodes the next code into `self.pending`, returns `false` at the end of the stream
+    fn decode_next(&mut self) -> io::Result<bool> {
+        if self.free_entry > self.max_code && self.n_bits < self.max_bits {
+            self.set_code_width(self.n_bits + 1)?;
+        }
+
+        let Some(mut code) = self.read_code()? else {
+            return Ok(false);
+        };
+
+        let Some(old_code) = self.old_code else {
+            if code >= CLEAR {
+                return Err(invalid_data("corrupted stream"));
+            }
+            self.first_char = code as u8;
+            self.old_code = Some(code);
+            self.pending.push(self.first_char);
+            return Ok(true);
+        };
+
+        if code == CLEAR && self.block_mode {
+            // The next code defines the entry 256, which can never be referenced
+            self.free_entry = CLEAR;
+            self.set_code_width(INIT_BITS)?;
+            return Ok(true);
+        }
+
+        let incoming_code = code;
+
+        if code >= self.free_entry {
+            // The code being defined right now: the previous string plus its own first character
+            if code > self.free_entry {
+                return Err(invalid_data("corrupted stream"));
+            }
+            self.pending.push(self.first_char);
+            code = old_code;
+        }
+
+        while code >= CLEAR {
+            self.pending.push(self.suffixes[code as usize]);
+            code = u32::from(self.prefixes[code as usize]);
+        }
+        self.first_char = self.suffixes[code as usize];
+        self.pending.push(self.first_char);
+
+        if self.free_entry < 1 << self.max_bits {
+            self.prefixes[self.free_entry as usize] = old_code as u16;
+            self.suffixes[self.free_entry as usize] = self.first_char;
+            self.free_entry += 1;
+        }
+
+        self.old_code = Some(incoming_code);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for LzwDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.header_read {
+            self.read_header()?;
+        }
+
+        while self.pending.is_empty() && !self.finished {
+            self.finished = !self.decode_next()?;
+        }
+
+        let mut written = 0;
+        while written < buf.len() {
+            let Some(byte) = self.pending.pop() else {
+                break;
+            };
+            buf[written] = byte;
+            written += 1;
+        }
+
+        Ok(written)
+    }
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid .Z file: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = vec![];
+        LzwDecoder::new(compressed).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+
+    #[test]
+    fn test_decode() {
+        let compressed = [
+            0x1F, 0x9D, 0x90, 0x54, 0x9E, 0x08, 0x29, 0xF2, 0x44, 0x8A, 0x93, 0x27, 0x54, 0x02, 0x0E, 0x2C, 0xA8, 0x90,
+            0xA0, 0x41, 0x84,
+        ];
+        assert_eq!(decode(&compressed).unwrap(), b"TOBEORNOTTOBEORTOBEORNOT");
+
+        // Uses codes that are defined by themselves
+        let compressed = [0x1F, 0x9D, 0x90, 0x61, 0x02, 0x0A, 0x1C, 0x48, 0x90, 0x20];
+        assert_eq!(decode(&compressed).unwrap(), [b'a'; 20]);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode(&[0x1F, 0x8B, 0x08]).is_err());
+        assert!(decode(&[0x1F, 0x9D, 0x90, 0xFF, 0xFF]).is_err());
+    }
+}
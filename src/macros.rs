@@ -4,6 +4,9 @@ use std::io;
 
 /// Macro that prints \[INFO\] messages, wraps [`eprintln`].
 ///
+/// Every message is also written to the file given with `--log-file`, even the ones not printed,
+/// like all of them with `--porcelain`.
+///
 /// There are essentially two different versions of the `info!()` macro:
 /// - `info!(accessible, ...)` should only be used for short, important
 ///   information which is expected to be useful for e.g. blind users whose
@@ -25,15 +28,18 @@ macro_rules! info {
 
         use $crate::{macros::stderr_check, utils::colors::{YELLOW, RESET}};
 
-        let mut stderr = stderr().lock();
+        if !$crate::utils::is_porcelain() {
+            let mut stderr = stderr().lock();
 
-        if $crate::accessible::is_running_in_accessible_mode() {
-            stderr_check(write!(stderr, "{}Info:{} ", *YELLOW, *RESET));
-        } else {
-            stderr_check(write!(stderr, "{}[INFO]{} ", *YELLOW, *RESET));
-        }
+            if $crate::accessible::is_running_in_accessible_mode() {
+                stderr_check(write!(stderr, "{}Info:{} ", *YELLOW, *RESET));
+            } else {
+                stderr_check(write!(stderr, "{}[INFO]{} ", *YELLOW, *RESET));
+            }
 
-        stderr_check(writeln!(stderr, $($arg)*));
+            stderr_check(writeln!(stderr, $($arg)*));
+        }
+        $crate::utils::log_to_file("INFO", format_args!($($arg)*));
     }};
     // Inccessible (long/no important) info message.
     // Print info message if ACCESSIBLE is not turned on
@@ -44,30 +50,37 @@ macro_rules! info {
 
         let mut stderr = stderr().lock();
 
-        if !$crate::accessible::is_running_in_accessible_mode() {
+        if !$crate::accessible::is_running_in_accessible_mode() && !$crate::utils::is_porcelain() {
             stderr_check(write!(stderr, "{}[INFO]{} ", *YELLOW, *RESET));
             stderr_check(writeln!(stderr, $($arg)*));
         }
+        $crate::utils::log_to_file("INFO", format_args!($($arg)*));
     }};
 }
 
 /// Macro that prints WARNING messages, wraps [`eprintln`].
+///
+/// Nothing is printed with `--quiet-errors`, but the message is still written to the file given
+/// with `--log-file`.
 #[macro_export]
 macro_rules! warning {
     ($($arg:tt)*) => {{
         use ::std::io::{stderr, Write};
 
-        use $crate::{macros::stderr_check, utils::colors::{ORANGE, RESET}};
+        use $crate::{macros::stderr_check, utils::{colors::{ORANGE, RESET}, warning_policy, WarningPolicy}};
 
-        let mut stderr = stderr().lock();
+        if warning_policy() != WarningPolicy::Silence {
+            let mut stderr = stderr().lock();
 
-        if $crate::accessible::is_running_in_accessible_mode() {
-            stderr_check(write!(stderr, "{}Warning:{} ", *ORANGE, *RESET));
-        } else {
-            stderr_check(write!(stderr, "{}[WARNING]{} ", *ORANGE, *RESET));
-        }
+            if $crate::accessible::is_running_in_accessible_mode() {
+                stderr_check(write!(stderr, "{}Warning:{} ", *ORANGE, *RESET));
+            } else {
+                stderr_check(write!(stderr, "{}[WARNING]{} ", *ORANGE, *RESET));
+            }
 
-        stderr_check(writeln!(stderr, $($arg)*));
+            stderr_check(writeln!(stderr, $($arg)*));
+        }
+        $crate::utils::log_to_file("WARNING", format_args!($($arg)*));
     }};
 }
 
@@ -1,36 +1,10 @@
-// Macros should be declared first
-pub mod macros;
-
-pub mod accessible;
-pub mod archive;
-pub mod check;
-pub mod cli;
-pub mod commands;
-pub mod error;
-pub mod extension;
-pub mod list;
-pub mod utils;
-
-use std::{env, path::PathBuf};
-
-use cli::CliArgs;
-use error::{Error, Result};
-use once_cell::sync::Lazy;
-use utils::{QuestionAction, QuestionPolicy};
-
-// Used in BufReader and BufWriter to perform less syscalls
-const BUFFER_CAPACITY: usize = 1024 * 32;
-
-/// Current directory or empty directory
-static CURRENT_DIRECTORY: Lazy<PathBuf> = Lazy::new(|| env::current_dir().unwrap_or_default());
-
-/// The status code returned from `ouch` on error
-pub const EXIT_FAILURE: i32 = libc::EXIT_FAILURE;
+use ouch::{cli::CliArgs, commands, error::Result, utils};
 
 fn main() {
     if let Err(err) = run() {
         eprintln!("{err}");
-        std::process::exit(EXIT_FAILURE);
+        utils::log_to_file("ERROR", format_args!("{err}"));
+        std::process::exit(err.exit_code());
     }
 }
 
@@ -0,0 +1,280 @@
+//! AppleDouble files, the "._name" files macOS writes next to "name" in archives to hold its
+//! resource fork and extended attributes, handled by `--macos-forks`.
+//!
+//! Zip archives made by the Finder keep them in a "__MACOSX" directory mirroring the archive,
+//! tar archives made by bsdtar keep them right next to the files.
+
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use fs_err as fs;
+
+/// Directory the Finder puts the AppleDouble files of zip archives in
+const MACOSX_DIR: &str = "__MACOSX";
+
+const APPLE_DOUBLE_MAGIC: u32 = 0x0005_1607;
+const RESOURCE_FORK_ID: u32 = 2;
+const FINDER_INFO_ID: u32 = 9;
+const FINDER_INFO_LEN: usize = 32;
+/// Magic of the extended attributes macOS stores after the Finder info
+const ATTR_MAGIC: &[u8] = b"ATTR";
+
+const RESOURCE_FORK_XATTR: &str = "com.apple.ResourceFork";
+const FINDER_INFO_XATTR: &str = "com.apple.FinderInfo";
+
+/// Checks if the entry at `path` is an AppleDouble file, or in the "__MACOSX" directory
+pub fn is_apple_double(path: &Path) -> bool {
+    let is_macosx_dir = |component: Component| component.as_os_str() == MACOSX_DIR;
+
+    path.components().next().is_some_and(is_macosx_dir)
+        || path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b"._"))
+}
+
+/// Extended attributes of the file an AppleDouble file belongs to, by name
+fn parse(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    if read_u32(data, 0)? != APPLE_DOUBLE_MAGIC {
+        return None;
+    }
+
+    let entry_count = read_u16(data, 24)? as usize;
+    let mut attributes = vec![];
+    for i in 0..entry_count {
+        let header = 26 + i * 12;
+        let id = read_u32(data, header)?;
+        let offset = read_u32(data, header + 4)? as usize;
+        let length = read_u32(data, header + 8)? as usize;
+        let entry = data.get(offset..offset.checked_add(length)?)?;
+
+        match id {
+            RESOURCE_FORK_ID if !entry.is_empty() => {
+                attributes.push((RESOURCE_FORK_XATTR.to_owned(), entry.to_vec()));
+            }
+            FINDER_INFO_ID => {
+                let finder_info = entry.get(..FINDER_INFO_LEN)?;
+                // Empty Finder info is left out, like macOS does
+                if finder_info.iter().any(|&byte| byte != 0) {
+                    attributes.push((FINDER_INFO_XATTR.to_owned(), finder_info.to_vec()));
+                }
+                attributes.extend(parse_attributes(data, offset + FINDER_INFO_LEN)?);
+            }
+            _ => {}
+        }
+    }
+
+    Some(attributes)
+}
+
+/// Extended attributes stored after the Finder info, whose header is at `start`, aligned after
+/// two bytes of padding, offsets in it are from the start of the file
+fn parse_attributes(data: &[u8], start: usize) -> Option<Vec<(String, Vec<u8>)>> {
+    let header = start + 2;
+    if data.get(header..header + 4) != Some(ATTR_MAGIC) {
+        return Some(vec![]);
+    }
+
+    let count = read_u16(data, header + 34)? as usize;
+    let mut position = header + 36;
+    let mut attributes = vec![];
+    for _ in 0..count {
+        let offset = read_u32(data, position)? as usize;
+        let length = read_u32(data, position + 4)? as usize;
+        let name_len = *data.get(position + 10)? as usize;
+        let name = data.get(position + 11..position + 11 + name_len)?;
+        let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(name)).into_owned();
+        let value = data.get(offset..offset.checked_add(length)?)?;
+        attributes.push((name, value.to_vec()));
+
+        // Entries are aligned to 4 bytes
+        position = (position + 11 + name_len + 3) & !3;
+    }
+
+    Some(attributes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+/// Sets the forks and extended attributes in the AppleDouble files unpacked in `dir` on the files
+/// they belong to, removing them, returns how many were merged
+///
+/// The ones without a file to be merged into, or that aren't valid, are left as they are.
+pub fn merge_apple_doubles(dir: &Path) -> io::Result<usize> {
+    let mut apple_doubles = vec![];
+    find_apple_doubles(dir, dir, &mut apple_doubles)?;
+
+    let mut merged = 0;
+    for (apple_double, target) in apple_doubles {
+        if fs::symlink_metadata(&target).is_err() {
+            continue;
+        }
+        let Some(attributes) = parse(&fs::read(&apple_double)?) else {
+            continue;
+        };
+        for (name, value) in attributes {
+            platform::set_xattr(&target, &name, &value)?;
+        }
+        fs::remove_file(&apple_double)?;
+        merged += 1;
+    }
+
+    // What is left of "__MACOSX" once its files were merged, only its empty directories are removed
+    let macosx_dir = dir.join(MACOSX_DIR);
+    if macosx_dir.is_dir() {
+        remove_empty_dirs(&macosx_dir)?;
+    }
+
+    Ok(merged)
+}
+
+/// Pushes the AppleDouble files under `path` to `apple_doubles`, with the file each belongs to
+fn find_apple_doubles(root: &Path, path: &Path, apple_doubles: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            find_apple_doubles(root, &path, apple_doubles)?;
+            continue;
+        }
+
+        let name = entry.file_name();
+        let Some(target_name) = name.as_encoded_bytes().strip_prefix(b"._") else {
+            continue;
+        };
+        // Safe because it was split at an ASCII prefix
+        let target_name = unsafe { OsStr::from_encoded_bytes_unchecked(target_name) };
+        let parent = path.parent().unwrap_or(root);
+        let target = match parent.strip_prefix(root.join(MACOSX_DIR)) {
+            Ok(relative) => root.join(relative).join(target_name),
+            Err(_) => parent.join(target_name),
+        };
+        apple_doubles.push((path, target));
+    }
+    Ok(())
+}
+
+/// Removes `dir` and the directories inside of it if they are empty, returns whether it was
+fn remove_empty_dirs(dir: &Path) -> io::Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        empty &= entry.file_type()?.is_dir() && remove_empty_dirs(&entry.path())?;
+    }
+    if empty {
+        fs::remove_dir(dir)?;
+    }
+    Ok(empty)
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+    pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let name = CString::new(name)?;
+        let result = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                libc::XATTR_NOFOLLOW,
+            )
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod platform {
+    use std::{io, path::Path};
+
+    /// Only macOS has resource forks, `--macos-forks merge` is turned into `keep` elsewhere
+    pub fn set_xattr(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// AppleDouble file with a resource fork, and Finder info followed by one extended attribute
+    fn apple_double() -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(APPLE_DOUBLE_MAGIC.to_be_bytes());
+        data.extend(0x0002_0000_u32.to_be_bytes());
+        data.extend([0; 16]);
+        data.extend(2_u16.to_be_bytes());
+
+        let finder_info_offset = 26 + 2 * 12;
+        let attr_header = finder_info_offset + FINDER_INFO_LEN + 2;
+        let attr_entry = attr_header + 36;
+        let name = b"com.apple.quarantine\0";
+        let value_offset = (attr_entry + 11 + name.len() + 3) & !3;
+        let value = b"0081;5f00;Safari;";
+        let fork_offset = value_offset + value.len();
+        let fork = b"resource fork";
+
+        data.extend(FINDER_INFO_ID.to_be_bytes());
+        data.extend((finder_info_offset as u32).to_be_bytes());
+        data.extend(((fork_offset - finder_info_offset) as u32).to_be_bytes());
+        data.extend(RESOURCE_FORK_ID.to_be_bytes());
+        data.extend((fork_offset as u32).to_be_bytes());
+        data.extend((fork.len() as u32).to_be_bytes());
+
+        let mut finder_info = [0; FINDER_INFO_LEN];
+        finder_info[..8].copy_from_slice(b"TEXTttxt");
+        data.extend(finder_info);
+        data.extend([0; 2]);
+        data.extend(ATTR_MAGIC);
+        data.extend([0; 28]);
+        data.extend(0_u16.to_be_bytes());
+        data.extend(1_u16.to_be_bytes());
+        data.extend((value_offset as u32).to_be_bytes());
+        data.extend((value.len() as u32).to_be_bytes());
+        data.extend(0_u16.to_be_bytes());
+        data.push(name.len() as u8);
+        data.extend(name);
+        data.resize(value_offset, 0);
+        data.extend(value);
+        data.extend(fork);
+        data
+    }
+
+    #[test]
+    fn test_parse_apple_double() {
+        let attributes = parse(&apple_double()).unwrap();
+        let mut finder_info = vec![0; FINDER_INFO_LEN];
+        finder_info[..8].copy_from_slice(b"TEXTttxt");
+
+        assert_eq!(
+            attributes,
+            [
+                (FINDER_INFO_XATTR.to_owned(), finder_info),
+                ("com.apple.quarantine".to_owned(), b"0081;5f00;Safari;".to_vec()),
+                (RESOURCE_FORK_XATTR.to_owned(), b"resource fork".to_vec()),
+            ]
+        );
+        assert_eq!(parse(b"not an AppleDouble file"), None);
+
+        assert!(is_apple_double(Path::new("dir/._file.txt")));
+        assert!(is_apple_double(Path::new("__MACOSX/dir/._file.txt")));
+        assert!(!is_apple_double(Path::new("dir/file.txt")));
+        assert!(!is_apple_double(Path::new("dir/__MACOSX")));
+    }
+}
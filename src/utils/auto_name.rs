@@ -0,0 +1,124 @@
+//! Timestamps added to the names of compressed outputs by `--auto-name`.
+
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{
+    error::{Error, FinalError},
+    extension::separate_known_extensions_from_name,
+};
+
+/// Timestamp format used by `--auto-name` when none is given, like "2024-06-01-153000"
+const DEFAULT_AUTO_NAME_FORMAT: &str = "%Y-%m-%d-%H%M%S";
+
+/// Adds the current local time, formatted with `format`, to the name of `path`, before its
+/// extensions, like "backup.tar.zst" -> "backup-2024-06-01-153000.tar.zst".
+pub fn timestamped_path(path: &Path, format: &str) -> crate::Result<PathBuf> {
+    let timestamp = format_time(now_local(), format).map_err(|specifier| {
+        let error = FinalError::with_title(format!("Invalid --auto-name format '{format}'"))
+            .detail(format!("Unknown specifier '{specifier}'"))
+            .hint("Use %Y, %m, %d, %H, %M, %S, and %% for a literal %")
+            .hint(format!("The default format is '{DEFAULT_AUTO_NAME_FORMAT}'"));
+        Error::InvalidUsage { reason: error }
+    })?;
+
+    let Some(file_name) = path.file_name() else {
+        return Ok(path.to_owned());
+    };
+    let (stem, _) = separate_known_extensions_from_name(path);
+    // The stem is the start of the file name, the rest are the extensions
+    let extensions = <[u8] as ByteSlice>::from_os_str(file_name)
+        .and_then(|name| name[stem.as_os_str().len()..].to_os_str().ok())
+        .unwrap_or_default();
+
+    let mut new_name = OsString::from(stem);
+    new_name.push("-");
+    new_name.push(timestamp);
+    new_name.push(extensions);
+
+    Ok(path.with_file_name(new_name))
+}
+
+/// Formats `time` with the strftime-like `format`, giving back the specifier not supported
+pub fn format_time(time: OffsetDateTime, format: &str) -> Result<String, String> {
+    let mut formatted = String::new();
+    let mut chars = format.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            formatted.push(ch);
+            continue;
+        }
+
+        // Writing to a String can't fail
+        let _ = match chars.next() {
+            Some('Y') => write!(formatted, "{:04}", time.year()),
+            Some('m') => write!(formatted, "{:02}", time.month() as u8),
+            Some('d') => write!(formatted, "{:02}", time.day()),
+            Some('H') => write!(formatted, "{:02}", time.hour()),
+            Some('M') => write!(formatted, "{:02}", time.minute()),
+            Some('S') => write!(formatted, "{:02}", time.second()),
+            Some('%') => write!(formatted, "%"),
+            Some(specifier) => return Err(format!("%{specifier}")),
+            None => return Err("%".into()),
+        };
+    }
+
+    Ok(formatted)
+}
+
+/// The current time in the local time zone, or in UTC where it isn't known
+pub fn now_local() -> OffsetDateTime {
+    let now = OffsetDateTime::now_utc();
+    match local_offset(now) {
+        Some(offset) => now.to_offset(offset),
+        None => now,
+    }
+}
+
+#[cfg(unix)]
+fn local_offset(time: OffsetDateTime) -> Option<UtcOffset> {
+    let timestamp = time.unix_timestamp() as libc::time_t;
+    // Safety: `tm` is only read after `localtime_r` fills it, which it only does if it succeeds
+    let tm = unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        if libc::localtime_r(&timestamp, &mut tm).is_null() {
+            return None;
+        }
+        tm
+    };
+
+    UtcOffset::from_whole_seconds(tm.tm_gmtoff as i32).ok()
+}
+
+#[cfg(not(unix))]
+fn local_offset(_time: OffsetDateTime) -> Option<UtcOffset> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month, Time};
+
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        let date = Date::from_calendar_date(2024, Month::June, 1).unwrap();
+        let time = date.with_time(Time::from_hms(15, 30, 0).unwrap()).assume_utc();
+
+        assert_eq!(
+            format_time(time, DEFAULT_AUTO_NAME_FORMAT),
+            Ok("2024-06-01-153000".into())
+        );
+        assert_eq!(format_time(time, "%d.%m.%Y 100%%"), Ok("01.06.2024 100%".into()));
+        assert_eq!(format_time(time, "%Y-%q"), Err("%q".into()));
+        assert_eq!(format_time(time, "%Y%"), Err("%".into()));
+    }
+}
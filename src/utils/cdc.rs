@@ -0,0 +1,195 @@
+//! Content-defined chunking of compressed streams, used by `--cdc`.
+//!
+//! The input is cut with FastCDC, at the positions where a rolling hash of the last bytes matches
+//! a pattern, so the cuts move along with the content when bytes are inserted or removed before
+//! them. Every chunk is compressed on its own, as a zstd frame or a gzip member, which makes the
+//! same chunks compress to the same bytes wherever they are, for storage that deduplicates.
+
+use std::io::{self, Write};
+
+/// Chunks are never smaller than this, except the last one
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+/// Size that the chunks are close to, on average
+const AVERAGE_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks are cut at this size when no boundary was found before
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Patterns of the hash matched before and after [`AVERAGE_CHUNK_SIZE`], harder to match before
+/// it and easier after, which keeps the chunk sizes close to it, the "normalized chunking" of
+/// FastCDC
+///
+/// The highest bits of the hash are used, they depend on the last 64 bytes, the lowest ones only
+/// on the last few.
+const MASK_BEFORE_AVERAGE: u64 = !0 << (64 - 18);
+const MASK_AFTER_AVERAGE: u64 = !0 << (64 - 14);
+
+/// Random values of every byte, added to the "gear" rolling hash
+const GEAR: [u64; 256] = gear_table();
+
+/// Fills [`GEAR`] with splitmix64, so it is the same in every build
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6f75_6368_2063_6463;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = value ^ (value >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Length of the first chunk of `data`, which only depends on its first [`MAX_CHUNK_SIZE`] bytes
+fn chunk_length(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let average = end.min(AVERAGE_CHUNK_SIZE);
+
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[usize::from(byte)]);
+        let mask = if i < average {
+            MASK_BEFORE_AVERAGE
+        } else {
+            MASK_AFTER_AVERAGE
+        };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Compresses one chunk on its own, into a complete zstd frame or gzip member
+pub type CompressChunk = Box<dyn Fn(&[u8]) -> io::Result<Vec<u8>> + Send>;
+
+/// Writer that compresses the data written to it in content-defined chunks, see the module docs
+///
+/// The data is buffered until a chunk boundary is certain, and the last chunks are written when
+/// it is dropped, like the other encoders that finish automatically.
+pub struct ChunkedEncoder<W: Write> {
+    inner: W,
+    compress_chunk: CompressChunk,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ChunkedEncoder<W> {
+    pub fn new(inner: W, compress_chunk: CompressChunk) -> Self {
+        Self {
+            inner,
+            compress_chunk,
+            buffer: Vec::with_capacity(MAX_CHUNK_SIZE * 2),
+        }
+    }
+
+    /// Compresses and writes the chunks at the start of the buffer, only the complete ones
+    /// unless `finish`
+    fn write_chunks(&mut self, finish: bool) -> io::Result<()> {
+        let mut start = 0;
+        while self.buffer.len() - start >= MAX_CHUNK_SIZE || (finish && start < self.buffer.len()) {
+            let length = chunk_length(&self.buffer[start..]);
+            let compressed = (self.compress_chunk)(&self.buffer[start..start + length])?;
+            self.inner.write_all(&compressed)?;
+            start += length;
+        }
+        self.buffer.drain(..start);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkedEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.write_chunks(false)?;
+        Ok(buf.len())
+    }
+
+    /// Only flushes the chunks written so far, a flush point in the middle of a chunk would move
+    /// the next boundaries
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for ChunkedEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.write_chunks(true).and_then(|()| self.inner.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chunks of `data`, as the encoder would cut them
+    fn chunks(data: &[u8]) -> Vec<&[u8]> {
+        let mut chunks = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (chunk, next) = rest.split_at(chunk_length(rest));
+            chunks.push(chunk);
+            rest = next;
+        }
+        chunks
+    }
+
+    fn random_bytes(len: usize, mut state: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_boundaries_stable_under_insertion() {
+        let data = random_bytes(4 * 1024 * 1024, 1);
+        let original = chunks(&data);
+        assert!(original.iter().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+        assert!(original.len() > 4 * 1024 * 1024 / MAX_CHUNK_SIZE);
+
+        let mut inserted = data.clone();
+        inserted.splice(100_000..100_000, random_bytes(1000, 2));
+        let changed = chunks(&inserted);
+
+        // Only the chunks around the insertion differ
+        let different = changed.iter().filter(|chunk| !original.contains(chunk)).count();
+        assert!(different <= 2, "{different} of {} chunks changed", changed.len());
+        assert_eq!(original.last(), changed.last());
+    }
+
+    #[test]
+    fn test_encoder_writes_every_chunk() {
+        let data = random_bytes(1024 * 1024 + 123, 3);
+        let mut output = vec![];
+        // Every chunk is stored after its length
+        let mut encoder = ChunkedEncoder::new(
+            &mut output,
+            Box::new(|chunk| Ok([&(chunk.len() as u32).to_le_bytes(), chunk].concat())),
+        );
+        // Written in small pieces, the boundaries are the same as when cut at once
+        for piece in data.chunks(1000) {
+            encoder.write_all(piece).unwrap();
+        }
+        drop(encoder);
+
+        let mut written = vec![];
+        let mut rest = &output[..];
+        while !rest.is_empty() {
+            let (length, next) = rest.split_at(4);
+            let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+            let (chunk, next) = next.split_at(length);
+            written.push(chunk);
+            rest = next;
+        }
+        assert_eq!(written, chunks(&data));
+    }
+}
@@ -0,0 +1,286 @@
+//! SHA-256 checksums of the compressed outputs, written to the file set by `--checksum-file` in
+//! the format of `sha256sum`, like the SHA256SUMS files of package mirrors.
+//!
+//! Also the checksums of the decompressed archives and of the files unpacked from them, printed
+//! with `--checksum`.
+
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use fs_err as fs;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+/// Chunks read through a [`TeeReader`] waiting to be hashed, a few keep the hashing thread busy
+/// without holding on to much memory
+const PENDING_CHUNKS: usize = 16;
+
+/// SHA-256 of the bytes written through a [`HashingWriter`], shared so it can be read once the
+/// writer is dropped by the encoders
+#[derive(Clone, Default)]
+pub struct Checksum(Arc<Mutex<Sha256>>);
+
+impl Checksum {
+    pub fn update(&self, data: &[u8]) {
+        self.0.lock().unwrap().update(data);
+    }
+
+    /// Hashes the rest of `reader`, for the outputs written by seeking back, which can't be hashed
+    /// as they are written
+    pub fn update_from(&self, reader: &mut impl Read) -> io::Result<()> {
+        let mut buffer = [0; 8192];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(()),
+                read => self.update(&buffer[..read]),
+            }
+        }
+    }
+
+    /// The checksum in lowercase hexadecimal
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.0.lock().unwrap().clone().finalize())
+    }
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Writer that hashes the bytes going through it into a [`Checksum`]
+pub struct HashingWriter<W> {
+    inner: W,
+    checksum: Checksum,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, checksum: Checksum) -> Self {
+        Self { inner, checksum }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksum.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// SHA-256 of everything read through a [`TeeReader`], hashed on a background thread so reading
+/// doesn't wait for the hashing
+pub struct BackgroundChecksum<R> {
+    reader: TeeReader<R>,
+    thread: JoinHandle<String>,
+}
+
+impl<R: Read> BackgroundChecksum<R> {
+    /// Returns the checksum, and the reader `inner` has to be read through to be hashed
+    pub fn new(inner: R) -> (Self, TeeReader<R>) {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(PENDING_CHUNKS);
+        let thread = thread::spawn(move || {
+            let mut hasher = Sha256::new();
+            // An empty chunk is only sent by `finish`, copies of the reader can outlive it
+            for chunk in receiver.iter().take_while(|chunk| !chunk.is_empty()) {
+                hasher.update(&chunk);
+            }
+            to_hex(&hasher.finalize())
+        });
+
+        let reader = TeeReader {
+            inner: Arc::new(Mutex::new(inner)),
+            sender,
+        };
+        (
+            Self {
+                reader: reader.clone(),
+                thread,
+            },
+            reader,
+        )
+    }
+
+    /// Reads what was left unread, like the padding at the end of tar archives that decoders stop
+    /// before, so all of it is hashed, and returns the checksum in lowercase hexadecimal
+    pub fn finish(mut self) -> io::Result<String> {
+        io::copy(&mut self.reader, &mut io::sink())?;
+        let _ = self.reader.sender.send(vec![]);
+        Ok(self.thread.join().expect("hashing thread panicked"))
+    }
+}
+
+/// Reader that sends a copy of the bytes read through it to the thread of a [`BackgroundChecksum`]
+pub struct TeeReader<R> {
+    inner: Arc<Mutex<R>>,
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl<R> Clone for TeeReader<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.lock().unwrap().read(buf)?;
+        if read > 0 {
+            // Only fails once the checksum is finished, then nothing is hashed anymore
+            let _ = self.sender.send(buf[..read].to_vec());
+        }
+        Ok(read)
+    }
+}
+
+/// SHA-256 of every file in `path`, or of `path` itself if it is a file, hashed in parallel, with
+/// their paths relative to it, or the file name for `path` itself. Symlinks aren't followed.
+pub fn hash_files(path: &Path) -> crate::Result<Vec<(PathBuf, String)>> {
+    let mut files = vec![];
+    find_files(path, &mut files)?;
+
+    files
+        .par_iter()
+        .map(|file| {
+            let checksum = Checksum::default();
+            checksum.update_from(&mut fs::File::open(file)?)?;
+            let relative = match file.strip_prefix(path) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative.to_owned(),
+                _ => file.file_name().map(PathBuf::from).unwrap_or_default(),
+            };
+            Ok((relative, checksum.to_hex()))
+        })
+        .collect()
+}
+
+fn find_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(path)?.file_type();
+    if file_type.is_dir() {
+        for entry in fs::read_dir(path)? {
+            find_files(&entry?.path(), files)?;
+        }
+    } else if file_type.is_file() {
+        files.push(path.to_owned());
+    }
+    Ok(())
+}
+
+/// Checksums of the decompressed archives, each followed by the ones of the files unpacked from
+/// it, printed once every archive is decompressed with `--checksum`
+#[derive(Debug, Default)]
+pub struct DigestTable(Mutex<Vec<Vec<(PathBuf, String)>>>);
+
+impl DigestTable {
+    /// Adds the checksum of one archive and the ones of the files unpacked from it, by the paths
+    /// they were unpacked to
+    pub fn add(&self, archive: &Path, checksum: String, mut files: Vec<(PathBuf, String)>) {
+        files.sort();
+        files.insert(0, (archive.to_owned(), checksum));
+        self.0.lock().unwrap().push(files);
+    }
+
+    /// Prints the checksums in the format of `sha256sum`, so they can be checked with
+    /// `sha256sum -c`, the archives in the order of their paths
+    pub fn print(&self) {
+        let mut archives = self.0.lock().unwrap();
+        archives.sort();
+        for (path, checksum) in archives.iter().flatten() {
+            let (escaped, name) = escape_name(&path.to_string_lossy());
+            println!("{}{checksum}  {name}", if escaped { "\\" } else { "" });
+        }
+    }
+}
+
+/// Adds the `checksum` of `output_path` to the checksum file at `checksum_path`, replacing the line
+/// of a previous output with the same name, so the outputs of several runs end up in the same file.
+///
+/// The name is relative to the directory of the checksum file when the output is inside of it,
+/// which is where `sha256sum -c` is run from.
+pub fn write_checksum_file(checksum_path: &Path, output_path: &Path, checksum: &Checksum) -> crate::Result<()> {
+    let checksum_dir = match checksum_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::canonicalize(dir)?,
+        _ => fs::canonicalize(".")?,
+    };
+    let output_path = fs::canonicalize(output_path)?;
+    let name = output_path.strip_prefix(&checksum_dir).unwrap_or(&output_path);
+    let (escaped, name) = escape_name(&name.to_string_lossy());
+    let line = format!("{}{}  {name}", if escaped { "\\" } else { "" }, checksum.to_hex());
+
+    let contents = match fs::read_to_string(checksum_path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let mut contents: String = contents
+        .lines()
+        .filter(|existing| existing.split_once("  ").map(|(_, existing_name)| existing_name) != Some(name.as_str()))
+        .flat_map(|existing| [existing, "\n"])
+        .collect();
+    contents.push_str(&line);
+    contents.push('\n');
+    fs::write(checksum_path, contents)?;
+    Ok(())
+}
+
+/// Escapes the backslashes and newlines of `name` like `sha256sum`, which marks the lines of the
+/// escaped names with a leading backslash, returning if it was escaped
+fn escape_name(name: &str) -> (bool, String) {
+    if name.contains(['\\', '\n', '\r']) {
+        let name = name.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+        (true, name)
+    } else {
+        (false, name.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let checksum_path = dir.path().join("SHA256SUMS");
+        let output_path = dir.path().join("a.tar.gz");
+        fs::write(&output_path, "").unwrap();
+
+        let checksum = Checksum::default();
+        let mut writer = HashingWriter::new(vec![], checksum.clone());
+        writer.write_all(b"abc").unwrap();
+        write_checksum_file(&checksum_path, &output_path, &checksum).unwrap();
+        // Written again, the line is replaced
+        write_checksum_file(&checksum_path, &output_path, &checksum).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&checksum_path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  a.tar.gz\n"
+        );
+        assert_eq!(escape_name("a\\b\nc"), (true, "a\\\\b\\nc".to_owned()));
+    }
+
+    #[test]
+    fn test_background_checksum() {
+        let (checksum, mut reader) = BackgroundChecksum::new(&b"abcdef"[..]);
+        let mut start = [0; 3];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"abc");
+
+        // What was left unread is hashed too
+        let expected = Checksum::default();
+        expected.update(b"abcdef");
+        assert_eq!(checksum.finish().unwrap(), expected.to_hex());
+    }
+}
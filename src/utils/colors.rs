@@ -4,12 +4,26 @@
 
 use std::env;
 
-use once_cell::sync::Lazy;
+use clap::ColorChoice;
+use once_cell::sync::{Lazy, OnceCell};
 
-static DISABLE_COLORED_TEXT: Lazy<bool> = Lazy::new(|| {
-    env::var_os("NO_COLOR").is_some() || atty::isnt(atty::Stream::Stdout) || atty::isnt(atty::Stream::Stderr)
+/// Set by `--color`, must be set before printing anything colored.
+static COLOR_CHOICE: OnceCell<ColorChoice> = OnceCell::new();
+
+static DISABLE_COLORED_TEXT: Lazy<bool> = Lazy::new(|| match COLOR_CHOICE.get().copied().unwrap_or_default() {
+    ColorChoice::Always => false,
+    ColorChoice::Never => true,
+    ColorChoice::Auto => {
+        env::var_os("NO_COLOR").is_some() || atty::isnt(atty::Stream::Stdout) || atty::isnt(atty::Stream::Stderr)
+    }
 });
 
+pub fn set_color_choice(choice: ColorChoice) {
+    if COLOR_CHOICE.get().is_none() {
+        COLOR_CHOICE.set(choice).unwrap();
+    }
+}
+
 macro_rules! color {
     ($name:ident = $value:literal) => {
         #[cfg(target_family = "unix")]
@@ -0,0 +1,56 @@
+//! Order of the stored entries set by `--preserve-order`, taken from an existing archive or from
+//! a list of paths, which the inputs are walked in instead of the order of the file system.
+
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+/// Position of each path in the order to follow
+#[derive(Debug, Default)]
+pub struct EntryOrder {
+    ranks: HashMap<PathBuf, usize>,
+}
+
+impl EntryOrder {
+    /// The order of `paths`, the directories that aren't listed come where their first entry does
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut ranks = HashMap::new();
+        for (rank, path) in paths.into_iter().enumerate() {
+            let path = normalize(&path);
+            for ancestor in path.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()) {
+                ranks.entry(ancestor.to_path_buf()).or_insert(rank);
+            }
+        }
+        Self { ranks }
+    }
+
+    /// The position of the entry stored as `path`, the ones that aren't listed come after the
+    /// others, in the order of the file system
+    pub fn rank(&self, path: &Path) -> usize {
+        self.ranks.get(&normalize(path)).copied().unwrap_or(usize::MAX)
+    }
+}
+
+/// `path` without its `.` components, the same entry is often listed as `./dir` and `dir/`
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_order() {
+        let order = EntryOrder::new(["b/z", "./a/", "b/y"].map(PathBuf::from));
+
+        assert_eq!(order.rank(Path::new("b")), 0);
+        assert_eq!(order.rank(Path::new("b/z")), 0);
+        assert_eq!(order.rank(Path::new("a")), 1);
+        assert_eq!(order.rank(Path::new("b/y")), 2);
+        assert_eq!(order.rank(Path::new("c")), usize::MAX);
+    }
+}
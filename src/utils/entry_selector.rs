@@ -0,0 +1,248 @@
+//! Picking the entries of an archive to unpack, with `--interactive`.
+//!
+//! The entries are listed with checkboxes, moved through with the arrow keys and selected with
+//! space. In accessible mode, and when stderr isn't a terminal, they are numbered instead, and the
+//! numbers of the selected ones are asked, like `--index` takes them.
+
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use console::{Key, Term};
+
+use super::{colors, strip_cur_dir, EscapedPathDisplay};
+use crate::{
+    accessible::is_running_in_accessible_mode,
+    cli::parse_index_ranges,
+    error::{Error, FinalError},
+    list::FileInArchive,
+};
+
+/// Lines above the entries, the question and the keys
+const HEADER_LINES: usize = 2;
+
+/// Asks which of the `entries` of the archive at `archive_path` to unpack, returns their paths
+/// without `.` and root components, none if the user cancelled
+pub fn select_entries(archive_path: &Path, entries: Vec<FileInArchive>) -> crate::Result<HashSet<PathBuf>> {
+    let names: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let name = EscapedPathDisplay::new(&entry.path).to_string();
+            if entry.is_dir && !name.ends_with('/') {
+                format!("{name}/")
+            } else {
+                name
+            }
+        })
+        .collect();
+    let prompt = format!(
+        "Which entries of '{}' do you want to unpack?",
+        EscapedPathDisplay::new(strip_cur_dir(archive_path))
+    );
+
+    let term = Term::stderr();
+    let selected = if is_running_in_accessible_mode() || !term.is_term() {
+        ask_numbers(&prompt, &names)?
+    } else {
+        EntrySelector::new(names.len()).run(&term, &prompt, &names)?
+    };
+
+    Ok(entries
+        .into_iter()
+        .zip(selected)
+        .filter(|(_, selected)| *selected)
+        .map(|(entry, _)| normal_path(&entry.path))
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect())
+}
+
+/// `path` without its `.`, `..` and root components, like the entries are compared
+pub fn normal_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Prints the numbered `names` and asks the numbers of the selected ones
+fn ask_numbers(prompt: &str, names: &[String]) -> crate::Result<Vec<bool>> {
+    println!("{prompt}");
+    for (i, name) in names.iter().enumerate() {
+        println!("  {}{}{}) {}", *colors::GREEN, i + 1, *colors::RESET, name);
+    }
+
+    loop {
+        print!("Numbers of the entries, like 1,3-5, or nothing to cancel: ");
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            let error = FinalError::with_title("Unexpected EOF when asking which entries to unpack")
+                .detail("Expected the numbers of the entries as answer, but found EOF instead.")
+                .hint("If using Ouch in scripting, consider using `--index` instead of `--interactive`.");
+            return Err(error.into());
+        }
+
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(vec![false; names.len()]);
+        }
+        match parse_index_ranges(answer) {
+            Ok(indexes) if indexes.last() <= names.len() => {
+                return Ok((1..=names.len()).map(|index| indexes.contains(index)).collect());
+            }
+            Ok(indexes) => println!("There is no entry {}, there are {}.", indexes.last(), names.len()),
+            Err(reason) => println!("Invalid numbers: {reason}."),
+        }
+    }
+}
+
+/// State of the list of entries with checkboxes
+#[derive(Debug)]
+struct EntrySelector {
+    /// Whether each entry is selected
+    selected: Vec<bool>,
+    /// Position of the highlighted entry
+    cursor: usize,
+    /// Position of the first entry shown
+    scroll: usize,
+}
+
+/// What the user did with the last key
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Continue,
+    Confirm,
+    Cancel,
+}
+
+impl EntrySelector {
+    fn new(len: usize) -> Self {
+        Self {
+            selected: vec![false; len],
+            cursor: 0,
+            scroll: 0,
+        }
+    }
+
+    /// Shows the entries until the user confirms or cancels, the selection is empty if cancelled
+    fn run(mut self, term: &Term, prompt: &str, names: &[String]) -> crate::Result<Vec<bool>> {
+        term.hide_cursor()?;
+        let result = self.show_until_done(term, prompt, names);
+        term.show_cursor()?;
+
+        match result? {
+            Outcome::Confirm => Ok(self.selected),
+            _ => Ok(vec![false; names.len()]),
+        }
+    }
+
+    fn show_until_done(&mut self, term: &Term, prompt: &str, names: &[String]) -> crate::Result<Outcome> {
+        loop {
+            let (rows, columns) = term.size();
+            let height = (usize::from(rows).saturating_sub(HEADER_LINES + 1)).clamp(1, names.len().max(1));
+            let width = usize::from(columns).saturating_sub(1);
+
+            let mut lines = vec![
+                prompt.to_owned(),
+                format!(
+                    "{}up/down moves, space selects, a selects all, enter unpacks, q cancels ({} selected){}",
+                    *colors::YELLOW,
+                    self.selected.iter().filter(|&&selected| selected).count(),
+                    *colors::RESET
+                ),
+            ];
+            for (i, name) in names.iter().enumerate().skip(self.scroll).take(height) {
+                let marker = if i == self.cursor { '>' } else { ' ' };
+                let checkbox = if self.selected[i] { 'x' } else { ' ' };
+                let line: String = format!("{marker} [{checkbox}] {name}").chars().take(width).collect();
+                if i == self.cursor {
+                    lines.push(format!("{}{line}{}", *colors::GREEN, *colors::RESET));
+                } else {
+                    lines.push(line);
+                }
+            }
+            for line in &lines {
+                term.write_line(line)?;
+            }
+
+            let key = match term.read_key() {
+                Ok(key) => key,
+                // Ctrl-C
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => Key::Escape,
+                Err(err) => return Err(Error::from(err)),
+            };
+            term.clear_last_lines(lines.len())?;
+
+            match self.handle(key, height) {
+                Outcome::Continue => {}
+                outcome => return Ok(outcome),
+            }
+        }
+    }
+
+    /// Applies `key`, with `height` entries shown at once
+    fn handle(&mut self, key: Key, height: usize) -> Outcome {
+        let last = self.selected.len().saturating_sub(1);
+        match key {
+            Key::ArrowUp | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            Key::ArrowDown | Key::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            Key::PageUp => self.cursor = self.cursor.saturating_sub(height),
+            Key::PageDown => self.cursor = (self.cursor + height).min(last),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = last,
+            Key::Char(' ') => {
+                if let Some(selected) = self.selected.get_mut(self.cursor) {
+                    *selected = !*selected;
+                }
+            }
+            Key::Char('a') => {
+                let all = self.selected.iter().all(|&selected| selected);
+                self.selected.fill(!all);
+            }
+            Key::Enter => return Outcome::Confirm,
+            Key::Escape | Key::Char('q') => return Outcome::Cancel,
+            _ => {}
+        }
+
+        // The highlighted entry is always shown
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + height {
+            self.scroll = self.cursor + 1 - height;
+        }
+        Outcome::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_selector_keys() {
+        let mut selector = EntrySelector::new(5);
+        assert_eq!(selector.handle(Key::Char(' '), 2), Outcome::Continue);
+        selector.handle(Key::ArrowDown, 2);
+        selector.handle(Key::ArrowDown, 2);
+        selector.handle(Key::Char(' '), 2);
+        assert_eq!(selector.selected, [true, false, true, false, false]);
+        // Scrolled so the highlighted entry is shown
+        assert_eq!((selector.cursor, selector.scroll), (2, 1));
+
+        selector.handle(Key::End, 2);
+        selector.handle(Key::ArrowDown, 2);
+        assert_eq!((selector.cursor, selector.scroll), (4, 3));
+        selector.handle(Key::Home, 2);
+        assert_eq!((selector.cursor, selector.scroll), (0, 0));
+
+        selector.handle(Key::Char('a'), 2);
+        assert!(selector.selected.iter().all(|&selected| selected));
+        selector.handle(Key::Char('a'), 2);
+        assert!(selector.selected.iter().all(|&selected| !selected));
+
+        assert_eq!(selector.handle(Key::Enter, 2), Outcome::Confirm);
+        assert_eq!(selector.handle(Key::Char('q'), 2), Outcome::Cancel);
+    }
+}
@@ -0,0 +1,108 @@
+//! Sizes of the compressed entries, printed with `-vv`.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use super::{Bytes, EscapedPathDisplay};
+use crate::info;
+
+/// Writer that counts the bytes going through it.
+///
+/// The count is shared, so it can be read while the writer is owned by the encoders.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: Arc<AtomicU64>,
+}
+
+impl<W: Write> CountingWriter<W> {
+    /// Returns the writer and a handle to its count of written bytes
+    pub fn new(inner: W) -> (Self, ByteCount) {
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = ByteCount(Arc::clone(&count));
+        (Self { inner, count }, counter)
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bytes written so far to a [`CountingWriter`]
+#[derive(Clone)]
+pub struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Prints the stored size of each file added to a stream, like a tar archive, as the bytes written
+/// to the output from the moment it was added until the next one is.
+///
+/// Encoders buffer their output, so these sizes are only approximate.
+pub struct EntrySizes {
+    written: ByteCount,
+    current: Option<Entry>,
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    /// Bytes written to the output when the entry was added
+    start: u64,
+}
+
+impl EntrySizes {
+    pub fn new(written: ByteCount) -> Self {
+        Self { written, current: None }
+    }
+
+    /// Prints the previous entry, and starts counting the bytes of this one
+    pub fn start_entry(&mut self, path: &Path, size: u64) {
+        self.finish_entry();
+        self.current = Some(Entry {
+            path: path.to_owned(),
+            size,
+            start: self.written.get(),
+        });
+    }
+
+    /// Prints the last entry, call it once the output is completely written
+    pub fn finish_entry(&mut self) {
+        if let Some(entry) = self.current.take() {
+            print_entry_size(&entry.path, entry.size, self.written.get() - entry.start);
+        }
+    }
+}
+
+/// Prints the original and stored sizes of a file, and the ratio between them
+pub fn print_entry_size(path: &Path, size: u64, stored_size: u64) {
+    let ratio = if size == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", stored_size as f64 / size as f64 * 100.0)
+    };
+
+    info!(
+        accessible,
+        "'{}': {} -> {} ({ratio})",
+        EscapedPathDisplay::new(path),
+        Bytes::new(size),
+        Bytes::new(stored_size)
+    );
+}
@@ -0,0 +1,104 @@
+//! Glob patterns given by `--exclude` and `--exclude-from`.
+
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::error::{Error, FinalError};
+
+/// Set of glob patterns for paths that should be skipped, both when compressing and when
+/// unpacking archives.
+///
+/// Patterns are matched against the whole relative path and against the file name alone, so
+/// `target` matches `dir/target` as well, and everything inside of excluded directories is
+/// excluded too.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludePatterns {
+    globs: GlobSet,
+}
+
+impl ExcludePatterns {
+    /// Builds the set from inline `patterns` merged with the patterns read from each of `files`.
+    ///
+    /// See [`read_patterns_file`] for the format of these files.
+    pub fn new(patterns: &[String], files: &[PathBuf]) -> crate::Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            builder.add(parse_glob(pattern, None)?);
+        }
+
+        for file in files {
+            for pattern in read_patterns_file(file)? {
+                builder.add(parse_glob(&pattern, Some(file))?);
+            }
+        }
+
+        let globs = builder
+            .build()
+            .map_err(|err| FinalError::with_title("Failed to build the exclude patterns").detail(err.to_string()))?;
+
+        Ok(Self { globs })
+    }
+
+    /// Checks if `path`, or any of its parent directories, matches any of the patterns.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self.globs.is_empty() {
+            return false;
+        }
+
+        path.ancestors().any(|ancestor| {
+            self.globs.is_match(ancestor) || ancestor.file_name().is_some_and(|name| self.globs.is_match(name))
+        })
+    }
+}
+
+fn parse_glob(pattern: &str, source_file: Option<&Path>) -> crate::Result<Glob> {
+    Glob::new(pattern).map_err(|err| {
+        let mut error = FinalError::with_title(format!("Invalid exclude pattern '{pattern}'")).detail(err.to_string());
+        if let Some(file) = source_file {
+            error = error.detail(format!("Pattern read from '{}'", file.display()));
+        }
+        Error::InvalidUsage { reason: error }
+    })
+}
+
+/// Reads one glob per line, ignoring blank lines and lines starting with `#`.
+fn read_patterns_file(path: &Path) -> crate::Result<Vec<String>> {
+    let contents = fs::read_to_string(path)?;
+
+    Ok(parse_patterns(&contents))
+}
+
+fn parse_patterns(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_patterns() {
+        let contents = "# build artifacts\ntarget\n\n  *.log  \n#*.txt\n";
+        assert_eq!(parse_patterns(contents), ["target", "*.log"]);
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let patterns = ExcludePatterns::new(&["*.log".to_owned(), "dir/target".to_owned()], &[]).unwrap();
+
+        assert!(patterns.is_excluded(Path::new("today.log")));
+        assert!(patterns.is_excluded(Path::new("dir/logs/today.log")));
+        assert!(patterns.is_excluded(Path::new("dir/target")));
+        assert!(patterns.is_excluded(Path::new("dir/target/debug/ouch")));
+        assert!(!patterns.is_excluded(Path::new("dir/src/main.rs")));
+        assert!(!ExcludePatterns::default().is_excluded(Path::new("today.log")));
+    }
+}
@@ -0,0 +1,118 @@
+//! Compression levels picked by the extension of each file, with `--compress-level-by-extension`.
+
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use crate::error::{Error, FinalError};
+
+/// Level that stores the files without compressing them
+pub const STORE_LEVEL: u8 = 0;
+
+/// Highest level, for the files that compress well
+const MAX_LEVEL: u8 = 9;
+
+/// Files that are already compressed, compressing them again only costs time
+const STORED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avi", "br", "bz2", "docx", "flac", "gif", "gz", "jar", "jpeg", "jpg", "lz4", "m4a", "mkv", "mov",
+    "mp3", "mp4", "odt", "ogg", "pdf", "png", "pptx", "rar", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// Text files, which compress well
+const MAX_LEVEL_EXTENSIONS: &[&str] = &[
+    "c", "css", "csv", "h", "htm", "html", "js", "json", "log", "md", "py", "rs", "sql", "svg", "toml", "txt", "xml",
+    "yaml", "yml",
+];
+
+/// Compression level of the files, by their lowercase extensions, the other files get the default level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionLevels {
+    levels: BTreeMap<String, u8>,
+}
+
+impl Default for ExtensionLevels {
+    fn default() -> Self {
+        let stored = STORED_EXTENSIONS.iter().map(|extension| (extension, STORE_LEVEL));
+        let max = MAX_LEVEL_EXTENSIONS.iter().map(|extension| (extension, MAX_LEVEL));
+
+        Self {
+            levels: stored
+                .chain(max)
+                .map(|(extension, level)| (extension.to_string(), level))
+                .collect(),
+        }
+    }
+}
+
+impl ExtensionLevels {
+    /// The built-in levels, changed by `rules`, like "txt:6,bmp:9,jpg:0", where level 0 stores
+    /// the files without compressing them.
+    pub fn with_rules(rules: &str) -> crate::Result<Self> {
+        let mut extension_levels = Self::default();
+
+        for rule in rules.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let parsed = rule.split_once(':').and_then(|(extension, level)| {
+                let extension = extension.trim().trim_start_matches('.').to_lowercase();
+                let level = level.trim().parse().ok().filter(|level| *level <= MAX_LEVEL)?;
+                (!extension.is_empty()).then_some((extension, level))
+            });
+
+            let Some((extension, level)) = parsed else {
+                let error = FinalError::with_title(format!("Invalid --compress-level-by-extension rule '{rule}'"))
+                    .detail(format!("Rules are EXTENSION:LEVEL, with a level from 0 to {MAX_LEVEL}"))
+                    .hint("Level 0 stores the files without compressing them, like 'jpg:0,txt:9'");
+                return Err(Error::InvalidUsage { reason: error });
+            };
+            extension_levels.levels.insert(extension, level);
+        }
+
+        Ok(extension_levels)
+    }
+
+    /// The level for the file at `path`, `None` if its extension has none
+    pub fn level_for(&self, path: &Path) -> Option<u8> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        self.levels.get(&extension).copied()
+    }
+}
+
+impl fmt::Display for ExtensionLevels {
+    /// Lists the extensions of each level, like "0 (stored): gz, jpg; 9: json, txt"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut by_level: BTreeMap<u8, Vec<&str>> = BTreeMap::new();
+        for (extension, level) in &self.levels {
+            by_level.entry(*level).or_default().push(extension);
+        }
+
+        for (i, (level, extensions)) in by_level.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            if *level == STORE_LEVEL {
+                write!(f, "{level} (stored): {}", extensions.join(", "))?;
+            } else {
+                write!(f, "{level}: {}", extensions.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_levels() {
+        let levels = ExtensionLevels::with_rules("jpg:5, .BMP:9,txt:0").unwrap();
+
+        assert_eq!(levels.level_for(Path::new("photo.JPG")), Some(5));
+        assert_eq!(levels.level_for(Path::new("image.bmp")), Some(9));
+        assert_eq!(levels.level_for(Path::new("notes.txt")), Some(STORE_LEVEL));
+        assert_eq!(levels.level_for(Path::new("video.mp4")), Some(STORE_LEVEL));
+        assert_eq!(levels.level_for(Path::new("data.json")), Some(MAX_LEVEL));
+        assert_eq!(levels.level_for(Path::new("program")), None);
+
+        assert!(ExtensionLevels::with_rules("jpg").is_err());
+        assert!(ExtensionLevels::with_rules("jpg:10").is_err());
+        assert!(ExtensionLevels::with_rules(":5").is_err());
+    }
+}
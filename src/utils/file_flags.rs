@@ -0,0 +1,156 @@
+//! File flags like immutable or append-only, stored and restored with `--preserve-flags`.
+//!
+//! They are stored by name, the same way bsdtar does, so both can read each other's archives.
+//! Flags without a name here are left out, and platforms without file flags never have any.
+
+use std::{io, path::Path};
+
+/// Key of the PAX extended header record with the flags of the next entry, like "schg,nodump"
+pub const PAX_FILE_FLAGS: &str = "SCHILY.fflags";
+
+/// Returns the names of the flags set on `path`, separated by commas, empty if it has none
+pub fn get_file_flags(path: &Path) -> io::Result<String> {
+    let flags = platform::get(path)?;
+    let names: Vec<_> = platform::FLAGS
+        .iter()
+        .filter(|(_, bit)| flags & bit != 0)
+        .map(|(name, _)| *name)
+        .collect();
+
+    Ok(names.join(","))
+}
+
+/// Sets the flags named in `names`, separated by commas, on `path`, keeping the ones already set
+pub fn set_file_flags(path: &Path, names: &str) -> io::Result<()> {
+    let flags = names
+        .split(',')
+        .filter_map(|name| platform::FLAGS.iter().find(|(known, _)| *known == name.trim()))
+        .fold(0, |flags, (_, bit)| flags | bit);
+
+    if flags == 0 {
+        return Ok(());
+    }
+    platform::set(path, platform::get(path)? | flags)
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::{
+        fs::File,
+        io,
+        os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+        path::Path,
+    };
+
+    // From linux/fs.h, these aren't in libc
+    const FS_IOC_GETFLAGS: libc::Ioctl = ior(b'f', 1) as libc::Ioctl;
+    const FS_IOC_SETFLAGS: libc::Ioctl = iow(b'f', 2) as libc::Ioctl;
+
+    /// Named like the flags bsdtar maps them to
+    pub const FLAGS: &[(&str, u32)] = &[
+        ("sappnd", 0x20),  // FS_APPEND_FL
+        ("schg", 0x10),    // FS_IMMUTABLE_FL
+        ("nodump", 0x40),  // FS_NODUMP_FL
+        ("noatime", 0x80), // FS_NOATIME_FL
+    ];
+
+    const fn ior(kind: u8, number: u32) -> u32 {
+        (2 << 30) | ((std::mem::size_of::<libc::c_long>() as u32) << 16) | ((kind as u32) << 8) | number
+    }
+
+    const fn iow(kind: u8, number: u32) -> u32 {
+        (1 << 30) | ((std::mem::size_of::<libc::c_long>() as u32) << 16) | ((kind as u32) << 8) | number
+    }
+
+    /// Flags are only supported on regular files and directories, which can be opened without
+    /// side effects, unlike devices and FIFOs
+    fn open(path: &Path) -> io::Result<Option<File>> {
+        let metadata = path.symlink_metadata()?;
+        if !metadata.is_file() && !metadata.is_dir() {
+            return Ok(None);
+        }
+
+        File::options()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+            .open(path)
+            .map(Some)
+    }
+
+    pub fn get(path: &Path) -> io::Result<u32> {
+        let Some(file) = open(path)? else {
+            return Ok(0);
+        };
+
+        let mut flags: libc::c_int = 0;
+        // Safety: the kernel writes an int to `flags`
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS, &mut flags) } == -1 {
+            let err = io::Error::last_os_error();
+            // Filesystems without flags, like tmpfs on older kernels
+            return match err.raw_os_error() {
+                Some(libc::ENOTTY | libc::EOPNOTSUPP) => Ok(0),
+                _ => Err(err),
+            };
+        }
+        Ok(flags as u32)
+    }
+
+    pub fn set(path: &Path, flags: u32) -> io::Result<()> {
+        let Some(file) = open(path)? else {
+            return Ok(());
+        };
+
+        let flags = flags as libc::c_int;
+        // Safety: the kernel reads an int from `flags`
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_SETFLAGS, &flags) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+mod platform {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+    #[cfg(target_os = "freebsd")]
+    use std::os::freebsd::fs::MetadataExt;
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::MetadataExt;
+
+    pub const FLAGS: &[(&str, u32)] = &[
+        ("uappnd", libc::UF_APPEND as u32),
+        ("uchg", libc::UF_IMMUTABLE as u32),
+        ("nodump", libc::UF_NODUMP as u32),
+        ("sappnd", libc::SF_APPEND as u32),
+        ("schg", libc::SF_IMMUTABLE as u32),
+    ];
+
+    pub fn get(path: &Path) -> io::Result<u32> {
+        Ok(path.symlink_metadata()?.st_flags())
+    }
+
+    pub fn set(path: &Path, flags: u32) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // Safety: `c_path` is a valid NUL-terminated string
+        if unsafe { libc::lchflags(c_path.as_ptr(), flags as _) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+mod platform {
+    use std::{io, path::Path};
+
+    pub const FLAGS: &[(&str, u32)] = &[];
+
+    pub fn get(_path: &Path) -> io::Result<u32> {
+        Ok(0)
+    }
+
+    pub fn set(_path: &Path, _flags: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+//! Creation and access times of files, stored and restored with `--preserve-btime` and
+//! `--atime-preserve`.
+//!
+//! They are stored in the same PAX records as bsdtar does, so both can read each other's archives.
+//! Most platforms can read creation times, but only macOS and Windows can set them.
+
+use std::{
+    fs::Metadata,
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use filetime::FileTime;
+
+use super::{warn_or_fail, EscapedPathDisplay};
+
+/// Key of the PAX extended header record with the creation time of the next entry, in seconds since
+/// the Unix epoch, like "1700000000.123456789"
+pub const PAX_CREATION_TIME: &str = "LIBARCHIVE.creationtime";
+
+/// Key of the PAX extended header record with the access time of the next entry, like
+/// [`PAX_CREATION_TIME`]
+pub const PAX_ACCESS_TIME: &str = "atime";
+
+/// Whether the creation times of the unpacked files can be set on this platform
+pub const CAN_SET_BIRTH_TIME: bool = cfg!(any(target_os = "macos", windows));
+
+/// Value of the [`PAX_CREATION_TIME`] record of a file with `metadata`, `None` if the platform or
+/// the file system doesn't keep creation times
+pub fn birth_time_record(metadata: &Metadata) -> Option<String> {
+    metadata.created().ok().map(format_pax_time)
+}
+
+/// Formats `time` like the values of the time records of PAX extended headers
+pub fn format_pax_time(time: SystemTime) -> String {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => format!("{}.{:09}", since_epoch.as_secs(), since_epoch.subsec_nanos()),
+        // Before 1970, the fraction still counts forward, like the other PAX times
+        Err(err) => {
+            let before_epoch = err.duration();
+            let seconds = before_epoch.as_secs() + u64::from(before_epoch.subsec_nanos() > 0);
+            let nanos = (1_000_000_000 - before_epoch.subsec_nanos()) % 1_000_000_000;
+            format!("-{seconds}.{nanos:09}")
+        }
+    }
+}
+
+/// Parses the value of a time record of PAX extended headers, `None` if it is malformed
+pub fn parse_pax_time(record: &str) -> Option<SystemTime> {
+    let (seconds, fraction) = record.trim().split_once('.').unwrap_or((record.trim(), ""));
+    if !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    // Nanoseconds, from at most 9 digits of the fraction
+    let digits = &fraction[..fraction.len().min(9)];
+    let nanos = format!("{digits:0<9}").parse::<u32>().ok()?;
+    let nanos = Duration::from_nanos(u64::from(nanos));
+
+    match seconds.strip_prefix('-') {
+        Some(seconds) => UNIX_EPOCH
+            .checked_sub(Duration::from_secs(seconds.parse().ok()?))?
+            .checked_add(nanos),
+        None => UNIX_EPOCH.checked_add(Duration::from_secs(seconds.parse().ok()?) + nanos),
+    }
+}
+
+/// Sets the creation time of `path` to `time`, does nothing to symlinks and on the platforms where
+/// [`CAN_SET_BIRTH_TIME`] is false
+pub fn set_birth_time(path: &Path, time: SystemTime) -> io::Result<()> {
+    if path.symlink_metadata()?.is_symlink() {
+        return Ok(());
+    }
+    platform::set(path, time)
+}
+
+/// Whether the access time of a file couldn't be restored, which is only warned about once
+static ACCESS_TIME_DENIED: AtomicBool = AtomicBool::new(false);
+
+/// Access time of a file saved before reading it, to set it back after, used by `--atime-preserve`
+pub struct AccessTime {
+    path: PathBuf,
+    accessed: FileTime,
+}
+
+impl AccessTime {
+    /// Saves the access time in `metadata` of the file at `path`, before it is read
+    pub fn save(path: &Path, metadata: &Metadata) -> Self {
+        Self {
+            path: path.to_owned(),
+            accessed: FileTime::from_last_access_time(metadata),
+        }
+    }
+
+    /// Sets the access time back to the saved one, after the file was read
+    ///
+    /// Without the permission to change the metadata of the file, the user is warned once.
+    pub fn restore(self) -> crate::Result<()> {
+        set_access_time(&self.path, self.accessed)
+    }
+}
+
+/// Sets the access time of `path`, which isn't followed if it's a symlink, warning once if it
+/// isn't permitted
+pub fn set_access_time(path: &Path, accessed: FileTime) -> crate::Result<()> {
+    let metadata = path.symlink_metadata()?;
+    let result = if metadata.is_symlink() {
+        filetime::set_symlink_file_times(path, accessed, FileTime::from_last_modification_time(&metadata))
+    } else {
+        filetime::set_file_atime(path, accessed)
+    };
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            if !ACCESS_TIME_DENIED.swap(true, Ordering::Relaxed) {
+                warn_or_fail(format!(
+                    "Not enough privileges to set the access time of '{}', --atime-preserve needs to \
+                     change the metadata of the files, skipping the ones that can't be changed.",
+                    EscapedPathDisplay::new(path)
+                ))?;
+            }
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::{
+        fs::{File, FileTimes},
+        io,
+        os::macos::fs::FileTimesExt,
+        path::Path,
+        time::SystemTime,
+    };
+
+    pub fn set(path: &Path, time: SystemTime) -> io::Result<()> {
+        // Directories can only be opened for reading, which is enough for the owner
+        File::open(path)?.set_times(FileTimes::new().set_created(time))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::{
+        fs::{File, FileTimes},
+        io,
+        os::windows::fs::{FileTimesExt, OpenOptionsExt},
+        path::Path,
+        time::SystemTime,
+    };
+
+    // From winnt.h and winbase.h
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    pub fn set(path: &Path, time: SystemTime) -> io::Result<()> {
+        // Directories can only be opened with backup semantics
+        File::options()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?
+            .set_times(FileTimes::new().set_created(time))
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod platform {
+    use std::{io, path::Path, time::SystemTime};
+
+    pub fn set(_path: &Path, _time: SystemTime) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pax_time() {
+        let time = |seconds, nanos| UNIX_EPOCH + Duration::new(seconds, nanos);
+        assert_eq!(parse_pax_time("1700000000"), Some(time(1_700_000_000, 0)));
+        assert_eq!(parse_pax_time("1700000000.5"), Some(time(1_700_000_000, 500_000_000)));
+        assert_eq!(
+            parse_pax_time("1700000000.1234567891"),
+            Some(time(1_700_000_000, 123_456_789))
+        );
+        assert_eq!(parse_pax_time("-1.25"), Some(UNIX_EPOCH - Duration::from_millis(750)));
+        assert_eq!(parse_pax_time("soon"), None);
+        assert_eq!(parse_pax_time("1.-5"), None);
+    }
+
+    #[test]
+    fn test_pax_time_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = std::fs::metadata(dir.path()).unwrap();
+        let Ok(created) = metadata.created() else {
+            // Not kept by this file system
+            assert_eq!(birth_time_record(&metadata), None);
+            return;
+        };
+        let record = birth_time_record(&metadata).unwrap();
+        assert_eq!(parse_pax_time(&record), Some(created));
+    }
+}
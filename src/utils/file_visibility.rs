@@ -1,6 +1,18 @@
-use std::path::Path;
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use super::{warning_policy, WarningPolicy};
+use super::{EntryOrder, ExcludePatterns};
+use crate::{info, utils::EscapedPathDisplay, warning};
 
 /// Determines which files should be read or ignored during directory walking
+#[derive(Clone)]
 pub struct FileVisibilityPolicy {
     /// Enables reading .ignore files.
     ///
@@ -19,6 +31,50 @@ pub struct FileVisibilityPolicy {
 
     /// Enables reading `.git/info/exclude` files.
     pub read_git_exclude: bool,
+
+    /// Paths matching these patterns are skipped.
+    ///
+    /// Empty by default.
+    pub exclude: ExcludePatterns,
+
+    /// Skips the directories and files of version control systems, like `.git`.
+    ///
+    /// Disabled by default.
+    pub exclude_vcs: bool,
+
+    /// How many paths were skipped by `exclude_vcs` so far, shared by the clones of the policy.
+    pub vcs_skipped: Arc<AtomicUsize>,
+
+    /// Directories at this depth are still included, but not descended into.
+    ///
+    /// Unlimited by default.
+    pub max_depth: Option<usize>,
+
+    /// Follow symlinks, walking into the directories they point to.
+    ///
+    /// Disabled by default.
+    pub follow_links: bool,
+
+    /// Skips the entries on other file systems than the walked path, like mount points.
+    ///
+    /// Disabled by default, only supported on Unix.
+    pub one_file_system: bool,
+
+    /// The paths of the inputs inside of these directories are stored relative to them.
+    ///
+    /// Empty by default, the inputs are stored by their names.
+    pub base_dirs: Vec<PathBuf>,
+
+    /// The entries are walked in this order, instead of the one of the file system.
+    ///
+    /// Unset by default.
+    pub entry_order: Option<Arc<EntryOrder>>,
+
+    /// Skips the entries without telling the user, or counting them in `vcs_skipped`, for the walks
+    /// made before the one that compresses them.
+    ///
+    /// Disabled by default.
+    pub silent: bool,
 }
 
 impl Default for FileVisibilityPolicy {
@@ -28,6 +84,15 @@ impl Default for FileVisibilityPolicy {
             read_hidden: true,
             read_git_ignore: false,
             read_git_exclude: false,
+            exclude: ExcludePatterns::default(),
+            exclude_vcs: false,
+            vcs_skipped: Arc::default(),
+            max_depth: None,
+            follow_links: false,
+            one_file_system: false,
+            base_dirs: vec![],
+            entry_order: None,
+            silent: false,
         }
     }
 }
@@ -67,13 +132,273 @@ impl FileVisibilityPolicy {
         Self { read_hidden, ..self }
     }
 
+    #[must_use]
+    /// Skips paths matching `exclude`.
+    pub fn exclude(self, exclude: ExcludePatterns) -> Self {
+        Self { exclude, ..self }
+    }
+
+    #[must_use]
+    /// Skips the directories and files of version control systems.
+    pub fn exclude_vcs(self, exclude_vcs: bool) -> Self {
+        Self { exclude_vcs, ..self }
+    }
+
+    #[must_use]
+    /// Stops descending into directories deeper than `max_depth`.
+    pub fn max_depth(self, max_depth: Option<usize>) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    #[must_use]
+    /// Follows symlinks, walking into the directories they point to.
+    pub fn follow_links(self, follow_links: bool) -> Self {
+        Self { follow_links, ..self }
+    }
+
+    #[must_use]
+    /// Skips the entries on other file systems than the walked path.
+    pub fn one_file_system(self, one_file_system: bool) -> Self {
+        Self {
+            one_file_system,
+            ..self
+        }
+    }
+
+    #[must_use]
+    /// Stores the paths of the inputs inside of `base_dirs` relative to them.
+    pub fn base_dirs(self, base_dirs: Vec<PathBuf>) -> Self {
+        Self { base_dirs, ..self }
+    }
+
+    #[must_use]
+    /// Walks the entries in `entry_order`.
+    pub fn entry_order(self, entry_order: Option<Arc<EntryOrder>>) -> Self {
+        Self { entry_order, ..self }
+    }
+
+    #[must_use]
+    /// Skips the entries without telling the user.
+    pub fn silent(self, silent: bool) -> Self {
+        Self { silent, ..self }
+    }
+
+    /// Sorts the inputs `files` in `entry_order`, if set, by the paths they are stored as.
+    pub fn sort_inputs(&self, files: &mut [PathBuf]) {
+        if let Some(entry_order) = &self.entry_order {
+            files.sort_by_cached_key(|file| {
+                let stored_path = self.split_walk_root(file).map_or(file.as_path(), |(_, path)| path);
+                entry_order.rank(stored_path)
+            });
+        }
+    }
+
+    /// Splits `path`, an input, into the directory it is walked from and the path walked from
+    /// there, which the stored paths start with.
+    ///
+    /// That is the parent of `path` and its name, or the deepest of `base_dirs` holding `path`
+    /// and the rest of it. Returns `None` for paths without a parent.
+    pub fn split_walk_root<'a>(&'a self, path: &'a Path) -> Option<(&'a Path, &'a Path)> {
+        let base_dir = self
+            .base_dirs
+            .iter()
+            .filter(|base_dir| path != *base_dir && path.starts_with(base_dir))
+            .max_by_key(|base_dir| base_dir.components().count());
+
+        match base_dir {
+            Some(base_dir) => {
+                let relative_path = path.strip_prefix(base_dir).ok()?;
+                Some((base_dir.as_path(), relative_path))
+            }
+            None => Some((path.parent()?, Path::new(path.file_name()?))),
+        }
+    }
+
+    /// Checks if `entry` is a non-empty directory whose contents were cut off by `max_depth`.
+    pub fn is_truncated_by_max_depth(&self, entry: &ignore::DirEntry) -> bool {
+        self.max_depth == Some(entry.depth())
+            && entry.file_type().is_some_and(|file_type| file_type.is_dir())
+            && entry
+                .path()
+                .read_dir()
+                .is_ok_and(|mut children| children.next().is_some())
+    }
+
     /// Walks through a directory using [`ignore::Walk`]
-    pub fn build_walker(&self, path: impl AsRef<Path>) -> ignore::Walk {
-        ignore::WalkBuilder::new(path)
+    ///
+    /// When following symlinks, the ones pointing to an ancestor directory would make the walk go on
+    /// forever, these cycles are detected by [`ignore`], which compares each directory with all of
+    /// its ancestors, and skipped with a warning.
+    pub fn build_walker(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> impl Iterator<Item = Result<ignore::DirEntry, ignore::Error>> {
+        let exclude = self.exclude.clone();
+        let silent = self.silent;
+        // Silent walks count the version control paths on their own
+        let vcs_skipped = self.exclude_vcs.then(|| {
+            if silent {
+                Arc::default()
+            } else {
+                Arc::clone(&self.vcs_skipped)
+            }
+        });
+        let root_device = if self.one_file_system {
+            path.as_ref().metadata().ok().as_ref().and_then(device)
+        } else {
+            None
+        };
+
+        let mut walker = ignore::WalkBuilder::new(path);
+        walker
             .git_exclude(self.read_git_exclude)
             .git_ignore(self.read_git_ignore)
             .ignore(self.read_ignore)
             .hidden(self.read_hidden)
-            .build()
+            .max_depth(self.max_depth)
+            .follow_links(self.follow_links)
+            .filter_entry(move |entry| {
+                !exclude.is_excluded(entry.path())
+                    && !is_vcs(entry, vcs_skipped.as_deref())
+                    && is_on_device(entry, root_device, silent)
+            });
+        // The paths walked are the ones stored, the walk starts from the directory holding the input
+        if let Some(entry_order) = self.entry_order.clone() {
+            walker.sort_by_file_path(move |a, b| entry_order.rank(a).cmp(&entry_order.rank(b)));
+        }
+        let walker = walker.build();
+
+        walker.filter(move |entry| {
+            let Err(err) = entry else {
+                return true;
+            };
+            let Some(symlink) = find_loop(err) else {
+                return true;
+            };
+
+            // Kept with --strict, the walk fails on it instead
+            if warning_policy() == WarningPolicy::Strict {
+                return true;
+            }
+            if silent {
+                return false;
+            }
+
+            warning!(
+                "Skipping '{}', it is a symlink to one of its own parent directories.",
+                EscapedPathDisplay::new(symlink)
+            );
+            false
+        })
+    }
+}
+
+/// Names of the version control directories and files skipped by `exclude_vcs`, the same ones as
+/// `tar --exclude-vcs`
+const VCS_NAMES: &[&str] = &[
+    "CVS",
+    ".cvsignore",
+    "RCS",
+    "SCCS",
+    ".git",
+    ".gitignore",
+    ".gitattributes",
+    ".gitmodules",
+    ".svn",
+    ".arch-ids",
+    "{arch}",
+    "=RELEASE-ID",
+    "=meta-update",
+    "=update",
+    ".bzr",
+    ".bzrignore",
+    ".bzrtags",
+    ".hg",
+    ".hgignore",
+    ".hgtags",
+    "_darcs",
+];
+
+/// Checks if `entry` is a version control directory or file, counting it in `vcs_skipped`, when
+/// they are skipped.
+fn is_vcs(entry: &ignore::DirEntry, vcs_skipped: Option<&AtomicUsize>) -> bool {
+    let Some(vcs_skipped) = vcs_skipped else {
+        return false;
+    };
+
+    let is_vcs = entry.file_name().to_str().is_some_and(|name| VCS_NAMES.contains(&name));
+    if is_vcs {
+        vcs_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+    is_vcs
+}
+
+/// The device of the file system the file of `metadata` is on, only known on Unix.
+fn device(metadata: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    return Some(std::os::unix::fs::MetadataExt::dev(metadata));
+
+    #[cfg(not(unix))]
+    return None;
+}
+
+/// Checks if `entry` is on `root_device`, skipping it with a message if it isn't, unless `silent`.
+fn is_on_device(entry: &ignore::DirEntry, root_device: Option<u64>, silent: bool) -> bool {
+    let Some(root_device) = root_device else {
+        return true;
+    };
+
+    match entry.metadata().ok().as_ref().and_then(device) {
+        Some(device) if device != root_device => {
+            if silent {
+                return false;
+            }
+            info!(
+                inaccessible,
+                "Skipping '{}', it is on another file system.",
+                EscapedPathDisplay::new(entry.path())
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Finds the symlink that caused a file system loop error, if `err` is one.
+fn find_loop(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => find_loop(err),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_one_file_system() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/file"), "content").unwrap();
+
+        // Everything in the walked directory is on its file system
+        let policy = FileVisibilityPolicy::default().one_file_system(true);
+        let entries: Vec<_> = policy.build_walker(dir.path()).map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 3);
+
+        // Entries on another device, like a mount point, are skipped
+        let root_device = device(&dir.path().metadata().unwrap());
+        let other_device = root_device.map(|device| device.wrapping_add(1));
+        for entry in &entries {
+            assert!(is_on_device(entry, root_device, false));
+            assert!(!is_on_device(entry, other_device, false));
+            assert!(!is_on_device(entry, other_device, true));
+            assert!(is_on_device(entry, None, false));
+        }
     }
 }
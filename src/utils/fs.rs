@@ -2,30 +2,57 @@
 
 use std::{
     env,
-    io::Read,
+    io::{self, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+use filetime::{set_file_mtime, FileTime};
 use fs_err as fs;
 
-use super::user_wants_to_overwrite;
-use crate::{extension::Extension, info, utils::EscapedPathDisplay, QuestionPolicy};
+use crate::{
+    cli::OverwritePolicy,
+    extension::Extension,
+    info,
+    utils::{EscapedPathDisplay, FileVisibilityPolicy},
+};
 
-/// Remove `path` asking the user to overwrite if necessary.
+/// Remove whatever is at `path`, if `overwrite_policy` says the file being written, modified at
+/// `modified`, replaces it, asking the user if needed.
 ///
 /// * `Ok(true)` means the path is clear,
-/// * `Ok(false)` means the user doesn't want to overwrite
+/// * `Ok(false)` means what is there is kept
 /// * `Err(_)` is an error
-pub fn clear_path(path: &Path, question_policy: QuestionPolicy) -> crate::Result<bool> {
-    if path.exists() && !user_wants_to_overwrite(path, question_policy)? {
+pub fn clear_path(path: &Path, modified: Option<SystemTime>, overwrite_policy: OverwritePolicy) -> crate::Result<bool> {
+    if fs::symlink_metadata(path).is_ok() && !overwrite_policy.replaces(path, modified)? {
         return Ok(false);
     }
 
-    remove_file_or_dir(path)?;
+    remove_destination(path)?;
 
     Ok(true)
 }
 
+/// Unlinks whatever is at `path` without following symlinks, so a symlink is removed instead of
+/// its target, and directories are removed recursively.
+///
+/// Does nothing if `path` doesn't exist.
+pub fn remove_destination(path: &Path) -> crate::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
 pub fn remove_file_or_dir(path: &Path) -> crate::Result<()> {
     if path.is_dir() {
         fs::remove_dir_all(path)?;
@@ -35,6 +62,111 @@ pub fn remove_file_or_dir(path: &Path) -> crate::Result<()> {
     Ok(())
 }
 
+/// Moves `from` to `to`, which can be either a file or a directory.
+///
+/// Works like [`fs::rename`], but if both paths are on different filesystems (which makes
+/// renaming fail with `EXDEV`), falls back to copying `from` recursively and then removing it.
+pub fn rename_or_copy(from: &Path, to: &Path) -> crate::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            if let Err(err) = copy_recursively(from, to) {
+                // Leaves no partial copy behind, like when the disk gets full
+                let _ = remove_file_or_dir(to);
+                return Err(err);
+            }
+            remove_file_or_dir(from)?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Moves `from` to `to` like [`rename_or_copy`], but existing directories are merged, and only the
+/// files of `from` that conflict with existing ones replace them, as `overwrite_policy` says, used
+/// by `--merge` and `--if-newer`.
+///
+/// With `keep_directory_symlinks`, set by `--keep-directory-symlink`, existing symlinks to
+/// directories are merged with the directories moved there like directories are, instead of being
+/// replaced.
+///
+/// Returns how many existing files were kept.
+pub fn move_merging(
+    from: &Path,
+    to: &Path,
+    overwrite_policy: OverwritePolicy,
+    keep_directory_symlinks: bool,
+) -> crate::Result<usize> {
+    let existing = match fs::symlink_metadata(to) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            rename_or_copy(from, to)?;
+            return Ok(0);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let metadata = fs::symlink_metadata(from)?;
+
+    let existing_is_dir = existing.is_dir() || (keep_directory_symlinks && is_directory_symlink(to));
+    if metadata.is_dir() && existing_is_dir {
+        let mut kept = 0;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            let to = to.join(entry.file_name());
+            kept += move_merging(&entry.path(), &to, overwrite_policy, keep_directory_symlinks)?;
+        }
+        return Ok(kept);
+    }
+
+    if !overwrite_policy.replaces(to, Some(metadata.modified()?))? {
+        return Ok(1);
+    }
+    remove_destination(to)?;
+    rename_or_copy(from, to)?;
+    Ok(0)
+}
+
+/// Checks if `path` is a symlink to a directory
+pub fn is_directory_symlink(path: &Path) -> bool {
+    path.is_symlink() && path.is_dir()
+}
+
+/// Copies `from` to `to` recursively, keeping permissions, modification times and symlinks.
+fn copy_recursively(from: &Path, to: &Path) -> crate::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        let target = fs::read_link(from)?;
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, to)?;
+        #[cfg(windows)]
+        if fs::metadata(from)?.is_dir() {
+            std::os::windows::fs::symlink_dir(target, to)?;
+        } else {
+            std::os::windows::fs::symlink_file(target, to)?;
+        }
+
+        return Ok(());
+    }
+
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        // Also copies the permissions
+        fs::copy(from, to)?;
+    }
+
+    set_file_mtime(to, FileTime::from_last_modification_time(&metadata))?;
+
+    Ok(())
+}
+
 /// Creates a directory at the path, if there is nothing there.
 pub fn create_dir_if_non_existent(path: &Path) -> crate::Result<()> {
     if !path.exists() {
@@ -46,15 +178,22 @@ pub fn create_dir_if_non_existent(path: &Path) -> crate::Result<()> {
     Ok(())
 }
 
-/// Returns current directory, but before change the process' directory to the
-/// one that contains the file pointed to by `filename`.
-pub fn cd_into_same_dir_as(filename: &Path) -> crate::Result<PathBuf> {
+/// Returns current directory, but before change the process' directory to the one `filename` is
+/// walked from when compressing, along with the path to walk from there.
+///
+/// See [`FileVisibilityPolicy::split_walk_root`].
+pub fn cd_into_walk_root<'a>(
+    filename: &'a Path,
+    file_visibility_policy: &'a FileVisibilityPolicy,
+) -> crate::Result<(PathBuf, &'a Path)> {
     let previous_location = env::current_dir()?;
 
-    let parent = filename.parent().ok_or(crate::Error::CompressingRootFolder)?;
-    env::set_current_dir(parent)?;
+    let (dir, walk_root) = file_visibility_policy
+        .split_walk_root(filename)
+        .ok_or(crate::Error::CompressingRootFolder)?;
+    env::set_current_dir(dir)?;
 
-    Ok(previous_location)
+    Ok((previous_location, walk_root))
 }
 
 /// Try to detect the file extension by looking for known magic strings
@@ -74,6 +213,9 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
     fn is_bz2(buf: &[u8]) -> bool {
         buf.starts_with(&[0x42, 0x5A, 0x68])
     }
+    fn is_bz3(buf: &[u8]) -> bool {
+        buf.starts_with(b"BZ3v1")
+    }
     fn is_xz(buf: &[u8]) -> bool {
         buf.starts_with(&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00])
     }
@@ -86,9 +228,26 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
     fn is_zst(buf: &[u8]) -> bool {
         buf.starts_with(&[0x28, 0xB5, 0x2F, 0xFD])
     }
+    fn is_z(buf: &[u8]) -> bool {
+        buf.starts_with(&[0x1F, 0x9D])
+    }
     fn is_sevenz(buf: &[u8]) -> bool {
         buf.starts_with(&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C])
     }
+    fn is_wim(buf: &[u8]) -> bool {
+        buf.starts_with(b"MSWIM\0\0\0")
+    }
+    // ISO 9660 images start with 16 empty sectors, their first volume descriptor has the magic
+    // number, or the one of UDF, which is told apart when reading the image
+    fn is_iso(path: &Path) -> bool {
+        let mut buf = [0; 5];
+        std::fs::File::open(path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(32769))?;
+                file.read_exact(&mut buf)
+            })
+            .is_ok_and(|()| matches!(&buf, b"CD001" | b"BEA01"))
+    }
 
     let buf = {
         let mut buf = [0; 270];
@@ -112,6 +271,8 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
         Some(Extension::new(&[Gzip], "gz"))
     } else if is_bz2(&buf) {
         Some(Extension::new(&[Bzip], "bz2"))
+    } else if is_bz3(&buf) {
+        Some(Extension::new(&[Bzip3], "bz3"))
     } else if is_xz(&buf) {
         Some(Extension::new(&[Lzma], "xz"))
     } else if is_lz4(&buf) {
@@ -120,8 +281,14 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
         Some(Extension::new(&[Snappy], "sz"))
     } else if is_zst(&buf) {
         Some(Extension::new(&[Zstd], "zst"))
+    } else if is_z(&buf) {
+        Some(Extension::new(&[Compress], "Z"))
     } else if is_sevenz(&buf) {
         Some(Extension::new(&[SevenZip], "7z"))
+    } else if is_wim(&buf) {
+        Some(Extension::new(&[Wim], "wim"))
+    } else if is_iso(path) {
+        Some(Extension::new(&[Iso], "iso"))
     } else {
         None
     }
@@ -135,3 +302,128 @@ pub fn is_symlink(path: &Path) -> bool {
         .map(|m| m.file_type().is_symlink())
         .unwrap_or(false)
 }
+
+/// Checks if the file system of the directory `dir` is case-insensitive, by creating a file with
+/// a lowercase name in it and looking it up in uppercase.
+pub fn is_case_insensitive(dir: &Path) -> io::Result<bool> {
+    let file = tempfile::Builder::new().prefix(".ouch-case-check").tempfile_in(dir)?;
+    let name = file.path().file_name().unwrap_or_default().to_string_lossy();
+
+    Ok(dir.join(name.to_uppercase()).exists())
+}
+
+/// Free space that the user can write to on the file system of `dir`, `None` if it can't be known,
+/// which is always the case outside of Unix
+pub fn available_space(dir: &Path) -> Option<u64> {
+    file_system_space(dir).map(|(available, _)| available)
+}
+
+/// Space taken on the file system of `dir` by the files of every user, `None` if it can't be
+/// known, like [`available_space`]
+pub fn used_space(dir: &Path) -> Option<u64> {
+    file_system_space(dir).map(|(_, used)| used)
+}
+
+/// The available and the used space of the file system of `dir`
+fn file_system_space(dir: &Path) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+        let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+        // Safety: `path` is a valid C string, and `stat` is only read once `statvfs` filled it
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let stat = unsafe { stat.assume_init() };
+        // The types of the fields are smaller than `u64` on some platforms
+        #[allow(clippy::useless_conversion)]
+        let (available, used, block_size) = (
+            u64::from(stat.f_bavail),
+            u64::from(stat.f_blocks).saturating_sub(u64::from(stat.f_bfree)),
+            u64::from(stat.f_frsize),
+        );
+        Some((available.saturating_mul(block_size), used.saturating_mul(block_size)))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_tree(root: &Path) {
+        fs::create_dir_all(root.join("dir/empty")).unwrap();
+        fs::write(root.join("dir/file"), "content").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("file", root.join("dir/link")).unwrap();
+    }
+
+    fn assert_tree(root: &Path) {
+        assert!(root.join("dir/empty").is_dir());
+        assert_eq!(fs::read_to_string(root.join("dir/file")).unwrap(), "content");
+        #[cfg(unix)]
+        assert_eq!(fs::read_link(root.join("dir/link")).unwrap(), Path::new("file"));
+    }
+
+    #[test]
+    fn test_copy_recursively() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        create_tree(from.path());
+
+        copy_recursively(&from.path().join("dir"), &to.path().join("dir")).unwrap();
+
+        assert_tree(from.path());
+        assert_tree(to.path());
+    }
+
+    #[test]
+    fn test_rename_or_copy_across_filesystems() {
+        // tmpfs is usually mounted in a different filesystem than the default temporary
+        // directory, which forces `rename` to fail with EXDEV, skip the test otherwise
+        let Ok(from) = tempfile::tempdir_in("/dev/shm") else {
+            return;
+        };
+        let to = tempfile::tempdir().unwrap();
+        create_tree(from.path());
+
+        rename_or_copy(&from.path().join("dir"), &to.path().join("dir")).unwrap();
+
+        assert!(!from.path().join("dir").exists());
+        assert_tree(to.path());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_remove_destination() {
+        let root = tempfile::tempdir().unwrap();
+        create_tree(root.path());
+        std::os::unix::fs::symlink("missing", root.path().join("dir/broken")).unwrap();
+
+        // Symlinks are removed without touching their targets
+        remove_destination(&root.path().join("dir/link")).unwrap();
+        remove_destination(&root.path().join("dir/broken")).unwrap();
+        assert!(fs::symlink_metadata(root.path().join("dir/link")).is_err());
+        assert!(fs::symlink_metadata(root.path().join("dir/broken")).is_err());
+        assert!(root.path().join("dir/file").is_file());
+
+        remove_destination(&root.path().join("dir")).unwrap();
+        assert!(!root.path().join("dir").exists());
+        remove_destination(&root.path().join("dir")).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_available_space() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_space(dir.path()).is_some_and(|space| space > 0));
+        assert_eq!(available_space(&dir.path().join("missing")), None);
+    }
+}
@@ -0,0 +1,99 @@
+//! Copy of the printed messages kept in the file given with `--log-file`, as an audit trail of
+//! long-running jobs.
+
+use std::{
+    fmt::{self, Write as _},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+use once_cell::sync::OnceCell;
+
+use super::auto_name::{format_time, now_local};
+use crate::{
+    error::{Error, FinalError},
+    utils::EscapedPathDisplay,
+};
+
+/// Where the messages are copied to, nothing is written if unset.
+static LOG_FILE: OnceCell<Mutex<File>> = OnceCell::new();
+
+/// Timestamp at the start of every line of the log file
+const LOG_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Copies the messages to the end of the file at `path`, which is created if needed.
+pub fn set_log_file(path: &Path) -> crate::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| {
+        let error = FinalError::with_title(format!("Cannot open the log file '{}'", EscapedPathDisplay::new(path)))
+            .detail(err.to_string());
+        Error::InvalidUsage { reason: error }
+    })?;
+
+    let _ = LOG_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Writes `message` to the log file as one line per line of it, if `--log-file` was given.
+///
+/// Each line starts with the time and `level`, and has its colors removed, messages that already
+/// start with their level, like errors, don't get it twice. Write errors are ignored, the log
+/// shouldn't stop the command.
+pub fn log_to_file(level: &str, message: fmt::Arguments) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+
+    // Safe unwrap, the format is valid
+    let time = format_time(now_local(), LOG_TIME_FORMAT).unwrap();
+    let message = strip_ansi_codes(&message.to_string());
+    let message = message
+        .strip_prefix(&format!("[{level}] "))
+        .or_else(|| message.strip_prefix(&format!("{level}: ")))
+        .unwrap_or(&message);
+
+    let mut lines = String::new();
+    for line in message.lines() {
+        // Writing to a String can't fail
+        let _ = writeln!(lines, "{time} [{level}] {line}");
+    }
+
+    // The file isn't buffered, so every message reaches it right away
+    let mut file = file.lock().unwrap();
+    let _ = file.write_all(lines.as_bytes());
+}
+
+/// Removes the escape sequences used for colors, like "\x1b[1;31m"
+fn strip_ansi_codes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            stripped.push(ch);
+            continue;
+        }
+        // Control Sequence Introducer, ends at its final byte, a letter
+        if chars.next() == Some('[') {
+            for ch in chars.by_ref() {
+                if ch.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi_codes() {
+        assert_eq!(strip_ansi_codes("\x1b[1;31m[ERROR]\x1b[0m message"), "[ERROR] message");
+        assert_eq!(strip_ansi_codes("no colors"), "no colors");
+    }
+}
@@ -3,34 +3,102 @@
 //! In here we have the logic for custom formatting, some file and directory utils, and user
 //! stdin interaction helpers.
 
+mod apple_double;
+mod auto_name;
+mod cdc;
+mod checksum;
 pub mod colors;
+mod entry_order;
+mod entry_selector;
+mod entry_sizes;
+mod exclude;
+mod extension_levels;
+mod file_flags;
+mod file_times;
 mod file_visibility;
 mod formatting;
 mod fs;
+mod log_file;
+mod name_template;
+mod owner;
+mod password;
+mod porcelain;
+mod progress_events;
+mod progress_report;
 mod question;
+mod rate_limit;
+mod retry;
+mod safe_symlinks;
+mod time_rounding;
+mod truncation;
+mod url;
+mod use_command;
+mod volumes;
+mod warnings;
 
+pub use apple_double::{is_apple_double, merge_apple_doubles};
+pub use auto_name::{format_time, timestamped_path};
+pub use cdc::ChunkedEncoder;
+pub use checksum::{hash_files, write_checksum_file, BackgroundChecksum, Checksum, DigestTable, HashingWriter};
+pub use entry_order::EntryOrder;
+pub use entry_selector::{normal_path, select_entries};
+pub use entry_sizes::{print_entry_size, CountingWriter, EntrySizes};
+pub use exclude::ExcludePatterns;
+pub use extension_levels::{ExtensionLevels, STORE_LEVEL};
+pub use file_flags::{get_file_flags, set_file_flags, PAX_FILE_FLAGS};
+pub use file_times::{
+    birth_time_record, format_pax_time, parse_pax_time, set_access_time, set_birth_time, AccessTime,
+    CAN_SET_BIRTH_TIME, PAX_ACCESS_TIME, PAX_CREATION_TIME,
+};
 pub use file_visibility::FileVisibilityPolicy;
 pub use formatting::{
     nice_directory_display, pretty_format_list_of_paths, strip_cur_dir, to_utf, Bytes, EscapedPathDisplay,
 };
 pub use fs::{
-    cd_into_same_dir_as, clear_path, create_dir_if_non_existent, is_symlink, remove_file_or_dir, try_infer_extension,
+    available_space, cd_into_walk_root, clear_path, create_dir_if_non_existent, is_case_insensitive,
+    is_directory_symlink, is_symlink, move_merging, remove_destination, remove_file_or_dir, rename_or_copy,
+    try_infer_extension, used_space,
 };
+pub use log_file::{log_to_file, set_log_file};
+pub use name_template::NameTemplate;
+pub use owner::Owner;
+pub use password::{forget_password, ArchivePassword};
+pub use porcelain::{is_porcelain, set_porcelain, write_porcelain_line};
+pub use progress_events::{emit_progress, json_string, set_progress_fd, ProgressEvent};
+pub use progress_report::{CompressionReport, ProgressReport};
 pub use question::{
-    ask_to_create_file, user_wants_to_continue, user_wants_to_overwrite, QuestionAction, QuestionPolicy,
+    ask_to_create_file, can_ask_user, user_chooses_one, user_wants_new_directory, user_wants_to_continue,
+    user_wants_to_create_dir, user_wants_to_overwrite, user_wants_to_overwrite_newer, QuestionAction, QuestionPolicy,
 };
-pub use utf8::{get_invalid_utf8_paths, is_invalid_utf8};
+pub use rate_limit::{RateLimit, RateLimitedReader};
+pub use retry::{retry_transient, RetryingWriter};
+pub use safe_symlinks::make_symlinks_safe;
+pub use time_rounding::{round_mtimes, round_time, touch_mtimes};
+pub use truncation::{find_truncated_error, TruncatedError, TruncationCheck};
+pub use url::{is_url, open_url, url_file_name};
+pub use use_command::{set_use_command, use_command_allowed};
+pub use utf8::to_utf8_lossy;
+pub use volumes::Volumes;
+pub use warnings::{set_warning_policy, warn_or_fail, warning_policy, WarningPolicy};
 
 mod utf8 {
-    use std::{ffi::OsStr, path::PathBuf};
+    use std::{borrow::Cow, path::Path};
 
-    /// Check, without allocating, if os_str can be converted into &str
-    pub fn is_invalid_utf8(os_str: impl AsRef<OsStr>) -> bool {
-        os_str.as_ref().to_str().is_none()
-    }
+    use super::{warn_or_fail, EscapedPathDisplay};
+
+    /// Converts `path` to the name stored in formats that only store UTF-8 names, like zip and 7z.
+    ///
+    /// Invalid bytes are replaced by �, with a warning, or an error with `--strict`.
+    pub fn to_utf8_lossy(path: &Path) -> crate::Result<Cow<'_, str>> {
+        let name = path.to_string_lossy();
+
+        if let Cow::Owned(_) = name {
+            warn_or_fail(format!(
+                "'{}' is not valid UTF-8, it is stored with the invalid bytes replaced.",
+                EscapedPathDisplay::new(path)
+            ))?;
+        }
 
-    /// Filter out list of paths that are not utf8 valid
-    pub fn get_invalid_utf8_paths(paths: &[PathBuf]) -> Vec<&PathBuf> {
-        paths.iter().filter(|path| is_invalid_utf8(path)).collect()
+        Ok(name)
     }
 }
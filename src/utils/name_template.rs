@@ -0,0 +1,120 @@
+//! Names of the decompressed outputs, set by `--output-name-template`.
+
+use std::path::PathBuf;
+
+use crate::error::{Error, FinalError};
+
+/// Template of the output names, like "{stem}_extracted", with `{{` and `}}` for literal braces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    /// Name of the file without its format extensions, like "photos" for "photos.tar.gz"
+    Stem,
+    /// Format extensions of the file, like "tar.gz"
+    Format,
+    /// Position of the file in the command line, from 1
+    Index,
+}
+
+impl NameTemplate {
+    /// Parses `template`, checking that its placeholders exist and that it makes a file name
+    pub fn parse(template: &str) -> crate::Result<Self> {
+        let invalid = |detail: String| {
+            let error = FinalError::with_title(format!("Invalid --output-name-template '{template}'"))
+                .detail(detail)
+                .hint("Placeholders are {stem}, {format} and {index}, like '{stem}_extracted'")
+                .hint("Literal braces are written {{ and }}");
+            Error::InvalidUsage { reason: error }
+        };
+
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let Some((name, rest)) = chars.as_str().split_once('}') else {
+                        return Err(invalid("A placeholder is missing its closing brace".into()));
+                    };
+                    let part = match name {
+                        "stem" => Part::Stem,
+                        "format" => Part::Format,
+                        "index" => Part::Index,
+                        name => return Err(invalid(format!("Unknown placeholder {{{name}}}"))),
+                    };
+                    chars = rest.chars();
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+                '}' => return Err(invalid("A closing brace has no placeholder".into())),
+                '/' | '\\' => {
+                    return Err(invalid(
+                        "Output names can't have path separators, use --dir to place them".into(),
+                    ))
+                }
+                ch => text.push(ch),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        let only_dots = parts
+            .iter()
+            .all(|part| matches!(part, Part::Text(text) if text.chars().all(|ch| ch == '.')));
+        if only_dots {
+            return Err(invalid("It makes no file name".into()));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// The output name of the `index`th file, from 1, named `stem` without its `format`
+    pub fn render(&self, stem: &str, format: &str, index: usize) -> PathBuf {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Stem => name.push_str(stem),
+                Part::Format => name.push_str(format),
+                Part::Index => name.push_str(&index.to_string()),
+            }
+        }
+        name.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_name_template() {
+        let template = NameTemplate::parse("{index}-{stem}_{format}{{x}}").unwrap();
+        assert_eq!(template.render("photos", "tar.gz", 3), Path::new("3-photos_tar.gz{x}"));
+
+        assert!(NameTemplate::parse("{size}").is_err());
+        assert!(NameTemplate::parse("{stem").is_err());
+        assert!(NameTemplate::parse("stem}").is_err());
+        assert!(NameTemplate::parse("dir/{stem}").is_err());
+        assert!(NameTemplate::parse("..").is_err());
+        assert!(NameTemplate::parse("").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Owner given to the unpacked files with `--chown`.
+
+use std::{io, path::Path};
+
+use crate::error::{Error, FinalError};
+
+/// User and group given to the unpacked files, `None` keeps the one they were created with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Owner {
+    /// Parses `USER`, `USER:GROUP`, `:GROUP` or `USER:`, the latter with the login group of USER,
+    /// like chown(1).
+    ///
+    /// Names are looked up in the passwd and group databases, falling back to numeric ids.
+    pub fn parse(spec: &str) -> crate::Result<Self> {
+        if cfg!(not(unix)) {
+            let error =
+                FinalError::with_title("Cannot use --chown").detail("Files only have owner ids on Unix-like systems");
+            return Err(Error::InvalidUsage { reason: error });
+        }
+
+        let invalid = |detail: String| {
+            let error = FinalError::with_title(format!("Invalid --chown owner '{spec}'"))
+                .detail(detail)
+                .hint("Use USER, USER:GROUP or :GROUP, by name or numeric id, like '1000:1000'");
+            Error::InvalidUsage { reason: error }
+        };
+
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+
+        let user = match user {
+            "" => None,
+            user => Some(platform::lookup_user(user).ok_or_else(|| invalid(format!("Unknown user '{user}'")))?),
+        };
+        let gid = match (group, user) {
+            (None | Some(""), None) => return Err(invalid("No user or group given".into())),
+            (None, Some(_)) => None,
+            // "USER:", the login group of the user
+            (Some(""), Some((uid, login_gid))) => {
+                Some(login_gid.ok_or_else(|| invalid(format!("User {uid} has no login group")))?)
+            }
+            (Some(group), _) => {
+                Some(platform::lookup_group(group).ok_or_else(|| invalid(format!("Unknown group '{group}'")))?)
+            }
+        };
+
+        Ok(Self {
+            uid: user.map(|(uid, _)| uid),
+            gid,
+        })
+    }
+
+    /// Gives `path` to this owner, without following it if it's a symlink
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        platform::lchown(path, self.uid, self.gid)
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::{ffi::CString, io, mem::MaybeUninit, path::Path};
+
+    /// The uid of `user`, a name or a numeric id, with its login group if it's in the passwd database
+    pub fn lookup_user(user: &str) -> Option<(u32, Option<u32>)> {
+        let name = CString::new(user).ok()?;
+        // Safety: `getpwnam_r` fills `passwd` with pointers into `buffer`, only read if it succeeds
+        let by_name = with_buffer(|passwd: &mut MaybeUninit<libc::passwd>, buffer, result| unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        });
+        if let Some(passwd) = by_name {
+            return Some((passwd.pw_uid, Some(passwd.pw_gid)));
+        }
+
+        let uid = user.parse().ok()?;
+        // Safety: same as above, with `getpwuid_r`
+        let by_uid = with_buffer(|passwd: &mut MaybeUninit<libc::passwd>, buffer, result| unsafe {
+            libc::getpwuid_r(uid, passwd.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), result)
+        });
+        Some((uid, by_uid.map(|passwd| passwd.pw_gid)))
+    }
+
+    /// The gid of `group`, a name or a numeric id
+    pub fn lookup_group(group: &str) -> Option<u32> {
+        let name = CString::new(group).ok()?;
+        // Safety: `getgrnam_r` fills `entry` with pointers into `buffer`, only read if it succeeds
+        let by_name = with_buffer(|entry: &mut MaybeUninit<libc::group>, buffer, result| unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        });
+
+        by_name.map(|entry| entry.gr_gid).or_else(|| group.parse().ok())
+    }
+
+    /// Runs one of the reentrant `get*_r` functions, growing the buffer for the strings of the entry
+    /// while it's too small, returns the entry if one was found
+    fn with_buffer<T>(
+        mut lookup: impl FnMut(&mut MaybeUninit<T>, &mut [libc::c_char], *mut *mut T) -> libc::c_int,
+    ) -> Option<T> {
+        let mut buffer = vec![0 as libc::c_char; 1024];
+        loop {
+            let mut entry = MaybeUninit::uninit();
+            let mut result = std::ptr::null_mut();
+            match lookup(&mut entry, &mut buffer, &mut result) {
+                libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+                // Only the ids are read, not the strings pointing into the buffer
+                // Safety: a non-null result points to `entry`, which was filled
+                0 if !result.is_null() => return Some(unsafe { entry.assume_init() }),
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn lchown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        std::os::unix::fs::lchown(path, uid, gid)
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use std::{io, path::Path};
+
+    pub fn lookup_user(_user: &str) -> Option<(u32, Option<u32>)> {
+        None
+    }
+
+    pub fn lookup_group(_group: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn lchown(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_owner() {
+        let owner = |uid, gid| Owner { uid, gid };
+
+        assert_eq!(Owner::parse("1000:1001").unwrap(), owner(Some(1000), Some(1001)));
+        assert_eq!(Owner::parse("1000").unwrap(), owner(Some(1000), None));
+        assert_eq!(Owner::parse(":1001").unwrap(), owner(None, Some(1001)));
+        assert!(Owner::parse("").is_err());
+        assert!(Owner::parse(":").is_err());
+        assert!(Owner::parse("no-such-user-here:0").is_err());
+        assert_eq!(Owner::parse("root:").unwrap(), owner(Some(0), Some(0)));
+    }
+}
@@ -0,0 +1,134 @@
+//! Passwords of encrypted archives, asked to the user or kept in the OS keyring with `--keyring`.
+
+use std::{path::Path, sync::Mutex};
+
+use crate::{
+    accessible::is_running_in_accessible_mode,
+    error::{Error, FinalError},
+    utils::{colors, EscapedPathDisplay},
+};
+
+/// Archives are decompressed in parallel, but only one password can be typed at a time
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Gives the password of one encrypted archive, only looked for once it is needed
+pub struct ArchivePassword<'a> {
+    archive_path: &'a Path,
+    /// Service name given to `--keyring`
+    keyring_service: Option<&'a str>,
+    password: Option<String>,
+    /// Whether `password` came from the keyring, instead of being typed by the user
+    from_keyring: bool,
+}
+
+impl<'a> ArchivePassword<'a> {
+    pub fn new(archive_path: &'a Path, keyring_service: Option<&'a str>) -> Self {
+        Self {
+            archive_path,
+            keyring_service,
+            password: None,
+            from_keyring: false,
+        }
+    }
+
+    /// Returns the password stored in the keyring, if any, otherwise asks the user for it
+    pub fn get(&mut self) -> crate::Result<&str> {
+        if self.password.is_none() {
+            self.password = self.stored_password()?;
+            self.from_keyring = self.password.is_some();
+        }
+
+        if self.password.is_none() {
+            self.password = Some(self.ask()?);
+        }
+
+        Ok(self.password.as_deref().unwrap())
+    }
+
+    /// Stores the password typed by the user in the keyring, once it decrypted the archive
+    pub fn remember(&self) -> crate::Result<()> {
+        let (Some(entry), Some(password)) = (self.keyring_entry()?, &self.password) else {
+            return Ok(());
+        };
+
+        if !self.from_keyring {
+            entry.set_password(password).map_err(keyring_error)?;
+        }
+        Ok(())
+    }
+
+    /// Error for a password that failed to decrypt the archive
+    pub fn wrong_password_error(&self) -> Error {
+        let error = FinalError::with_title(format!(
+            "Wrong password for '{}'",
+            EscapedPathDisplay::new(self.archive_path)
+        ));
+
+        let error = if self.from_keyring {
+            error
+                .detail("The password was read from the keyring")
+                .hint("Use --forget-password to type it again.")
+        } else {
+            error
+        };
+
+        Error::PasswordError { reason: error }
+    }
+
+    fn stored_password(&self) -> crate::Result<Option<String>> {
+        let Some(entry) = self.keyring_entry()? else {
+            return Ok(None);
+        };
+
+        match entry.get_password() {
+            Ok(password) => Ok(Some(password)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(keyring_error(err)),
+        }
+    }
+
+    fn keyring_entry(&self) -> crate::Result<Option<keyring::Entry>> {
+        self.keyring_service
+            .map(|service| keyring_entry(service, self.archive_path))
+            .transpose()
+    }
+
+    fn ask(&self) -> crate::Result<String> {
+        let _lock = PROMPT_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let path = EscapedPathDisplay::new(self.archive_path);
+
+        let prompt = if is_running_in_accessible_mode() {
+            format!("{}Info:{} Password for '{path}': ", *colors::YELLOW, *colors::RESET)
+        } else {
+            format!("{}[INFO]{} Password for '{path}': ", *colors::YELLOW, *colors::RESET)
+        };
+
+        // Read from the terminal, even if stdin is redirected
+        rpassword::prompt_password(prompt).map_err(|err| {
+            let error = FinalError::with_title(format!("Could not ask for the password of '{path}'"))
+                .detail(err.to_string())
+                .hint("Passwords are typed in a terminal, or stored with --keyring.");
+
+            Error::PasswordError { reason: error }
+        })
+    }
+}
+
+/// Removes the password of `archive_path` from the keyring, set by `--forget-password`
+pub fn forget_password(keyring_service: &str, archive_path: &Path) -> crate::Result<()> {
+    match keyring_entry(keyring_service, archive_path)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(err) => Err(keyring_error(err)),
+    }
+}
+
+/// The passwords are stored under the canonical path of their archives
+fn keyring_entry(keyring_service: &str, archive_path: &Path) -> crate::Result<keyring::Entry> {
+    keyring::Entry::new(keyring_service, &archive_path.to_string_lossy()).map_err(keyring_error)
+}
+
+fn keyring_error(err: keyring::Error) -> Error {
+    FinalError::with_title("Could not access the keyring")
+        .detail(err.to_string())
+        .into()
+}
@@ -0,0 +1,29 @@
+//! Minimal output for scripts, given with `--porcelain`: nothing is printed on success, and errors
+//! are printed as tab-separated lines.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether `--porcelain` was given
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_porcelain(porcelain: bool) {
+    PORCELAIN.store(porcelain, Ordering::Relaxed);
+}
+
+/// Checks if the info messages are silenced and the errors printed for scripts
+pub fn is_porcelain() -> bool {
+    PORCELAIN.load(Ordering::Relaxed)
+}
+
+/// Writes one `kind<TAB>message` line of an error, with the tabs and newlines of `message` turned
+/// into spaces so every line stays one field of one kind
+pub fn write_porcelain_line(f: &mut fmt::Formatter, kind: &str, message: &str) -> fmt::Result {
+    let message: String = message
+        .chars()
+        .map(|c| if matches!(c, '\t' | '\n' | '\r') { ' ' } else { c })
+        .collect();
+    write!(f, "{kind}\t{message}")
+}
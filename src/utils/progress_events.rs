@@ -0,0 +1,94 @@
+//! Newline-delimited JSON progress events for frontends, written to the file descriptor given
+//! with `--progress-fd`, independently of what is printed to stderr.
+
+use std::{fmt::Write as _, fs::File, io::Write, path::Path, sync::Mutex};
+
+use once_cell::sync::OnceCell;
+
+use crate::error::{Error, FinalError};
+
+/// Where the events are written, nothing is written if unset.
+static PROGRESS_FILE: OnceCell<Mutex<File>> = OnceCell::new();
+
+pub enum ProgressEvent<'a> {
+    /// A file was compressed or unpacked, `bytes` is its uncompressed size
+    Entry { path: &'a Path, bytes: u64 },
+    /// The archive at `path` was fully compressed or unpacked
+    Done { path: &'a Path },
+}
+
+/// Writes the progress events to the already open file descriptor `fd`.
+#[cfg(unix)]
+pub fn set_progress_fd(fd: i32) -> crate::Result<()> {
+    use std::os::unix::io::FromRawFd;
+
+    // Safety: only checks if `fd` is open
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        let error = FinalError::with_title(format!("Cannot write progress events to the file descriptor {fd}"))
+            .detail("It is not open")
+            .hint("Open it before running ouch, like `ouch --progress-fd 3 ... 3>progress.json`");
+        return Err(Error::InvalidUsage { reason: error });
+    }
+
+    // Safety: `fd` is open, and it is never closed, as the static is never dropped
+    let file = unsafe { File::from_raw_fd(fd) };
+    let _ = PROGRESS_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_progress_fd(_fd: i32) -> crate::Result<()> {
+    let error = FinalError::with_title("--progress-fd is only supported on Unix");
+    Err(Error::InvalidUsage { reason: error })
+}
+
+/// Writes `event` as a line of JSON, if `--progress-fd` was given.
+///
+/// Write errors are ignored, a frontend that stopped reading shouldn't stop the command.
+pub fn emit_progress(event: ProgressEvent) {
+    let Some(file) = PROGRESS_FILE.get() else {
+        return;
+    };
+
+    let line = match event {
+        ProgressEvent::Entry { path, bytes } => {
+            format!(r#"{{"type":"entry","path":{},"bytes":{bytes}}}"#, json_string(path))
+        }
+        ProgressEvent::Done { path } => format!(r#"{{"type":"done","path":{}}}"#, json_string(path)),
+    };
+
+    let mut file = file.lock().unwrap();
+    let _ = writeln!(file, "{line}");
+}
+
+/// Quotes `path` as a JSON string, the bytes that aren't UTF-8 are replaced by �
+pub fn json_string(path: &Path) -> String {
+    let mut string = String::from('"');
+    for ch in path.to_string_lossy().chars() {
+        match ch {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\r' => string.push_str("\\r"),
+            '\t' => string.push_str("\\t"),
+            ch if ch.is_control() => {
+                let _ = write!(string, "\\u{:04x}", ch as u32);
+            }
+            ch => string.push(ch),
+        }
+    }
+    string.push('"');
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string(Path::new("dir/file.txt")), r#""dir/file.txt""#);
+        assert_eq!(json_string(Path::new("a \"b\"\\c")), r#""a \"b\"\\c""#);
+        assert_eq!(json_string(Path::new("line\nbreak\u{1}")), r#""line\nbreak\u0001""#);
+    }
+}
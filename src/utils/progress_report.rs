@@ -0,0 +1,173 @@
+//! Progress of decompression printed as text in the accessible mode, for screen readers, like
+//! "Extracted 42% (1.20 GiB of 2.90 GiB) at 50.00 MiB/s, ~30s remaining", and of the files being
+//! compressed, in both modes.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use super::{Bytes, EscapedPathDisplay};
+use crate::{accessible::is_running_in_accessible_mode, info};
+
+/// Time between two reports of the progress
+const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reader of the compressed file that reports how much of it was read, in the accessible mode,
+/// at most every [`REPORT_INTERVAL`].
+///
+/// Seeking moves the progress too, so the zip archives read in place report where their entries are.
+pub struct ProgressReport<R> {
+    inner: R,
+    /// Size of the compressed file
+    total: u64,
+    position: u64,
+    start: Instant,
+    last_report: Instant,
+    enabled: bool,
+}
+
+impl<R> ProgressReport<R> {
+    /// Reports the progress of reading `inner`, which is `total` bytes long, unless not in the
+    /// accessible mode, or `quiet`
+    pub fn new(inner: R, total: u64, quiet: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            inner,
+            total,
+            position: 0,
+            start: now,
+            last_report: now,
+            enabled: is_running_in_accessible_mode() && !quiet && total > 0,
+        }
+    }
+
+    fn report(&mut self) {
+        if !self.enabled || self.last_report.elapsed() < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+        info!(
+            accessible,
+            "{}",
+            progress_line(self.position.min(self.total), self.total, self.start.elapsed())
+        );
+    }
+}
+
+impl<R: Read> Read for ProgressReport<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.position += bytes_read as u64;
+        self.report();
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReport<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Reports the file being compressed.
+///
+/// The first file is printed, then the file being compressed at most every [`REPORT_INTERVAL`],
+/// along with the previous file if it was that slow, so thousands of small files don't flood the
+/// output, or generate lots of spoken text for users of screen readers, braille displays and so on.
+pub struct CompressionReport {
+    quiet: bool,
+    /// When a file was last reported, none before the first one
+    last_report: Option<Instant>,
+    /// The file being compressed and when it started
+    current: Option<(PathBuf, Instant)>,
+}
+
+impl CompressionReport {
+    pub fn new(quiet: bool) -> Self {
+        Self {
+            quiet,
+            last_report: None,
+            current: None,
+        }
+    }
+
+    /// Reports that `path` is starting to be compressed
+    pub fn start(&mut self, path: &Path) {
+        if self.quiet {
+            return;
+        }
+
+        let now = Instant::now();
+        if let Some((previous, started)) = self.current.replace((path.to_owned(), now)) {
+            let elapsed = now - started;
+            if elapsed >= REPORT_INTERVAL {
+                info!(
+                    accessible,
+                    "Compressed '{}' in {}.",
+                    EscapedPathDisplay::new(&previous),
+                    format_duration(elapsed)
+                );
+            }
+        }
+        let due = match self.last_report {
+            Some(last_report) => now - last_report >= REPORT_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.last_report = Some(now);
+            info!(accessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
+        }
+    }
+}
+
+/// The report of `done` of the `total` bytes read in `elapsed`
+fn progress_line(done: u64, total: u64, elapsed: Duration) -> String {
+    let percent = done * 100 / total;
+    let mut line = format!("Extracted {percent}% ({} of {})", Bytes::new(done), Bytes::new(total));
+
+    let seconds = elapsed.as_secs_f64();
+    if done > 0 && seconds > 0.0 {
+        let rate = done as f64 / seconds;
+        let remaining = Duration::from_secs_f64((total - done) as f64 / rate);
+        line.push_str(&format!(
+            " at {}/s, ~{} remaining",
+            Bytes::new(rate as u64),
+            format_duration(remaining)
+        ));
+    }
+    line.push('.');
+    line
+}
+
+/// `duration` rounded to the seconds, like "1h 2m", "2m 5s" or "30s", for the time remaining or
+/// taken by something
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{seconds}s"),
+        (0, minutes, seconds) => format!("{minutes}m {seconds}s"),
+        (hours, minutes, _) => format!("{hours}h {minutes}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        assert_eq!(
+            progress_line(1_200_000_000, 2_900_000_000, Duration::from_secs(24)),
+            "Extracted 41% (1.20 GiB of 2.90 GiB) at 50.00 MiB/s, ~34s remaining."
+        );
+        assert_eq!(
+            progress_line(0, 1000, Duration::from_secs(2)),
+            "Extracted 0% (0 B of 1.00 kiB)."
+        );
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h 2m");
+    }
+}
@@ -1,4 +1,4 @@
-//! Utils related to asking [Y/n] questions to the user.
+//! Utils related to asking [Y/n] questions to the user, or to choose between options.
 //!
 //! Example:
 //!   "Do you want to overwrite 'archive.tar.gz'? [Y/n]"
@@ -7,6 +7,7 @@ use std::{
     borrow::Cow,
     io::{self, Write},
     path::Path,
+    time::SystemTime,
 };
 
 use fs_err as fs;
@@ -14,6 +15,7 @@ use fs_err as fs;
 use super::{strip_cur_dir, to_utf};
 use crate::{
     accessible::is_running_in_accessible_mode,
+    cli::OverwritePolicy,
     error::{Error, FinalError, Result},
     utils::{self, colors},
 };
@@ -38,6 +40,46 @@ pub enum QuestionAction {
     Decompression,
 }
 
+impl OverwritePolicy {
+    /// Resolves the policy of the flags given, `--overwrite-policy` comes first, then `--if-newer`
+    /// and `--remove-destination`, which conflict with each other, then `--yes` and `--no`
+    pub fn resolve(
+        overwrite_policy: Option<OverwritePolicy>,
+        if_newer: bool,
+        remove_destination: bool,
+        question_policy: QuestionPolicy,
+    ) -> Self {
+        match (overwrite_policy, question_policy) {
+            (Some(overwrite_policy), _) => overwrite_policy,
+            _ if if_newer => Self::IfNewer,
+            _ if remove_destination => Self::RemoveDestination,
+            (None, QuestionPolicy::AlwaysYes) => Self::Overwrite,
+            (None, QuestionPolicy::AlwaysNo) => Self::Skip,
+            (None, QuestionPolicy::Ask) => Self::Ask,
+        }
+    }
+
+    /// Checks if whatever is at `path` should be replaced by a file modified at `modified`,
+    /// asking the user if needed, an unknown time counts as newer
+    pub fn replaces(self, path: &Path, modified: Option<SystemTime>) -> crate::Result<bool> {
+        match self {
+            Self::Ask => user_wants_to_overwrite(path, QuestionPolicy::Ask),
+            Self::Overwrite | Self::RemoveDestination => Ok(true),
+            Self::Skip => Ok(false),
+            Self::IfNewer => match modified {
+                Some(modified) => Ok(modified > fs::symlink_metadata(path)?.modified()?),
+                None => Ok(true),
+            },
+        }
+    }
+}
+
+impl From<QuestionPolicy> for OverwritePolicy {
+    fn from(question_policy: QuestionPolicy) -> Self {
+        Self::resolve(None, false, false, question_policy)
+    }
+}
+
 /// Check if QuestionPolicy flags were set, otherwise, ask user if they want to overwrite.
 pub fn user_wants_to_overwrite(path: &Path, question_policy: QuestionPolicy) -> crate::Result<bool> {
     match question_policy {
@@ -52,14 +94,50 @@ pub fn user_wants_to_overwrite(path: &Path, question_policy: QuestionPolicy) ->
     }
 }
 
-/// Create the file if it doesn't exist and if it does then ask to overwrite it.
-/// If the user doesn't want to overwrite then we return [`Ok(None)`]
-pub fn ask_to_create_file(path: &Path, question_policy: QuestionPolicy) -> Result<Option<fs::File>> {
+/// Ask the user if the output `path`, which is newer than all of the inputs, should be overwritten
+/// anyway, only when [`can_ask_user`] allows it.
+pub fn user_wants_to_overwrite_newer(path: &Path) -> crate::Result<bool> {
+    let path = to_utf(strip_cur_dir(path));
+    let path = Some(&*path);
+    let placeholder = Some("FILE");
+    Confirmation::new(
+        "'FILE' is newer than all of the inputs, do you want to overwrite it?",
+        placeholder,
+    )
+    .ask(path)
+}
+
+/// Check if QuestionPolicy flags were set, otherwise, ask the user if the missing directory `path`
+/// should be created.
+pub fn user_wants_to_create_dir(path: &Path, question_policy: QuestionPolicy) -> crate::Result<bool> {
+    match question_policy {
+        QuestionPolicy::AlwaysYes => Ok(true),
+        QuestionPolicy::AlwaysNo => Ok(false),
+        QuestionPolicy::Ask => {
+            let path = to_utf(strip_cur_dir(path));
+            let path = Some(&*path);
+            let placeholder = Some("FILE");
+            Confirmation::new(
+                "The directory 'FILE' does not exist, do you want to create it?",
+                placeholder,
+            )
+            .ask(path)
+        }
+    }
+}
+
+/// Create the file if it doesn't exist and if it does then replace it as `overwrite_policy` says,
+/// see [`utils::clear_path`] for `modified`.
+/// If it isn't replaced then we return [`Ok(None)`]
+pub fn ask_to_create_file(
+    path: &Path,
+    modified: Option<SystemTime>,
+    overwrite_policy: OverwritePolicy,
+) -> Result<Option<fs::File>> {
     match fs::OpenOptions::new().write(true).create_new(true).open(path) {
         Ok(w) => Ok(Some(w)),
         Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
-            if user_wants_to_overwrite(path, question_policy)? {
-                utils::remove_file_or_dir(path)?;
+            if utils::clear_path(path, modified, overwrite_policy)? {
                 Ok(Some(fs::File::create(path)?))
             } else {
                 Ok(None)
@@ -91,6 +169,41 @@ pub fn user_wants_to_continue(
     }
 }
 
+/// Check if QuestionPolicy flags were set, otherwise, ask the user if the `count` entries at the
+/// root of an archive should be unpacked into the new directory `path`, instead of next to it.
+///
+/// When the user can't be asked, they go in the new directory, like archives with fewer entries.
+pub fn user_wants_new_directory(path: &Path, count: usize, question_policy: QuestionPolicy) -> crate::Result<bool> {
+    match question_policy {
+        QuestionPolicy::AlwaysYes => Ok(true),
+        QuestionPolicy::AlwaysNo => Ok(false),
+        QuestionPolicy::Ask if !can_ask_user(question_policy) => Ok(true),
+        QuestionPolicy::Ask => {
+            let path = to_utf(strip_cur_dir(path));
+            let path = Some(&*path);
+            let placeholder = Some("FILE");
+            let prompt =
+                format!("The archive has {count} entries at its root, do you want to unpack them into 'FILE'?");
+            Confirmation::new(&prompt, placeholder).ask(path)
+        }
+    }
+}
+
+/// Check if a question that can't be answered by `--yes` or `--no`, like choosing between
+/// options, can be asked to the user.
+pub fn can_ask_user(question_policy: QuestionPolicy) -> bool {
+    question_policy == QuestionPolicy::Ask && atty::is(atty::Stream::Stdin)
+}
+
+/// Ask the user to choose one of `options` for `path`, returns the chosen index.
+///
+/// Returns [`Ok(None)`] if the user cancels by giving an empty answer.
+pub fn user_chooses_one(path: &Path, prompt: &str, options: &[String]) -> crate::Result<Option<usize>> {
+    let path = to_utf(strip_cur_dir(path));
+    let prompt = prompt.replace("FILE", &path);
+    Selection::new(&prompt, options).ask()
+}
+
 /// Confirmation dialog for end user with [Y/n] question.
 ///
 /// If the placeholder is found in the prompt text, it will be replaced to form the final message.
@@ -166,3 +279,108 @@ impl<'a> Confirmation<'a> {
         }
     }
 }
+
+/// Selection dialog for end user, where one of the numbered options must be chosen.
+pub struct Selection<'a> {
+    /// The message to be displayed above the options.
+    /// e.g.: "Which format is 'archive.gz'?"
+    pub prompt: &'a str,
+
+    /// The options to choose from, displayed as a numbered list
+    pub options: &'a [String],
+}
+
+impl<'a> Selection<'a> {
+    /// Creates a new Selection.
+    pub const fn new(prompt: &'a str, options: &'a [String]) -> Self {
+        Self { prompt, options }
+    }
+
+    /// Displays the options and receives the index of the chosen one, or `None` if the answer is empty
+    pub fn ask(&self) -> crate::Result<Option<usize>> {
+        println!("{}", self.prompt);
+        for (i, option) in self.options.iter().enumerate() {
+            println!("  {}{}{}) {}", *colors::GREEN, i + 1, *colors::RESET, option);
+        }
+
+        // Ask the same question to end while no valid answers are given
+        loop {
+            if is_running_in_accessible_mode() {
+                print!("Number of the option, or nothing to cancel: ");
+            } else {
+                print!("[1-{}, empty to cancel] ", self.options.len());
+            }
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            let bytes_read = io::stdin().read_line(&mut answer)?;
+
+            if bytes_read == 0 {
+                let error = FinalError::with_title("Unexpected EOF when asking question.")
+                    .detail("When asking the user:")
+                    .detail(format!("  \"{}\"", self.prompt))
+                    .detail("Expected the number of an option as answer, but found EOF instead.")
+                    .hint("If using Ouch in scripting, consider using `--format`.");
+
+                return Err(error.into());
+            }
+
+            match answer.trim() {
+                "" => return Ok(None),
+                answer => match answer.parse::<usize>() {
+                    Ok(number @ 1..) if number <= self.options.len() => return Ok(Some(number - 1)),
+                    _ => continue, // Try again
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_overwrite_policy() {
+        use OverwritePolicy::*;
+        use QuestionPolicy::{AlwaysNo, AlwaysYes};
+
+        assert_eq!(OverwritePolicy::resolve(None, false, false, QuestionPolicy::Ask), Ask);
+        assert_eq!(OverwritePolicy::resolve(None, false, false, AlwaysYes), Overwrite);
+        assert_eq!(OverwritePolicy::resolve(None, false, false, AlwaysNo), Skip);
+        // --if-newer and --remove-destination come before --yes and --no
+        assert_eq!(OverwritePolicy::resolve(None, true, false, AlwaysNo), IfNewer);
+        assert_eq!(OverwritePolicy::resolve(None, false, true, AlwaysNo), RemoveDestination);
+        // --overwrite-policy comes first
+        assert_eq!(OverwritePolicy::resolve(Some(Skip), true, false, AlwaysYes), Skip);
+        assert_eq!(OverwritePolicy::resolve(Some(Ask), false, true, AlwaysYes), Ask);
+    }
+
+    #[test]
+    fn test_overwrite_policy_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "old").unwrap();
+        let existing = fs::metadata(&path).unwrap().modified().unwrap();
+        let newer = Some(existing + Duration::from_secs(1));
+        let older = Some(existing - Duration::from_secs(1));
+
+        for policy in [OverwritePolicy::Overwrite, OverwritePolicy::RemoveDestination] {
+            assert!(policy.replaces(&path, older).unwrap());
+        }
+        assert!(!OverwritePolicy::Skip.replaces(&path, newer).unwrap());
+        assert!(OverwritePolicy::IfNewer.replaces(&path, newer).unwrap());
+        assert!(!OverwritePolicy::IfNewer.replaces(&path, older).unwrap());
+        assert!(!OverwritePolicy::IfNewer.replaces(&path, Some(existing)).unwrap());
+        // Downloads have no time of their own, they are always newer
+        assert!(OverwritePolicy::IfNewer.replaces(&path, None).unwrap());
+
+        // Kept, or removed so it can be written
+        assert!(!utils::clear_path(&path, older, OverwritePolicy::IfNewer).unwrap());
+        assert!(path.exists());
+        assert!(utils::clear_path(&path, newer, OverwritePolicy::IfNewer).unwrap());
+        assert!(!path.exists());
+    }
+}
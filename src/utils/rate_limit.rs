@@ -0,0 +1,98 @@
+//! Throttling of the decompressed bytes, set by `--limit-rate`.
+
+use std::{
+    io::{self, Read},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Token bucket shared by every archive being decompressed, so the limit applies to their total.
+///
+/// It holds up to one second worth of bytes.
+#[derive(Debug)]
+pub struct RateLimit {
+    bytes_per_second: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that can go through right now, negative when readers have to wait
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimit {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            bucket: Mutex::new(Bucket {
+                available: 0.0,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` from the bucket, sleeping until they are refilled if it runs out
+    pub fn consume(&self, bytes: usize) {
+        let rate = self.bytes_per_second as f64;
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.available = (bucket.available + elapsed * rate).min(rate);
+            bucket.last_refill = now;
+
+            bucket.available -= bytes as f64;
+            (-bucket.available / rate).max(0.0)
+        };
+
+        // Sleeping without the lock, the other readers also wait for their own share
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+/// Reader that goes only as fast as allowed by a [`RateLimit`], if there is one.
+pub struct RateLimitedReader<R> {
+    inner: R,
+    rate_limit: Option<Arc<RateLimit>>,
+}
+
+impl<R: Read> RateLimitedReader<R> {
+    pub fn new(inner: R, rate_limit: Option<Arc<RateLimit>>) -> Self {
+        Self { inner, rate_limit }
+    }
+}
+
+impl<R: Read> Read for RateLimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.consume(bytes_read);
+        }
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limited_reader() {
+        let data = vec![0; 3000];
+        let rate_limit = Arc::new(RateLimit::new(10_000));
+        let mut reader = RateLimitedReader::new(data.as_slice(), Some(rate_limit));
+
+        let start = Instant::now();
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        let elapsed = start.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(250), "took {elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "took {elapsed:?}");
+    }
+}
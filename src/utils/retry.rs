@@ -0,0 +1,169 @@
+//! Retries of the writes that fail with transient errors, like the ones of flaky network file
+//! systems, with `--retries`.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crate::{utils::EscapedPathDisplay, warning};
+
+/// Wait before the first retry, doubled for each of the next ones
+const FIRST_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest wait between two retries
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Runs `operation` on the file at `path`, running it again up to `retries` times while it fails
+/// with a transient error, waiting longer before each retry.
+///
+/// Every retry is reported with a warning, the other errors are returned right away.
+pub fn retry_transient<T>(retries: u32, path: &Path, mut operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = FIRST_DELAY;
+
+    for attempt in 1.. {
+        match operation() {
+            Err(err) if attempt <= retries && is_transient(&err) => {
+                warning!(
+                    "Transient error writing '{}': {err}, retrying in {} ms ({attempt}/{retries}).",
+                    EscapedPathDisplay::new(path),
+                    delay.as_millis(),
+                );
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_DELAY);
+            }
+            result => return result,
+        }
+    }
+    unreachable!("the attempts are only bounded by returning")
+}
+
+/// Checks if `err` may go away by trying again, like the I/O errors and timeouts of network file
+/// systems, a full disk or a denied permission won't
+fn is_transient(err: &io::Error) -> bool {
+    if matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    ) {
+        return true;
+    }
+
+    // Errors with the path of the file, from `fs_err`, hold the original error
+    let os_error = err.raw_os_error().or_else(|| {
+        err.get_ref()
+            .and_then(|inner| inner.source())
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .and_then(io::Error::raw_os_error)
+    });
+    is_transient_os_error(os_error)
+}
+
+#[cfg(unix)]
+fn is_transient_os_error(os_error: Option<i32>) -> bool {
+    matches!(os_error, Some(libc::EIO | libc::ETIMEDOUT | libc::EAGAIN))
+}
+
+#[cfg(not(unix))]
+fn is_transient_os_error(_os_error: Option<i32>) -> bool {
+    false
+}
+
+/// Writer whose writes are retried with [`retry_transient`], a failed write wrote nothing, so it
+/// can be repeated as is
+pub struct RetryingWriter<W> {
+    inner: W,
+    retries: u32,
+    /// Path of the file written to, for the warnings
+    path: PathBuf,
+}
+
+impl<W: Write> RetryingWriter<W> {
+    pub fn new(inner: W, retries: u32, path: &Path) -> Self {
+        Self {
+            inner,
+            retries,
+            path: path.to_owned(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for RetryingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        retry_transient(self.retries, &self.path, || self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        retry_transient(self.retries, &self.path, || self.inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_transient() {
+        let path = Path::new("file");
+
+        // Fails twice with a transient error, then succeeds
+        let mut calls = 0;
+        let result = retry_transient(2, path, || {
+            calls += 1;
+            if calls <= 2 {
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Out of retries
+        let mut calls = 0;
+        let result: io::Result<()> = retry_transient(1, path, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::TimedOut))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+
+        // Permanent errors aren't retried
+        let mut calls = 0;
+        let result: io::Result<()> = retry_transient(3, path, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&io::Error::from_raw_os_error(libc::EIO)));
+        assert!(!is_transient(&io::Error::from_raw_os_error(libc::ENOSPC)));
+        assert!(!is_transient(&io::Error::from_raw_os_error(libc::EACCES)));
+
+        // Wrapped with the path of the file, like the errors of `fs_err`
+        #[derive(Debug)]
+        struct WithPath(io::Error);
+        impl std::fmt::Display for WithPath {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{} in 'file'", self.0)
+            }
+        }
+        impl std::error::Error for WithPath {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+        let wrapped = |code| io::Error::other(WithPath(io::Error::from_raw_os_error(code)));
+        assert!(is_transient(&wrapped(libc::EIO)));
+        assert!(!is_transient(&wrapped(libc::ENOSPC)));
+    }
+}
@@ -0,0 +1,152 @@
+//! Symlinks unpacked from archives that lead outside of them, handled by `--safe-symlinks`.
+//!
+//! Their targets are resolved through the symlinks of the archive itself, as the file system
+//! would, so links to other links that point outside can't get past.
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use super::{warn_or_fail, EscapedPathDisplay};
+use crate::info;
+
+/// Symlinks followed while resolving one target before giving up, like the limit of Linux
+const MAX_HOPS: u32 = 40;
+
+/// Makes the symlinks unpacked in `root` whose targets are outside of it safe, returns how many
+/// symlinks were removed
+///
+/// Absolute targets that are files inside `root`, when seen from it, are copied in place of the
+/// symlinks, like `/etc/hosts` pointing to `etc/hosts` of an archived file system. The others are
+/// removed with a warning.
+pub fn make_symlinks_safe(root: &Path) -> crate::Result<usize> {
+    let mut symlinks = vec![];
+    find_symlinks(root, Path::new(""), &mut symlinks)?;
+
+    // Decided before changing anything, so the order of the symlinks doesn't matter
+    let mut unsafe_symlinks = vec![];
+    for (path, target) in symlinks {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if resolve(root, dir, &target, &mut 0).is_some() {
+            continue;
+        }
+
+        // Seen from the root of the archive, as if it was the root of a file system
+        let copied = target
+            .has_root()
+            .then(|| resolve(root, Path::new(""), &strip_root(&target), &mut 0))
+            .flatten()
+            .map(|resolved| root.join(resolved))
+            .filter(|resolved| fs::symlink_metadata(resolved).is_ok_and(|metadata| metadata.is_file()));
+        unsafe_symlinks.push((path, target, copied));
+    }
+
+    let mut removed = 0;
+    for (path, target, copied) in unsafe_symlinks {
+        let full_path = root.join(&path);
+        fs::remove_file(&full_path)?;
+        match copied {
+            Some(copied) => {
+                fs::copy(&copied, &full_path)?;
+                info!(
+                    accessible,
+                    "Unpacked the symlink '{}' to '{}' as a copy of its target in the archive.",
+                    EscapedPathDisplay::new(&path),
+                    EscapedPathDisplay::new(&target)
+                );
+            }
+            None => {
+                removed += 1;
+                warn_or_fail(format!(
+                    "Skipped the symlink '{}', its target '{}' is outside of the archive.",
+                    EscapedPathDisplay::new(&path),
+                    EscapedPathDisplay::new(&target)
+                ))?;
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Pushes the symlinks in `dir` of `root`, relative to `root`, to `symlinks`, with their targets
+fn find_symlinks(root: &Path, dir: &Path, symlinks: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            let target = fs::read_link(entry.path())?;
+            symlinks.push((path, target));
+        } else if file_type.is_dir() {
+            find_symlinks(root, &path, symlinks)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `target` of a symlink in `dir`, both relative to `root`, following the symlinks found
+/// on the way, `None` if it leads outside of `root` or there are too many symlinks
+fn resolve(root: &Path, dir: &Path, target: &Path, hops: &mut u32) -> Option<PathBuf> {
+    let mut resolved = dir.to_owned();
+    for component in target.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(name) => {
+                resolved.push(name);
+                if let Ok(link_target) = fs::read_link(root.join(&resolved)) {
+                    *hops += 1;
+                    if *hops > MAX_HOPS {
+                        return None;
+                    }
+                    resolved.pop();
+                    resolved = resolve(root, &resolved, &link_target, hops)?;
+                }
+            }
+        }
+    }
+    Some(resolved)
+}
+
+/// `path` without its root and prefix
+fn strip_root(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::Prefix(_) | Component::RootDir))
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("p/q")).unwrap();
+        fs::create_dir(root.join("r")).unwrap();
+        symlink("../../r", root.join("p/q/x")).unwrap();
+
+        let resolve = |dir: &str, target: &str| resolve(root, Path::new(dir), Path::new(target), &mut 0);
+        assert_eq!(resolve("p/q", "x/file"), Some(PathBuf::from("r/file")));
+        assert_eq!(resolve("p", "../r"), Some(PathBuf::from("r")));
+        assert_eq!(resolve("", "../r"), None);
+        assert_eq!(resolve("", "/etc/passwd"), None);
+        // Fine when read as text, but "x" leads to "r", at the root
+        assert_eq!(resolve("p/q", "x/../../.."), None);
+
+        symlink("loop", root.join("loop")).unwrap();
+        assert_eq!(resolve("", "loop"), None);
+    }
+}
@@ -0,0 +1,64 @@
+//! Rounding of the modification times stored and restored, set by `--time-rounding`, and the
+//! modification times set to now by `--touch`.
+//!
+//! Times are rounded down to a multiple of the granularity, like FAT file systems do with their
+//! 2 second resolution, so rounding them again leaves them as they are.
+
+use std::{io, path::Path};
+
+use filetime::FileTime;
+use fs_err as fs;
+
+/// Rounds `seconds` since the Unix epoch down to a multiple of `granularity` seconds
+pub fn round_time(seconds: i64, granularity: u64) -> i64 {
+    let granularity = granularity.clamp(1, i64::MAX as u64) as i64;
+    seconds - seconds.rem_euclid(granularity)
+}
+
+/// Rounds the modification times of `path` and of everything inside of it, without following
+/// symlinks
+pub fn round_mtimes(path: &Path, granularity: u64) -> io::Result<()> {
+    map_mtimes(path, &|modified| {
+        FileTime::from_unix_time(round_time(modified.unix_seconds(), granularity), 0)
+    })
+}
+
+/// Sets the modification times of `path` and of everything inside of it to `time`, without
+/// following symlinks
+pub fn touch_mtimes(path: &Path, time: FileTime) -> io::Result<()> {
+    map_mtimes(path, &|_| time)
+}
+
+/// Replaces the modification times of `path` and of everything inside of it by what `map` returns
+/// for them, the directories after their contents
+fn map_mtimes(path: &Path, map: &impl Fn(FileTime) -> FileTime) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            map_mtimes(&entry?.path(), map)?;
+        }
+    }
+
+    let modified = FileTime::from_last_modification_time(&metadata);
+    let mapped = map(modified);
+    if mapped != modified {
+        let accessed = FileTime::from_last_access_time(&metadata);
+        filetime::set_symlink_file_times(path, accessed, mapped)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_time() {
+        assert_eq!(round_time(1_700_000_001, 2), 1_700_000_000);
+        assert_eq!(round_time(1_700_000_000, 2), 1_700_000_000);
+        assert_eq!(round_time(1_700_000_059, 60), 1_700_000_040);
+        assert_eq!(round_time(1_700_000_001, 1), 1_700_000_001);
+        // Before the epoch, still rounded down
+        assert_eq!(round_time(-1, 2), -2);
+    }
+}
@@ -0,0 +1,103 @@
+//! Detection of archives that end before they should, like interrupted downloads.
+
+use std::{
+    error,
+    fmt::{self, Display},
+    io::{self, Read},
+};
+
+/// Reader wrapped around each layer of the decoder chain, counting the decompressed bytes.
+///
+/// An unexpected EOF error from the inner reader gets this count attached as a [`TruncatedError`].
+pub struct TruncationCheck<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R: Read> TruncationCheck<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, bytes_read: 0 }
+    }
+}
+
+impl<R: Read> Read for TruncationCheck<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(bytes_read) => {
+                self.bytes_read += bytes_read as u64;
+                Ok(bytes_read)
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                // Keep the original message from the innermost layer, but with the count of
+                // this one, which is closer to the final decompressed output
+                let reason = match find_truncated_error(&err) {
+                    Some(truncated) => truncated.reason.clone(),
+                    None => err.to_string(),
+                };
+                let truncated = TruncatedError {
+                    reason,
+                    bytes_read: self.bytes_read,
+                };
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, truncated))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Payload of the unexpected EOF errors given by [`TruncationCheck`].
+#[derive(Debug, Clone)]
+pub struct TruncatedError {
+    /// The original error message
+    pub reason: String,
+    /// How many bytes were successfully decompressed before the error
+    pub bytes_read: u64,
+}
+
+impl Display for TruncatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.reason.fmt(f)
+    }
+}
+
+impl error::Error for TruncatedError {}
+
+/// Searches the chain of sources of `err` for a [`TruncatedError`], some libraries, like `tar`,
+/// wrap the errors they receive with more context.
+pub fn find_truncated_error(err: &io::Error) -> Option<&TruncatedError> {
+    let mut current: &(dyn error::Error + 'static) = err.get_ref()?;
+
+    loop {
+        if let Some(truncated) = current.downcast_ref::<TruncatedError>() {
+            return Some(truncated);
+        }
+
+        current = match current.downcast_ref::<io::Error>() {
+            Some(io_err) => io_err.get_ref()?,
+            None => current.source()?,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_truncated_gzip() {
+        let data = vec![b'a'; 100_000];
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let truncated = &compressed[..compressed.len() - 10];
+        let mut reader = TruncationCheck::new(flate2::read::GzDecoder::new(TruncationCheck::new(truncated)));
+        let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let truncated = find_truncated_error(&err).unwrap();
+        assert!(truncated.bytes_read > 0 && truncated.bytes_read <= data.len() as u64);
+    }
+}
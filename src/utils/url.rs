@@ -0,0 +1,131 @@
+//! Archives decompressed straight from HTTP(S) URLs, streamed into the decoders without being
+//! saved first.
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use super::Bytes;
+use crate::{
+    error::{Error, FinalError},
+    info,
+};
+
+/// Time between two reports of the download progress
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Checks if `path` is an HTTP(S) URL rather than a file
+pub fn is_url(path: &Path) -> bool {
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+    ["http://", "https://"].iter().any(|scheme| {
+        path.get(..scheme.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// The file name at the end of the path of `url`, without the query and the fragment, like
+/// "data.tar.gz" for "https://host/files/data.tar.gz?version=2", the formats are detected from it
+pub fn url_file_name(url: &Path) -> PathBuf {
+    let url = url.to_string_lossy();
+    let without_scheme = url.split_once("://").map_or(&*url, |(_, rest)| rest);
+    let path = without_scheme.split(['?', '#']).next().unwrap_or_default();
+    // The first segment is the host, which isn't a file name
+    let name = path
+        .split_once('/')
+        .map_or("", |(_, path)| path.rsplit('/').next().unwrap_or_default());
+    PathBuf::from(name)
+}
+
+/// Starts downloading `url`, following redirects, the download progress is reported every second
+/// unless `quiet`
+pub fn open_url(url: &Path, quiet: bool) -> crate::Result<Box<dyn Read + Send>> {
+    let url = url.to_string_lossy();
+    let response = ureq::get(&url).call().map_err(|err| {
+        let error = FinalError::with_title(format!("Could not download '{url}'"))
+            .detail(err.to_string())
+            .hint("Check the URL, or download the file and decompress it from the disk");
+        Error::Custom { reason: error }
+    })?;
+
+    let total = response.header("Content-Length").and_then(|length| length.parse().ok());
+    Ok(Box::new(DownloadProgress {
+        inner: response.into_reader(),
+        url: url.into_owned(),
+        downloaded: 0,
+        total,
+        last_report: Instant::now(),
+        finished: false,
+        quiet,
+    }))
+}
+
+/// Reader of a download that reports how much of it was read so far
+struct DownloadProgress<R> {
+    inner: R,
+    url: String,
+    downloaded: u64,
+    /// Size of the download, if the server sent it
+    total: Option<u64>,
+    last_report: Instant,
+    /// The end was reached and reported, the decoders may keep reading it
+    finished: bool,
+    quiet: bool,
+}
+
+impl<R: Read> Read for DownloadProgress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.downloaded += bytes_read as u64;
+
+        let at_end = bytes_read == 0 && !buf.is_empty();
+        if self.quiet || self.finished {
+            return Ok(bytes_read);
+        }
+        if at_end || self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            self.last_report = Instant::now();
+            self.finished = at_end;
+            match self.total {
+                Some(total) => info!(
+                    inaccessible,
+                    "Downloaded {} of {} from '{}'.",
+                    Bytes::new(self.downloaded),
+                    Bytes::new(total),
+                    self.url
+                ),
+                None => info!(
+                    inaccessible,
+                    "Downloaded {} from '{}'.",
+                    Bytes::new(self.downloaded),
+                    self.url
+                ),
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_file_name() {
+        assert!(is_url(Path::new("https://example.com/file.gz")));
+        assert!(is_url(Path::new("HTTP://example.com/file.gz")));
+        assert!(!is_url(Path::new("/home/user/http://file.gz")));
+        assert!(!is_url(Path::new("ftp://example.com/file.gz")));
+
+        let name = |url| url_file_name(Path::new(url));
+        assert_eq!(name("https://example.com/files/data.tar.gz"), Path::new("data.tar.gz"));
+        assert_eq!(
+            name("https://example.com/data.zst?version=2#top"),
+            Path::new("data.zst")
+        );
+        assert_eq!(name("http://example.com"), Path::new(""));
+        assert_eq!(name("http://example.com/"), Path::new(""));
+    }
+}
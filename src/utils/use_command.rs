@@ -0,0 +1,16 @@
+//! Permission to run external programs for the formats ouch can't handle itself, given with
+//! `--use-command`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether running external programs was allowed with `--use-command`
+static USE_COMMAND: AtomicBool = AtomicBool::new(false);
+
+pub fn set_use_command(use_command: bool) {
+    USE_COMMAND.store(use_command, Ordering::Relaxed);
+}
+
+/// Checks if external programs, like `wimlib-imagex` or `bzip3`, can be run
+pub fn use_command_allowed() -> bool {
+    USE_COMMAND.load(Ordering::Relaxed)
+}
@@ -0,0 +1,276 @@
+//! Split 7z archives, made of volumes named like "archive.7z.001", "archive.7z.002"...
+//!
+//! The volumes are read one after the other, as the archive they were split from, so they don't
+//! have to be concatenated first.
+
+use std::{
+    ffi::OsString,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+
+use super::EscapedPathDisplay;
+use crate::{
+    error::{Error, FinalError},
+    extension::split_volume_number,
+};
+
+/// Signature at the start of 7z archives
+const SEVENZ_SIGNATURE: &[u8] = b"7z\xBC\xAF\x27\x1C";
+/// Size of the start header of 7z archives, which ends with the position and size of the last header
+const SEVENZ_START_HEADER_SIZE: u64 = 32;
+
+/// Reader of a file, or of all the volumes of a split 7z archive, as one
+pub struct Volumes {
+    files: Vec<fs::File>,
+    /// Position where each file starts, and where the last one ends
+    starts: Vec<u64>,
+    /// Index of the file read from
+    current: usize,
+    position: u64,
+}
+
+impl Volumes {
+    /// Opens the volumes of the split 7z archive that starts with `path`, a name like
+    /// "archive.7z.001", or only `path` if it isn't named like a volume
+    ///
+    /// Fails if `path` isn't the first volume, or if the volumes after it are missing or out of
+    /// sequence.
+    pub fn open(path: &Path) -> crate::Result<Self> {
+        let Some((archive_name, number)) = volume_name(path) else {
+            return Self::new(vec![fs::File::open(path)?]);
+        };
+        let width = number.len();
+        let volume_path = |number: usize| {
+            // Already split at a dot
+            let mut name = OsString::from(archive_name.to_os_str().unwrap());
+            name.push(format!(".{number:0width$}"));
+            path.with_file_name(name)
+        };
+
+        if volume_number(number) != Some(1) {
+            let error = FinalError::with_title(format!(
+                "'{}' is not the first volume of a split 7z archive",
+                EscapedPathDisplay::new(path)
+            ))
+            .detail("The volumes of a split archive are read from the first one")
+            .hint(format!(
+                "Decompress '{}' instead, the volumes after it are found next to it",
+                EscapedPathDisplay::new(&volume_path(1))
+            ));
+            return Err(Error::Custom { reason: error });
+        }
+
+        let mut paths = vec![];
+        while volume_path(paths.len() + 1).exists() {
+            paths.push(volume_path(paths.len() + 1));
+        }
+        let files = paths.iter().map(fs::File::open).collect::<io::Result<_>>()?;
+        let mut volumes = Self::new(files)?;
+
+        // A later volume without the ones before it means some are missing
+        if let Some(later) = later_volume(path, archive_name, paths.len())? {
+            return Err(missing_volume_error(&volume_path(paths.len() + 1), Some(&later)));
+        }
+        // All volumes have the size of the first one, except the last one that can be smaller
+        for (i, path) in paths.iter().enumerate().skip(1) {
+            let expected_size = if i + 1 < paths.len() {
+                volumes.volume_len(i) == volumes.volume_len(0)
+            } else {
+                volumes.volume_len(i) <= volumes.volume_len(0)
+            };
+            if !expected_size {
+                let error = FinalError::with_title(format!(
+                    "'{}' is not the size of the volumes before it",
+                    EscapedPathDisplay::new(path)
+                ))
+                .detail("All the volumes of a split archive but the last one have the same size")
+                .hint("The volume may be incomplete, or a volume of another archive");
+                return Err(Error::Custom { reason: error });
+            }
+        }
+        if volumes.archive_len()? > volumes.total_size() {
+            return Err(missing_volume_error(&volume_path(paths.len() + 1), None));
+        }
+
+        volumes.rewind()?;
+        Ok(volumes)
+    }
+
+    /// Whether `path` is named like a volume of a split 7z archive
+    pub fn is_volume(path: &Path) -> bool {
+        volume_name(path).is_some()
+    }
+
+    fn new(files: Vec<fs::File>) -> crate::Result<Self> {
+        let mut starts = vec![0];
+        for file in &files {
+            starts.push(starts.last().unwrap() + file.metadata()?.len());
+        }
+        Ok(Self {
+            files,
+            starts,
+            current: 0,
+            position: 0,
+        })
+    }
+
+    /// Size of all the volumes together
+    pub fn total_size(&self) -> u64 {
+        *self.starts.last().unwrap()
+    }
+
+    fn volume_len(&self, index: usize) -> u64 {
+        self.starts[index + 1] - self.starts[index]
+    }
+
+    /// Size of the 7z archive, from its start header, or 0 if it doesn't start like one
+    fn archive_len(&mut self) -> io::Result<u64> {
+        let mut header = [0; SEVENZ_START_HEADER_SIZE as usize];
+        self.rewind()?;
+        if self.read_exact(&mut header).is_err() || !header.starts_with(SEVENZ_SIGNATURE) {
+            return Ok(0);
+        }
+        let next_header_offset = u64::from_le_bytes(header[12..20].try_into().unwrap());
+        let next_header_size = u64::from_le_bytes(header[20..28].try_into().unwrap());
+        Ok(SEVENZ_START_HEADER_SIZE
+            .saturating_add(next_header_offset)
+            .saturating_add(next_header_size))
+    }
+}
+
+/// A volume of the archive named `archive_name` next to `first`, numbered after `count`
+fn later_volume(first: &Path, archive_name: &[u8], count: usize) -> io::Result<Option<PathBuf>> {
+    let dir = match first.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let later = volume_name(&path)
+            .is_some_and(|(name, number)| name == archive_name && volume_number(number) > Some(count));
+        if later {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Name of the archive the volume at `path` is from and the number of the volume, see
+/// [`split_volume_number`]
+fn volume_name(path: &Path) -> Option<(&[u8], &[u8])> {
+    path.file_name()
+        .and_then(<[u8] as ByteSlice>::from_os_str)
+        .and_then(split_volume_number)
+}
+
+fn volume_number(number: &[u8]) -> Option<usize> {
+    number.to_str().ok()?.parse().ok()
+}
+
+/// Error of the volume at `missing` not being found, `later` being a volume found after it
+fn missing_volume_error(missing: &Path, later: Option<&Path>) -> Error {
+    let mut error = FinalError::with_title(format!(
+        "Volume '{}' of the split 7z archive is missing",
+        EscapedPathDisplay::new(missing)
+    ));
+    error = match later {
+        Some(later) => error.detail(format!(
+            "'{}' was found, but not the volumes before it",
+            EscapedPathDisplay::new(later)
+        )),
+        None => error.detail("The volumes found end before the archive does"),
+    };
+    Error::Custom {
+        reason: error.hint("Put all the volumes of the archive in the same directory, with their original names"),
+    }
+}
+
+impl Read for Volumes {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(file) = self.files.get_mut(self.current) {
+            let bytes_read = file.read(buf)?;
+            if bytes_read > 0 || buf.is_empty() {
+                self.position += bytes_read as u64;
+                return Ok(bytes_read);
+            }
+            // The next volume is read from its start
+            self.current += 1;
+            if let Some(next) = self.files.get_mut(self.current) {
+                next.rewind()?;
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for Volumes {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.total_size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+
+        // The last volume holds the positions past the end
+        self.current = self.starts[1..]
+            .iter()
+            .position(|&end| position < end)
+            .unwrap_or(self.files.len().saturating_sub(1));
+        if let Some(file) = self.files.get_mut(self.current) {
+            file.seek(SeekFrom::Start(position - self.starts[self.current]))?;
+        }
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volumes_read_and_seek_as_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("data.7z.001");
+        fs::write(&first, b"abcd").unwrap();
+        fs::write(dir.path().join("data.7z.002"), b"efgh").unwrap();
+        fs::write(dir.path().join("data.7z.003"), b"ij").unwrap();
+
+        let mut volumes = Volumes::open(&first).unwrap();
+        assert_eq!(volumes.total_size(), 10);
+        let mut content = String::new();
+        volumes.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "abcdefghij");
+
+        let mut buf = [0; 3];
+        volumes.seek(SeekFrom::Start(3)).unwrap();
+        volumes.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"def");
+        volumes.seek(SeekFrom::End(-3)).unwrap();
+        volumes.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hij");
+        volumes.seek(SeekFrom::Current(-6)).unwrap();
+        volumes.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"efg");
+    }
+
+    #[test]
+    fn test_volumes_out_of_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("data.7z.001");
+        fs::write(&first, b"abcd").unwrap();
+        fs::write(dir.path().join("data.7z.003"), b"ij").unwrap();
+        assert!(Volumes::open(&first).is_err());
+        assert!(Volumes::open(&dir.path().join("data.7z.003")).is_err());
+
+        fs::write(dir.path().join("data.7z.002"), b"ef").unwrap();
+        assert!(Volumes::open(&first).is_err());
+        fs::write(dir.path().join("data.7z.002"), b"efgh").unwrap();
+        assert!(Volumes::open(&first).is_ok());
+    }
+}
@@ -0,0 +1,42 @@
+//! How warnings are reported, set by `--quiet-errors` and `--strict`.
+
+use once_cell::sync::OnceCell;
+
+use crate::{error::FinalError, warning};
+
+/// Must be set before printing any warning.
+static WARNING_POLICY: OnceCell<WarningPolicy> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WarningPolicy {
+    /// Print every warning
+    #[default]
+    Print,
+    /// Don't print warnings, errors are still printed
+    Silence,
+    /// Fail instead of warning about skipped files and risky changes, the other warnings are printed
+    Strict,
+}
+
+pub fn set_warning_policy(policy: WarningPolicy) {
+    if WARNING_POLICY.get().is_none() {
+        WARNING_POLICY.set(policy).unwrap();
+    }
+}
+
+pub fn warning_policy() -> WarningPolicy {
+    WARNING_POLICY.get().copied().unwrap_or_default()
+}
+
+/// Warns about something that didn't go as asked, like a skipped file, or fails with `--strict`.
+pub fn warn_or_fail(message: String) -> crate::Result<()> {
+    if warning_policy() == WarningPolicy::Strict {
+        let error = FinalError::with_title("Stopped on a warning, because of --strict")
+            .detail(message)
+            .hint("Remove --strict to only warn about it.");
+        return Err(error.into());
+    }
+
+    warning!("{message}");
+    Ok(())
+}
@@ -1,12 +1,13 @@
 #[macro_use]
 mod utils;
 
-use std::{iter::once, path::PathBuf};
+use std::{io::Write, iter::once, path::PathBuf};
 
 use fs_err as fs;
 use parse_display::Display;
 use proptest::sample::size_range;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
+use sha2::Digest;
 use tempfile::tempdir;
 use test_strategy::{proptest, Arbitrary};
 
@@ -77,7 +78,7 @@ fn create_random_files(dir: impl Into<PathBuf>, depth: u8, rng: &mut SmallRng) {
 
     // create more random files in 0 to 2 new directories
     for _ in 0..rng.gen_range(0..=2u32) {
-        create_random_files(&tempfile::tempdir_in(dir).unwrap().into_path(), depth - 1, rng);
+        create_random_files(tempfile::tempdir_in(dir).unwrap().into_path(), depth - 1, rng);
     }
 }
 
@@ -146,3 +147,2667 @@ fn multiple_files(
     ouch!("-A", "d", archive, "-d", after);
     assert_same_directory(before, after, !matches!(ext, DirectoryExtension::Zip));
 }
+
+// zip archives with an entry larger than 4 GB need the ZIP64 extensions
+#[test]
+#[cfg(feature = "slow-tests")]
+fn zip64_large_file() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    let before_file = &before.join("file");
+    let archive = &dir.join("archive.zip");
+    let after = &dir.join("after");
+
+    // Sparse, so it doesn't take up the disk space until it is decompressed
+    let size = u64::from(u32::MAX) + 1024 * 1024;
+    fs::File::create(before_file).unwrap().set_len(size).unwrap();
+
+    ouch!("-A", "c", before_file, archive);
+    ouch!("-A", "d", archive, "-d", after);
+    assert_eq!(fs::metadata(after.join("file")).unwrap().len(), size);
+}
+
+// zip archives with more than 65535 entries need the ZIP64 extensions
+#[test]
+#[cfg(feature = "slow-tests")]
+fn zip64_many_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    let before_dir = &before.join("dir");
+    fs::create_dir_all(before_dir).unwrap();
+    let archive = &dir.join("archive.zip");
+    let after = &dir.join("after");
+
+    for i in 0..70_000 {
+        fs::write(before_dir.join(i.to_string()), []).unwrap();
+    }
+
+    ouch!("-A", "c", before_dir, archive);
+    ouch!("-A", "d", archive, "-d", after);
+    assert_eq!(fs::read_dir(after.join("dir")).unwrap().count(), 70_000);
+}
+
+// scripts can tell failures apart by the exit code
+#[test]
+fn exit_codes() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "content").unwrap();
+
+    // compressing without a format is a usage error
+    utils::cargo_bin()
+        .args(["compress", "--yes"])
+        .arg(file)
+        .arg(dir.join("output"))
+        .assert()
+        .code(2);
+
+    // a gzip stream cut in half is a truncated archive
+    let archive = &dir.join("file.gz");
+    ouch!("c", file, archive);
+    let compressed = fs::read(archive).unwrap();
+    fs::write(archive, &compressed[..compressed.len() / 2]).unwrap();
+    utils::cargo_bin()
+        .args(["decompress", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .assert()
+        .code(3);
+}
+
+// --strict fails on the warnings about skipped files, --quiet-errors hides them
+#[test]
+fn strict_and_quiet_errors() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+
+    // the output is inside of the input, so it gets skipped
+    let archive = &input.join("archive.tar");
+    utils::cargo_bin()
+        .args(["compress", "--yes", "--strict"])
+        .arg(input)
+        .arg(archive)
+        .assert()
+        .failure();
+    assert!(!archive.exists());
+
+    let output = ouch!("compress", "--quiet-errors", input, archive);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("WARNING"));
+}
+
+// archives with many entries at their root are unpacked into a directory named after them
+#[test]
+fn many_root_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let files: Vec<_> = (0..4).map(|i| dir.join(format!("file{i}"))).collect();
+    for file in &files {
+        fs::write(file, "content").unwrap();
+    }
+
+    let one = &dir.join("one.tar");
+    ouch!("c", &files[0], one);
+    ouch!("d", one, "-d", dir.join("one"));
+    assert!(dir.join("one/file0").is_file());
+
+    // Several entries go in a directory named after the archive, without asking
+    let few = &dir.join("few.tar");
+    ouch!("c", &files[0], &files[1], few);
+    utils::cargo_bin()
+        .args(["decompress", "--no"])
+        .arg(few)
+        .arg("-d")
+        .arg(dir.join("few"))
+        .assert()
+        .success();
+    assert!(dir.join("few/few/file0").is_file());
+    assert!(!dir.join("few/file0").exists());
+
+    let many = &dir.join("many.tar");
+    ouch!("c", &files[0], &files[1], &files[2], &files[3], many);
+    ouch!("d", many, "-d", dir.join("yes"));
+    assert!(dir.join("yes/many/file3").is_file());
+
+    // Without a terminal to ask, they also go in a new directory
+    utils::cargo_bin()
+        .arg("decompress")
+        .arg(many)
+        .arg("-d")
+        .arg(dir.join("scripted"))
+        .assert()
+        .success();
+    assert!(dir.join("scripted/many/file3").is_file());
+
+    utils::cargo_bin()
+        .args(["decompress", "--no"])
+        .arg(many)
+        .arg("-d")
+        .arg(dir.join("no"))
+        .assert()
+        .success();
+    assert!(dir.join("no/file3").is_file());
+}
+
+// --format decompresses files whose extensions are misleading
+#[test]
+fn decompress_with_format() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "content").unwrap();
+
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("c", file, archive);
+    let weird = &dir.join("weird.dat");
+    fs::rename(archive, weird).unwrap();
+
+    ouch!("d", weird, "--format", "tar.gz", "-d", dir.join("out"));
+    assert_eq!(fs::read_to_string(dir.join("out/file")).unwrap(), "content");
+
+    let compressed = &dir.join("file.gz");
+    ouch!("c", file, compressed);
+    fs::rename(compressed, weird).unwrap();
+
+    ouch!("d", weird, "--format", "gz", "-d", dir.join("single"));
+    assert_eq!(fs::read_to_string(dir.join("single/weird")).unwrap(), "content");
+}
+
+#[test]
+fn bench() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content ".repeat(1000)).unwrap();
+
+    let output = ouch!("bench", input, "--formats", "tar.gz,zip");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let formats: Vec<_> = stdout
+        .lines()
+        .skip(2)
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(formats.len(), 2);
+    assert!(formats.contains(&"tar.gz") && formats.contains(&"zip"));
+
+    // only the table is printed, no archive is left behind
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+
+    utils::cargo_bin()
+        .args(["bench", "--formats", "tar.Z"])
+        .arg(input)
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn probe() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+
+    ouch!("c", input, dir.join("archive.tar.zst"));
+    fs::copy(dir.join("archive.tar.zst"), dir.join("archive")).unwrap();
+    fs::copy(dir.join("archive.tar.zst"), dir.join("archive.zip")).unwrap();
+
+    let output = ouch!(
+        "probe",
+        dir.join("archive.tar.zst"),
+        dir.join("archive"),
+        dir.join("archive.zip"),
+        input
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let probes: Vec<_> = stdout.split("\n\n").collect();
+    assert_eq!(probes.len(), 4);
+
+    assert!(probes[0].contains("Formats: [Tar, Zstd]"));
+    assert!(probes[0].contains("Detected from: file extension, confirmed by the magic bytes"));
+    assert!(probes[0].contains("Encrypted: no"));
+    assert!(probes[1].contains("Formats: [Zstd]"));
+    assert!(probes[1].contains("Detected from: magic bytes"));
+    assert!(probes[2].contains("Formats: [Zstd]"));
+    assert!(probes[2].contains("differ from the file extension `zip`"));
+    assert!(probes[3].contains("Formats: unknown"));
+}
+
+// names that aren't UTF-8 are kept by tar, replaced in zip and 7z, and recreated from zip archives
+// written elsewhere
+#[cfg(unix)]
+#[test]
+fn invalid_utf8_names() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    use bstr::ByteSlice;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let name = OsStr::from_bytes(b"a\xffb");
+    fs::write(input.join(name), "content").unwrap();
+
+    ouch!("c", input, dir.join("archive.tar"));
+    ouch!("d", dir.join("archive.tar"), "-d", dir.join("tar"));
+    assert!(dir.join("tar/input").join(name).is_file());
+
+    for format in ["zip", "7z"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("c", input, &archive);
+        ouch!("d", &archive, "-d", dir.join(format));
+        assert!(dir.join(format).join("input/a\u{FFFD}b").is_file());
+    }
+
+    // ouch can't write such a zip archive, so the name is patched in
+    fs::write(input.join("aXb"), "content").unwrap();
+    fs::remove_file(input.join(name)).unwrap();
+    let archive = &dir.join("patched.zip");
+    ouch!("c", input, archive);
+    let bytes = fs::read(archive).unwrap().replace(b"aXb", b"a\xffb");
+    fs::write(archive, bytes).unwrap();
+    ouch!("d", archive, "-d", dir.join("patched"));
+    assert!(dir.join("patched/input").join(name).is_file());
+}
+
+#[cfg(unix)]
+#[test]
+fn progress_fd() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let archive = &dir.join("archive.zip");
+
+    // stdout is the file descriptor 1
+    let output = ouch!("--progress-fd", "1", "c", input, archive);
+    let events: Vec<_> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    assert_eq!(
+        events,
+        [
+            r#"{"type":"entry","path":"input","bytes":7}"#.to_owned(),
+            format!(r#"{{"type":"done","path":"{}"}}"#, archive.display()),
+        ]
+    );
+
+    utils::cargo_bin()
+        .args(["--progress-fd", "100", "d"])
+        .arg(archive)
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn wim_requires_use_command() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let archive = &dir.join("archive.wim");
+
+    utils::cargo_bin().arg("c").arg(input).arg(archive).assert().code(2);
+    assert!(!archive.exists());
+
+    // wimlib-imagex can't be found without a PATH to look for it in
+    utils::cargo_bin()
+        .args(["--use-command", "c"])
+        .arg(input)
+        .arg(archive)
+        .env("PATH", "")
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn bzip3_requires_use_command() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let archive = &dir.join("archive.tar.bz3");
+
+    utils::cargo_bin().arg("c").arg(input).arg(archive).assert().code(2);
+    assert!(!archive.exists());
+
+    // bzip3 can't be found without a PATH to look for it in
+    utils::cargo_bin()
+        .args(["--use-command", "c"])
+        .arg(input)
+        .arg(archive)
+        .env("PATH", "")
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn bzip3_round_trip() {
+    // Only checked where bzip3 is installed
+    if std::process::Command::new("bzip3").arg("-V").output().is_err() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    write_random_content(
+        &mut fs::File::create(input.join("file")).unwrap(),
+        &mut SmallRng::from_entropy(),
+    );
+
+    let archive = &dir.join("archive.tar.bz3");
+    ouch!("--use-command", "c", input, archive);
+    let output = &dir.join("output");
+    ouch!("--use-command", "d", archive, "-d", output);
+    assert_same_directory(input, output.join("input"), false);
+}
+
+#[test]
+fn log_file() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let log_file = &dir.join("ouch.log");
+
+    ouch!(
+        "--log-file",
+        log_file,
+        "--color",
+        "always",
+        "c",
+        input,
+        dir.join("archive.zip")
+    );
+    utils::cargo_bin()
+        .arg("--log-file")
+        .arg(log_file)
+        .arg("d")
+        .arg(dir.join("missing.zip"))
+        .assert()
+        .failure();
+
+    // appended to, without colors
+    let log = fs::read_to_string(log_file).unwrap();
+    assert!(!log.contains('\x1b'));
+    let lines: Vec<_> = log.lines().collect();
+    assert!(lines[0].ends_with("[INFO] Compressing 'input'."));
+    assert!(lines.iter().any(|line| line.contains("[INFO] Successfully compressed")));
+    assert!(lines.last().unwrap().contains("[ERROR] "));
+}
+
+#[test]
+fn extraction_order() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for (name, size) in [("b", 10), ("c", 30), ("a", 20)] {
+        fs::write(input.join(name), "x".repeat(size)).unwrap();
+    }
+    let archive = &dir.join("archive.zip");
+    ouch!("c", input.join("b"), input.join("c"), input.join("a"), archive);
+
+    // the progress events, written to stdout, are in the order the entries are unpacked in
+    let unpacked_entries = |order: &str| -> Vec<String> {
+        let output_dir = dir.join(format!("output-{order}"));
+        let output = ouch!("--progress-fd", "1", "d", archive, "-d", output_dir, "--order", order);
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split(r#""path":""#).nth(1))
+            .filter_map(|path| path.split('"').next())
+            .filter(|path| !path.ends_with("archive.zip"))
+            .map(str::to_owned)
+            .collect()
+    };
+    assert_eq!(unpacked_entries("archive"), ["b", "c", "a"]);
+    assert_eq!(unpacked_entries("name"), ["a", "b", "c"]);
+    assert_eq!(unpacked_entries("size-desc"), ["c", "a", "b"]);
+
+    ouch!("c", input, dir.join("archive.tar"));
+    utils::cargo_bin()
+        .args(["d", "--order", "name"])
+        .arg(dir.join("archive.tar"))
+        .assert()
+        .code(2);
+}
+
+// on case-insensitive file systems, the second file would overwrite the first one
+#[test]
+fn case_conflicts() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.tar");
+
+    // built by hand, the files can't be created next to each other on case-insensitive file systems
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    for (name, content) in [("File.txt", "upper"), ("file.txt", "lower")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+    }
+    builder.finish().unwrap();
+
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output, "--case-conflicts", "rename");
+    let mut contents: Vec<_> = fs::read_dir(output.join("archive"))
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, ["lower", "upper"]);
+}
+
+#[test]
+fn decompress_tar_with_parent_dirs() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.tar");
+
+    // built by hand, `tar` refuses to write paths with '..' components
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    for name in ["a/../../etc/x", "a/b", "../escaped"] {
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_size(name.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, name.as_bytes()).unwrap();
+    }
+    builder.finish().unwrap();
+    let outside = ["etc/x", "escaped", "output/etc/x", "output/escaped"];
+
+    let output = &dir.join("output");
+    let assert = utils::cargo_bin()
+        .arg("d")
+        .arg(archive)
+        .arg("-d")
+        .arg(output)
+        .assert()
+        .success();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("Skipping 'a/../../etc/x'"), "{stderr}");
+    assert_eq!(fs::read_to_string(output.join("a/b")).unwrap(), "a/b");
+    for path in outside {
+        assert!(!dir.join(path).exists(), "{path}");
+    }
+
+    fs::remove_dir_all(output).unwrap();
+    ouch!("d", archive, "-d", output, "--parent-dirs", "strip");
+    assert_eq!(
+        fs::read_to_string(output.join("archive/a/etc/x")).unwrap(),
+        "a/../../etc/x"
+    );
+    assert_eq!(
+        fs::read_to_string(output.join("archive/escaped")).unwrap(),
+        "../escaped"
+    );
+    for path in &outside[..2] {
+        assert!(!dir.join(path).exists(), "{path}");
+    }
+}
+
+#[test]
+fn compress_into_missing_directory() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+
+    ouch!("c", input, dir.join("a/b/archive.tar"));
+    assert!(dir.join("a/b/archive.tar").is_file());
+
+    utils::cargo_bin()
+        .args(["compress", "--mkdir"])
+        .arg(input)
+        .arg(dir.join("c/archive.zip"))
+        .assert()
+        .success();
+    assert!(dir.join("c/archive.zip").is_file());
+
+    utils::cargo_bin()
+        .args(["compress", "--no"])
+        .arg(input)
+        .arg(dir.join("d/archive.zip"))
+        .assert()
+        .success();
+    assert!(!dir.join("d").exists());
+}
+
+#[test]
+fn decompress_subtree() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("a/b/c")).unwrap();
+    fs::write(input.join("a/b/c/file"), "nested").unwrap();
+    fs::write(input.join("a/b/file"), "inside").unwrap();
+    fs::write(input.join("a/outside"), "outside").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        // the trailing slash and the leading "./" are ignored
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output, "--subtree", "./input/a/b/");
+
+        assert_eq!(fs::read(output.join("archive/c/file")).unwrap(), b"nested");
+        assert_eq!(fs::read(output.join("archive/file")).unwrap(), b"inside");
+        assert_eq!(fs::read_dir(output.join("archive")).unwrap().count(), 2);
+
+        utils::cargo_bin()
+            .args(["d", "--yes", "--subtree", "input/missing", "-d"])
+            .arg(dir.join(format!("missing-{format}")))
+            .arg(archive)
+            .assert()
+            .code(2);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn decompress_with_chown() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar");
+    ouch!("c", input, archive);
+
+    // giving the files to their current owner never needs privileges
+    let metadata = fs::metadata(input.join("file")).unwrap();
+    let owner = format!("{}:{}", metadata.uid(), metadata.gid());
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output, "--chown", owner);
+    let unpacked = fs::metadata(output.join("input/file")).unwrap();
+    assert_eq!((unpacked.uid(), unpacked.gid()), (metadata.uid(), metadata.gid()));
+
+    utils::cargo_bin()
+        .args(["d", "--yes", "--chown", "no-such-user-here", "-d"])
+        .arg(dir.join("invalid"))
+        .arg(archive)
+        .assert()
+        .code(2);
+    // the owner is checked before anything is written
+    assert!(!dir.join("invalid").exists());
+}
+
+#[test]
+fn compress_with_base_dirs() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let project = &dir.join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::create_dir_all(project.join("build")).unwrap();
+    fs::write(project.join("src/a.txt"), "a").unwrap();
+    fs::write(project.join("build/b.txt"), "b").unwrap();
+    fs::write(dir.join("outside.txt"), "outside").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!(
+            "c",
+            project.join("src/a.txt"),
+            project.join("build/b.txt"),
+            dir.join("outside.txt"),
+            archive,
+            "--base",
+            project
+        );
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output);
+        assert_eq!(fs::read(output.join("archive/src/a.txt")).unwrap(), b"a");
+        assert_eq!(fs::read(output.join("archive/build/b.txt")).unwrap(), b"b");
+        // stored by its name, it isn't inside of the base directory
+        assert_eq!(fs::read(output.join("archive/outside.txt")).unwrap(), b"outside");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn tar_hard_links() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "x".repeat(100_000)).unwrap();
+    fs::hard_link(input.join("file"), input.join("link")).unwrap();
+
+    let linked = &dir.join("linked.tar");
+    let copied = &dir.join("copied.tar");
+    ouch!("c", input, linked);
+    ouch!("c", input, copied, "--hard-dereference");
+
+    // the contents are only stored once, the other path is a link to it
+    let entry_types: Vec<_> = tar::Archive::new(fs::File::open(linked).unwrap())
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().header().entry_type())
+        .filter(|entry_type| !entry_type.is_dir())
+        .collect();
+    assert_eq!(
+        entry_types
+            .iter()
+            .filter(|entry_type| entry_type.is_hard_link())
+            .count(),
+        1
+    );
+    assert!(fs::metadata(linked).unwrap().len() < fs::metadata(copied).unwrap().len());
+
+    let output = &dir.join("output");
+    ouch!("d", linked, "-d", output);
+    assert_eq!(
+        fs::read(output.join("input/file")).unwrap(),
+        "x".repeat(100_000).as_bytes()
+    );
+    assert_eq!(
+        fs::read(output.join("input/link")).unwrap(),
+        "x".repeat(100_000).as_bytes()
+    );
+}
+
+#[test]
+fn compress_level_by_extension() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for name in ["photo.jpg", "notes.txt", "data.bin"] {
+        fs::write(input.join(name), "text ".repeat(1000)).unwrap();
+    }
+
+    let methods = |archive: &PathBuf| {
+        let mut archive = zip::ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+        ["photo.jpg", "notes.txt", "data.bin"].map(|name| {
+            let entry = archive.by_name(&format!("input/{name}")).unwrap();
+            entry.compression()
+        })
+    };
+    let (stored, deflated) = (zip::CompressionMethod::Stored, zip::CompressionMethod::Deflated);
+
+    let archive = &dir.join("default.zip");
+    ouch!("c", input, archive, "--compress-level-by-extension");
+    assert_eq!(methods(archive), [stored, deflated, deflated]);
+
+    let archive = &dir.join("rules.zip");
+    ouch!("c", input, archive, "--compress-level-by-extension=jpg:9,bin:0");
+    assert_eq!(methods(archive), [deflated, deflated, stored]);
+}
+
+#[test]
+fn compress_with_metadata() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let tool = format!("tool: ouch {}", env!("CARGO_PKG_VERSION"));
+
+    for name in ["archive.zip", "archive.tar.gz", "archive.zip.xz"] {
+        let archive = &dir.join(name);
+        ouch!("c", input, archive, "--metadata");
+
+        let output = ouch!("d", archive, "-d", dir.join(format!("out-{name}")), "--show-metadata");
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains(&tool), "{name}: {stdout}");
+        // The global header holding it isn't unpacked as a file
+        assert_eq!(fs::read_dir(dir.join(format!("out-{name}"))).unwrap().count(), 1);
+    }
+
+    let archive = &dir.join("input.gz");
+    ouch!("c", input, archive, "--metadata", "--sidecar");
+    let sidecar = fs::read_to_string(dir.join("input.gz.meta.json")).unwrap();
+    assert!(sidecar.starts_with(&format!("{{\"tool\":\"ouch {}\"", env!("CARGO_PKG_VERSION"))));
+    let output = ouch!("d", archive, "-d", dir.join("out-gz"), "--show-metadata");
+    assert!(String::from_utf8(output.stdout).unwrap().contains(&tool));
+
+    let archive = &dir.join("plain.zip");
+    ouch!("c", input, archive);
+    let output = ouch!("d", archive, "-d", dir.join("out-plain"), "--show-metadata");
+    assert!(String::from_utf8(output.stdout).unwrap().contains("has no metadata"));
+}
+
+#[test]
+fn decompress_with_retries() {
+    #[cfg(unix)]
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("nested/file"), "content").unwrap();
+    #[cfg(unix)]
+    fs::set_permissions(input.join("nested/file"), Permissions::from_mode(0o640)).unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output, "--retries", "2");
+        let unpacked = output.join("input/nested/file");
+        assert_eq!(fs::read_to_string(&unpacked).unwrap(), "content", "{format}");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&unpacked).unwrap().permissions().mode() & 0o777,
+            0o640,
+            "{format}"
+        );
+    }
+
+    let archive = &dir.join("file.gz");
+    ouch!("c", input.join("nested/file"), archive);
+    ouch!("d", archive, "-d", dir.join("output-gz"), "--retries", "2");
+    assert_eq!(fs::read_to_string(dir.join("output-gz/file")).unwrap(), "content");
+}
+
+#[test]
+fn decompress_zip_with_backslash_separators() {
+    use std::io::Write;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    // Like the archives of some Windows tools, which store backslashes instead of slashes
+    let archive = &dir.join("windows.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(archive).unwrap());
+    let options = zip::write::FileOptions::default();
+    writer.add_directory("dir\\sub\\", options).unwrap();
+    writer.start_file("dir\\sub\\file.txt", options).unwrap();
+    writer.write_all(b"content").unwrap();
+    writer.start_file("dir\\other.txt", options).unwrap();
+    writer.write_all(b"other").unwrap();
+    writer.finish().unwrap();
+
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output);
+    assert_eq!(fs::read_to_string(output.join("dir/sub/file.txt")).unwrap(), "content");
+    assert_eq!(fs::read_to_string(output.join("dir/other.txt")).unwrap(), "other");
+
+    // Kept as part of the file names, where backslashes aren't separators
+    #[cfg(unix)]
+    {
+        let output = &dir.join("kept");
+        ouch!("d", archive, "-d", output, "--normalize-separators=false");
+        assert_eq!(
+            fs::read_to_string(output.join("windows/dir\\sub\\file.txt")).unwrap(),
+            "content"
+        );
+    }
+}
+
+#[test]
+fn decompress_if_newer() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let archived = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+    for name in ["edited", "stale", "same"] {
+        fs::write(input.join(name), "archived").unwrap();
+        filetime::set_file_mtime(input.join(name), archived).unwrap();
+    }
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("c", input, archive);
+
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output);
+    let unpacked = &output.join("input");
+    // Edited after unpacking, stale from before the archive, or the same as in it
+    fs::write(unpacked.join("edited"), "edited").unwrap();
+    fs::write(unpacked.join("stale"), "stale").unwrap();
+    filetime::set_file_mtime(
+        unpacked.join("stale"),
+        filetime::FileTime::from_unix_time(1_500_000_000, 0),
+    )
+    .unwrap();
+    fs::write(unpacked.join("same"), "same").unwrap();
+    filetime::set_file_mtime(unpacked.join("same"), archived).unwrap();
+    fs::write(unpacked.join("local"), "local").unwrap();
+
+    ouch!("d", archive, "-d", output, "--if-newer");
+    assert_eq!(fs::read_to_string(unpacked.join("edited")).unwrap(), "edited");
+    assert_eq!(fs::read_to_string(unpacked.join("stale")).unwrap(), "archived");
+    assert_eq!(fs::read_to_string(unpacked.join("same")).unwrap(), "same");
+    assert_eq!(fs::read_to_string(unpacked.join("local")).unwrap(), "local");
+}
+
+#[test]
+fn empty_directories() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("cache")).unwrap();
+    fs::create_dir_all(input.join("nested/empty/deeper")).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+
+    for format in ["tar", "tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output);
+        assert_same_directory(input, output.join("input"), false);
+        for empty in ["cache", "nested/empty/deeper"] {
+            let empty = output.join("input").join(empty);
+            assert_eq!(
+                fs::read_dir(&empty).unwrap().count(),
+                0,
+                "{format}: {}",
+                empty.display()
+            );
+        }
+
+        let listed = ouch!("l", archive);
+        let listed = String::from_utf8(listed.stdout).unwrap();
+        assert!(listed.lines().any(|line| line == "input/cache/"), "{format}: {listed}");
+    }
+}
+
+#[test]
+fn decompress_with_max_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for i in 0..4 {
+        fs::write(input.join(format!("file{i}")), "content").unwrap();
+    }
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        // The directory and its 4 files
+        let output = &dir.join(format!("output-{format}"));
+        let assert = utils::cargo_bin()
+            .args(["d", "--yes", "--max-entries", "4", "-d"])
+            .arg(output)
+            .arg(archive)
+            .assert()
+            .code(1);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("more than 4 entries"), "{format}: {stderr}");
+        // The partially unpacked files are removed
+        assert_eq!(fs::read_dir(output).unwrap().count(), 0, "{format}");
+
+        ouch!("d", archive, "-d", output, "--max-entries", "5");
+        assert_same_directory(input, output.join("input"), false);
+    }
+}
+
+/// Serves `body` to the requests of the returned URL, which is redirected once, then to the ones
+/// of the URL it redirects to
+fn serve_over_http(path: &str, body: Vec<u8>) -> String {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(&stream);
+            reader.read_line(&mut request_line).unwrap();
+            // Skip the headers
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            if request_line.contains("/redirected/") {
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).unwrap();
+                stream.write_all(&body).unwrap();
+            } else {
+                let location = request_line.split(' ').nth(1).unwrap().replacen('/', "/redirected/", 1);
+                write!(
+                    stream,
+                    "HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\n\r\n"
+                )
+                .unwrap();
+            }
+        }
+    });
+
+    format!("http://{address}/{path}")
+}
+
+#[test]
+fn decompress_from_url() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("c", input, archive);
+
+    let url = serve_over_http("files/archive.tar.zst?token=1", fs::read(archive).unwrap());
+    let output = &dir.join("output");
+    ouch!("d", &url, "-d", output);
+    assert_same_directory(input, output.join("input"), false);
+
+    // Single files are named after the URL
+    let compressed = &dir.join("file.gz");
+    ouch!("c", input.join("file"), compressed);
+    let url = serve_over_http("file.gz", fs::read(compressed).unwrap());
+    ouch!("d", &url, "-d", output);
+    assert_eq!(fs::read(output.join("file")).unwrap(), b"content");
+
+    // Zip archives can't be streamed
+    let assert = utils::cargo_bin()
+        .args(["d", "--yes", "http://127.0.0.1:1/archive.zip"])
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("Cannot decompress zip archives from URLs"), "{stderr}");
+}
+
+#[test]
+fn decompress_with_output_name_template() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("first.tar.gz");
+    ouch!("c", input, archive);
+    let other_archive = &dir.join("second.zip");
+    ouch!("c", input.join("file"), other_archive);
+    let compressed = &dir.join("third.zst");
+    ouch!("c", input.join("file"), compressed);
+
+    let output = &dir.join("output");
+    ouch!(
+        "d",
+        archive,
+        other_archive,
+        compressed,
+        "-d",
+        output,
+        "--output-name-template",
+        "{index}_{stem}_{format}"
+    );
+    assert_same_directory(input, output.join("1_first_tar.gz/input"), false);
+    assert_eq!(fs::read(output.join("2_second_zip/file")).unwrap(), b"content");
+    assert_eq!(fs::read(output.join("3_third_zst")).unwrap(), b"content");
+
+    utils::cargo_bin()
+        .args(["d", "--yes", "--output-name-template", "{size}"])
+        .arg(archive)
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn decompress_concatenated_tar() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let first = &dir.join("first");
+    let second = &dir.join("second");
+    fs::write(first, "first").unwrap();
+    fs::write(second, "second").unwrap();
+    ouch!("c", first, dir.join("first.tar"));
+    ouch!("c", second, dir.join("second.tar"));
+
+    let mut concatenated = fs::read(dir.join("first.tar")).unwrap();
+    concatenated.extend(fs::read(dir.join("second.tar")).unwrap());
+    let archive = &dir.join("archive.tar");
+    fs::write(archive, concatenated).unwrap();
+
+    // Only the first archive is read by default
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output);
+    assert!(output.join("first").exists());
+    assert!(!output.join("second").exists());
+
+    let output = &dir.join("output-concat");
+    ouch!("d", archive, "-d", output, "--concat-tar");
+    assert_eq!(fs::read(output.join("archive/first")).unwrap(), b"first");
+    assert_eq!(fs::read(output.join("archive/second")).unwrap(), b"second");
+}
+
+#[test]
+fn compress_no_overwrite_newer() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("c", input, archive);
+
+    // The archive is newer than every input
+    let later = filetime::FileTime::from_unix_time(filetime::FileTime::now().unix_seconds() + 3600, 0);
+    filetime::set_file_mtime(archive, later).unwrap();
+    let assert = utils::cargo_bin()
+        .args(["c", "--yes", "--no-overwrite-newer"])
+        .arg(input)
+        .arg(archive)
+        .assert()
+        .code(1);
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("Refusing to overwrite"), "{stderr}");
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&fs::metadata(archive).unwrap()),
+        later
+    );
+
+    ouch!("c", input, archive, "--no-overwrite-newer", "--force");
+    assert_ne!(
+        filetime::FileTime::from_last_modification_time(&fs::metadata(archive).unwrap()),
+        later
+    );
+
+    // A file inside of the input directory is newer than the archive
+    let earlier = filetime::FileTime::from_unix_time(filetime::FileTime::now().unix_seconds() - 3600, 0);
+    filetime::set_file_mtime(archive, earlier).unwrap();
+    fs::write(input.join("file"), "changed").unwrap();
+    ouch!("c", input, archive, "--no-overwrite-newer");
+    ouch!("d", archive, "-d", dir.join("output"));
+    assert_eq!(fs::read(dir.join("output/input/file")).unwrap(), b"changed");
+}
+
+#[test]
+fn decompress_with_unpack_strategies() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("file"), "file").unwrap();
+    fs::write(input.join("nested/inner"), "inner").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("c", input, archive);
+
+    // A single directory at the root, still unpacked into a directory named after the archive
+    let output = &dir.join("subdir");
+    ouch!("d", archive, "-d", output, "--no-smart-unpack");
+    assert_same_directory(input, output.join("archive/input"), false);
+
+    let output = &dir.join("flat");
+    ouch!("d", archive, "-d", output, "--flatten");
+    assert_eq!(fs::read(output.join("file")).unwrap(), b"file");
+    assert_eq!(fs::read(output.join("inner")).unwrap(), b"inner");
+    assert_eq!(fs::read_dir(output).unwrap().count(), 2);
+}
+
+#[test]
+fn compress_format_disagreeing_with_output() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let output = &dir.join("out.zip");
+
+    let assert = utils::cargo_bin()
+        .args(["c", "--yes", "--format", "tar.gz"])
+        .arg(input)
+        .arg(output)
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("--force-format-on-output"), "{stderr}");
+    assert!(!output.exists());
+
+    // Agreeing formats, or outputs without extensions, are fine
+    ouch!("c", input, dir.join("out.tgz"), "--format", "tar.gz");
+    ouch!("c", input, dir.join("out"), "--format", "tar.gz");
+
+    ouch!(
+        "c",
+        input,
+        output,
+        "--format",
+        "tar.gz",
+        "--force-format-on-output",
+        "format"
+    );
+    assert_eq!(&fs::read(output).unwrap()[..2], [0x1F, 0x8B]);
+
+    ouch!(
+        "c",
+        input,
+        output,
+        "--format",
+        "tar.gz",
+        "--force-format-on-output",
+        "extension"
+    );
+    assert_eq!(&fs::read(output).unwrap()[..2], b"PK");
+}
+
+#[cfg(unix)]
+#[test]
+fn decompress_preserving_permissions() {
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("shared")).unwrap();
+    fs::write(input.join("script"), "#!/bin/sh").unwrap();
+    fs::set_permissions(input.join("script"), Permissions::from_mode(0o751)).unwrap();
+    fs::set_permissions(input.join("shared"), Permissions::from_mode(0o1777)).unwrap();
+    let mode = |path: PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output);
+        assert_eq!(mode(output.join("input/script")), 0o751, "{format}");
+        // zip archives only store the regular permissions
+        let shared = if format == "zip" { 0o777 } else { 0o1777 };
+        assert_eq!(mode(output.join("input/shared")), shared, "{format}");
+
+        let output = &dir.join(format!("output-{format}-umask"));
+        ouch!("d", archive, "-d", output, "--no-preserve-permissions");
+        assert_eq!(mode(output.join("input/script")) & 0o700, 0o700, "{format}");
+        assert_eq!(mode(output.join("input/shared")) & 0o1000, 0, "{format}");
+
+        // The sticky bit is restored without warnings, so --strict doesn't fail on it
+        let output_dir = &dir.join(format!("output-{format}-strict"));
+        let output = ouch!("d", archive, "-d", output_dir, "--strict");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains("Applying"), "{format}: {stderr}");
+        assert_eq!(mode(output_dir.join("input/shared")), shared, "{format}");
+
+        // Still accepted, but it does nothing more than the default
+        let output_dir = &dir.join(format!("output-{format}-deprecated"));
+        let output = ouch!("d", archive, "-d", output_dir, "--preserve-permissions");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--preserve-permissions is deprecated"), "{stderr}");
+        assert_eq!(mode(output_dir.join("input/script")), 0o751, "{format}");
+    }
+}
+
+#[test]
+fn decompress_merging() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let layer = |name: &str, files: &[(&str, &str)]| {
+        let input = dir.join(name).join("tree");
+        for (path, content) in files {
+            let path = input.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let archive = dir.join(format!("{name}.tar.gz"));
+        ouch!("c", input, &archive);
+        archive
+    };
+    let base = layer("base", &[("a", "base"), ("nested/b", "base"), ("c", "base")]);
+    let patch = layer("patch", &[("a", "patch"), ("nested/d", "patch")]);
+    let output = &dir.join("output");
+    let read = |path: &str| fs::read_to_string(output.join("tree").join(path)).unwrap();
+
+    ouch!("d", base, "-d", output);
+    utils::cargo_bin()
+        .arg("d")
+        .arg(&patch)
+        .arg("-d")
+        .arg(output)
+        .args(["--merge", "--no"])
+        .assert()
+        .success();
+    assert_eq!(read("a"), "base");
+    assert_eq!(read("nested/d"), "patch");
+
+    ouch!("d", &patch, "-d", output, "--merge");
+    assert_eq!(read("a"), "patch");
+    assert_eq!(read("nested/b"), "base");
+    assert_eq!(read("nested/d"), "patch");
+    assert_eq!(read("c"), "base");
+}
+
+#[test]
+fn list_sorted() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for (name, size) in [("file10", 1), ("file2", 3), ("file1", 2)] {
+        fs::write(input.join(name), "x".repeat(size)).unwrap();
+    }
+    let archive = &dir.join("archive.zip");
+    ouch!(
+        "c",
+        input.join("file10"),
+        input.join("file2"),
+        input.join("file1"),
+        archive
+    );
+
+    let list = |args: &[&str]| {
+        let output = utils::cargo_bin().arg("l").arg(archive).args(args).unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout.lines().skip(1).map(str::to_owned).collect::<Vec<_>>()
+    };
+    assert_eq!(list(&[]), ["file10", "file2", "file1"]);
+    assert_eq!(list(&["--sort", "name"]), ["file1", "file10", "file2"]);
+    assert_eq!(list(&["--sort", "natural"]), ["file1", "file2", "file10"]);
+    assert_eq!(list(&["--sort", "natural", "--reverse"]), ["file10", "file2", "file1"]);
+    assert_eq!(list(&["--sort", "size"]), ["file10", "file1", "file2"]);
+}
+
+#[test]
+fn compress_zstd_with_threads() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    // Larger than the jobs of zstd at level 1, so it is split between the threads
+    let mut data = vec![0; 6 * 1024 * 1024];
+    SmallRng::seed_from_u64(1).fill(&mut data[..3 * 1024 * 1024]);
+    fs::write(input, &data).unwrap();
+
+    // The jobs are larger than the input at level 19
+    for (level, split) in [("1", true), ("19", false)] {
+        let archive = &dir.join(format!("input-{level}.zst"));
+        let compressed = ouch!("c", input, archive, "--level", level, "--threads", "4");
+        let stderr = String::from_utf8(compressed.stderr).unwrap();
+        assert_eq!(
+            !stderr.contains("--threads has no effect"),
+            split,
+            "level {level}: {stderr}"
+        );
+        let output = &dir.join(format!("output-{level}"));
+        ouch!("d", archive, "-d", output);
+        let decompressed = fs::read(output.join(format!("input-{level}"))).unwrap();
+        assert!(decompressed == data, "level {level}");
+    }
+
+    // Too small to be split
+    let small = &dir.join("small");
+    fs::write(small, "content").unwrap();
+    let output = ouch!("c", small, dir.join("small.zst"), "--threads", "4");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--threads has no effect"), "{stderr}");
+
+    // Lowered to fit in the total
+    let output = ouch!(
+        "c",
+        input,
+        dir.join("capped.zst"),
+        "--threads",
+        "4",
+        "--total-threads=2"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Compressing with 2 zstd threads"), "{stderr}");
+
+    // Used as the threads of zstd without --threads, as many as there are CPUs
+    let output = ouch!("c", input, dir.join("budget.zst"), "--total-threads=1");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Compressing with 1 zstd threads, of the 1 allowed by --total-threads."),
+        "{stderr}"
+    );
+
+    // Only zstd uses threads
+    let output = ouch!("c", input, dir.join("ignored.gz"), "--total-threads");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--total-threads only has an effect when compressing with zstd"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn compress_zstd_with_ldm() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    // The same block twice, further apart than the window of zstd without long distance matching
+    let mut data = vec![0; 10 * 1024 * 1024];
+    SmallRng::seed_from_u64(2).fill(&mut data[..]);
+    data.copy_within(..2 * 1024 * 1024, 8 * 1024 * 1024);
+    fs::write(input, &data).unwrap();
+
+    let without_ldm = &dir.join("without.zst");
+    ouch!("c", input, without_ldm);
+    let with_ldm = &dir.join("with.zst");
+    ouch!("c", input, with_ldm, "--ldm");
+
+    let without_ldm_size = fs::metadata(without_ldm).unwrap().len();
+    let with_ldm_size = fs::metadata(with_ldm).unwrap().len();
+    assert!(
+        with_ldm_size + 1024 * 1024 < without_ldm_size,
+        "{with_ldm_size} with --ldm, {without_ldm_size} without"
+    );
+
+    let output = &dir.join("output");
+    ouch!("d", with_ldm, "-d", output);
+    assert!(fs::read(output.join("with")).unwrap() == data);
+}
+
+#[test]
+fn compress_exclude_vcs() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    for file in [
+        "src/main.rs",
+        "src/main.log",
+        ".git/HEAD",
+        "vendor/lib/.svn/entries",
+        ".gitignore",
+    ] {
+        let file = input.join(file);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, "content").unwrap();
+    }
+    let archive = &dir.join("archive.tar");
+    let output = ouch!("c", input, archive, "--exclude-vcs", "--exclude", "*.log");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Skipped 3 version control paths"), "{stderr}");
+
+    let listed = ouch!("l", archive);
+    let mut listed = String::from_utf8(listed.stdout)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    listed.sort();
+    assert_eq!(
+        listed,
+        [
+            "input/",
+            "input/src/",
+            "input/src/main.rs",
+            "input/vendor/",
+            "input/vendor/lib/"
+        ]
+    );
+}
+
+#[test]
+fn compress_to_stdout() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("sub/file.txt"), "content").unwrap();
+    fs::write(dir.join("extra.txt"), "extra").unwrap();
+
+    // Zip archives can't be streamed, they go through a temporary file
+    for format in ["tar.zst", "zip"] {
+        let output = utils::cargo_bin()
+            .args(["compress", "--format", format, "--to-stdout"])
+            .arg(input)
+            .arg(dir.join("extra.txt"))
+            .assert()
+            .success()
+            .get_output()
+            .clone();
+        let archive = &dir.join(format!("archive.{format}"));
+        fs::write(archive, output.stdout).unwrap();
+
+        let output_dir = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output_dir);
+        assert_same_directory(input, output_dir.join("archive/input"), false);
+        assert_eq!(fs::read(output_dir.join("archive/extra.txt")).unwrap(), b"extra");
+    }
+
+    // The formats can't be detected without an output file
+    utils::cargo_bin()
+        .args(["compress", "--to-stdout"])
+        .arg(input)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn decompress_strip_extension() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    for name in ["report.txt.gz", "data.gz"] {
+        ouch!("c", input, dir.join(name));
+    }
+    // Detected from its contents, it has no extension to remove
+    fs::copy(dir.join("data.gz"), dir.join("blob")).unwrap();
+
+    for (args, outputs) in [
+        (&[][..], ["report.txt", "data", "blob.out"]),
+        (&["--strip-extension", "all"], ["report", "data", "blob.out"]),
+        (
+            &["--strip-extension", "keep"],
+            ["report.txt.gz.out", "data.gz.out", "blob.out"],
+        ),
+    ] {
+        utils::cargo_bin()
+            .args(["decompress", "--yes"])
+            .args(["report.txt.gz", "data.gz", "blob"].map(|name| dir.join(name)))
+            .args(["--dir".as_ref(), dir.as_os_str()])
+            .args(args)
+            .assert()
+            .success();
+        for output in outputs {
+            assert_eq!(fs::read(dir.join(output)).unwrap(), b"content", "{args:?} {output}");
+            fs::remove_file(dir.join(output)).unwrap();
+        }
+    }
+
+    // Into another directory, the compressed files are safe from being overwritten
+    let output_dir = &dir.join("output");
+    ouch!("d", dir.join("data.gz"), "-d", output_dir, "--strip-extension", "keep");
+    assert_eq!(fs::read(output_dir.join("data.gz")).unwrap(), b"content");
+}
+
+#[test]
+fn compress_with_checksum_file() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let checksum_file = &dir.join("SHA256SUMS");
+
+    // A plain zip archive is hashed after it is written, the others as they are
+    for output in ["a.tar.gz", "b.zip", "a.tar.gz"] {
+        ouch!("c", input, dir.join(output), "--checksum-file", checksum_file);
+    }
+
+    let sha256 = |name: &str| -> String {
+        let digest = sha2::Sha256::digest(fs::read(dir.join(name)).unwrap());
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    };
+    assert_eq!(
+        fs::read_to_string(checksum_file).unwrap(),
+        format!("{}  b.zip\n{}  a.tar.gz\n", sha256("b.zip"), sha256("a.tar.gz"))
+    );
+}
+
+#[test]
+fn compress_preserve_order() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    for name in ["a", "m", "z", "sub/b"] {
+        fs::write(input.join(name), name).unwrap();
+    }
+    let order = &dir.join("order");
+    fs::write(order, "input/z\ninput/sub/b\ninput/a\n").unwrap();
+
+    let list = |archive: &std::path::Path| {
+        let output = utils::cargo_bin().arg("l").arg(archive).unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout.lines().skip(1).map(str::to_owned).collect::<Vec<_>>()
+    };
+
+    // The entries that aren't listed come last
+    let tar = &dir.join("archive.tar");
+    ouch!("c", input, tar, "--preserve-order", order);
+    let expected = ["input/", "input/z", "input/sub/", "input/sub/b", "input/a", "input/m"];
+    assert_eq!(list(tar), expected);
+
+    // Recompressed in the order of the first archive
+    let zip = &dir.join("archive.zip");
+    ouch!("c", input, zip, "--preserve-order", tar);
+    assert_eq!(list(zip), expected);
+}
+
+#[test]
+fn decompress_macos_forks_strip() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    fs::write(input.join("._file"), "fork").unwrap();
+    let macosx = &dir.join("__MACOSX");
+    fs::create_dir_all(macosx.join("input")).unwrap();
+    fs::write(macosx.join("input/._file"), "fork").unwrap();
+    let archive = &dir.join("archive.zip");
+    ouch!("c", input, macosx, archive);
+
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output, "--macos-forks", "strip");
+    assert_eq!(fs::read(output.join("input/file")).unwrap(), b"content");
+    assert!(!output.join("input/._file").exists());
+    assert!(!output.join("__MACOSX").exists());
+
+    // Kept as they are by default
+    let output = &dir.join("kept");
+    ouch!("d", archive, "-d", output);
+    assert_eq!(fs::read(output.join("archive/input/._file")).unwrap(), b"fork");
+    assert_eq!(fs::read(output.join("archive/__MACOSX/input/._file")).unwrap(), b"fork");
+}
+
+#[test]
+fn compress_and_decompress_with_time_rounding() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    filetime::set_file_mtime(input.join("file"), filetime::FileTime::from_unix_time(1_700_000_059, 0)).unwrap();
+    let mtime = |path: &std::path::Path| {
+        filetime::FileTime::from_last_modification_time(&fs::metadata(path).unwrap()).unix_seconds()
+    };
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive, "--time-rounding", "2");
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output);
+        assert_eq!(mtime(&output.join("input/file")), 1_700_000_058, "{format}");
+
+        // Rounded again when decompressing
+        let output = &dir.join(format!("rounded-{format}"));
+        ouch!("d", archive, "-d", output, "--time-rounding", "60");
+        assert_eq!(mtime(&output.join("input/file")), 1_700_000_040, "{format}");
+    }
+}
+
+#[test]
+fn decompress_in_memory() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("config");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("a.toml"), "a = 1").unwrap();
+    fs::write(input.join("sub/b.toml"), "b = 2").unwrap();
+    let single = &dir.join("single.toml");
+    fs::write(single, "c = 3").unwrap();
+
+    for format in ["tar.gz", "zip", "zip.xz", "7z", "7z.zst"] {
+        let archive = &dir.join(format!("config.{format}"));
+        ouch!("c", input, archive);
+
+        let mut files = ouch::decompress_in_memory(archive, 1024).unwrap();
+        files.sort();
+        let expected = [
+            (PathBuf::from("config/a.toml"), b"a = 1".to_vec()),
+            (PathBuf::from("config/sub/b.toml"), b"b = 2".to_vec()),
+        ];
+        assert_eq!(files, expected, "{format}");
+        // Over the limit once the second file is read
+        assert!(ouch::decompress_in_memory(archive, 9).is_err(), "{format}");
+    }
+
+    let compressed = &dir.join("single.toml.bz2");
+    ouch!("c", single, compressed);
+    let files = ouch::decompress_in_memory(compressed, 1024).unwrap();
+    assert_eq!(files, [(PathBuf::from("single.toml"), b"c = 3".to_vec())]);
+}
+
+#[test]
+fn decompress_zip_deflate64() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content, content, content").unwrap();
+    let archive = &dir.join("archive.zip");
+    ouch!("c", input, archive);
+
+    // Deflate64 streams without its longer matches and distances are deflate streams, so only the
+    // method ids of the local and central headers need to be changed
+    let with_method = |method: u8| {
+        let mut data = fs::read(archive).unwrap();
+        for i in 0..data.len() - 12 {
+            match &data[i..i + 4] {
+                b"PK\x03\x04" if data[i + 8] == 8 => data[i + 8] = method,
+                b"PK\x01\x02" if data[i + 10] == 8 => data[i + 10] = method,
+                _ => {}
+            }
+        }
+        data
+    };
+
+    let deflate64 = &dir.join("deflate64.zip");
+    fs::write(deflate64, with_method(9)).unwrap();
+    let output = &dir.join("output");
+    ouch!("d", deflate64, "-d", output, "--verify-crc");
+    assert_eq!(
+        fs::read(output.join("input/file")).unwrap(),
+        b"content, content, content"
+    );
+
+    let lzma = &dir.join("lzma.zip");
+    fs::write(lzma, with_method(14)).unwrap();
+    let assert = utils::cargo_bin()
+        .args(["decompress", "--yes"])
+        .arg(lzma)
+        .arg("-d")
+        .arg(dir.join("lzma"))
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("compressed with method 14"), "{stderr}");
+}
+
+#[test]
+fn porcelain_output() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+
+    let output = ouch!("c", input, archive, "--porcelain");
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    let output = ouch!("d", archive, "-d", dir.join("output"), "--porcelain");
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    let assert = utils::cargo_bin()
+        .args(["decompress", "--yes", "--porcelain"])
+        .arg(dir.join("missing.tar.gz"))
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    let lines: Vec<_> = stderr.lines().collect();
+    assert!(lines[0].starts_with("error\t"), "{stderr}");
+    assert_eq!(lines[1], "detail\tFile not found");
+}
+
+#[cfg(unix)]
+#[test]
+fn decompress_keep_directory_symlink() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("data");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("data.tar.gz");
+    ouch!("c", input, archive);
+
+    let output = &dir.join("output");
+    let target = &dir.join("target");
+    fs::create_dir_all(output).unwrap();
+    fs::create_dir(target).unwrap();
+    fs::write(target.join("existing"), "existing").unwrap();
+    std::os::unix::fs::symlink(target, output.join("data")).unwrap();
+
+    ouch!("d", archive, "-d", output, "--keep-directory-symlink");
+    assert!(output.join("data").is_symlink());
+    assert_eq!(fs::read(target.join("file")).unwrap(), b"content");
+    assert_eq!(fs::read(target.join("existing")).unwrap(), b"existing");
+
+    // Replaced by default
+    ouch!("d", archive, "-d", output);
+    assert!(!output.join("data").is_symlink());
+    assert!(!output.join("data/existing").exists());
+    assert!(target.join("existing").exists());
+}
+
+#[test]
+fn decompress_with_checksum() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let sha256 = |data: &[u8]| format!("{:x}", sha2::Sha256::digest(data));
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+        let output = &dir.join(format!("output-{format}"));
+        let stdout = ouch!("d", archive, "-d", output, "--checksum").stdout;
+
+        let expected = format!(
+            "{}  {}\n{}  {}\n",
+            sha256(&fs::read(archive).unwrap()),
+            archive.display(),
+            sha256(b"content"),
+            output.join("input/file").display(),
+        );
+        assert_eq!(String::from_utf8(stdout).unwrap(), expected, "{format}");
+    }
+}
+
+#[test]
+fn list_and_decompress_by_index() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for name in ["a", "b", "c"] {
+        fs::write(input.join(name), name).unwrap();
+    }
+
+    for format in ["zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let stdout = String::from_utf8(ouch!("l", archive, "--index").stdout).unwrap();
+        let listed: Vec<(usize, &str)> = stdout
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let (index, path) = line.trim_start().split_once("  ").unwrap();
+                (index.parse().unwrap(), path)
+            })
+            .collect();
+        let (b_index, _) = listed.iter().find(|(_, path)| path.ends_with("input/b")).unwrap();
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output, "--index", b_index.to_string());
+        assert_eq!(fs::read(output.join("input/b")).unwrap(), b"b", "{format}");
+        assert!(
+            !output.join("input/a").exists() && !output.join("input/c").exists(),
+            "{format}"
+        );
+
+        utils::cargo_bin()
+            .args(["decompress", "--yes", "--index", "99", "-d"])
+            .arg(dir.join("out-of-range"))
+            .arg(archive)
+            .assert()
+            .failure();
+    }
+}
+
+#[test]
+fn list_flat_json() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("a \"quoted\" name"), "contents").unwrap();
+
+    let zip = &dir.join("archive.zip");
+    let tar = &dir.join("archive.tar");
+    ouch!("c", input, zip);
+    ouch!("c", input, tar);
+
+    let stdout = String::from_utf8(ouch!("l", zip, tar, "--flat-json").stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.first(), Some(&"["));
+    assert_eq!(lines.last(), Some(&"]"));
+
+    let entries = &lines[1..lines.len() - 1];
+    assert_eq!(entries.len(), 4, "{stdout}");
+    let file = entries
+        .iter()
+        .find(|entry| entry.contains("archive.zip") && entry.contains(r#""type":"file""#))
+        .unwrap();
+    assert!(file.contains(r#"\"quoted\" name","#), "{file}");
+    assert!(file.contains(r#""size":8,"#), "{file}");
+    assert!(!file.contains(r#""compressed_size":null"#), "{file}");
+    assert!(entries[0].ends_with("},") && !entries[3].ends_with(','), "{stdout}");
+    assert!(entries.iter().any(|entry| entry.contains(r#""type":"dir""#)));
+    #[cfg(unix)]
+    assert!(file.contains(r#""mode":"0"#), "{file}");
+}
+
+#[test]
+fn compress_reports_files_throttled() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for i in 0..100 {
+        fs::write(input.join(format!("file{i}")), "contents").unwrap();
+    }
+
+    for format in ["tar", "zip", "7z"] {
+        for (name, mode) in [("regular", &[][..]), ("accessible", &["-A"][..])] {
+            let output = utils::cargo_bin()
+                .args(mode)
+                .arg("c")
+                .arg(input)
+                .arg(dir.join(format!("{name}.{format}")))
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            let reported: Vec<_> = stderr.lines().filter(|line| line.contains("Compressing")).collect();
+            // The first file is reported right away, the other ones are compressed too fast
+            assert!(reported[0].ends_with("Compressing 'input'."), "{format}: {stderr}");
+            assert!(reported.len() < 10, "{format}: {stderr}");
+        }
+    }
+}
+
+#[test]
+fn compress_without_dir_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub/deep")).unwrap();
+    fs::create_dir(input.join("empty")).unwrap();
+    fs::write(input.join("sub/deep/file"), "contents").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive, "--no-dir-entries");
+
+        let stdout = String::from_utf8(ouch!("l", archive).stdout).unwrap();
+        let listed: Vec<&str> = stdout.lines().skip(1).collect();
+        assert_eq!(listed, ["input/sub/deep/file"], "{format}");
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output);
+        assert_eq!(
+            fs::read(output.join("input/sub/deep/file")).unwrap(),
+            b"contents",
+            "{format}"
+        );
+        assert!(!output.join("input/empty").exists(), "{format}");
+
+        // --dry-run lists the same entries, without the directories
+        let dry_run = &dir.join(format!("dry-run.{format}"));
+        let stdout = String::from_utf8(ouch!("c", input, dry_run, "--no-dir-entries", "--dry-run").stdout).unwrap();
+        let listed: Vec<&str> = stdout.lines().skip(1).collect();
+        assert_eq!(listed, ["input/sub/deep/file"], "{format}");
+        assert!(!dry_run.exists(), "{format}");
+    }
+}
+
+#[test]
+fn decompress_batch_into_dir_per_archive() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let first = &dir.join("first");
+    let second = &dir.join("second");
+    fs::create_dir_all(first.join("input")).unwrap();
+    fs::create_dir_all(second.join("input")).unwrap();
+    fs::write(first.join("input/file"), "first").unwrap();
+    fs::write(second.join("input/file"), "second").unwrap();
+
+    let tar = &dir.join("a.tar.gz");
+    let zip = &dir.join("b.zip");
+    ouch!("c", first.join("input"), tar);
+    ouch!("c", second.join("input"), zip);
+
+    let output = &dir.join("output");
+    ouch!("d", tar, zip, "-d", output);
+    assert_eq!(fs::read(output.join("a/input/file")).unwrap(), b"first");
+    assert_eq!(fs::read(output.join("b/input/file")).unwrap(), b"second");
+
+    let shared = &dir.join("shared");
+    ouch!("d", tar, zip, "-d", shared, "--shared-dir", "--merge");
+    assert!(shared.join("input/file").exists());
+    assert!(!shared.join("a").exists() && !shared.join("b").exists());
+}
+
+#[test]
+fn list_print0() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("with space")).unwrap();
+    fs::write(input.join("with space/file"), "contents").unwrap();
+    fs::write(input.join("skipped.log"), "contents").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let stdout = ouch!("l", archive, "--print0", "--exclude", "*.log").stdout;
+        let mut names: Vec<&[u8]> = stdout.split(|&byte| byte == 0).collect();
+        assert_eq!(names.pop(), Some(&b""[..]), "{format}");
+        names.sort();
+        assert_eq!(
+            names,
+            [&b"input"[..], b"input/with space", b"input/with space/file"],
+            "{format}"
+        );
+    }
+}
+
+#[test]
+fn decompress_with_touch() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "contents").unwrap();
+    let archived = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(input.join("file"), archived).unwrap();
+
+    for format in ["tar", "zip"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let kept = &dir.join(format!("kept-{format}"));
+        ouch!("d", archive, "-d", kept);
+        let modified = filetime::FileTime::from_last_modification_time(&fs::metadata(kept.join("input/file")).unwrap());
+        assert_eq!(modified.unix_seconds(), archived.unix_seconds(), "{format}");
+
+        let touched = &dir.join(format!("touched-{format}"));
+        ouch!("d", archive, "-d", touched, "--touch");
+        for path in [touched.join("input"), touched.join("input/file")] {
+            let modified = filetime::FileTime::from_last_modification_time(&fs::metadata(&path).unwrap());
+            assert!(modified.unix_seconds() > 1_700_000_000, "{format}: {path:?}");
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn decompress_with_safe_symlinks() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.tar");
+
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, "etc/hosts", &b"hosts"[..]).unwrap();
+    let symlinks = [
+        ("absolute", "/etc/hosts"),
+        ("missing", "/etc/passwd"),
+        ("relative", "../outside"),
+        ("dir/up", ".."),
+        ("dir/sneaky", "up/.."),
+        ("inside", "etc/hosts"),
+    ];
+    for (path, target) in symlinks {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, path, target).unwrap();
+    }
+    builder.finish().unwrap();
+
+    let output = &dir.join("output");
+    let stderr = ouch!("d", archive, "-d", output, "--safe-symlinks").stderr;
+    let stderr = String::from_utf8(stderr).unwrap();
+    let unpacked = &output.join("archive");
+
+    assert_eq!(fs::read(unpacked.join("absolute")).unwrap(), b"hosts");
+    assert!(!unpacked.join("absolute").is_symlink());
+    for skipped in ["missing", "relative", "dir/sneaky"] {
+        assert!(fs::symlink_metadata(unpacked.join(skipped)).is_err(), "{skipped}");
+        assert!(stderr.contains(&format!("Skipped the symlink '{skipped}'")), "{stderr}");
+    }
+    assert!(unpacked.join("dir/up").is_symlink());
+    assert!(unpacked.join("inside").is_symlink());
+
+    // Unpacked as they are by default
+    let output = &dir.join("unsafe");
+    ouch!("d", archive, "-d", output);
+    assert!(output.join("archive/relative").is_symlink());
+}
+
+#[test]
+fn decompress_with_head() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(input.join("large"), &contents).unwrap();
+    fs::write(input.join("small"), "small").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("c", input, archive);
+
+        let output_dir = &dir.join(format!("output-{format}"));
+        let output = ouch!("d", archive, "-d", output_dir, "--head", "1K");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            fs::read(output_dir.join("input/large")).unwrap(),
+            &contents[..1024],
+            "{format}"
+        );
+        assert_eq!(fs::read(output_dir.join("input/small")).unwrap(), b"small", "{format}");
+        assert!(stderr.contains("--head cut the rest"), "{format}: {stderr}");
+        assert!(!stderr.contains("small' were unpacked"), "{format}: {stderr}");
+    }
+
+    let archive = &dir.join("large.gz");
+    ouch!("c", input.join("large"), archive);
+    let output_dir = &dir.join("output-gz");
+    let stderr = String::from_utf8(ouch!("d", archive, "-d", output_dir, "--head", "100").stderr).unwrap();
+    assert_eq!(fs::read(output_dir.join("large")).unwrap(), &contents[..100]);
+    assert!(stderr.contains("--head cut the rest"), "{stderr}");
+}
+
+#[test]
+fn decompress_does_not_overwrite_temp_dir() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("temp");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar");
+    ouch!("c", input, archive);
+
+    // The unpacked "temp" directory would be moved over the one holding it
+    let output = &dir.join("output");
+    fs::create_dir_all(output.join("temp")).unwrap();
+    fs::write(output.join("temp/kept"), "kept").unwrap();
+    let temp_dir = output.join("temp");
+    utils::cargo_bin()
+        .args(["d", "--yes", "-d"])
+        .arg(output)
+        .arg(format!("--temp-dir={}", temp_dir.display()))
+        .arg(archive)
+        .assert()
+        .failure();
+    assert_eq!(fs::read(output.join("temp/kept")).unwrap(), b"kept");
+    assert!(!output.join("temp/file").exists());
+}
+
+#[test]
+fn compress_with_cdc() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let mut state = 1u32;
+    let contents: Vec<u8> = (0..1024 * 1024)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            // Partly compressible, like real files
+            (state >> 24) as u8 & 0x3f
+        })
+        .collect();
+    fs::write(dir.join("original"), &contents).unwrap();
+    fs::write(dir.join("inserted"), [&b"inserted bytes"[..], &contents].concat()).unwrap();
+
+    for format in ["gz", "zst"] {
+        let compressed = |name: &str| {
+            let output = dir.join(format!("{name}.{format}"));
+            ouch!("c", dir.join(name), &output, "--cdc");
+            fs::read(output).unwrap()
+        };
+        let original = compressed("original");
+        let inserted = compressed("inserted");
+
+        // Only the chunks at the start differ, the others are compressed to the same bytes
+        let common_suffix = original
+            .iter()
+            .rev()
+            .zip(inserted.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        assert!(
+            common_suffix > original.len() / 2,
+            "{format}: {common_suffix} of {}",
+            original.len()
+        );
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("d", dir.join(format!("original.{format}")), "-d", output);
+        assert_eq!(fs::read(output.join("original")).unwrap(), contents, "{format}");
+    }
+}
+
+#[test]
+fn decompress_interactive_needs_terminal() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let archive = &dir.join("archive.zip");
+    ouch!("c", input, archive);
+
+    // stdin is not a terminal in the tests
+    let output = utils::cargo_bin()
+        .args(["d", "--interactive", "-d"])
+        .arg(dir.join("output"))
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Cannot use --interactive"));
+    assert!(!dir.join("output/input").exists());
+}
+
+#[test]
+fn compress_and_decompress_with_preserve_btime() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar");
+    ouch!("c", input, archive, "--preserve-btime");
+
+    let mut stored = vec![];
+    for entry in tar::Archive::new(fs::File::open(archive).unwrap()).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let created = entry.pax_extensions().unwrap().and_then(|mut extensions| {
+            extensions
+                .find(|extension| extension.as_ref().unwrap().key() == Ok("LIBARCHIVE.creationtime"))
+                .map(|extension| extension.unwrap().value().unwrap().to_owned())
+        });
+        stored.push((entry.path().unwrap().into_owned(), created));
+    }
+    // Only where the file system keeps creation times
+    if fs::metadata(input.join("file")).unwrap().created().is_ok() {
+        assert!(stored.iter().all(|(_, created)| created.is_some()), "{stored:?}");
+    }
+
+    let output_dir = &dir.join("output");
+    let output = ouch!("d", archive, "-d", output_dir, "--preserve-btime");
+    assert_eq!(fs::read(output_dir.join("input/file")).unwrap(), b"content");
+    if cfg!(any(target_os = "macos", windows)) {
+        let created = |path: &std::path::Path| fs::metadata(path).unwrap().created().unwrap();
+        assert_eq!(created(&output_dir.join("input/file")), created(&input.join("file")));
+    } else {
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("can't set creation times"), "{stderr}");
+    }
+}
+
+#[test]
+fn compress_and_decompress_with_atime_preserve() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let file = &input.join("file");
+    fs::write(file, "content").unwrap();
+    // Older than the modification time, so reading the file updates it even with relatime
+    let accessed = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    let access_time = |path: &std::path::Path| filetime::FileTime::from_last_access_time(&fs::metadata(path).unwrap());
+
+    for format in ["tar", "zip", "7z", "gz"] {
+        filetime::set_file_atime(file, accessed).unwrap();
+        let (source, archive) = match format {
+            "gz" => (file, dir.join("file.gz")),
+            _ => (input, dir.join(format!("archive.{format}"))),
+        };
+        ouch!("c", source, &archive, "--atime-preserve");
+        assert_eq!(access_time(file), accessed, "{format}");
+    }
+
+    let output_dir = &dir.join("output");
+    ouch!("d", dir.join("archive.tar"), "-d", output_dir, "--preserve-atime");
+    assert_eq!(access_time(&output_dir.join("input/file")), accessed);
+}
+
+#[test]
+fn decompress_split_sevenz_volumes() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let mut state = 1u32;
+    // Incompressible, so the archive is split in a few volumes
+    let contents: Vec<u8> = (0..200_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 24) as u8
+        })
+        .collect();
+    fs::write(input.join("file"), &contents).unwrap();
+    let archive = &dir.join("archive.7z");
+    ouch!("c", input, archive);
+
+    // Split like 7-Zip does, in volumes of the same size but the last one
+    let archive_contents = fs::read(archive).unwrap();
+    let volumes: Vec<_> = archive_contents.chunks(64 * 1024).collect();
+    assert!(volumes.len() > 2);
+    let volume_path = |number: usize| dir.join(format!("archive.7z.{number:03}"));
+    for (i, volume) in volumes.iter().enumerate() {
+        fs::write(volume_path(i + 1), volume).unwrap();
+    }
+    fs::remove_file(archive).unwrap();
+
+    let output_dir = &dir.join("output");
+    ouch!("d", "-d", output_dir, volume_path(1));
+    assert_eq!(fs::read(output_dir.join("input/file")).unwrap(), contents);
+
+    // Only the first volume is decompressed
+    let output = utils::cargo_bin()
+        .args(["d", "--yes", "-d"])
+        .arg(dir.join("second"))
+        .arg(volume_path(2))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("not the first volume"));
+
+    // Missing volumes are reported before anything is unpacked
+    for missing in [2, volumes.len()] {
+        fs::rename(volume_path(missing), dir.join("moved")).unwrap();
+        let output = utils::cargo_bin()
+            .args(["d", "--yes", "-d"])
+            .arg(dir.join("missing"))
+            .arg(volume_path(1))
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("is missing"));
+        assert!(!dir.join("missing").exists());
+        fs::rename(dir.join("moved"), volume_path(missing)).unwrap();
+    }
+}
+
+#[test]
+fn compress_with_max_depth() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("shallow/deep/deeper")).unwrap();
+    fs::create_dir_all(input.join("other/deep")).unwrap();
+    fs::create_dir_all(input.join("empty/deep")).unwrap();
+    fs::write(input.join("top"), "top").unwrap();
+    fs::write(input.join("shallow/file"), "shallow").unwrap();
+    fs::write(input.join("shallow/deep/file"), "deep").unwrap();
+    fs::write(input.join("other/deep/file"), "deep").unwrap();
+
+    for format in ["tar", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        let output = ouch!("c", "--max-depth=2", input, archive);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        // "input/empty/deep" has no contents to cut off
+        assert!(
+            stderr.contains("2 subtrees deeper than --max-depth were stored without their contents"),
+            "{format}: {stderr}"
+        );
+
+        let output_dir = &dir.join(format!("output-{format}"));
+        ouch!("d", archive, "-d", output_dir);
+        let unpacked = &output_dir.join("input");
+        assert_eq!(fs::read(unpacked.join("top")).unwrap(), b"top");
+        assert_eq!(fs::read(unpacked.join("shallow/file")).unwrap(), b"shallow");
+        // The truncated directories are kept, without their contents
+        for truncated in ["shallow/deep", "other/deep", "empty/deep"] {
+            let truncated = unpacked.join(truncated);
+            assert!(truncated.is_dir(), "{format}: {}", truncated.display());
+            assert_eq!(fs::read_dir(truncated).unwrap().count(), 0, "{format}");
+        }
+    }
+}
+
+#[test]
+fn decompress_zip_with_verify_crc_reports_corrupted_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(archive).unwrap());
+    let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    writer.start_file("intact", options).unwrap();
+    writer.write_all(b"intact contents").unwrap();
+    writer.start_file("corrupted", options).unwrap();
+    writer.write_all(b"original contents").unwrap();
+    writer.finish().unwrap();
+
+    // Stored entries are kept as they are, so their contents can be changed in place
+    let mut data = fs::read(archive).unwrap();
+    let position = data.windows(8).position(|window| window == b"original").unwrap();
+    data[position..position + 8].copy_from_slice(b"modified");
+    fs::write(archive, data).unwrap();
+
+    let output = utils::cargo_bin()
+        .args(["d", "--yes", "--verify-crc", "-d"])
+        .arg(dir.join("output"))
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("CRC-32 mismatch in entry 'corrupted'"), "{stderr}");
+    assert!(
+        stderr.contains("1 entries do not match their stored CRC-32: corrupted"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("'intact'"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn tar_fifo_round_trip() {
+    use std::{os::unix::fs::FileTypeExt, process::Command};
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    // FIFOs, unlike device files, can be created without privileges
+    assert!(Command::new("mkfifo")
+        .arg(input.join("pipe"))
+        .status()
+        .unwrap()
+        .success());
+
+    let archive = &dir.join("archive.tar");
+    ouch!("c", input, archive);
+    let output_dir = &dir.join("output");
+    ouch!("d", archive, "-d", output_dir);
+
+    let pipe = fs::symlink_metadata(output_dir.join("input/pipe")).unwrap();
+    assert!(pipe.file_type().is_fifo());
+    assert_eq!(fs::read(output_dir.join("input/file")).unwrap(), b"content");
+}
+
+#[test]
+fn compress_with_rsyncable() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content, ".repeat(100_000)).unwrap();
+
+    let archive = &dir.join("archive.tar.zst");
+    let output = ouch!("c", "--rsyncable", input, archive);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("--rsyncable"));
+    let output_dir = &dir.join("output");
+    ouch!("d", archive, "-d", output_dir);
+    assert_same_directory(input, output_dir.join("input"), false);
+
+    let archive = &dir.join("archive.tar.gz");
+    let output = ouch!("c", "--rsyncable", input, archive);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--rsyncable only has an effect when compressing with zstd, ignoring it."),
+        "{stderr}"
+    );
+    let output_dir = &dir.join("output-gz");
+    ouch!("d", archive, "-d", output_dir);
+    assert_same_directory(input, output_dir.join("input"), false);
+}
+
+#[cfg(unix)]
+#[test]
+fn decompress_into_root_needs_force() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    // Unique, so nothing of another run is mistaken for it
+    let name = format!("ouch-sensitive-{}", dir.file_name().unwrap().to_str().unwrap());
+    let input = &dir.join(&name);
+    fs::write(input, "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("c", input, archive);
+
+    let root_entries = || fs::read_dir("/").unwrap().count();
+    let entries_before = root_entries();
+    let output = utils::cargo_bin()
+        .args(["d", "--yes", "-d", "/"])
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Refusing to decompress into '/'"), "{stderr}");
+    assert!(stderr.contains("Use --force"), "{stderr}");
+
+    // Not even a temporary directory is left behind
+    assert!(!PathBuf::from("/").join(&name).exists());
+    assert_eq!(root_entries(), entries_before);
+}
+
+#[cfg(unix)]
+#[test]
+fn compress_symlink_to_parent_dir() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("sub/file"), "content").unwrap();
+    std::os::unix::fs::symlink("..", input.join("sub/loop")).unwrap();
+    std::os::unix::fs::symlink("sub", input.join("link")).unwrap();
+
+    for format in ["tar", "zip"] {
+        // Directory symlinks are stored as links, the cycle is never walked into
+        let archive = &dir.join(format!("links.{format}"));
+        let output = ouch!("c", input, archive);
+        assert!(!String::from_utf8(output.stderr).unwrap().contains("Skipping"));
+        let output_dir = &dir.join(format!("links-{format}"));
+        ouch!("d", archive, "-d", output_dir);
+        let unpacked = &output_dir.join("input");
+        assert_eq!(fs::read_link(unpacked.join("sub/loop")).unwrap(), PathBuf::from(".."));
+        assert_eq!(fs::read_link(unpacked.join("link")).unwrap(), PathBuf::from("sub"));
+        assert_eq!(fs::read(unpacked.join("sub/file")).unwrap(), b"content");
+
+        // Followed with --dereference, except the symlinks to their own parent directories, which
+        // are reported once, even though the inputs are walked before compressing them
+        let archive = &dir.join(format!("dereferenced.{format}"));
+        let output = ouch!("c", "--dereference", "--verify", input, archive);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        for symlink in ["input/sub/loop", "input/link/loop"] {
+            let message = format!("Skipping '{symlink}', it is a symlink to one of its own parent directories.");
+            assert_eq!(stderr.matches(&message).count(), 1, "{format}: {stderr}");
+        }
+        let output_dir = &dir.join(format!("dereferenced-{format}"));
+        ouch!("d", archive, "-d", output_dir);
+        let unpacked = &output_dir.join("input");
+        assert!(!unpacked.join("sub/loop").exists());
+        assert!(!unpacked.join("link").is_symlink());
+        assert_eq!(fs::read(unpacked.join("link/file")).unwrap(), b"content");
+    }
+}
+
+#[test]
+fn compress_zip_with_update() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let archived = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+    for name in ["changed", "same", "removed"] {
+        fs::write(input.join(name), "archived").unwrap();
+        filetime::set_file_mtime(input.join(name), archived).unwrap();
+    }
+    let archive = &dir.join("archive.zip");
+    ouch!("c", input, archive);
+
+    fs::write(input.join("changed"), "changed").unwrap();
+    filetime::set_file_mtime(
+        input.join("changed"),
+        filetime::FileTime::from_unix_time(1_600_003_600, 0),
+    )
+    .unwrap();
+    // Not newer than its entry, so the entry is kept as it is
+    fs::write(input.join("same"), "not read").unwrap();
+    filetime::set_file_mtime(input.join("same"), archived).unwrap();
+    fs::remove_file(input.join("removed")).unwrap();
+    fs::write(input.join("added"), "added").unwrap();
+
+    let output = ouch!("c", "--update", input, archive);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // "input/", "same" and "removed" are copied over from the existing archive
+    assert!(
+        stderr.contains("1 entries updated, 1 added and 3 kept unchanged."),
+        "{stderr}"
+    );
+
+    let output_dir = &dir.join("output");
+    ouch!("d", archive, "-d", output_dir);
+    let unpacked = &output_dir.join("input");
+    assert_eq!(fs::read_to_string(unpacked.join("changed")).unwrap(), "changed");
+    assert_eq!(fs::read_to_string(unpacked.join("same")).unwrap(), "archived");
+    assert_eq!(fs::read_to_string(unpacked.join("removed")).unwrap(), "archived");
+    assert_eq!(fs::read_to_string(unpacked.join("added")).unwrap(), "added");
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&fs::metadata(unpacked.join("same")).unwrap()),
+        archived
+    );
+}
+
+#[test]
+fn compress_with_sevenz_method() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content, ".repeat(1000)).unwrap();
+
+    for method in ["copy", "bzip2"] {
+        let archive = &dir.join(format!("{method}.7z"));
+        let output = utils::cargo_bin()
+            .args(["c", "--yes", &format!("--7z-method={method}")])
+            .arg(input)
+            .arg(archive)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{method}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains(&format!("Cannot compress 7z archives with the method '{method}'")),
+            "{stderr}"
+        );
+        assert!(
+            stderr.contains("Use '--7z-method lzma2' instead, its fastest level is '--fast'."),
+            "{stderr}"
+        );
+        assert!(!archive.exists());
+    }
+
+    let archive = &dir.join("lzma2.7z");
+    ouch!("c", "--7z-method=lzma2", "--fast", input, archive);
+    let output_dir = &dir.join("output");
+    ouch!("d", archive, "-d", output_dir);
+    assert_same_directory(input, output_dir.join("input"), false);
+}
+
+#[cfg(unix)]
+#[test]
+fn compress_without_free_space() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    // A sparse file, larger than the free space of any disk, without taking any of it
+    let input = &dir.join("input");
+    fs::File::create(input).unwrap().set_len(1 << 43).unwrap();
+
+    let archive = &dir.join("archive.tar");
+    let output = utils::cargo_bin()
+        .args(["compress", "--no"])
+        .arg(input)
+        .arg(archive)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(5), "{stderr}");
+    assert!(stderr.contains("Not enough free space to compress"), "{stderr}");
+    assert!(!archive.exists());
+}
+
+#[test]
+fn sevenz_in_memory_only_when_chained() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+
+    for (format, in_memory) in [("7z", false), ("7z.gz", true)] {
+        let archive = &dir.join(format!("archive.{format}"));
+        let output_dir = &dir.join(format!("output-{format}"));
+        let outputs = [
+            ouch!("c", input, archive),
+            ouch!("l", archive),
+            ouch!("d", archive, "-d", output_dir),
+        ];
+        for output in outputs {
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert_eq!(stderr.contains("run out of RAM"), in_memory, "{format}: {stderr}");
+        }
+        assert_same_directory(input, output_dir.join("input"), false);
+    }
+}
+
+#[test]
+fn decompress_misnamed_file_only_with_yes() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let file = &dir.join("file");
+    fs::write(file, "content").unwrap();
+    ouch!("c", file, dir.join("file.gz"));
+    fs::rename(dir.join("file.gz"), dir.join("file.zst")).unwrap();
+
+    // --no refuses to decompress with the detected format
+    let output = utils::cargo_bin()
+        .args(["d", "file.zst", "-d", "no", "--no"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("differ from the detected extension"));
+    assert!(!dir.join("no").exists());
+
+    // --yes decompresses it as the contents say
+    ouch!("d", dir.join("file.zst"), "-d", dir.join("yes"));
+    assert_eq!(fs::read(dir.join("yes/file")).unwrap(), b"content");
+}
+
+#[test]
+fn decompress_with_temp_dir() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("c", input, archive);
+
+    let temp_dir = &dir.join("temp");
+    let output = &dir.join("output");
+    ouch!("d", archive, "-d", output, format!("--temp-dir={}", temp_dir.display()));
+
+    assert_eq!(fs::read(output.join("input/file")).unwrap(), b"content");
+    // the staging directory is removed once the files are moved out of it
+    assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn compress_with_auto_name() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+
+    ouch!("c", input, dir.join("backup.tar.zst"), "--auto-name=%Y_stamp");
+    let names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("backup-"))
+        .collect();
+    assert_eq!(names.len(), 1);
+    assert!(names[0].ends_with("_stamp.tar.zst"));
+
+    utils::cargo_bin()
+        .args(["compress", "--auto-name=%q"])
+        .arg(input)
+        .arg(dir.join("backup.zip"))
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn compress_with_verify() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("a"), "a content").unwrap();
+    fs::write(input.join("sub/b"), "b content").unwrap();
+
+    for format in ["tar.gz", "zip", "7z", "zip.xz"] {
+        let output = ouch!("compress", "--verify", input, dir.join(format!("archive.{format}")));
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("2 files match their sources"));
+    }
+}
@@ -1,7 +1,7 @@
-/// Snapshot tests for Ouch's output.
-///
-/// See CONTRIBUTING.md for a brief guide on how to use [`insta`] for these tests.
-/// [`insta`]: https://docs.rs/insta
+//! Snapshot tests for Ouch's output.
+//!
+//! See CONTRIBUTING.md for a brief guide on how to use [`insta`] for these tests.
+//! [`insta`]: https://docs.rs/insta
 
 #[macro_use]
 mod utils;
@@ -98,6 +98,17 @@ fn ui_test_ok_compress() {
     ui!(run_ouch("ouch compress input output.gz", dir));
 }
 
+#[test]
+fn ui_test_ok_compress_verbose() {
+    let (_dropper, dir) = testdir().unwrap();
+
+    // prepare
+    std::fs::write(dir.join("input"), "ouch ".repeat(1000)).unwrap();
+
+    ui!(run_ouch("ouch compress -vv input output.zip", dir));
+    ui!(run_ouch("ouch compress -vv input output.gz", dir));
+}
+
 #[test]
 fn ui_test_ok_decompress() {
     let (_dropper, dir) = testdir().unwrap();
@@ -109,6 +120,13 @@ fn ui_test_ok_decompress() {
     ui!(run_ouch("ouch decompress output.zst", dir));
 }
 
+#[test]
+fn ui_test_ok_formats() {
+    let (_dropper, dir) = testdir().unwrap();
+
+    ui!(run_ouch("ouch formats", dir));
+}
+
 #[test]
 fn ui_test_usage_help_flag() {
     ui!(output_to_string(ouch!("--help")));